@@ -0,0 +1,286 @@
+//! Content-defined chunking for large blobs.
+//!
+//! Blobs over [`CHUNKING_THRESHOLD`] are split with a FastCDC-style gear hash
+//! into chunks stored as ordinary blobs, plus a manifest blob (media type
+//! [`CHUNK_MANIFEST_MEDIA_TYPE`]) listing them in order. Cut points depend
+//! only on nearby content, so an edit in a large file changes just the chunks
+//! around it.
+
+use std::io::Read;
+
+use crate::error::CoreError;
+use crate::hash::content_hash;
+use crate::id::ObjectId;
+use crate::object::Object;
+use crate::types::Blob;
+
+/// Media type marking a blob whose payload is a [`ChunkManifest`].
+pub const CHUNK_MANIFEST_MEDIA_TYPE: &str = "application/vnd.claw.chunk-manifest";
+
+pub const MIN_CHUNK_SIZE: usize = 256 * 1024;
+pub const AVG_CHUNK_SIZE: usize = 1024 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Content up to this size is stored as a single blob.
+pub const CHUNKING_THRESHOLD: usize = MAX_CHUNK_SIZE;
+
+// Normalized chunking: a stricter mask before the average size and a looser
+// one after it pulls chunk sizes towards the average.
+const MASK_STRICT: u64 = !0u64 << (64 - 22);
+const MASK_LOOSE: u64 = !0u64 << (64 - 18);
+
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64 with a fixed seed; the table is part of the chunk format.
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x636c_6177_6364_6331;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Length of the first chunk of `data`.
+///
+/// `data` should hold at least [`MAX_CHUNK_SIZE`] bytes unless it is the
+/// tail of the stream.
+pub fn cut_point(data: &[u8]) -> usize {
+    let len = data.len();
+    if len <= MIN_CHUNK_SIZE {
+        return len;
+    }
+    let end = len.min(MAX_CHUNK_SIZE);
+    let normal = end.min(AVG_CHUNK_SIZE);
+    let mut hash = 0u64;
+    for (i, byte) in data.iter().enumerate().take(normal).skip(MIN_CHUNK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & MASK_STRICT == 0 {
+            return i + 1;
+        }
+    }
+    for (i, byte) in data.iter().enumerate().take(end).skip(normal) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & MASK_LOOSE == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Splits a reader into content-defined chunks, buffering at most
+/// [`MAX_CHUNK_SIZE`] bytes.
+pub struct Chunker<R> {
+    reader: R,
+    buf: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: Vec::with_capacity(MAX_CHUNK_SIZE),
+            eof: false,
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        while !self.eof && self.buf.len() < MAX_CHUNK_SIZE {
+            let filled = self.buf.len();
+            self.buf.resize(MAX_CHUNK_SIZE, 0);
+            match self.reader.read(&mut self.buf[filled..]) {
+                Ok(0) => {
+                    self.buf.truncate(filled);
+                    self.eof = true;
+                }
+                Ok(n) => self.buf.truncate(filled + n),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                    self.buf.truncate(filled);
+                }
+                Err(e) => {
+                    self.buf.truncate(filled);
+                    return Err(e);
+                }
+            }
+        }
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let cut = cut_point(&self.buf);
+        let rest = self.buf.split_off(cut);
+        let chunk = std::mem::replace(&mut self.buf, rest);
+        self.buf.reserve(MAX_CHUNK_SIZE - self.buf.len());
+        Ok(Some(chunk))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkRef {
+    pub id: ObjectId,
+    pub size: u64,
+}
+
+/// Ordered list of the chunk blobs making up one large blob.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkManifest {
+    pub total_size: u64,
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkManifest {
+    pub fn to_blob(&self) -> Result<Blob, CoreError> {
+        Ok(Blob {
+            data: crate::proto_conv::encode_chunk_manifest(self)?,
+            media_type: Some(CHUNK_MANIFEST_MEDIA_TYPE.to_string()),
+        })
+    }
+
+    /// Parse `blob` as a manifest; `None` if it is an ordinary blob.
+    pub fn from_blob(blob: &Blob) -> Option<Result<Self, CoreError>> {
+        blob.is_chunk_manifest()
+            .then(|| crate::proto_conv::decode_chunk_manifest(&blob.data))
+    }
+}
+
+impl Blob {
+    pub fn is_chunk_manifest(&self) -> bool {
+        self.media_type.as_deref() == Some(CHUNK_MANIFEST_MEDIA_TYPE)
+    }
+}
+
+/// Id an object would be stored under, without storing it.
+pub fn hash_object(obj: &Object) -> Result<ObjectId, CoreError> {
+    Ok(content_hash(obj.type_tag(), &obj.serialize_payload()?))
+}
+
+/// Turn the contents of `reader` into a blob, chunked if it is larger than
+/// [`CHUNKING_THRESHOLD`].
+///
+/// Every object produced (chunks first, then the manifest) goes to `sink`,
+/// which stores or just hashes it and returns its id. Returns the id of the
+/// blob or manifest.
+pub fn build_blob<R, E>(
+    reader: R,
+    mut sink: impl FnMut(&Object) -> Result<ObjectId, E>,
+) -> Result<ObjectId, E>
+where
+    R: Read,
+    E: From<CoreError>,
+{
+    let mut head = Vec::new();
+    let mut reader = reader;
+    (&mut reader)
+        .take(CHUNKING_THRESHOLD as u64 + 1)
+        .read_to_end(&mut head)
+        .map_err(CoreError::from)?;
+    if head.len() <= CHUNKING_THRESHOLD {
+        return sink(&Object::Blob(Blob {
+            data: head,
+            media_type: None,
+        }));
+    }
+
+    let mut chunker = Chunker::new(std::io::Cursor::new(head).chain(reader));
+    let mut manifest = ChunkManifest {
+        total_size: 0,
+        chunks: Vec::new(),
+    };
+    while let Some(data) = chunker.next_chunk().map_err(CoreError::from)? {
+        let size = data.len() as u64;
+        let id = sink(&Object::Blob(Blob {
+            data,
+            media_type: None,
+        }))?;
+        manifest.total_size += size;
+        manifest.chunks.push(ChunkRef { id, size });
+    }
+    sink(&Object::Blob(manifest.to_blob()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn chunks_of(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(data);
+        let mut out = Vec::new();
+        while let Some(chunk) = chunker.next_chunk().unwrap() {
+            out.push(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn chunks_reassemble_within_bounds() {
+        let data = pseudo_random(12 * 1024 * 1024, 7);
+        let chunks = chunks_of(&data);
+        assert!(chunks.len() > 3);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() > MIN_CHUNK_SIZE && chunk.len() <= MAX_CHUNK_SIZE);
+        }
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn edit_only_changes_nearby_chunks() {
+        let data = pseudo_random(12 * 1024 * 1024, 11);
+        let mut edited = data.clone();
+        edited[6 * 1024 * 1024] ^= 0xff;
+        let before = chunks_of(&data);
+        let after = chunks_of(&edited);
+        let shared = after.iter().filter(|c| before.contains(c)).count();
+        assert!(shared >= after.len() - 2, "{shared} of {}", after.len());
+    }
+
+    #[test]
+    fn small_content_is_a_plain_blob() {
+        let mut objects = Vec::new();
+        let id = build_blob(&b"small"[..], |obj| {
+            objects.push(obj.clone());
+            hash_object(obj)
+        })
+        .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(id, hash_object(&objects[0]).unwrap());
+    }
+
+    #[test]
+    fn large_content_gets_a_manifest() {
+        let data = pseudo_random(CHUNKING_THRESHOLD + 1024 * 1024, 3);
+        let mut objects = Vec::new();
+        build_blob::<_, CoreError>(&data[..], |obj| {
+            objects.push(obj.clone());
+            hash_object(obj)
+        })
+        .unwrap();
+        let Some(Object::Blob(last)) = objects.last() else {
+            panic!("expected a manifest blob");
+        };
+        let manifest = ChunkManifest::from_blob(last).unwrap().unwrap();
+        assert_eq!(manifest.total_size, data.len() as u64);
+        assert_eq!(manifest.chunks.len(), objects.len() - 1);
+        assert_eq!(
+            Object::Blob(last.clone()).dependencies().len(),
+            manifest.chunks.len()
+        );
+    }
+}
@@ -9,6 +9,9 @@ const COF_VERSION: u8 = 0x01;
 pub enum Compression {
     None = 0x00,
     Zstd = 0x01,
+    /// Zstd with a trained dictionary; the dictionary id follows the
+    /// uncompressed length as a uvarint.
+    ZstdDict = 0x02,
 }
 
 impl Compression {
@@ -16,6 +19,7 @@ impl Compression {
         match v {
             0x00 => Some(Self::None),
             0x01 => Some(Self::Zstd),
+            0x02 => Some(Self::ZstdDict),
             _ => None,
         }
     }
@@ -49,7 +53,7 @@ impl CofFlags {
     }
 }
 
-fn encode_uvarint(mut value: u64, buf: &mut Vec<u8>) {
+pub fn encode_uvarint(mut value: u64, buf: &mut Vec<u8>) {
     loop {
         let mut byte = (value & 0x7F) as u8;
         value >>= 7;
@@ -63,7 +67,7 @@ fn encode_uvarint(mut value: u64, buf: &mut Vec<u8>) {
     }
 }
 
-fn decode_uvarint(data: &[u8], pos: &mut usize) -> Result<u64, CoreError> {
+pub fn decode_uvarint(data: &[u8], pos: &mut usize) -> Result<u64, CoreError> {
     let mut result: u64 = 0;
     let mut shift = 0u32;
     loop {
@@ -86,26 +90,88 @@ fn decode_uvarint(data: &[u8], pos: &mut usize) -> Result<u64, CoreError> {
     Ok(result)
 }
 
+/// Seals and opens the payload of objects stored with the encrypted flag.
+pub trait PayloadCipher {
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CoreError>;
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CoreError>;
+}
+
+/// Looks up trained zstd dictionaries by the id stored in a COF header.
+pub trait DictionarySource {
+    fn dictionary(&self, id: u32) -> Option<&[u8]>;
+}
+
+impl DictionarySource for std::collections::HashMap<u32, Vec<u8>> {
+    fn dictionary(&self, id: u32) -> Option<&[u8]> {
+        self.get(&id).map(Vec::as_slice)
+    }
+}
+
+/// Key and dictionaries available when decoding.
+#[derive(Clone, Copy, Default)]
+pub struct CofContext<'a> {
+    pub cipher: Option<&'a dyn PayloadCipher>,
+    pub dictionaries: Option<&'a dyn DictionarySource>,
+}
+
 /// Encode a payload into COF v1 format.
 /// Format: [4B magic][1B version][1B type_tag][1B flags][1B compression][uvarint uncompressed_len][payload][4B CRC32]
 pub fn cof_encode(type_tag: TypeTag, payload: &[u8]) -> Result<Vec<u8>, CoreError> {
-    let compression = if payload.len() > 64 {
-        Compression::Zstd
-    } else {
-        Compression::None
-    };
+    encode(type_tag, payload, None, None)
+}
 
-    let compressed = match compression {
-        Compression::None => payload.to_vec(),
-        Compression::Zstd => {
-            zstd::encode_all(payload, 3).map_err(|e| CoreError::Compression(e.to_string()))?
+/// Encode with the payload compressed and then sealed by `cipher`, setting
+/// the encrypted flag. Length and CRC32 still describe the plaintext.
+pub fn cof_encode_sealed(
+    type_tag: TypeTag,
+    payload: &[u8],
+    cipher: &dyn PayloadCipher,
+) -> Result<Vec<u8>, CoreError> {
+    encode(type_tag, payload, None, Some(cipher))
+}
+
+/// Encode with the payload compressed against the trained dictionary `dict`,
+/// recorded in the header as `dict_id`. Falls back to no compression when
+/// the dictionary doesn't help.
+pub fn cof_encode_with_dictionary(
+    type_tag: TypeTag,
+    payload: &[u8],
+    dict_id: u32,
+    dict: &[u8],
+) -> Result<Vec<u8>, CoreError> {
+    encode(type_tag, payload, Some((dict_id, dict)), None)
+}
+
+fn encode(
+    type_tag: TypeTag,
+    payload: &[u8],
+    dictionary: Option<(u32, &[u8])>,
+    cipher: Option<&dyn PayloadCipher>,
+) -> Result<Vec<u8>, CoreError> {
+    let (compression, compressed) = match dictionary {
+        Some((_, dict)) if !payload.is_empty() => {
+            let compressed = zstd::bulk::Compressor::with_dictionary(3, dict)
+                .and_then(|mut c| c.compress(payload))
+                .map_err(|e| CoreError::Compression(e.to_string()))?;
+            if compressed.len() < payload.len() {
+                (Compression::ZstdDict, compressed)
+            } else {
+                (Compression::None, payload.to_vec())
+            }
         }
+        None if payload.len() > 64 => (
+            Compression::Zstd,
+            zstd::encode_all(payload, 3).map_err(|e| CoreError::Compression(e.to_string()))?,
+        ),
+        _ => (Compression::None, payload.to_vec()),
+    };
+
+    let compressed = match cipher {
+        Some(cipher) => cipher.seal(&compressed)?,
+        None => compressed,
     };
 
-    let flags = CofFlags::new(
-        compression != Compression::None,
-        false, // encryption flag set at higher layer
-    );
+    let flags = CofFlags::new(compression != Compression::None, cipher.is_some());
 
     let mut buf = Vec::with_capacity(4 + 4 + compressed.len() + 10 + 4);
 
@@ -118,6 +184,11 @@ pub fn cof_encode(type_tag: TypeTag, payload: &[u8]) -> Result<Vec<u8>, CoreErro
 
     // Uncompressed length
     encode_uvarint(payload.len() as u64, &mut buf);
+    if compression == Compression::ZstdDict {
+        if let Some((dict_id, _)) = dictionary {
+            encode_uvarint(dict_id as u64, &mut buf);
+        }
+    }
 
     // Payload
     buf.extend_from_slice(&compressed);
@@ -130,7 +201,16 @@ pub fn cof_encode(type_tag: TypeTag, payload: &[u8]) -> Result<Vec<u8>, CoreErro
 }
 
 /// Decode COF v1 format, returning (TypeTag, decompressed payload).
+///
+/// Encrypted objects fail with [`CoreError::Encrypted`]; use
+/// [`cof_decode_with`] to open them.
 pub fn cof_decode(data: &[u8]) -> Result<(TypeTag, Vec<u8>), CoreError> {
+    cof_decode_with(data, CofContext::default())
+}
+
+/// Decode COF v1 format, opening encrypted payloads and looking up
+/// dictionaries through `ctx`.
+pub fn cof_decode_with(data: &[u8], ctx: CofContext<'_>) -> Result<(TypeTag, Vec<u8>), CoreError> {
     if data.len() < 12 {
         return Err(CoreError::Deserialization("data too short for COF".into()));
     }
@@ -149,8 +229,7 @@ pub fn cof_decode(data: &[u8]) -> Result<(TypeTag, Vec<u8>), CoreError> {
     // Type tag
     let type_tag = TypeTag::from_u8(data[5]).ok_or(CoreError::UnknownTypeTag(data[5]))?;
 
-    // Flags (data[6]) - reserved for future use
-    let _flags = data[6];
+    let flags = CofFlags(data[6]);
 
     // Compression
     let compression = Compression::from_u8(data[7])
@@ -159,6 +238,10 @@ pub fn cof_decode(data: &[u8]) -> Result<(TypeTag, Vec<u8>), CoreError> {
     // Uncompressed length
     let mut pos = 8;
     let uncompressed_len = decode_uvarint(data, &mut pos)? as usize;
+    let dict_id = match compression {
+        Compression::ZstdDict => Some(decode_uvarint(data, &mut pos)? as u32),
+        _ => None,
+    };
 
     // CRC32 check: last 4 bytes
     if data.len() < pos + 4 {
@@ -174,21 +257,49 @@ pub fn cof_decode(data: &[u8]) -> Result<(TypeTag, Vec<u8>), CoreError> {
         data[crc_offset + 3],
     ]);
 
-    // Compressed payload
-    let compressed = &data[pos..crc_offset];
+    // Compressed payload, sealed if the encrypted flag is set
+    let stored = &data[pos..crc_offset];
+    let opened;
+    let compressed = if flags.is_encrypted() {
+        let cipher = ctx.cipher.ok_or(CoreError::Encrypted)?;
+        opened = cipher.open(stored)?;
+        opened.as_slice()
+    } else {
+        stored
+    };
 
     // Decompress
     let payload = match compression {
         Compression::None => compressed.to_vec(),
         Compression::Zstd => {
-            let mut decompressed = zstd::decode_all(compressed)
+            zstd::decode_all(compressed).map_err(|e| CoreError::Decompression(e.to_string()))?
+        }
+        Compression::ZstdDict => {
+            let dict_id = dict_id.unwrap_or_default();
+            let dict = ctx
+                .dictionaries
+                .and_then(|d| d.dictionary(dict_id))
+                .ok_or(CoreError::UnknownDictionary(dict_id))?;
+            // The header length is untrusted: preallocate only what the
+            // compressed data plausibly expands to, and stop reading one byte
+            // past the claimed length so the check below rejects the rest.
+            let mut out =
+                Vec::with_capacity(uncompressed_len.min(compressed.len().saturating_mul(4)));
+            let limit = (uncompressed_len as u64).saturating_add(1);
+            zstd::stream::read::Decoder::with_dictionary(compressed, dict)
+                .and_then(|d| {
+                    std::io::Read::read_to_end(&mut std::io::Read::take(d, limit), &mut out)
+                })
                 .map_err(|e| CoreError::Decompression(e.to_string()))?;
-            if decompressed.len() != uncompressed_len {
-                decompressed.truncate(uncompressed_len);
-            }
-            decompressed
+            out
         }
     };
+    if payload.len() != uncompressed_len {
+        return Err(CoreError::LengthMismatch {
+            expected: uncompressed_len,
+            actual: payload.len(),
+        });
+    }
 
     // CRC32 of uncompressed payload per spec
     let actual_crc = crc32fast::hash(&payload);
@@ -202,6 +313,21 @@ pub fn cof_decode(data: &[u8]) -> Result<(TypeTag, Vec<u8>), CoreError> {
     Ok((type_tag, payload))
 }
 
+/// The dictionary id of COF-encoded data compressed with a dictionary.
+pub fn cof_dictionary_id(data: &[u8]) -> Option<u32> {
+    if data.len() < 12 || &data[..4] != MAGIC || data[7] != Compression::ZstdDict as u8 {
+        return None;
+    }
+    let mut pos = 8;
+    decode_uvarint(data, &mut pos).ok()?;
+    decode_uvarint(data, &mut pos).ok().map(|id| id as u32)
+}
+
+/// Whether COF-encoded data has the encrypted flag set.
+pub fn cof_is_encrypted(data: &[u8]) -> bool {
+    data.len() > 6 && &data[..4] == MAGIC && CofFlags(data[6]).is_encrypted()
+}
+
 /// Peek at the type tag from COF-encoded data without fully decoding.
 ///
 /// This is useful when the raw COF bytes will be forwarded over the wire
@@ -250,6 +376,103 @@ mod tests {
         assert!(cof_decode(&encoded).is_err());
     }
 
+    struct XorCipher(u8);
+
+    impl PayloadCipher for XorCipher {
+        fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CoreError> {
+            Ok(plaintext.iter().map(|b| b ^ self.0).collect())
+        }
+        fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CoreError> {
+            self.seal(sealed)
+        }
+    }
+
+    #[test]
+    fn sealed_roundtrip_requires_cipher() {
+        let payload = vec![b'z'; 500];
+        let encoded = cof_encode_sealed(TypeTag::Blob, &payload, &XorCipher(0x5a)).unwrap();
+        assert!(cof_is_encrypted(&encoded));
+        assert!(!encoded.windows(8).any(|w| w == b"zzzzzzzz"));
+        assert!(matches!(cof_decode(&encoded), Err(CoreError::Encrypted)));
+        let ctx = CofContext {
+            cipher: Some(&XorCipher(0x5a)),
+            ..Default::default()
+        };
+        let (tag, decoded) = cof_decode_with(&encoded, ctx).unwrap();
+        assert_eq!(tag, TypeTag::Blob);
+        assert_eq!(decoded, payload);
+        assert!(!cof_is_encrypted(
+            &cof_encode(TypeTag::Blob, &payload).unwrap()
+        ));
+    }
+
+    #[test]
+    fn dictionary_roundtrip_requires_dictionary() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!("{{\"title\":\"intent {i}\",\"status\":\"open\"}}").into_bytes())
+            .collect();
+        let dict = zstd::dict::from_samples(&samples, 1024).unwrap();
+        let payload = br#"{"title":"intent 999","status":"open"}"#;
+
+        let encoded = cof_encode_with_dictionary(TypeTag::Intent, payload, 7, &dict).unwrap();
+        assert_eq!(cof_dictionary_id(&encoded), Some(7));
+        assert!(encoded.len() < cof_encode(TypeTag::Intent, payload).unwrap().len());
+        assert!(matches!(
+            cof_decode(&encoded),
+            Err(CoreError::UnknownDictionary(7))
+        ));
+
+        let dicts: std::collections::HashMap<u32, Vec<u8>> = [(7, dict)].into();
+        let ctx = CofContext {
+            dictionaries: Some(&dicts),
+            ..Default::default()
+        };
+        let (tag, decoded) = cof_decode_with(&encoded, ctx).unwrap();
+        assert_eq!(tag, TypeTag::Intent);
+        assert_eq!(decoded, payload);
+    }
+
+    #[test]
+    fn length_mismatch_detected() {
+        let payload = vec![b'a'; 1000];
+        let mut encoded = cof_encode(TypeTag::Blob, &payload).unwrap();
+        // Header length is a 2-byte uvarint at offset 8; claim one byte fewer.
+        encoded[8] -= 1;
+        assert!(matches!(
+            cof_decode(&encoded),
+            Err(CoreError::LengthMismatch {
+                expected: 999,
+                actual: 1000
+            })
+        ));
+    }
+
+    #[test]
+    fn huge_header_length_is_rejected_without_preallocating() {
+        let samples: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!("{{\"title\":\"intent {i}\",\"status\":\"open\"}}").into_bytes())
+            .collect();
+        let dict = zstd::dict::from_samples(&samples, 1024).unwrap();
+        let payload = br#"{"title":"intent 999","status":"open"}"#;
+        let encoded = cof_encode_with_dictionary(TypeTag::Intent, payload, 7, &dict).unwrap();
+
+        let mut pos = 8;
+        decode_uvarint(&encoded, &mut pos).unwrap();
+        let mut corrupt = encoded[..8].to_vec();
+        encode_uvarint(u64::MAX, &mut corrupt);
+        corrupt.extend_from_slice(&encoded[pos..]);
+
+        let dicts: std::collections::HashMap<u32, Vec<u8>> = [(7, dict)].into();
+        let ctx = CofContext {
+            dictionaries: Some(&dicts),
+            ..Default::default()
+        };
+        assert!(matches!(
+            cof_decode_with(&corrupt, ctx),
+            Err(CoreError::LengthMismatch { actual, .. }) if actual == payload.len()
+        ));
+    }
+
     #[test]
     fn invalid_magic_rejected() {
         let mut data = cof_encode(TypeTag::Blob, b"test").unwrap();
@@ -10,6 +10,8 @@ pub enum CoreError {
     UnknownTypeTag(u8),
     #[error("CRC32 mismatch: expected {expected:#010x}, got {actual:#010x}")]
     Crc32Mismatch { expected: u32, actual: u32 },
+    #[error("payload length mismatch: header says {expected} bytes, got {actual}")]
+    LengthMismatch { expected: usize, actual: usize },
     #[error("decompression error: {0}")]
     Decompression(String),
     #[error("compression error: {0}")]
@@ -22,6 +24,12 @@ pub enum CoreError {
     InvalidObjectId(String),
     #[error("payload too large: {size} bytes (max {max})")]
     PayloadTooLarge { size: usize, max: usize },
+    #[error("payload is encrypted and no key is available")]
+    Encrypted,
+    #[error("unknown compression dictionary {0:08x}")]
+    UnknownDictionary(u32),
+    #[error("encryption error: {0}")]
+    Encryption(String),
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -150,6 +150,8 @@ pub struct Conflict {
     pub status: ::prost::alloc::string::String,
     #[prost(uint64, tag = "10")]
     pub created_at_ms: u64,
+    #[prost(string, tag = "11")]
+    pub kind: ::prost::alloc::string::String,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct Evidence {
@@ -243,3 +245,42 @@ pub struct RefLog {
     #[prost(message, repeated, tag = "2")]
     pub entries: ::prost::alloc::vec::Vec<RefLogEntry>,
 }
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct OperationRef {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "2")]
+    pub target: ::core::option::Option<super::common::ObjectId>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Operation {
+    #[prost(message, optional, tag = "1")]
+    pub parent: ::core::option::Option<super::common::ObjectId>,
+    #[prost(message, repeated, tag = "2")]
+    pub refs: ::prost::alloc::vec::Vec<OperationRef>,
+    #[prost(string, tag = "3")]
+    pub head_ref: ::prost::alloc::string::String,
+    #[prost(message, optional, tag = "4")]
+    pub head_target: ::core::option::Option<super::common::ObjectId>,
+    #[prost(string, tag = "5")]
+    pub command: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub author: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "7")]
+    pub timestamp: u64,
+}
+/// Payload of a chunked blob's manifest (see claw_core::chunking).
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChunkRef {
+    #[prost(message, optional, tag = "1")]
+    pub id: ::core::option::Option<super::common::ObjectId>,
+    #[prost(uint64, tag = "2")]
+    pub size: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ChunkManifest {
+    #[prost(uint64, tag = "1")]
+    pub total_size: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub chunks: ::prost::alloc::vec::Vec<ChunkRef>,
+}
@@ -1,3 +1,4 @@
+pub mod chunking;
 pub mod cof;
 pub mod error;
 pub mod generated;
@@ -20,6 +20,7 @@ pub enum TypeTag {
     Policy = 0x0A,
     Workstream = 0x0B,
     RefLog = 0x0C,
+    Operation = 0x0D,
 }
 
 impl TypeTag {
@@ -37,6 +38,7 @@ impl TypeTag {
             0x0A => Some(Self::Policy),
             0x0B => Some(Self::Workstream),
             0x0C => Some(Self::RefLog),
+            0x0D => Some(Self::Operation),
             _ => None,
         }
     }
@@ -55,6 +57,7 @@ impl TypeTag {
             Self::Policy => "policy",
             Self::Workstream => "workstream",
             Self::RefLog => "reflog",
+            Self::Operation => "operation",
         }
     }
 }
@@ -73,6 +76,7 @@ pub enum Object {
     Policy(Policy),
     Workstream(Workstream),
     RefLog(RefLog),
+    Operation(Operation),
 }
 
 impl Object {
@@ -90,6 +94,7 @@ impl Object {
             Object::Policy(_) => TypeTag::Policy,
             Object::Workstream(_) => TypeTag::Workstream,
             Object::RefLog(_) => TypeTag::RefLog,
+            Object::Operation(_) => TypeTag::Operation,
         }
     }
 
@@ -101,7 +106,14 @@ impl Object {
         let mut deps = HashSet::new();
 
         match self {
-            Object::Blob(_) | Object::Intent(_) | Object::Policy(_) | Object::Workstream(_) => {}
+            Object::Intent(_) | Object::Policy(_) | Object::Workstream(_) => {}
+            Object::Blob(blob) => {
+                if let Some(Ok(manifest)) = crate::chunking::ChunkManifest::from_blob(blob) {
+                    for chunk in &manifest.chunks {
+                        deps.insert(chunk.id);
+                    }
+                }
+            }
             Object::Tree(tree) => {
                 for entry in &tree.entries {
                     deps.insert(entry.object_id);
@@ -168,6 +180,17 @@ impl Object {
                     deps.insert(entry.new_target);
                 }
             }
+            Object::Operation(op) => {
+                if let Some(id) = op.parent {
+                    deps.insert(id);
+                }
+                for r in &op.refs {
+                    deps.insert(r.target);
+                }
+                if let Some(id) = op.head_target {
+                    deps.insert(id);
+                }
+            }
         }
 
         let mut out: Vec<_> = deps.into_iter().collect();
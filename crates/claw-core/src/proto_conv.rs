@@ -3,6 +3,7 @@
 
 use prost::Message;
 
+use crate::chunking::{ChunkManifest, ChunkRef};
 use crate::error::CoreError;
 use crate::generated::{common as pc, objects as po};
 use crate::id::{ChangeId, IntentId, ObjectId};
@@ -83,6 +84,7 @@ pub fn serialize_object(obj: &Object) -> Result<Vec<u8>, CoreError> {
         Object::Policy(p) => encode(&policy_to_proto(p)),
         Object::Workstream(w) => encode(&workstream_to_proto(w)),
         Object::RefLog(r) => encode(&reflog_to_proto(r)),
+        Object::Operation(o) => encode(&operation_to_proto(o)),
     }
 }
 
@@ -120,6 +122,9 @@ pub fn deserialize_object(type_tag: TypeTag, data: &[u8]) -> Result<Object, Core
         TypeTag::RefLog => Ok(Object::RefLog(reflog_from_proto(&decode::<po::RefLog>(
             data,
         )?)?)),
+        TypeTag::Operation => Ok(Object::Operation(operation_from_proto(&decode::<
+            po::Operation,
+        >(data)?)?)),
     }
 }
 
@@ -465,6 +470,7 @@ fn conflict_to_proto(c: &Conflict) -> po::Conflict {
             ConflictStatus::Resolved => "resolved".into(),
         },
         created_at_ms: c.created_at_ms,
+        kind: c.kind.as_str().into(),
     }
 }
 
@@ -483,6 +489,13 @@ fn conflict_from_proto(p: &po::Conflict) -> Result<Conflict, CoreError> {
         "resolved" => ConflictStatus::Resolved,
         _ => ConflictStatus::Open,
     };
+    let kind = match p.kind.as_str() {
+        "delete-modify" => ConflictKind::DeleteModify,
+        "mode" => ConflictKind::Mode,
+        "symlink" => ConflictKind::Symlink,
+        "file-directory" => ConflictKind::FileDirectory,
+        _ => ConflictKind::Content,
+    };
     Ok(Conflict {
         base_revision: opt_oid_from_proto(&p.base_revision)?,
         left_revision,
@@ -506,6 +519,7 @@ fn conflict_from_proto(p: &po::Conflict) -> Result<Conflict, CoreError> {
             .collect::<Result<_, _>>()?,
         status,
         created_at_ms: p.created_at_ms,
+        kind,
     })
 }
 
@@ -715,3 +729,90 @@ fn reflog_from_proto(p: &po::RefLog) -> Result<RefLog, CoreError> {
         entries,
     })
 }
+
+// === ChunkManifest ===
+
+pub(crate) fn encode_chunk_manifest(m: &ChunkManifest) -> Result<Vec<u8>, CoreError> {
+    encode(&po::ChunkManifest {
+        total_size: m.total_size,
+        chunks: m
+            .chunks
+            .iter()
+            .map(|c| po::ChunkRef {
+                id: Some(oid_to_proto(&c.id)),
+                size: c.size,
+            })
+            .collect(),
+    })
+}
+
+pub(crate) fn decode_chunk_manifest(data: &[u8]) -> Result<ChunkManifest, CoreError> {
+    let p = decode::<po::ChunkManifest>(data)?;
+    let chunks = p
+        .chunks
+        .iter()
+        .map(|c| {
+            let id = oid_from_proto(
+                c.id.as_ref()
+                    .ok_or_else(|| CoreError::Deserialization("missing chunk id".into()))?,
+            )?;
+            Ok(ChunkRef { id, size: c.size })
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+    Ok(ChunkManifest {
+        total_size: p.total_size,
+        chunks,
+    })
+}
+
+// === Operation ===
+
+fn operation_to_proto(o: &Operation) -> po::Operation {
+    po::Operation {
+        parent: opt_oid_to_proto(&o.parent),
+        refs: o
+            .refs
+            .iter()
+            .map(|r| po::OperationRef {
+                name: r.name.clone(),
+                target: Some(oid_to_proto(&r.target)),
+            })
+            .collect(),
+        head_ref: o.head_ref.clone().unwrap_or_default(),
+        head_target: opt_oid_to_proto(&o.head_target),
+        command: o.command.clone(),
+        author: o.author.clone(),
+        timestamp: o.timestamp,
+    }
+}
+
+fn operation_from_proto(p: &po::Operation) -> Result<Operation, CoreError> {
+    let refs = p
+        .refs
+        .iter()
+        .map(|r| {
+            let target = oid_from_proto(
+                r.target
+                    .as_ref()
+                    .ok_or_else(|| CoreError::Deserialization("missing ref target".into()))?,
+            )?;
+            Ok(OperationRef {
+                name: r.name.clone(),
+                target,
+            })
+        })
+        .collect::<Result<Vec<_>, CoreError>>()?;
+    Ok(Operation {
+        parent: opt_oid_from_proto(&p.parent)?,
+        refs,
+        head_ref: if p.head_ref.is_empty() {
+            None
+        } else {
+            Some(p.head_ref.clone())
+        },
+        head_target: opt_oid_from_proto(&p.head_target)?,
+        command: p.command.clone(),
+        author: p.author.clone(),
+        timestamp: p.timestamp,
+    })
+}
@@ -8,6 +8,36 @@ pub enum ConflictStatus {
     Resolved,
 }
 
+/// What the two sides disagree about.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictKind {
+    /// Both sides edited the contents and the edits could not be merged.
+    #[default]
+    Content,
+    /// One side deleted the file and the other modified it.
+    DeleteModify,
+    /// Both sides changed the file and disagree on its mode.
+    Mode,
+    /// Both sides changed a path that is a symlink on at least one of them.
+    Symlink,
+    /// The path is a file on one side and a directory on the other.
+    FileDirectory,
+}
+
+impl ConflictKind {
+    /// The kind's name on the wire, as in serde.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ConflictKind::Content => "content",
+            ConflictKind::DeleteModify => "delete-modify",
+            ConflictKind::Mode => "mode",
+            ConflictKind::Symlink => "symlink",
+            ConflictKind::FileDirectory => "file-directory",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Conflict {
     pub base_revision: Option<ObjectId>,
@@ -21,4 +51,6 @@ pub struct Conflict {
     pub resolution_patch_ids: Vec<ObjectId>,
     pub status: ConflictStatus,
     pub created_at_ms: u64,
+    #[serde(default)]
+    pub kind: ConflictKind,
 }
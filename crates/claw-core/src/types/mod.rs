@@ -3,6 +3,7 @@ mod capsule;
 mod change;
 mod conflict;
 mod intent;
+mod operation;
 mod patch;
 mod policy;
 mod reflog;
@@ -14,8 +15,9 @@ mod workstream;
 pub use blob::Blob;
 pub use capsule::{Capsule, CapsulePublic, CapsuleSignature, Evidence};
 pub use change::{Change, ChangeStatus};
-pub use conflict::{Conflict, ConflictStatus};
+pub use conflict::{Conflict, ConflictKind, ConflictStatus};
 pub use intent::{Intent, IntentStatus};
+pub use operation::{Operation, OperationRef};
 pub use patch::{Patch, PatchOp};
 pub use policy::{Policy, Visibility};
 pub use reflog::{RefLog, RefLogEntry};
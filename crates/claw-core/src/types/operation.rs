@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+use crate::id::ObjectId;
+
+/// One entry in the repository operation log: the full ref state and HEAD
+/// after a mutating command ran.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation {
+    /// The operation this one follows; `None` for the first.
+    pub parent: Option<ObjectId>,
+    /// Every ref except the operation log itself, sorted by name.
+    pub refs: Vec<OperationRef>,
+    /// Set when HEAD is symbolic.
+    pub head_ref: Option<String>,
+    /// Set when HEAD is detached.
+    pub head_target: Option<ObjectId>,
+    pub command: String,
+    pub author: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationRef {
+    pub name: String,
+    pub target: ObjectId,
+}
@@ -16,25 +16,77 @@ pub fn git_sha1(data: &[u8]) -> [u8; 20] {
 }
 
 fn sha1_hash(data: &[u8]) -> [u8; 20] {
-    // SHA-1 constants
-    let mut h0: u32 = 0x67452301;
-    let mut h1: u32 = 0xEFCDAB89;
-    let mut h2: u32 = 0x98BADCFE;
-    let mut h3: u32 = 0x10325476;
-    let mut h4: u32 = 0xC3D2E1F0;
-
-    let bit_len = (data.len() as u64) * 8;
-
-    // Pad message
-    let mut padded = data.to_vec();
-    padded.push(0x80);
-    while (padded.len() % 64) != 56 {
-        padded.push(0);
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// Incremental SHA-1, for hashing objects too large to hold in memory.
+pub struct Sha1 {
+    state: [u32; 5],
+    /// Bytes of an incomplete 64-byte block.
+    pending: Vec<u8>,
+    len: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Self {
+        Self {
+            // SHA-1 constants
+            state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+            pending: Vec::with_capacity(64),
+            len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = (64 - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            self.process(&block);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.process(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 20] {
+        let bit_len = self.len * 8;
+
+        // Pad message
+        let mut padded = std::mem::take(&mut self.pending);
+        padded.push(0x80);
+        while (padded.len() % 64) != 56 {
+            padded.push(0);
+        }
+        padded.extend_from_slice(&bit_len.to_be_bytes());
+        for block in padded.chunks(64) {
+            self.process(block);
+        }
+
+        let mut result = [0u8; 20];
+        for (out, word) in result.chunks_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        result
     }
-    padded.extend_from_slice(&bit_len.to_be_bytes());
 
-    // Process each 512-bit block
-    for chunk in padded.chunks(64) {
+    /// Process one 512-bit block.
+    fn process(&mut self, chunk: &[u8]) {
         let mut w = [0u32; 80];
         for i in 0..16 {
             w[i] = u32::from_be_bytes([
@@ -48,7 +100,7 @@ fn sha1_hash(data: &[u8]) -> [u8; 20] {
             w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
         }
 
-        let (mut a, mut b, mut c, mut d, mut e) = (h0, h1, h2, h3, h4);
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
 
         for (i, wi) in w.iter().enumerate() {
             let (f, k) = match i {
@@ -71,20 +123,10 @@ fn sha1_hash(data: &[u8]) -> [u8; 20] {
             a = temp;
         }
 
-        h0 = h0.wrapping_add(a);
-        h1 = h1.wrapping_add(b);
-        h2 = h2.wrapping_add(c);
-        h3 = h3.wrapping_add(d);
-        h4 = h4.wrapping_add(e);
+        for (h, v) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
     }
-
-    let mut result = [0u8; 20];
-    result[0..4].copy_from_slice(&h0.to_be_bytes());
-    result[4..8].copy_from_slice(&h1.to_be_bytes());
-    result[8..12].copy_from_slice(&h2.to_be_bytes());
-    result[12..16].copy_from_slice(&h3.to_be_bytes());
-    result[16..20].copy_from_slice(&h4.to_be_bytes());
-    result
 }
 
 #[cfg(test)]
@@ -108,4 +150,15 @@ mod tests {
         // printf 'hello' | git hash-object --stdin
         assert_eq!(hex_str, "b6fc4c620b67d95f953a5c1c1230aaab5db5a1b0");
     }
+
+    #[test]
+    fn incremental_sha1_matches_one_shot() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        for split in [0, 1, 63, 64, 65, 500, 1000] {
+            let mut hasher = Sha1::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hasher.finish(), sha1_hash(&data), "split at {split}");
+        }
+    }
 }
@@ -1,11 +1,13 @@
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
-use claw_store::ClawStore;
+use claw_store::{ClawStore, StoreError};
 
-use crate::blob_convert::{git_sha1, to_git_blob};
+use crate::blob_convert::{git_sha1, Sha1};
 use crate::commit_convert::to_git_commit;
 use crate::tree_convert::to_git_tree;
 use crate::GitExportError;
@@ -140,15 +142,41 @@ impl<'a> GitExporter<'a> {
             return Ok(*sha1);
         }
 
-        let obj = self.store.load_object(blob_id)?;
-        let blob = match obj {
-            Object::Blob(b) => b,
-            _ => return Err(GitExportError::InvalidType("expected blob".into())),
+        // Stream the content through the hasher and into a temporary object
+        // file, so a large chunked blob is never held in memory whole.
+        let size = match self.store.blob_size(blob_id) {
+            Ok(size) => size,
+            Err(StoreError::NotABlob(_)) => {
+                return Err(GitExportError::InvalidType("expected blob".into()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let tmp_path = git_dir.join(format!("tmp_blob_{}", blob_id.to_hex()));
+        let mut writer = HashingWriter {
+            sha1: Sha1::new(),
+            out: StoredZlib::new(BufWriter::new(File::create(&tmp_path)?)),
+        };
+        writer.write_all(format!("blob {size}\0").as_bytes())?;
+        let written = self.store.write_blob_to(blob_id, &mut writer);
+        let finished = written.map_err(GitExportError::from).and_then(|_| {
+            let mut file = writer.out.finish()?;
+            file.flush()?;
+            Ok(writer.sha1.finish())
+        });
+        let sha1 = match finished {
+            Ok(sha1) => sha1,
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e);
+            }
         };
 
-        let git_data = to_git_blob(&blob.data);
-        let sha1 = git_sha1(&git_data);
-        self.write_git_object(git_dir, &sha1, &git_data)?;
+        let path = self.object_path(git_dir, &sha1)?;
+        if path.exists() {
+            std::fs::remove_file(&tmp_path)?;
+        } else {
+            std::fs::rename(&tmp_path, &path)?;
+        }
         self.sha1_map.insert(*blob_id, sha1);
 
         Ok(sha1)
@@ -160,10 +188,7 @@ impl<'a> GitExporter<'a> {
         sha1: &[u8; 20],
         data: &[u8],
     ) -> Result<(), GitExportError> {
-        let hex = hex::encode(sha1);
-        let dir = git_dir.join(&hex[..2]);
-        std::fs::create_dir_all(&dir)?;
-        let path = dir.join(&hex[2..]);
+        let path = self.object_path(git_dir, sha1)?;
         if !path.exists() {
             // Git stores objects zlib-compressed
             let compressed = miniz_compress(data);
@@ -171,51 +196,152 @@ impl<'a> GitExporter<'a> {
         }
         Ok(())
     }
+
+    /// Where the loose object `sha1` goes, creating its fan-out directory.
+    fn object_path(&self, git_dir: &Path, sha1: &[u8; 20]) -> Result<PathBuf, GitExportError> {
+        let hex = hex::encode(sha1);
+        let dir = git_dir.join(&hex[..2]);
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.join(&hex[2..]))
+    }
+}
+
+/// Hashes what is written to it while compressing it into `out`.
+struct HashingWriter<W: Write> {
+    sha1: Sha1,
+    out: StoredZlib<W>,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.sha1.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
 }
 
 /// Minimal zlib/deflate compression (for git object storage)
 fn miniz_compress(data: &[u8]) -> Vec<u8> {
-    // Use zstd as a stand-in -- but git uses zlib.
-    // For proper git compat we need zlib. Let's implement a store-only mode
-    // that writes raw git objects (uncompressed) -- git can handle this with
-    // `git unpack-objects` or we add proper zlib later.
-    //
-    // For MVP determinism testing, write a simple zlib wrapper:
-    // zlib header (0x78, 0x01 = no compression) + raw data + adler32
-    let mut result = Vec::with_capacity(data.len() + 11);
-    // zlib header for no compression
-    result.push(0x78);
-    result.push(0x01);
-
-    // Deflate "stored" blocks
-    let mut offset = 0;
-    while offset < data.len() {
-        let remaining = data.len() - offset;
-        let block_size = remaining.min(65535);
-        let is_final = offset + block_size >= data.len();
-
-        result.push(if is_final { 0x01 } else { 0x00 });
-        let len = block_size as u16;
-        result.extend_from_slice(&len.to_le_bytes());
-        let nlen = !len;
-        result.extend_from_slice(&nlen.to_le_bytes());
-        result.extend_from_slice(&data[offset..offset + block_size]);
-        offset += block_size;
-    }
-
-    // Adler-32 checksum
-    let adler = adler32(data);
-    result.extend_from_slice(&adler.to_be_bytes());
-
-    result
+    let mut out = StoredZlib::new(Vec::with_capacity(data.len() + 11));
+    out.write_all(data).expect("writing to a Vec cannot fail");
+    out.finish().expect("writing to a Vec cannot fail")
+}
+
+/// Largest deflate "stored" block.
+const STORED_BLOCK_MAX: usize = 65535;
+
+/// A zlib stream of uncompressed deflate blocks, written as data arrives.
+///
+/// Git requires zlib but any valid stream will do, and stored blocks keep
+/// this dependency-free: a zlib header (0x78, 0x01 = no compression), the
+/// raw data in blocks of at most 64 KiB, then an Adler-32 checksum.
+struct StoredZlib<W: Write> {
+    inner: W,
+    block: Vec<u8>,
+    adler_a: u32,
+    adler_b: u32,
+    header_written: bool,
+}
+
+impl<W: Write> StoredZlib<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            block: Vec::with_capacity(STORED_BLOCK_MAX),
+            adler_a: 1,
+            adler_b: 0,
+            header_written: false,
+        }
+    }
+
+    fn write_block(&mut self, is_final: bool) -> std::io::Result<()> {
+        if !self.header_written {
+            self.inner.write_all(&[0x78, 0x01])?;
+            self.header_written = true;
+        }
+        let len = self.block.len() as u16;
+        self.inner.write_all(&[u8::from(is_final)])?;
+        self.inner.write_all(&len.to_le_bytes())?;
+        self.inner.write_all(&(!len).to_le_bytes())?;
+        self.inner.write_all(&self.block)?;
+        self.block.clear();
+        Ok(())
+    }
+
+    /// Write the last block and the checksum, returning the inner writer.
+    fn finish(mut self) -> std::io::Result<W> {
+        self.write_block(true)?;
+        let adler = (self.adler_b << 16) | self.adler_a;
+        self.inner.write_all(&adler.to_be_bytes())?;
+        Ok(self.inner)
+    }
 }
 
-fn adler32(data: &[u8]) -> u32 {
-    let mut a: u32 = 1;
-    let mut b: u32 = 0;
-    for &byte in data {
-        a = (a + byte as u32) % 65521;
-        b = (b + a) % 65521;
+impl<W: Write> Write for StoredZlib<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.block.len() == STORED_BLOCK_MAX {
+            self.write_block(false)?;
+        }
+        let n = (STORED_BLOCK_MAX - self.block.len()).min(buf.len());
+        for &byte in &buf[..n] {
+            self.adler_a = (self.adler_a + byte as u32) % 65521;
+            self.adler_b = (self.adler_b + self.adler_a) % 65521;
+        }
+        self.block.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob_convert::to_git_blob;
+    use claw_core::chunking::CHUNKING_THRESHOLD;
+
+    /// The payload of a zlib stream made of stored blocks.
+    fn inflate_stored(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut pos = 2;
+        loop {
+            let is_final = data[pos] & 1 == 1;
+            let len = u16::from_le_bytes([data[pos + 1], data[pos + 2]]) as usize;
+            pos += 5;
+            out.extend_from_slice(&data[pos..pos + len]);
+            pos += len;
+            if is_final {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_blobs_export_as_whole_git_blobs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let data: Vec<u8> = (0..CHUNKING_THRESHOLD as u32 + 300_000)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let id = store.store_blob_from_reader(data.as_slice()).unwrap();
+        assert!(store.is_chunked_blob(&id).unwrap());
+
+        let git_dir = tmp.path().join("git-objects");
+        std::fs::create_dir_all(&git_dir).unwrap();
+        let mut exporter = GitExporter::new(&store);
+        let sha1 = exporter.export_blob(&id, &git_dir).unwrap();
+        let expected = to_git_blob(&data);
+        assert_eq!(sha1, git_sha1(&expected));
+
+        let hex = hex::encode(sha1);
+        let file = std::fs::read(git_dir.join(&hex[..2]).join(&hex[2..])).unwrap();
+        assert_eq!(inflate_stored(&file), expected);
+        assert_eq!(std::fs::read_dir(&git_dir).unwrap().count(), 1);
     }
-    (b << 16) | a
 }
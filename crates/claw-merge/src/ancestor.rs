@@ -1,65 +1,184 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
-use claw_store::ClawStore;
+use claw_store::revision_graph::{is_ancestor_by, resolve, resolve_in_memory, GraphEntry};
+use claw_store::{ClawStore, StoreError};
 
 use crate::MergeError;
 
-/// Find the lowest common ancestor of two revisions via BFS on the DAG.
-pub fn find_lca(
+const LEFT: u8 = 1;
+const RIGHT: u8 = 2;
+/// Reachable from a common ancestor already found, so not a best one.
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Every best common ancestor of two revisions: common ancestors that are
+/// not ancestors of another common ancestor. Highest generation first.
+///
+/// Revisions are visited in descending generation order, so every
+/// descendant of a revision is reached before it and its flags are final
+/// when it is popped.
+pub fn merge_bases(
     store: &ClawStore,
     left: &ObjectId,
     right: &ObjectId,
-) -> Result<Option<ObjectId>, MergeError> {
-    if left == right {
-        return Ok(Some(*left));
-    }
-
-    let mut left_ancestors: HashSet<ObjectId> = HashSet::new();
-    let mut right_ancestors: HashSet<ObjectId> = HashSet::new();
-    let mut left_queue: VecDeque<ObjectId> = VecDeque::new();
-    let mut right_queue: VecDeque<ObjectId> = VecDeque::new();
+) -> Result<Vec<ObjectId>, MergeError> {
+    merge_bases_by(&[*left], right, |id| resolve(store, id))
+}
 
-    left_ancestors.insert(*left);
-    right_ancestors.insert(*right);
-    left_queue.push_back(*left);
-    right_queue.push_back(*right);
+/// The best common ancestors of `right` and a revision whose parents are
+/// `lefts`, without recording revisions missing from the revision graph,
+/// for callers that must not write to the repository.
+pub(crate) fn merge_bases_in_memory(
+    store: &ClawStore,
+    lefts: &[ObjectId],
+    right: &ObjectId,
+) -> Result<Vec<ObjectId>, MergeError> {
+    merge_bases_by(lefts, right, |id| resolve_in_memory(store, id))
+}
 
-    loop {
-        let left_done = left_queue.is_empty();
-        let right_done = right_queue.is_empty();
+fn merge_bases_by(
+    lefts: &[ObjectId],
+    right: &ObjectId,
+    entry: impl Fn(&ObjectId) -> Result<Option<GraphEntry>, StoreError>,
+) -> Result<Vec<ObjectId>, MergeError> {
+    if lefts.contains(right) {
+        return Ok(vec![*right]);
+    }
 
-        if left_done && right_done {
-            return Ok(None);
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut queue: BinaryHeap<(u64, [u8; 32])> = BinaryHeap::new();
+    let mut queued: HashSet<ObjectId> = HashSet::new();
+    // Queued revisions not known to be reachable from a common ancestor; the
+    // walk is over once there are none.
+    let mut pending = 0usize;
+    let sides = lefts.iter().map(|id| (id, LEFT)).chain([(right, RIGHT)]);
+    for (id, side) in sides {
+        if let Some(start) = entry(id)? {
+            flags.insert(*id, side);
+            if queued.insert(*id) {
+                queue.push((start.generation, *id.as_bytes()));
+                pending += 1;
+            }
         }
+    }
 
-        // Expand left
-        if let Some(id) = left_queue.pop_front() {
-            if right_ancestors.contains(&id) {
-                return Ok(Some(id));
+    let mut candidates = Vec::new();
+    while pending > 0 {
+        let Some((generation, bytes)) = queue.pop() else {
+            break;
+        };
+        let id = ObjectId::from_bytes(bytes);
+        queued.remove(&id);
+        if flags[&id] & STALE == 0 {
+            pending -= 1;
+        }
+        let mut side = flags[&id] & (LEFT | RIGHT | STALE);
+        if side & (LEFT | RIGHT) == LEFT | RIGHT {
+            if side & STALE == 0 && flags[&id] & RESULT == 0 {
+                flags.insert(id, flags[&id] | RESULT);
+                candidates.push((generation, id));
             }
-            if let Ok(Object::Revision(rev)) = store.load_object(&id) {
-                for parent in &rev.parents {
-                    if left_ancestors.insert(*parent) {
-                        left_queue.push_back(*parent);
-                    }
+            side |= STALE;
+        }
+        let Some(current) = entry(&id)? else {
+            continue;
+        };
+        for parent in current.parents {
+            let parent_flags = flags.entry(parent).or_insert(0);
+            if *parent_flags & side == side {
+                continue;
+            }
+            let was_stale = *parent_flags & STALE != 0;
+            *parent_flags |= side;
+            let stale = *parent_flags & STALE != 0;
+            if queued.contains(&parent) {
+                if stale && !was_stale {
+                    pending -= 1;
+                }
+            } else if let Some(parent_entry) = entry(&parent)? {
+                queued.insert(parent);
+                queue.push((parent_entry.generation, *parent.as_bytes()));
+                if !stale {
+                    pending += 1;
                 }
             }
         }
+    }
 
-        // Expand right
-        if let Some(id) = right_queue.pop_front() {
-            if left_ancestors.contains(&id) {
-                return Ok(Some(id));
-            }
-            if let Ok(Object::Revision(rev)) = store.load_object(&id) {
-                for parent in &rev.parents {
-                    if right_ancestors.insert(*parent) {
-                        right_queue.push_back(*parent);
-                    }
-                }
+    // A candidate found before a descendant of it was marked stale can still
+    // be an ancestor of another candidate.
+    let mut bases: Vec<ObjectId> = Vec::new();
+    for (i, (_, candidate)) in candidates.iter().enumerate() {
+        let mut redundant = false;
+        for (j, (_, other)) in candidates.iter().enumerate() {
+            if i != j && is_ancestor_by(candidate, other, &entry)? {
+                redundant = true;
+                break;
             }
         }
+        if !redundant {
+            bases.push(*candidate);
+        }
+    }
+    Ok(bases)
+}
+
+/// Find the lowest common ancestor of two revisions: the best common
+/// ancestor with the highest generation, if there are several.
+pub fn find_lca(
+    store: &ClawStore,
+    left: &ObjectId,
+    right: &ObjectId,
+) -> Result<Option<ObjectId>, MergeError> {
+    Ok(merge_bases(store, left, right)?.into_iter().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::object::Object;
+    use claw_core::types::Revision;
+
+    fn make_rev(store: &ClawStore, parents: Vec<ObjectId>, msg: &str) -> ObjectId {
+        let rev = Revision {
+            change_id: None,
+            parents,
+            patches: vec![],
+            snapshot_base: None,
+            tree: None,
+            capsule_id: None,
+            author: "test".to_string(),
+            created_at_ms: 0,
+            summary: msg.to_string(),
+            policy_evidence: vec![],
+        };
+        store.store_object(&Object::Revision(rev)).unwrap()
+    }
+
+    #[test]
+    fn criss_cross_has_two_bases() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        //   a - b - m1 - l
+        //    \    X
+        //     c - m2 - r
+        let a = make_rev(&store, vec![], "A");
+        let b = make_rev(&store, vec![a], "B");
+        let c = make_rev(&store, vec![a], "C");
+        let m1 = make_rev(&store, vec![b, c], "M1");
+        let m2 = make_rev(&store, vec![c, b], "M2");
+        let l = make_rev(&store, vec![m1], "L");
+        let r = make_rev(&store, vec![m2], "R");
+
+        let mut bases = merge_bases(&store, &l, &r).unwrap();
+        bases.sort_by_key(|id| *id.as_bytes());
+        let mut expected = vec![b, c];
+        expected.sort_by_key(|id| *id.as_bytes());
+        assert_eq!(bases, expected);
+
+        assert_eq!(merge_bases(&store, &b, &r).unwrap(), vec![b]);
+        assert_eq!(find_lca(&store, &l, &a).unwrap(), Some(a));
     }
 }
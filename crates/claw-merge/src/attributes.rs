@@ -0,0 +1,440 @@
+//! Per-path merge strategies from a `.clawattributes` file.
+//!
+//! Each non-empty, non-comment line is a glob and a strategy:
+//!
+//! ```text
+//! Cargo.lock      merge=ours
+//! CHANGELOG.md    merge=union
+//! *.json          codec=json/tree
+//! generated/**    driver=regen
+//! ```
+//!
+//! Globs without a `/` match the file name in any directory. When several
+//! lines match a path the last one wins. A strategy only applies to paths
+//! both sides changed.
+//!
+//! `driver=` only names a driver. What it runs comes from the local
+//! `[merge.drivers.<name>]` table in `.claw/repo.toml`, so checking out a
+//! revision can never make a merge execute a command; rules naming a
+//! driver that is not configured there are ignored.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::Patch;
+use claw_patch::text_line::{merge3_regions, MergeRegion};
+use claw_patch::CodecRegistry;
+use claw_store::repo::{read_config, MergeDriver};
+use claw_store::ClawStore;
+use globset::{GlobBuilder, GlobMatcher};
+
+use crate::emit::{find_blob_content_at_path, group_contents};
+use crate::MergeError;
+
+/// Path of the attributes file in a revision's tree.
+pub const ATTRIBUTES_FILE: &str = ".clawattributes";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the left side's changes and drop the right's.
+    Ours,
+    /// Keep the right side's changes and drop the left's.
+    Theirs,
+    /// Keep both sides' lines where they conflict, left first.
+    Union,
+    /// Merge the file contents with another codec.
+    Codec(String),
+    /// Run the merge driver of this name from the local repo config.
+    Driver(String),
+}
+
+#[derive(Debug, Default)]
+pub struct MergeAttributes {
+    rules: Vec<(GlobMatcher, MergeStrategy)>,
+    drivers: BTreeMap<String, MergeDriver>,
+    root: PathBuf,
+}
+
+impl MergeAttributes {
+    /// Parse an attributes file. No drivers are configured, so rules
+    /// naming one never apply.
+    pub fn parse(text: &str) -> Result<Self, MergeError> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid =
+                |reason: String| MergeError::Attributes(format!("line {}: {reason}", number + 1));
+            let (pattern, spec) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("missing strategy for '{line}'")))?;
+            let strategy = match spec.trim().split_once('=') {
+                Some(("merge", "ours")) => MergeStrategy::Ours,
+                Some(("merge", "theirs")) => MergeStrategy::Theirs,
+                Some(("merge", "union")) => MergeStrategy::Union,
+                Some(("codec", codec)) => MergeStrategy::Codec(codec.to_string()),
+                Some(("driver", name))
+                    if !name.is_empty() && !name.contains(char::is_whitespace) =>
+                {
+                    MergeStrategy::Driver(name.to_string())
+                }
+                _ => return Err(invalid(format!("unknown strategy '{}'", spec.trim()))),
+            };
+            let glob = if pattern.contains('/') {
+                pattern.trim_start_matches('/').to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            let matcher = GlobBuilder::new(&glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| invalid(e.to_string()))?
+                .compile_matcher();
+            rules.push((matcher, strategy));
+        }
+        Ok(Self {
+            rules,
+            ..Self::default()
+        })
+    }
+
+    /// The attributes file in a revision's tree, or no rules if it has none,
+    /// with the merge drivers configured in the local repo config.
+    pub fn load(store: &ClawStore, revision: &ObjectId) -> Result<Self, MergeError> {
+        let data = find_blob_content_at_path(store, revision, ATTRIBUTES_FILE)?;
+        Self::from_file(store, data.as_deref())
+    }
+
+    /// The rules in the contents of an attributes file, with the merge
+    /// drivers configured in the local repo config.
+    pub(crate) fn from_file(store: &ClawStore, data: Option<&[u8]>) -> Result<Self, MergeError> {
+        let mut attributes = match data {
+            Some(data) => Self::parse(&String::from_utf8_lossy(data))?,
+            None => Self::default(),
+        };
+        attributes.root = store.root().to_path_buf();
+        if attributes.names_drivers() {
+            match read_config(store.layout()) {
+                Ok(config) => attributes.drivers = config.merge.drivers,
+                Err(e) => tracing::warn!("cannot read merge drivers: {e}"),
+            }
+            for (_, strategy) in &attributes.rules {
+                if let MergeStrategy::Driver(name) = strategy {
+                    if !attributes.drivers.contains_key(name) {
+                        tracing::warn!("merge driver '{name}' is not configured; ignoring it");
+                    }
+                }
+            }
+        }
+        Ok(attributes)
+    }
+
+    pub fn strategy_for(&self, path: &str) -> Option<&MergeStrategy> {
+        self.rules
+            .iter()
+            .rev()
+            .filter(|(_, strategy)| match strategy {
+                MergeStrategy::Driver(name) => self.drivers.contains_key(name),
+                _ => true,
+            })
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map(|(_, strategy)| strategy)
+    }
+
+    fn names_drivers(&self) -> bool {
+        self.rules
+            .iter()
+            .any(|(_, strategy)| matches!(strategy, MergeStrategy::Driver(_)))
+    }
+
+    /// Run the configured merge driver `name` on one file. `Ok(None)` means
+    /// it reported a conflict.
+    ///
+    /// The driver runs in the repository root without a shell. Besides the
+    /// `%O`, `%A`, `%B` and `%P` argument substitutions it gets the same
+    /// values in `CLAW_MERGE_BASE`, `CLAW_MERGE_LEFT`, `CLAW_MERGE_RIGHT`
+    /// and `CLAW_MERGE_PATH`, and writes the result to the left file.
+    pub(crate) fn run_driver(
+        &self,
+        name: &str,
+        path: &str,
+        base: &[u8],
+        left: &[u8],
+        right: &[u8],
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(driver) = self.drivers.get(name) else {
+            return Ok(None);
+        };
+        let dir = std::env::temp_dir().join(format!(
+            "claw-merge-{}-{}",
+            std::process::id(),
+            claw_core::id::ConflictId::new()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let result = (|| {
+            let file = |name: &str, data: &[u8]| -> std::io::Result<PathBuf> {
+                let file = dir.join(name);
+                std::fs::write(&file, data)?;
+                Ok(file)
+            };
+            let (base_file, left_file, right_file) = (
+                file("base", base)?,
+                file("left", left)?,
+                file("right", right)?,
+            );
+            let args = driver.args.iter().map(|arg| {
+                arg.replace("%O", &base_file.to_string_lossy())
+                    .replace("%A", &left_file.to_string_lossy())
+                    .replace("%B", &right_file.to_string_lossy())
+                    .replace("%P", path)
+            });
+            let status = Command::new(self.program(&driver.command))
+                .args(args)
+                .current_dir(&self.root)
+                .env("CLAW_MERGE_BASE", &base_file)
+                .env("CLAW_MERGE_LEFT", &left_file)
+                .env("CLAW_MERGE_RIGHT", &right_file)
+                .env("CLAW_MERGE_PATH", path)
+                .stdin(Stdio::null())
+                .status()?;
+            if !status.success() {
+                return Ok(None);
+            }
+            std::fs::read(&left_file).map(Some)
+        })();
+        let _ = std::fs::remove_dir_all(&dir);
+        result
+    }
+
+    /// A driver command with a `/` is relative to the repository root;
+    /// a bare name is looked up on `PATH`.
+    fn program(&self, command: &str) -> PathBuf {
+        let program = Path::new(command);
+        if command.contains('/') && program.is_relative() {
+            self.root.join(program)
+        } else {
+            program.to_path_buf()
+        }
+    }
+}
+
+/// Merge one `(path, codec)` group both sides changed using `strategy`.
+/// Returns the merged patches, or `None` if the strategy could not merge
+/// them and the group conflicts.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn merge_with_strategy(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    attributes: &MergeAttributes,
+    strategy: &MergeStrategy,
+    codec_id: &str,
+    path: &str,
+    left_ids: &[ObjectId],
+    right_ids: &[ObjectId],
+) -> Result<Option<Vec<ObjectId>>, MergeError> {
+    match strategy {
+        MergeStrategy::Ours => return Ok(Some(left_ids.to_vec())),
+        MergeStrategy::Theirs => return Ok(Some(right_ids.to_vec())),
+        _ => {}
+    }
+    let (base, left, right) = group_contents(
+        store, registry, ancestor, codec_id, path, left_ids, right_ids,
+    )?;
+    let merged = match strategy {
+        MergeStrategy::Union => union(&base, &left, &right),
+        MergeStrategy::Codec(other) => registry.get(other)?.merge3(&base, &left, &right).ok(),
+        MergeStrategy::Driver(name) => attributes
+            .run_driver(name, path, &base, &left, &right)
+            .unwrap_or_else(|e| {
+                tracing::warn!("merge driver '{name}' for {path} failed to run: {e}");
+                None
+            }),
+        MergeStrategy::Ours | MergeStrategy::Theirs => unreachable!("handled above"),
+    };
+    let Some(merged) = merged else {
+        return Ok(None);
+    };
+    let patch = Patch {
+        target_path: path.to_string(),
+        codec_id: codec_id.to_string(),
+        base_object: None,
+        result_object: None,
+        ops: registry.get(codec_id)?.diff(&base, &merged)?,
+        codec_payload: None,
+    };
+    Ok(Some(vec![
+        store.store_object_for_path(path, &Object::Patch(patch))?
+    ]))
+}
+
+/// Line-wise merge that resolves every conflict by keeping the left lines
+/// followed by the right ones. `None` if any side is not UTF-8.
+pub(crate) fn union(base: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+    let (Ok(base_str), Ok(left_str), Ok(right_str)) = (
+        std::str::from_utf8(base),
+        std::str::from_utf8(left),
+        std::str::from_utf8(right),
+    ) else {
+        return None;
+    };
+    let mut lines = Vec::new();
+    for region in merge3_regions(base_str, left_str, right_str) {
+        match region {
+            MergeRegion::Clean(clean) => lines.extend(clean),
+            MergeRegion::Conflict { left, right, .. } => {
+                lines.extend(left);
+                lines.extend(right);
+            }
+        }
+    }
+    let mut merged = lines.join("\n");
+    if !merged.is_empty() && (left_str.ends_with('\n') || right_str.ends_with('\n')) {
+        merged.push('\n');
+    }
+    Some(merged.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::{merge, MergeResult};
+    use crate::test_support::commit_files;
+
+    /// A revision with `.clawattributes` and `CHANGELOG.md`.
+    fn commit(
+        store: &ClawStore,
+        registry: &CodecRegistry,
+        parent: Option<ObjectId>,
+        changelog: &str,
+    ) -> ObjectId {
+        let attributes = (ATTRIBUTES_FILE, "CHANGELOG.md merge=union\n");
+        commit_files(
+            store,
+            registry,
+            parent,
+            &[attributes, ("CHANGELOG.md", changelog)],
+        )
+    }
+
+    #[test]
+    fn merge_consults_attributes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "- old\n");
+        let left = commit(&store, &registry, Some(base), "- left\n- old\n");
+        let right = commit(&store, &registry, Some(base), "- right\n- old\n");
+        let result = merge(&store, &registry, &left, &right, "test", "merge").unwrap();
+        assert!(result.conflicts.is_empty());
+        let merged_id = store
+            .store_object(&Object::Revision(result.revision))
+            .unwrap();
+        let merged = find_blob_content_at_path(&store, &merged_id, "CHANGELOG.md")
+            .unwrap()
+            .unwrap();
+        assert_eq!(merged, b"- left\n- right\n- old\n".to_vec());
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let attributes = MergeAttributes::parse(
+            "# lockfiles\n\
+             *.lock merge=ours\n\
+             vendor/** merge=theirs\n\
+             vendor/keep.lock codec=text/line\n\
+             vendor/** driver=regen\n",
+        )
+        .unwrap();
+        assert_eq!(
+            attributes.strategy_for("crates/a/Cargo.lock"),
+            Some(&MergeStrategy::Ours)
+        );
+        assert_eq!(
+            attributes.strategy_for("vendor/x.lock"),
+            Some(&MergeStrategy::Theirs)
+        );
+        assert_eq!(
+            attributes.strategy_for("vendor/keep.lock"),
+            Some(&MergeStrategy::Codec("text/line".to_string()))
+        );
+        assert_eq!(attributes.strategy_for("src/main.rs"), None);
+        assert!(MergeAttributes::parse("*.md merge=sideways").is_err());
+        assert!(MergeAttributes::parse("gen/** driver=./regen.sh %A").is_err());
+    }
+
+    /// Both sides rewrite the only line of `a.txt`, which `.clawattributes`
+    /// hands to the `take-right` driver.
+    fn merge_with_driver(store: &ClawStore) -> MergeResult {
+        let registry = CodecRegistry::default();
+        let attributes = (ATTRIBUTES_FILE, "a.txt driver=take-right\n");
+        let base = commit_files(store, &registry, None, &[attributes, ("a.txt", "base\n")]);
+        let left = commit_files(
+            store,
+            &registry,
+            Some(base),
+            &[attributes, ("a.txt", "left\n")],
+        );
+        let right = commit_files(
+            store,
+            &registry,
+            Some(base),
+            &[attributes, ("a.txt", "right\n")],
+        );
+        merge(store, &registry, &left, &right, "test", "merge").unwrap()
+    }
+
+    #[test]
+    fn committed_driver_is_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        let result = merge_with_driver(&store);
+        assert_eq!(result.conflicts.len(), 1);
+        assert_eq!(result.conflicts[0].file_path, "a.txt");
+    }
+
+    #[test]
+    fn configured_driver_runs_without_a_shell() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let mut config = read_config(store.layout()).unwrap();
+        config.merge.drivers.insert(
+            "take-right".to_string(),
+            MergeDriver {
+                command: "cp".to_string(),
+                args: vec!["%B".to_string(), "%A".to_string()],
+            },
+        );
+        claw_store::repo::write_config(store.layout(), &config).unwrap();
+
+        let result = merge_with_driver(&store);
+        assert!(result.conflicts.is_empty());
+        let merged_id = store
+            .store_object(&Object::Revision(result.revision))
+            .unwrap();
+        let merged = find_blob_content_at_path(&store, &merged_id, "a.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(merged, b"right\n".to_vec());
+    }
+
+    #[test]
+    fn union_keeps_both_sides() {
+        let merged = union(
+            b"# Changelog\n- old\n",
+            b"# Changelog\n- left\n- old\n",
+            b"# Changelog\n- right\n- old\n",
+        );
+        assert_eq!(
+            merged.unwrap(),
+            b"# Changelog\n- left\n- right\n- old\n".to_vec()
+        );
+    }
+}
@@ -1,42 +1,203 @@
-use std::collections::HashSet;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
+use claw_core::types::Patch;
+use claw_patch::CodecRegistry;
+use claw_store::tree_diff::{diff_trees, flatten_tree, ChangeKind};
 use claw_store::ClawStore;
 
+use crate::renames;
 use crate::MergeError;
 
-/// Collect all patches from ancestor to head, walking the revision DAG.
+const HEAD: u8 = 1;
+const BASE: u8 = 2;
+
+/// Collect all patches from ancestor to head: those of every revision
+/// reachable from `head` but not from `ancestor`, parents first.
+///
+/// A merge revision's patches restate changes from its parents' histories
+/// and miss any conflict resolution, so when the range contains a merge the
+/// patches are derived from the two trees instead.
 pub fn collect_patches(
     store: &ClawStore,
+    registry: &CodecRegistry,
     ancestor: &ObjectId,
     head: &ObjectId,
 ) -> Result<Vec<ObjectId>, MergeError> {
     let mut patches = Vec::new();
-    let mut visited = HashSet::new();
-    collect_recursive(store, head, ancestor, &mut patches, &mut visited)?;
+    for id in revisions_between(store, ancestor, head)? {
+        if let Object::Revision(rev) = store.load_object(&id)? {
+            if rev.parents.len() > 1 {
+                return tree_patches(store, registry, ancestor, head);
+            }
+            patches.extend_from_slice(&rev.patches);
+        }
+    }
+    Ok(patches)
+}
+
+/// One patch per changed file between the trees of two revisions.
+pub fn tree_patches(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    from: &ObjectId,
+    to: &ObjectId,
+) -> Result<Vec<ObjectId>, MergeError> {
+    let from_tree = revision_tree(store, from)?;
+    let to_tree = revision_tree(store, to)?;
+    tree_diff_patches(store, registry, from_tree.as_ref(), to_tree.as_ref())
+}
+
+/// One patch per changed file between two trees, using the codec for each
+/// path (the registry fallback for unknown extensions). As in a snapshot, a
+/// moved file deletes its old path and is patched from its old content,
+/// which the patch names as its base, at the new one.
+pub fn tree_diff_patches(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    from_tree: Option<&ObjectId>,
+    to_tree: Option<&ObjectId>,
+) -> Result<Vec<ObjectId>, MergeError> {
+    let old_files = match from_tree {
+        Some(tree) => flatten_tree(store, tree)?,
+        None => Default::default(),
+    };
+    let changes = diff_trees(store, from_tree, to_tree, "")?;
+    let mut patches = Vec::new();
+    for change in renames::detect(store, registry, &old_files, changes, None) {
+        let old = match change.kind {
+            ChangeKind::Added => None,
+            _ => change.old_id,
+        };
+        let new = match change.kind {
+            ChangeKind::Deleted => None,
+            _ => change.new_id,
+        };
+        if let (ChangeKind::Renamed, Some(old_path)) = (&change.kind, &change.old_path) {
+            patches.extend(file_patch(store, registry, old_path, old, None, false)?);
+        }
+        let moved = matches!(change.kind, ChangeKind::Renamed | ChangeKind::Copied);
+        patches.extend(file_patch(store, registry, &change.path, old, new, moved)?);
+    }
     Ok(patches)
 }
 
-fn collect_recursive(
+/// The patch taking `path` from blob `old` to blob `new`, if there is
+/// anything to record; a moved file's patch is kept even without ops.
+fn file_patch(
     store: &ClawStore,
-    current: &ObjectId,
-    stop_at: &ObjectId,
-    patches: &mut Vec<ObjectId>,
-    visited: &mut HashSet<ObjectId>,
-) -> Result<(), MergeError> {
-    if current == stop_at || !visited.insert(*current) {
-        return Ok(());
+    registry: &CodecRegistry,
+    path: &str,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    moved: bool,
+) -> Result<Option<ObjectId>, MergeError> {
+    let Some(codec) = registry.get_for_path(path) else {
+        return Ok(None);
+    };
+    let mut contents = Vec::with_capacity(2);
+    for id in [old, new] {
+        match id {
+            // Chunked blobs are too large for structural patches.
+            Some(id) if store.is_chunked_blob(&id)? => return Ok(None),
+            Some(id) => contents.push(store.read_blob(&id)?),
+            None => contents.push(Vec::new()),
+        }
+    }
+    let ops = codec.diff(&contents[0], &contents[1])?;
+    if ops.is_empty() && !moved {
+        return Ok(None);
+    }
+    let patch = Patch {
+        target_path: path.to_string(),
+        codec_id: codec.id().to_string(),
+        base_object: old,
+        result_object: new,
+        ops,
+        codec_payload: None,
+    };
+    Ok(Some(
+        store.store_object_for_path(path, &Object::Patch(patch))?,
+    ))
+}
+
+pub(crate) fn revision_tree(
+    store: &ClawStore,
+    id: &ObjectId,
+) -> Result<Option<ObjectId>, MergeError> {
+    match store.load_object(id)? {
+        Object::Revision(rev) => Ok(rev.tree),
+        _ => Ok(None),
     }
+}
 
-    let obj = store.load_object(current)?;
-    if let Object::Revision(rev) = obj {
-        // Recurse to parents first (topological order)
-        for parent in &rev.parents {
-            collect_recursive(store, parent, stop_at, patches, visited)?;
+/// Revisions reachable from `head` and not from `base`, in ascending
+/// generation order.
+///
+/// Walks the revision graph from both ends, highest generation first, and
+/// stops once everything left to visit is reachable from `base`.
+pub fn revisions_between(
+    store: &ClawStore,
+    base: &ObjectId,
+    head: &ObjectId,
+) -> Result<Vec<ObjectId>, MergeError> {
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut queue: BinaryHeap<(u64, [u8; 32])> = BinaryHeap::new();
+    let mut queued: HashSet<ObjectId> = HashSet::new();
+    // Queued revisions reachable only from `head`; the walk is over once
+    // there are none.
+    let mut pending = 0usize;
+    for (id, side) in [(base, BASE), (head, HEAD)] {
+        if let Some(entry) = store.revision_entry(id)? {
+            let was_pending = flags.get(id) == Some(&HEAD);
+            *flags.entry(*id).or_insert(0) |= side;
+            let is_pending = flags[id] == HEAD;
+            if queued.insert(*id) {
+                queue.push((entry.generation, *id.as_bytes()));
+                pending += usize::from(is_pending);
+            } else if was_pending && !is_pending {
+                pending -= 1;
+            }
+        }
+    }
+
+    let mut range = Vec::new();
+    while pending > 0 {
+        let Some((_, bytes)) = queue.pop() else {
+            break;
+        };
+        let id = ObjectId::from_bytes(bytes);
+        queued.remove(&id);
+        let side = flags[&id];
+        if side == HEAD {
+            pending -= 1;
+        }
+        let Some(entry) = store.revision_entry(&id)? else {
+            continue;
+        };
+        if side == HEAD {
+            range.push(id);
+        }
+        for parent in entry.parents {
+            let parent_flags = flags.entry(parent).or_insert(0);
+            if *parent_flags & side == side {
+                continue;
+            }
+            let was_pending = *parent_flags == HEAD;
+            *parent_flags |= side;
+            let is_pending = *parent_flags == HEAD;
+            if queued.contains(&parent) {
+                if was_pending && !is_pending {
+                    pending -= 1;
+                }
+            } else if let Some(parent_entry) = store.revision_entry(&parent)? {
+                queued.insert(parent);
+                queue.push((parent_entry.generation, *parent.as_bytes()));
+                pending += usize::from(is_pending);
+            }
         }
-        // Then collect this revision's patches
-        patches.extend_from_slice(&rev.patches);
     }
-    Ok(())
+    range.reverse();
+    Ok(range)
 }
@@ -1,22 +1,43 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
-use claw_core::types::{Conflict, ConflictStatus, Patch, Revision};
+use claw_core::types::{Conflict, ConflictKind, ConflictStatus, FileMode, Patch, Revision};
 use claw_patch::CodecRegistry;
+use claw_store::tree_diff::flatten_tree;
 use claw_store::ClawStore;
 
-use crate::ancestor::find_lca;
-use crate::collect::collect_patches;
+use crate::ancestor::merge_bases;
+use crate::attributes::{merge_with_strategy, MergeAttributes};
+use crate::collect::{collect_patches, revision_tree};
 use crate::group::group_patches;
 use crate::rebase::commute_rebase;
+use crate::renames::{followed_moves, FollowedMove};
+use crate::rerere::replay_group;
+use crate::tree_conflicts::tree_conflicts;
 use crate::MergeError;
 
+/// Base, left and right contents of one file.
+pub(crate) type GroupContents = (Vec<u8>, Vec<u8>, Vec<u8>);
+
 pub struct MergeResult {
     pub revision: Revision,
     pub new_patches: Vec<ObjectId>,
     pub conflicts: Vec<Conflict>,
+    /// The merge base, or the virtual base built from several.
     pub ancestor: ObjectId,
+    /// Paths whose conflicts were resolved with a recorded resolution.
+    pub reused_resolutions: Vec<String>,
+    /// Paths that conflicted while merging several bases into the virtual
+    /// one; the virtual base has the first base's content there.
+    pub base_conflicts: Vec<String>,
 }
 
+/// Ref namespace pinning virtual merge bases, so that gc keeps one until
+/// the merge using it is done.
+pub const VIRTUAL_BASE_PREFIX: &str = "merge-bases";
+
 /// Perform a merge of two revision heads.
 pub fn merge(
     store: &ClawStore,
@@ -26,19 +47,78 @@ pub fn merge(
     author: &str,
     message: &str,
 ) -> Result<MergeResult, MergeError> {
-    // 1. Find common ancestor
-    let ancestor = find_lca(store, left_head, right_head)?.ok_or(MergeError::NoCommonAncestor)?;
+    // 1. Find the merge base
+    let (ancestor, base_conflicts) = merge_base(store, registry, left_head, right_head, author)?;
 
     // 2. Collect patches from ancestor to each head
-    let left_patches = collect_patches(store, &ancestor, left_head)?;
-    let right_patches = collect_patches(store, &ancestor, right_head)?;
+    let left_patches = collect_patches(store, registry, &ancestor, left_head)?;
+    let right_patches = collect_patches(store, registry, &ancestor, right_head)?;
+
+    let mut result = merge_with_patches(
+        store,
+        registry,
+        &ancestor,
+        (left_head, &left_patches),
+        (right_head, &right_patches),
+        author,
+        message,
+    )?;
+    result.base_conflicts = base_conflicts;
+    Ok(result)
+}
+
+/// Merge two sets of patches made against `ancestor`, per path, with
+/// commute and then merge3 fallback. Each side pairs the revision whose
+/// tree holds that side's content with its patches.
+///
+/// The result revision has both heads as parents; callers replaying a
+/// single revision replace them.
+pub fn merge_with_patches(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    (left_head, left_patches): (&ObjectId, &[ObjectId]),
+    (right_head, right_patches): (&ObjectId, &[ObjectId]),
+    author: &str,
+    message: &str,
+) -> Result<MergeResult, MergeError> {
+    let ancestor = *ancestor;
+
+    let ancestor_tree = revision_tree(store, &ancestor)?;
+    let left_tree = revision_tree(store, left_head)?;
+    let right_tree = revision_tree(store, right_head)?;
 
     // 3. Group by (target_path, codec_id)
-    let left_groups = group_patches(store, &left_patches)?;
-    let right_groups = group_patches(store, &right_patches)?;
+    let attributes = MergeAttributes::load(store, left_head)?;
+    let mut left_groups = group_patches(store, left_patches)?;
+    let mut right_groups = group_patches(store, right_patches)?;
+
+    // A file one side moved takes the other side's edits along with it.
+    let flatten = |tree: Option<&ObjectId>| match tree {
+        Some(tree) => flatten_tree(store, tree),
+        None => Ok(Default::default()),
+    };
+    let base_files = flatten(ancestor_tree.as_ref())?;
+    let left_changed = left_groups.keys().cloned().collect();
+    let right_changed = right_groups.keys().cloned().collect();
+    let (left_moves, right_moves) = followed_moves(
+        store,
+        registry,
+        &base_files,
+        (&flatten(left_tree.as_ref())?, &left_changed),
+        (&flatten(right_tree.as_ref())?, &right_changed),
+    );
+    let mut followed = follow_moves(store, &base_files, &left_moves, &mut right_groups)?;
+    followed.extend(follow_moves(
+        store,
+        &base_files,
+        &right_moves,
+        &mut left_groups,
+    )?);
 
     let mut merged_patches = Vec::new();
     let mut conflicts = Vec::new();
+    let mut reused_resolutions = Vec::new();
 
     // All paths from both sides
     let all_keys: std::collections::BTreeSet<_> = left_groups
@@ -52,8 +132,52 @@ pub fn merge(
         .unwrap()
         .as_millis() as u64;
 
-    // 4. Per-group merge
+    // 4. Deletions, modes, symlinks and file/directory clashes are decided
+    // on the trees; the patches of those paths are not merged.
+    let mut tree_conflicts = tree_conflicts(
+        store,
+        ancestor_tree.as_ref(),
+        left_tree.as_ref(),
+        right_tree.as_ref(),
+    )?;
+    tree_conflicts
+        .retain(|(path, kind)| !(*kind == ConflictKind::DeleteModify && followed.contains(path)));
+    let in_tree_conflict = |path: &str| {
+        tree_conflicts
+            .iter()
+            .any(|(p, _)| path == p || path.starts_with(&format!("{p}/")))
+    };
+    for (path, kind) in &tree_conflicts {
+        let patch_ids = |groups: &std::collections::BTreeMap<(String, String), Vec<ObjectId>>| {
+            groups
+                .iter()
+                .filter(|((p, _), _)| p == path || p.starts_with(&format!("{path}/")))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect()
+        };
+        conflicts.push(Conflict {
+            base_revision: Some(ancestor),
+            left_revision: *left_head,
+            right_revision: *right_head,
+            file_path: path.clone(),
+            codec_id: registry
+                .get_for_path(path)
+                .map_or("binary", |codec| codec.id())
+                .to_string(),
+            left_patch_ids: patch_ids(&left_groups),
+            right_patch_ids: patch_ids(&right_groups),
+            resolution_patch_ids: vec![],
+            status: ConflictStatus::Open,
+            created_at_ms: now_ms,
+            kind: *kind,
+        });
+    }
+
+    // 5. Per-group merge
     for key in &all_keys {
+        if in_tree_conflict(&key.0) {
+            continue;
+        }
         let left_ids = left_groups.get(key);
         let right_ids = right_groups.get(key);
 
@@ -66,79 +190,49 @@ pub fn merge(
             }
             (Some(l), Some(r)) => {
                 let (path, codec_id) = key;
-
-                // Try commutation-based rebase
-                match commute_rebase(store, registry, codec_id, l, r) {
-                    Ok((rebased_right, _)) => {
-                        // Success: left patches + rebased right patches
-                        merged_patches.extend_from_slice(l);
-                        for ops in rebased_right {
-                            let patch = Patch {
-                                target_path: path.clone(),
-                                codec_id: codec_id.clone(),
-                                base_object: None,
-                                result_object: None,
-                                ops,
-                                codec_payload: None,
-                            };
-                            let id = store.store_object(&Object::Patch(patch))?;
-                            merged_patches.push(id);
-                        }
-                    }
-                    Err(_) => {
-                        // Commute failed -- try merge3 fallback
-                        match try_merge3_fallback(store, registry, &ancestor, codec_id, path, l, r)
-                        {
-                            Ok(merge3_patch_id) => {
-                                merged_patches.push(merge3_patch_id);
-                            }
-                            Err(_) => {
-                                // merge3 also failed -- emit a conflict
-                                let conflict = Conflict {
-                                    base_revision: Some(ancestor),
-                                    left_revision: *left_head,
-                                    right_revision: *right_head,
-                                    file_path: path.clone(),
-                                    codec_id: codec_id.clone(),
-                                    left_patch_ids: l.clone(),
-                                    right_patch_ids: r.clone(),
-                                    resolution_patch_ids: vec![],
-                                    status: ConflictStatus::Open,
-                                    created_at_ms: now_ms,
-                                };
-                                conflicts.push(conflict);
-                            }
-                        }
+                let merged = match attributes.strategy_for(path) {
+                    Some(strategy) => merge_with_strategy(
+                        store,
+                        registry,
+                        &ancestor,
+                        &attributes,
+                        strategy,
+                        codec_id,
+                        path,
+                        l,
+                        r,
+                    )?,
+                    None => merge_group(store, registry, &ancestor, codec_id, path, l, r)?,
+                };
+                let replayed = match merged {
+                    Some(_) => None,
+                    None => replay_group(store, registry, &ancestor, codec_id, path, l, r)?,
+                };
+                match (merged, replayed) {
+                    (Some(ids), _) => merged_patches.extend(ids),
+                    (None, Some(id)) => {
+                        merged_patches.push(id);
+                        reused_resolutions.push(path.clone());
                     }
+                    (None, None) => conflicts.push(Conflict {
+                        base_revision: Some(ancestor),
+                        left_revision: *left_head,
+                        right_revision: *right_head,
+                        file_path: path.clone(),
+                        codec_id: codec_id.clone(),
+                        left_patch_ids: l.clone(),
+                        right_patch_ids: r.clone(),
+                        resolution_patch_ids: vec![],
+                        status: ConflictStatus::Open,
+                        created_at_ms: now_ms,
+                        kind: ConflictKind::Content,
+                    }),
                 }
             }
             (None, None) => unreachable!(),
         }
     }
 
-    // Build merged tree from base + left + right trees using merged patches
-    let ancestor_tree = {
-        let obj = store.load_object(&ancestor)?;
-        match obj {
-            Object::Revision(rev) => rev.tree,
-            _ => None,
-        }
-    };
-    let left_tree = {
-        let obj = store.load_object(left_head)?;
-        match obj {
-            Object::Revision(rev) => rev.tree,
-            _ => None,
-        }
-    };
-    let right_tree = {
-        let obj = store.load_object(right_head)?;
-        match obj {
-            Object::Revision(rev) => rev.tree,
-            _ => None,
-        }
-    };
-
     let tree_id = if conflicts.is_empty() {
         Some(crate::tree_build::build_merged_tree(
             store,
@@ -176,9 +270,146 @@ pub fn merge(
         new_patches: merged_patches,
         conflicts,
         ancestor,
+        reused_resolutions,
+        base_conflicts: Vec::new(),
     })
 }
 
+/// Patches grouped by `(target_path, codec_id)`.
+type Groups = BTreeMap<(String, String), Vec<ObjectId>>;
+
+/// Retarget the other side's patches of each followed move to the file's
+/// new path, so they merge with the mover's edits there instead of
+/// conflicting with its deletion. Returns the old paths followed.
+fn follow_moves(
+    store: &ClawStore,
+    base_files: &BTreeMap<String, (ObjectId, FileMode)>,
+    moves: &[FollowedMove],
+    other_groups: &mut Groups,
+) -> Result<Vec<String>, MergeError> {
+    let mut followed = Vec::new();
+    for FollowedMove { old, new, codec_id } in moves {
+        let Some((base_blob, _)) = base_files.get(old) else {
+            continue;
+        };
+        let Some(ids) = other_groups.remove(&(old.clone(), codec_id.clone())) else {
+            continue;
+        };
+        let mut moved = Vec::with_capacity(ids.len());
+        for (i, id) in ids.iter().enumerate() {
+            let Object::Patch(mut patch) = store.load_object(id)? else {
+                continue;
+            };
+            patch.target_path = new.clone();
+            if i == 0 {
+                patch.base_object = Some(*base_blob);
+            }
+            moved.push(store.store_object_for_path(new, &Object::Patch(patch))?);
+        }
+        other_groups.insert((new.clone(), codec_id.clone()), moved);
+        followed.push(old.clone());
+    }
+    Ok(followed)
+}
+
+/// The single best common ancestor of two heads, or a virtual base made by
+/// merging all of them when there are several (criss-cross histories).
+///
+/// The virtual base is stored as a revision whose parents are the bases it
+/// merged, so patches are collected from everything those bases reach, and
+/// pinned under [`VIRTUAL_BASE_PREFIX`]. If merging the bases conflicts, the
+/// virtual base keeps the first base's content at the conflicting paths,
+/// which are returned with it.
+pub(crate) fn merge_base(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    left_head: &ObjectId,
+    right_head: &ObjectId,
+    author: &str,
+) -> Result<(ObjectId, Vec<String>), MergeError> {
+    let mut bases = merge_bases(store, left_head, right_head)?.into_iter();
+    let mut base = bases.next().ok_or(MergeError::NoCommonAncestor)?;
+    let mut conflicted: Vec<String> = Vec::new();
+    let mut is_virtual = false;
+    for other in bases {
+        let virtual_base = merge(store, registry, &base, &other, author, "virtual merge base")?;
+        let paths = virtual_base.conflicts.into_iter().map(|c| c.file_path);
+        for path in virtual_base.base_conflicts.into_iter().chain(paths) {
+            if !conflicted.contains(&path) {
+                conflicted.push(path);
+            }
+        }
+        base = store.store_object(&Object::Revision(virtual_base.revision))?;
+        is_virtual = true;
+    }
+    if is_virtual {
+        store.set_ref(&format!("{VIRTUAL_BASE_PREFIX}/{}", base.to_hex()), &base)?;
+    }
+    Ok((base, conflicted))
+}
+
+/// Refs pinning virtual merge bases made more than `max_age` ago, except
+/// those in `keep`.
+pub fn expired_virtual_bases(
+    store: &ClawStore,
+    max_age: Duration,
+    keep: &[ObjectId],
+) -> Result<Vec<String>, MergeError> {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let cutoff = now_ms.saturating_sub(max_age.as_millis() as u64);
+    let mut expired = Vec::new();
+    for (name, id) in store.list_refs(&format!("{VIRTUAL_BASE_PREFIX}/"))? {
+        if keep.contains(&id) {
+            continue;
+        }
+        match store.load_object(&id) {
+            Ok(Object::Revision(rev)) if rev.created_at_ms >= cutoff => {}
+            _ => expired.push(name),
+        }
+    }
+    Ok(expired)
+}
+
+/// Merge one `(path, codec)` group both sides changed: commute the right
+/// patches past the left ones, or fall back to merge3. `None` if both fail.
+fn merge_group(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    codec_id: &str,
+    path: &str,
+    left_ids: &[ObjectId],
+    right_ids: &[ObjectId],
+) -> Result<Option<Vec<ObjectId>>, MergeError> {
+    // Try commutation-based rebase
+    if let Ok((rebased_right, _)) = commute_rebase(store, registry, codec_id, left_ids, right_ids) {
+        // Success: left patches + rebased right patches
+        let mut merged = left_ids.to_vec();
+        for ops in rebased_right {
+            let patch = Patch {
+                target_path: path.to_string(),
+                codec_id: codec_id.to_string(),
+                base_object: None,
+                result_object: None,
+                ops,
+                codec_payload: None,
+            };
+            merged.push(store.store_object_for_path(path, &Object::Patch(patch))?);
+        }
+        return Ok(Some(merged));
+    }
+
+    // Commute failed -- try merge3 fallback
+    Ok(try_merge3_fallback(
+        store, registry, ancestor, codec_id, path, left_ids, right_ids,
+    )
+    .ok()
+    .map(|id| vec![id]))
+}
+
 /// Try merge3 fallback: reconstruct base/left/right file content, run 3-way merge.
 fn try_merge3_fallback(
     store: &ClawStore,
@@ -190,27 +421,9 @@ fn try_merge3_fallback(
     right_ids: &[ObjectId],
 ) -> Result<ObjectId, MergeError> {
     let codec = registry.get(codec_id)?;
-
-    // Find base content from ancestor's tree
-    let base_content = find_blob_content_at_path(store, ancestor, path)?.unwrap_or_default();
-
-    // Apply left patches to get left content
-    let mut left_content = base_content.clone();
-    for id in left_ids {
-        let obj = store.load_object(id)?;
-        if let Object::Patch(p) = obj {
-            left_content = codec.apply(&left_content, &p.ops)?;
-        }
-    }
-
-    // Apply right patches to get right content
-    let mut right_content = base_content.clone();
-    for id in right_ids {
-        let obj = store.load_object(id)?;
-        if let Object::Patch(p) = obj {
-            right_content = codec.apply(&right_content, &p.ops)?;
-        }
-    }
+    let (base_content, left_content, right_content) = group_contents(
+        store, registry, ancestor, codec_id, path, left_ids, right_ids,
+    )?;
 
     // 3-way merge
     let merged_content = codec.merge3(&base_content, &left_content, &right_content)?;
@@ -226,12 +439,63 @@ fn try_merge3_fallback(
         ops: merged_ops,
         codec_payload: None,
     };
-    let id = store.store_object(&Object::Patch(patch))?;
+    let id = store.store_object_for_path(path, &Object::Patch(patch))?;
     Ok(id)
 }
 
+/// The base content of `path` and the left and right contents made by
+/// applying each side's patches to it.
+pub(crate) fn group_contents(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    codec_id: &str,
+    path: &str,
+    left_ids: &[ObjectId],
+    right_ids: &[ObjectId],
+) -> Result<GroupContents, MergeError> {
+    let codec = registry.get(codec_id)?;
+    let load = |ids: &[ObjectId]| -> Result<Vec<Patch>, MergeError> {
+        let mut patches = Vec::new();
+        for id in ids {
+            if let Object::Patch(p) = store.load_object(id)? {
+                patches.push(p);
+            }
+        }
+        Ok(patches)
+    };
+    let (left_patches, right_patches) = (load(left_ids)?, load(right_ids)?);
+
+    // Find base content from ancestor's tree, or where a moved file came from
+    let base_content = match find_blob_content_at_path(store, ancestor, path)? {
+        Some(content) => content,
+        None => moved_from(store, left_patches.first().or(right_patches.first()))?,
+    };
+
+    // Apply each side's patches to get its content
+    let mut left_content = base_content.clone();
+    for p in &left_patches {
+        left_content = codec.apply(&left_content, &p.ops)?;
+    }
+    let mut right_content = base_content.clone();
+    for p in &right_patches {
+        right_content = codec.apply(&right_content, &p.ops)?;
+    }
+
+    Ok((base_content, left_content, right_content))
+}
+
+/// Content a path the base lacks starts from: that of the file the first
+/// patch says it was moved or copied from, or nothing for a new file.
+pub(crate) fn moved_from(store: &ClawStore, first: Option<&Patch>) -> Result<Vec<u8>, MergeError> {
+    match first.and_then(|p| p.base_object) {
+        Some(id) => Ok(store.read_blob(&id)?),
+        None => Ok(Vec::new()),
+    }
+}
+
 /// Walk the tree from a revision to find blob content at a given file path.
-fn find_blob_content_at_path(
+pub(crate) fn find_blob_content_at_path(
     store: &ClawStore,
     revision_id: &ObjectId,
     path: &str,
@@ -269,10 +533,7 @@ fn find_in_tree(
         if entry.name == target_name {
             if path_parts.len() == 1 {
                 // This is the final component - should be a blob
-                if let Ok(Object::Blob(b)) = store.load_object(&entry.object_id) {
-                    return Ok(Some(b.data));
-                }
-                return Ok(None);
+                return Ok(store.read_blob(&entry.object_id).ok());
             } else {
                 // Recurse into subdirectory
                 return find_in_tree(store, &entry.object_id, &path_parts[1..]);
@@ -282,3 +543,72 @@ fn find_in_tree(
 
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit, commit_files, first_file};
+
+    #[test]
+    fn edits_follow_a_file_the_other_side_moved() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n");
+        let left = commit_files(
+            &store,
+            &registry,
+            Some(base),
+            &[("b.txt", "one\n2\n3\n4\n5\n6\n")],
+        );
+        let right = commit(&store, &registry, Some(base), "1\n2\n3\n4\n5\nsix\n");
+
+        for (first, second) in [(left, right), (right, left)] {
+            let result = merge(&store, &registry, &first, &second, "test", "merge").unwrap();
+            assert!(result.conflicts.is_empty(), "{:?}", result.conflicts);
+            let tree = result.revision.tree.unwrap();
+            let Object::Tree(entries) = store.load_object(&tree).unwrap() else {
+                panic!("expected a tree");
+            };
+            let names: Vec<_> = entries.entries.iter().map(|e| e.name.as_str()).collect();
+            assert_eq!(names, ["b.txt"]);
+            assert_eq!(first_file(&store, &tree), "one\n2\n3\n4\n5\nsix\n");
+        }
+    }
+
+    #[test]
+    fn conflicting_bases_are_reported_and_the_virtual_base_is_pinned() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let root = commit(&store, &registry, None, "1\n");
+        let x = commit(&store, &registry, Some(root), "x\n");
+        let y = commit(&store, &registry, Some(root), "y\n");
+        // Criss-cross merges of x and y, each resolved its own way.
+        let [m1, m2] = [(x, y, "x\n"), (y, x, "y\n")].map(|(first, second, content)| {
+            let single = commit(&store, &registry, Some(first), content);
+            let Object::Revision(mut rev) = store.load_object(&single).unwrap() else {
+                panic!("expected a revision");
+            };
+            rev.parents = vec![first, second];
+            store.store_object(&Object::Revision(rev)).unwrap()
+        });
+
+        let result = merge(&store, &registry, &m1, &m2, "test", "merge").unwrap();
+        assert_eq!(result.base_conflicts, vec!["a.txt"]);
+        let pin = format!("{VIRTUAL_BASE_PREFIX}/{}", result.ancestor.to_hex());
+        assert_eq!(store.get_ref(&pin).unwrap(), Some(result.ancestor));
+
+        std::thread::sleep(Duration::from_millis(5));
+        let keep = [result.ancestor];
+        assert!(expired_virtual_bases(&store, Duration::ZERO, &keep)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            expired_virtual_bases(&store, Duration::ZERO, &[]).unwrap(),
+            vec![pin]
+        );
+    }
+}
@@ -1,15 +1,26 @@
+use claw_core::id::ObjectId;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum MergeError {
     #[error("no common ancestor found")]
     NoCommonAncestor,
+    #[error("invalid revision: {0}")]
+    InvalidRevision(String),
     #[error("store error: {0}")]
     Store(#[from] claw_store::StoreError),
     #[error("patch error: {0}")]
     Patch(#[from] claw_patch::PatchError),
     #[error("core error: {0}")]
     Core(#[from] claw_core::CoreError),
+    #[error("heads {first} and {second} conflict in {path}")]
+    HeadsConflict {
+        path: String,
+        first: ObjectId,
+        second: ObjectId,
+    },
+    #[error("invalid .clawattributes: {0}")]
+    Attributes(String),
     #[error("merge conflict in {path}: {reason}")]
     Conflict { path: String, reason: String },
 }
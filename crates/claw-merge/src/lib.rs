@@ -1,9 +1,19 @@
 pub mod ancestor;
+pub mod attributes;
 pub mod collect;
 pub mod emit;
 pub mod error;
 pub mod group;
+pub mod octopus;
+pub mod pick;
+pub mod preview;
 pub mod rebase;
+pub mod renames;
+pub mod rerere;
+pub mod stack;
+#[cfg(test)]
+mod test_support;
 pub mod tree_build;
+pub mod tree_conflicts;
 
 pub use error::MergeError;
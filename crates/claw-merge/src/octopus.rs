@@ -0,0 +1,330 @@
+//! Octopus merges: integrating several heads into one revision.
+//!
+//! The heads are folded into the left head one at a time, all against a
+//! single base shared by every head, so each step sees the patches of the
+//! steps before it. Conflicts between the left head and one of the heads are
+//! reported with the head that caused them, and a conflicted path keeps the
+//! left version for the steps after. Two heads whose changes to a path don't
+//! merge with each other abort the merge, as does a head changing a path
+//! that already conflicts.
+
+use std::collections::BTreeMap;
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{Conflict, Revision};
+use claw_patch::CodecRegistry;
+use claw_store::ClawStore;
+
+use crate::collect::{collect_patches, revision_tree};
+use crate::emit::{find_blob_content_at_path, merge_base, merge_with_patches};
+use crate::group::group_patches;
+use crate::tree_build::{build_merged_tree, overlay_paths};
+use crate::MergeError;
+
+pub struct OctopusResult {
+    pub revision: Revision,
+    /// Each conflict with the index in `heads` of the head that caused it.
+    pub conflicts: Vec<(usize, Conflict)>,
+    /// The base shared by the left head and every merged head.
+    pub ancestor: ObjectId,
+    /// Paths whose conflicts were resolved with a recorded resolution.
+    pub reused_resolutions: Vec<String>,
+    /// Paths that conflicted while building a virtual merge base.
+    pub base_conflicts: Vec<String>,
+}
+
+/// Merge every head in `heads` into `left_head`.
+///
+/// The result revision has `left_head` followed by `heads` as parents.
+/// Fails with [`MergeError::HeadsConflict`] if two of the heads change a
+/// path in ways that cannot be merged.
+pub fn merge_octopus(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    left_head: &ObjectId,
+    heads: &[ObjectId],
+    author: &str,
+    message: &str,
+) -> Result<OctopusResult, MergeError> {
+    let mut ancestor = *left_head;
+    let mut base_conflicts: Vec<String> = Vec::new();
+    for head in heads {
+        let (base, conflicted) = merge_base(store, registry, &ancestor, head, author)?;
+        ancestor = base;
+        for path in conflicted {
+            if !base_conflicts.contains(&path) {
+                base_conflicts.push(path);
+            }
+        }
+    }
+
+    let mut current = *left_head;
+    let mut current_patches = collect_patches(store, registry, &ancestor, left_head)?;
+    // The heads that changed each path so far.
+    let mut changed_by: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    let mut conflicts: Vec<(usize, Conflict)> = Vec::new();
+    let mut reused_resolutions = Vec::new();
+    let mut last = None;
+
+    for (index, head) in heads.iter().enumerate() {
+        let head_patches = collect_patches(store, registry, &ancestor, head)?;
+        let result = merge_with_patches(
+            store,
+            registry,
+            &ancestor,
+            (&current, &current_patches),
+            (head, &head_patches),
+            author,
+            message,
+        )?;
+
+        let earlier = |path: &str| changed_by.get(path).into_iter().flatten().copied();
+        let mut next_patches = result.new_patches;
+        let mut conflicted = Vec::new();
+        for mut conflict in result.conflicts {
+            let path = &conflict.file_path;
+            if let Some((first, _)) = conflicts.iter().find(|(_, c)| c.file_path == *path) {
+                return Err(MergeError::HeadsConflict {
+                    path: path.clone(),
+                    first: heads[*first],
+                    second: *head,
+                });
+            }
+            for first in earlier(path) {
+                if !heads_agree(store, registry, &ancestor, &heads[first], head, path)? {
+                    return Err(MergeError::HeadsConflict {
+                        path: path.clone(),
+                        first: heads[first],
+                        second: *head,
+                    });
+                }
+            }
+            // The left side is the left head's change, with whatever
+            // earlier heads merged into it.
+            conflict.left_revision = current;
+            next_patches.extend(conflict.left_patch_ids.iter().copied());
+            conflicted.push(path.clone());
+            conflicts.push((index, conflict));
+        }
+        // A head that changed a path an earlier head conflicted on merged
+        // into its left side, which now also has this head's change.
+        let head_groups = group_patches(store, &head_patches)?;
+        let next_groups = group_patches(store, &next_patches)?;
+        let mut merged_into = Vec::new();
+        for (at, (first, conflict)) in conflicts.iter_mut().enumerate() {
+            let path = conflict.file_path.clone();
+            let under = |p: &str| p == path || p.starts_with(&format!("{path}/"));
+            if *first == index || !head_groups.keys().any(|(p, _)| under(p)) {
+                continue;
+            }
+            if !heads_agree(store, registry, &ancestor, &heads[*first], head, &path)? {
+                return Err(MergeError::HeadsConflict {
+                    path,
+                    first: heads[*first],
+                    second: *head,
+                });
+            }
+            conflict.left_patch_ids = next_groups
+                .iter()
+                .filter(|((p, _), _)| under(p))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect();
+            merged_into.push(at);
+        }
+        for (path, _) in head_groups.into_keys() {
+            changed_by.entry(path).or_default().push(index);
+        }
+
+        // Later heads merge into the clean result with the conflicted paths
+        // as the left side had them.
+        let mut revision = result.revision;
+        if !conflicted.is_empty() {
+            let clean = build_merged_tree(
+                store,
+                registry,
+                revision_tree(store, &ancestor)?.as_ref(),
+                revision_tree(store, &current)?.as_ref(),
+                revision_tree(store, head)?.as_ref(),
+                &next_patches,
+            )?;
+            let current_tree = revision_tree(store, &current)?;
+            revision.tree = Some(overlay_paths(
+                store,
+                Some(&clean),
+                current_tree.as_ref(),
+                &conflicted,
+            )?);
+        }
+        revision.patches = next_patches.clone();
+        reused_resolutions.extend(result.reused_resolutions);
+        current_patches = next_patches;
+        current = store.store_object(&Object::Revision(revision.clone()))?;
+        for at in merged_into {
+            conflicts[at].1.left_revision = current;
+        }
+        last = Some(revision);
+    }
+
+    let Some(mut revision) = last else {
+        return Err(MergeError::InvalidRevision(
+            "octopus merge needs at least one head".to_string(),
+        ));
+    };
+    revision.parents = std::iter::once(*left_head)
+        .chain(heads.iter().copied())
+        .collect();
+    revision.patches = current_patches;
+    if !conflicts.is_empty() {
+        revision.tree = revision_tree(store, left_head)?;
+    }
+
+    Ok(OctopusResult {
+        revision,
+        conflicts,
+        ancestor,
+        reused_resolutions,
+        base_conflicts,
+    })
+}
+
+/// Whether the changes two heads made to `path` merge with each other.
+fn heads_agree(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    first: &ObjectId,
+    second: &ObjectId,
+    path: &str,
+) -> Result<bool, MergeError> {
+    let version = |revision| find_blob_content_at_path(store, revision, path);
+    Ok(match (version(first)?, version(second)?) {
+        (a, b) if a == b => true,
+        (Some(a), Some(b)) => {
+            let base = version(ancestor)?.unwrap_or_default();
+            registry
+                .get_for_path(path)
+                .is_some_and(|codec| codec.merge3(&base, &a, &b).is_ok())
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::group_contents;
+    use crate::test_support::{commit, first_file};
+
+    #[test]
+    fn folds_heads_and_attributes_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let left = commit(
+            &store,
+            &registry,
+            Some(base),
+            "one\n2\n3\n4\n5\n6\n7\n8\n9\n",
+        );
+        let first = commit(
+            &store,
+            &registry,
+            Some(base),
+            "1\n2\n3\n4\nfive\n6\n7\n8\n9\n",
+        );
+        let second = commit(
+            &store,
+            &registry,
+            Some(base),
+            "1\n2\n3\n4\n5\n6\n7\n8\nnine\n",
+        );
+
+        let merged = merge_octopus(&store, &registry, &left, &[first, second], "t", "m").unwrap();
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(merged.ancestor, base);
+        assert_eq!(merged.revision.parents, vec![left, first, second]);
+        assert_eq!(
+            first_file(&store, &merged.revision.tree.unwrap()),
+            "one\n2\n3\n4\nfive\n6\n7\n8\nnine\n"
+        );
+
+        let rival = commit(
+            &store,
+            &registry,
+            Some(base),
+            "1\n2\n3\n4\nFIVE\n6\n7\n8\n9\n",
+        );
+        let err = merge_octopus(&store, &registry, &left, &[first, rival], "t", "m");
+        assert!(matches!(
+            err,
+            Err(MergeError::HeadsConflict { first: f, second: s, .. }) if f == first && s == rival
+        ));
+
+        let clash = commit(
+            &store,
+            &registry,
+            Some(base),
+            "ONE\n2\n3\n4\n5\n6\n7\n8\n9\n",
+        );
+        let merged = merge_octopus(&store, &registry, &left, &[clash, second], "t", "m").unwrap();
+        assert_eq!(merged.conflicts.len(), 1);
+        let (index, conflict) = &merged.conflicts[0];
+        assert_eq!(*index, 0);
+        assert_eq!(conflict.right_revision, clash);
+        // The later head's change to the conflicted file is on its left side.
+        assert_ne!(conflict.left_revision, left);
+        let left_side = find_blob_content_at_path(&store, &conflict.left_revision, "a.txt");
+        assert_eq!(
+            left_side.unwrap().unwrap(),
+            b"one\n2\n3\n4\n5\n6\n7\n8\nnine\n"
+        );
+        let (_, left_side, _) = group_contents(
+            &store,
+            &registry,
+            &base,
+            &conflict.codec_id,
+            "a.txt",
+            &conflict.left_patch_ids,
+            &conflict.right_patch_ids,
+        )
+        .unwrap();
+        assert_eq!(left_side, b"one\n2\n3\n4\n5\n6\n7\n8\nnine\n");
+    }
+
+    #[test]
+    fn heads_that_merge_with_each_other_do_not_abort() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let left = commit(
+            &store,
+            &registry,
+            Some(base),
+            "one\n2\n3\n4\n5\n6\n7\n8\n9\n",
+        );
+        let first = commit(
+            &store,
+            &registry,
+            Some(base),
+            "1\n2\n3\n4\nfive\n6\n7\n8\n9\n",
+        );
+        // Conflicts with the left head, but not with the first head.
+        let clash = commit(
+            &store,
+            &registry,
+            Some(base),
+            "ONE\n2\n3\n4\n5\n6\n7\n8\n9\n",
+        );
+
+        let merged = merge_octopus(&store, &registry, &left, &[first, clash], "t", "m").unwrap();
+        assert_eq!(merged.conflicts.len(), 1);
+        let (index, conflict) = &merged.conflicts[0];
+        assert_eq!(*index, 1);
+        assert_eq!(conflict.right_revision, clash);
+    }
+}
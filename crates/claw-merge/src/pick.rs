@@ -0,0 +1,184 @@
+//! Replaying a single revision onto another head: cherry-pick and revert.
+//!
+//! Both are merges against a chosen base. A cherry-pick merges the
+//! revision's own patches into the head using the revision's parent as the
+//! base; a revert merges the revision's patches inverted with
+//! `Codec::invert`, using the revision itself as the base.
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{Patch, Revision};
+use claw_patch::CodecRegistry;
+use claw_store::ClawStore;
+
+use crate::collect::{revision_tree, tree_diff_patches, tree_patches};
+use crate::emit::{merge_with_patches, MergeResult};
+use crate::MergeError;
+
+/// Apply the changes `revision` made on top of `head`.
+///
+/// The result revision has `head` as its only parent. Conflicts are
+/// reported like a merge, with the picked revision as the right side.
+pub fn cherry_pick(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    head: &ObjectId,
+    revision: &ObjectId,
+    author: &str,
+    message: &str,
+) -> Result<MergeResult, MergeError> {
+    let (rev, parent) = load_single_parent(store, revision)?;
+    let left_patches = tree_patches(store, registry, &parent, head)?;
+    let right_patches = revision_patches(store, registry, &rev, &parent, revision)?;
+    let result = merge_with_patches(
+        store,
+        registry,
+        &parent,
+        (head, &left_patches),
+        (revision, &right_patches),
+        author,
+        message,
+    )?;
+    linearize(store, registry, head, result)
+}
+
+/// Undo the changes `revision` made, on top of `head`.
+///
+/// The revision's patches are inverted and merged against the revision
+/// itself; conflicts show the revision's parent as the right side.
+pub fn revert(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    head: &ObjectId,
+    revision: &ObjectId,
+    author: &str,
+    message: &str,
+) -> Result<MergeResult, MergeError> {
+    let (rev, parent) = load_single_parent(store, revision)?;
+    let left_patches = tree_patches(store, registry, revision, head)?;
+    let forward = revision_patches(store, registry, &rev, &parent, revision)?;
+    let mut inverted = Vec::with_capacity(forward.len());
+    for id in forward.iter().rev() {
+        let Object::Patch(patch) = store.load_object(id)? else {
+            continue;
+        };
+        let codec = registry.get(&patch.codec_id)?;
+        let ops = codec.invert(&patch.ops)?;
+        let target_path = patch.target_path.clone();
+        let inverse = Patch {
+            target_path: patch.target_path,
+            codec_id: patch.codec_id,
+            base_object: patch.result_object,
+            result_object: patch.base_object,
+            ops,
+            codec_payload: None,
+        };
+        inverted.push(store.store_object_for_path(&target_path, &Object::Patch(inverse))?);
+    }
+    let result = merge_with_patches(
+        store,
+        registry,
+        revision,
+        (head, &left_patches),
+        (&parent, &inverted),
+        author,
+        message,
+    )?;
+    linearize(store, registry, head, result)
+}
+
+fn load_single_parent(
+    store: &ClawStore,
+    id: &ObjectId,
+) -> Result<(Revision, ObjectId), MergeError> {
+    let Object::Revision(rev) = store.load_object(id)? else {
+        return Err(MergeError::InvalidRevision(format!(
+            "{id} is not a revision"
+        )));
+    };
+    match rev.parents.as_slice() {
+        [parent] => {
+            let parent = *parent;
+            Ok((rev, parent))
+        }
+        [] => Err(MergeError::InvalidRevision(format!(
+            "{id} has no parent to replay against"
+        ))),
+        _ => Err(MergeError::InvalidRevision(format!(
+            "{id} is a merge revision"
+        ))),
+    }
+}
+
+/// The revision's patches, or patches derived from its tree when it has
+/// none (such as a completed conflicted merge).
+fn revision_patches(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    rev: &Revision,
+    parent: &ObjectId,
+    id: &ObjectId,
+) -> Result<Vec<ObjectId>, MergeError> {
+    if rev.patches.is_empty() {
+        tree_patches(store, registry, parent, id)
+    } else {
+        Ok(rev.patches.clone())
+    }
+}
+
+/// Turn a merge result into a single-parent revision on `head` whose
+/// patches take `head` to the merged tree.
+fn linearize(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    head: &ObjectId,
+    mut result: MergeResult,
+) -> Result<MergeResult, MergeError> {
+    result.revision.parents = vec![*head];
+    if result.conflicts.is_empty() {
+        let head_tree = revision_tree(store, head)?;
+        let patches = tree_diff_patches(
+            store,
+            registry,
+            head_tree.as_ref(),
+            result.revision.tree.as_ref(),
+        )?;
+        result.revision.patches = patches.clone();
+        result.new_patches = patches;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{commit, first_file};
+
+    #[test]
+    fn pick_and_revert_replay_one_revision() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n");
+        let fix = commit(&store, &registry, Some(base), "1\n2\n3\n4\nfive\n");
+        let head = commit(&store, &registry, Some(base), "one\n2\n3\n4\n5\n");
+
+        let picked = cherry_pick(&store, &registry, &head, &fix, "test", "pick").unwrap();
+        assert!(picked.conflicts.is_empty());
+        assert_eq!(picked.revision.parents, vec![head]);
+        assert_eq!(
+            first_file(&store, &picked.revision.tree.unwrap()),
+            "one\n2\n3\n4\nfive\n"
+        );
+
+        let on_top = commit(&store, &registry, Some(fix), "one\n2\n3\n4\nfive\n");
+        let reverted = revert(&store, &registry, &on_top, &fix, "test", "revert").unwrap();
+        assert!(reverted.conflicts.is_empty());
+        assert_eq!(reverted.revision.parents, vec![on_top]);
+        assert_eq!(
+            first_file(&store, &reverted.revision.tree.unwrap()),
+            "one\n2\n3\n4\n5\n"
+        );
+    }
+}
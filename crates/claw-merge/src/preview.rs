@@ -0,0 +1,546 @@
+//! Predicting the outcome of a merge without performing it.
+//!
+//! A preview compares the contents of the base, left and right trees
+//! directly and tries each file both sides changed the way a merge would:
+//! a `.clawattributes` strategy, then commutation and merge3, then a
+//! recorded resolution. Merge drivers are never run; the files they would
+//! handle are listed separately. With several merge bases the preview
+//! builds the same virtual base as integrate, in memory.
+//!
+//! Nothing is written to the repository, not even revision graph entries,
+//! so the preview sees one diff per side rather than each revision's
+//! patches.
+
+use std::collections::{BTreeSet, HashMap};
+
+use claw_core::chunking;
+use claw_core::id::ObjectId;
+use claw_core::types::{ConflictKind, FileMode};
+use claw_patch::text_line::{merge3_regions, MergeRegion};
+use claw_patch::{Codec, CodecRegistry};
+use claw_store::ClawStore;
+
+use crate::ancestor::merge_bases_in_memory;
+use crate::attributes::{union, MergeAttributes, MergeStrategy, ATTRIBUTES_FILE};
+use crate::collect::revision_tree;
+use crate::renames::{followed_moves, FollowedMove};
+use crate::tree_conflicts::{entry_conflicts, flatten_entries, Entries};
+use crate::{rerere, MergeError};
+
+#[derive(Debug)]
+pub struct MergePreview {
+    /// The merge bases the preview was computed against. With several, it
+    /// used a virtual base merged from them, as integrate does.
+    pub bases: Vec<ObjectId>,
+    /// Paths only one side changed, or both changed the same way.
+    pub clean: Vec<String>,
+    /// Paths both sides changed that would merge without conflicts.
+    pub auto_merged: Vec<String>,
+    /// Paths both sides changed that a merge driver from the local config
+    /// would merge. The preview does not run drivers, so it cannot tell
+    /// whether they would succeed.
+    pub driver_resolved: Vec<String>,
+    pub conflicts: Vec<PreviewConflict>,
+}
+
+#[derive(Debug)]
+pub struct PreviewConflict {
+    pub path: String,
+    pub kind: ConflictKind,
+    /// The conflicting hunks of a text file; empty for tree conflicts and
+    /// files that are not text.
+    pub hunks: Vec<PreviewHunk>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct PreviewHunk {
+    /// 1-based line in the base where the hunk starts.
+    pub base_line: usize,
+    pub base: Vec<String>,
+    pub left: Vec<String>,
+    pub right: Vec<String>,
+}
+
+/// Predict which paths merging `right_head` into `left_head` would leave
+/// clean, merge automatically, or conflict on.
+pub fn preview_merge(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    left_head: &ObjectId,
+    right_head: &ObjectId,
+) -> Result<MergePreview, MergeError> {
+    let (bases, base) = base_files(store, registry, &[*left_head], right_head)?;
+    let left = Files::of_revision(store, left_head)?;
+    let right = Files::of_revision(store, right_head)?;
+    let attributes = MergeAttributes::load(store, left_head)?;
+    let outcome = predict(store, registry, &attributes, &base, &left, &right)?;
+    Ok(MergePreview {
+        bases,
+        clean: outcome.clean,
+        auto_merged: outcome.auto_merged,
+        driver_resolved: outcome.driver_resolved,
+        conflicts: outcome.conflicts,
+    })
+}
+
+/// Every file of one version: blob id and mode by path, and the contents
+/// of blobs merged in memory rather than read from the store.
+#[derive(Default, Clone)]
+struct Files {
+    entries: Entries,
+    merged: HashMap<ObjectId, Vec<u8>>,
+}
+
+impl Files {
+    fn of_revision(store: &ClawStore, id: &ObjectId) -> Result<Self, MergeError> {
+        let mut entries = Entries::new();
+        if let Some(tree) = revision_tree(store, id)? {
+            flatten_entries(store, &tree, "", &mut entries)?;
+        }
+        Ok(Self {
+            entries,
+            merged: HashMap::new(),
+        })
+    }
+
+    /// The contents of `path`, empty if it is absent.
+    fn read(&self, store: &ClawStore, path: &str) -> Result<Vec<u8>, MergeError> {
+        match self.entries.get(path) {
+            Some((id, _)) => match self.merged.get(id) {
+                Some(data) => Ok(data.clone()),
+                None => Ok(store.read_blob(id)?),
+            },
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Copy `path` from `other`, or remove it if `other` lacks it.
+    fn take(&mut self, other: &Files, path: &str) {
+        match other.entries.get(path) {
+            Some(entry) => {
+                if let Some(data) = other.merged.get(&entry.0) {
+                    self.merged.insert(entry.0, data.clone());
+                }
+                self.entries.insert(path.to_string(), *entry);
+            }
+            None => {
+                self.entries.remove(path);
+            }
+        }
+    }
+
+    fn insert_merged(
+        &mut self,
+        path: &str,
+        mode: FileMode,
+        data: Vec<u8>,
+    ) -> Result<(), MergeError> {
+        let id = chunking::build_blob(data.as_slice(), chunking::hash_object)?;
+        self.entries.insert(path.to_string(), (id, mode));
+        self.merged.insert(id, data);
+        Ok(())
+    }
+
+    fn attributes(&self, store: &ClawStore) -> Result<MergeAttributes, MergeError> {
+        let data = match self.entries.contains_key(ATTRIBUTES_FILE) {
+            true => Some(self.read(store, ATTRIBUTES_FILE)?),
+            false => None,
+        };
+        MergeAttributes::from_file(store, data.as_deref())
+    }
+}
+
+/// The merge bases of `right` and a revision with parents `lefts`, and the
+/// files integrate would merge against: those of the only base, or of a
+/// virtual base folded from several. Like integrate, a base that conflicts
+/// with the ones before it leaves the files so far unchanged; so does one
+/// that needs a driver, since the preview cannot run it.
+fn base_files(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    lefts: &[ObjectId],
+    right: &ObjectId,
+) -> Result<(Vec<ObjectId>, Files), MergeError> {
+    let bases = merge_bases_in_memory(store, lefts, right)?;
+    let (first, others) = bases.split_first().ok_or(MergeError::NoCommonAncestor)?;
+    let mut files = Files::of_revision(store, first)?;
+    for (i, other) in others.iter().enumerate() {
+        let (_, base) = base_files(store, registry, &bases[..=i], other)?;
+        let attributes = files.attributes(store)?;
+        let other = Files::of_revision(store, other)?;
+        let outcome = predict(store, registry, &attributes, &base, &files, &other)?;
+        if outcome.conflicts.is_empty() && outcome.driver_resolved.is_empty() {
+            files = outcome.merged;
+        }
+    }
+    Ok((bases, files))
+}
+
+/// What merging `right` into `left` against `base` would do.
+struct Outcome {
+    clean: Vec<String>,
+    auto_merged: Vec<String>,
+    driver_resolved: Vec<String>,
+    conflicts: Vec<PreviewConflict>,
+    /// The merged files; only complete when nothing conflicted and no
+    /// driver was needed.
+    merged: Files,
+}
+
+fn predict(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    attributes: &MergeAttributes,
+    base: &Files,
+    left: &Files,
+    right: &Files,
+) -> Result<Outcome, MergeError> {
+    let followed = follow_moves(store, registry, base, left, right);
+    let (base, left, right) = match &followed {
+        Some((base, left, right)) => (base, left, right),
+        None => (base, left, right),
+    };
+    let mut conflicts: Vec<PreviewConflict> =
+        entry_conflicts(&base.entries, &left.entries, &right.entries)
+            .into_iter()
+            .map(|(path, kind)| PreviewConflict {
+                path,
+                kind,
+                hunks: vec![],
+            })
+            .collect();
+    let tree_conflicted: Vec<String> = conflicts.iter().map(|c| c.path.clone()).collect();
+
+    let mut clean = Vec::new();
+    let mut auto_merged = Vec::new();
+    let mut driver_resolved = Vec::new();
+    let mut merged = left.clone();
+    let paths: BTreeSet<&String> = base
+        .entries
+        .keys()
+        .chain(left.entries.keys())
+        .chain(right.entries.keys())
+        .collect();
+    for path in paths {
+        if tree_conflicted
+            .iter()
+            .any(|p| path == p || path.starts_with(&format!("{p}/")))
+        {
+            continue;
+        }
+        let (b, l, r) = (
+            base.entries.get(path),
+            left.entries.get(path),
+            right.entries.get(path),
+        );
+        if l == b && r == b {
+            continue;
+        }
+        if l == b || r == b || l == r {
+            if l == b {
+                merged.take(right, path);
+            }
+            clean.push(path.clone());
+            continue;
+        }
+
+        let (base_content, left_content, right_content) = (
+            base.read(store, path)?,
+            left.read(store, path)?,
+            right.read(store, path)?,
+        );
+        let result = match attributes.strategy_for(path) {
+            Some(MergeStrategy::Driver(_)) => {
+                driver_resolved.push(path.clone());
+                continue;
+            }
+            Some(strategy) => merge_with_strategy(
+                registry,
+                strategy,
+                &base_content,
+                &left_content,
+                &right_content,
+            ),
+            None => registry.get_for_path(path).and_then(|codec| {
+                merge_contents(codec.as_ref(), &base_content, &left_content, &right_content)
+            }),
+        };
+        let result = match result {
+            Some(result) => Some(result),
+            None => rerere::replay(store, &base_content, &left_content, &right_content)?,
+        };
+        match (result, l) {
+            (Some(result), Some((_, mode))) => {
+                merged.insert_merged(path, *mode, result)?;
+                auto_merged.push(path.clone());
+            }
+            _ => conflicts.push(PreviewConflict {
+                path: path.clone(),
+                kind: ConflictKind::Content,
+                hunks: hunks(&base_content, &left_content, &right_content),
+            }),
+        }
+    }
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(Outcome {
+        clean,
+        auto_merged,
+        driver_resolved,
+        conflicts,
+        merged,
+    })
+}
+
+/// The three versions with the other side's copy of each file a side moved
+/// put at its new path, as the merge follows moves; `None` if there are
+/// none to follow.
+fn follow_moves(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    base: &Files,
+    left: &Files,
+    right: &Files,
+) -> Option<(Files, Files, Files)> {
+    let changed = |side: &Files| -> BTreeSet<(String, String)> {
+        base.entries
+            .keys()
+            .chain(side.entries.keys())
+            .filter(|path| base.entries.get(*path) != side.entries.get(*path))
+            .filter_map(|path| Some((path.clone(), registry.get_for_path(path)?.id().to_string())))
+            .collect()
+    };
+    let (left_changed, right_changed) = (changed(left), changed(right));
+    let (left_moves, right_moves) = followed_moves(
+        store,
+        registry,
+        &base.entries,
+        (&left.entries, &left_changed),
+        (&right.entries, &right_changed),
+    );
+    if left_moves.is_empty() && right_moves.is_empty() {
+        return None;
+    }
+    let (mut base, mut left, mut right) = (base.clone(), left.clone(), right.clone());
+    for (moves, other) in [(left_moves, &mut right), (right_moves, &mut left)] {
+        for FollowedMove { old, new, .. } in moves {
+            if let Some(entry) = other.entries.remove(&old) {
+                other.entries.insert(new.clone(), entry);
+            }
+            if let Some(entry) = base.entries.get(&old).copied() {
+                base.entries.insert(new, entry);
+            }
+        }
+    }
+    Some((base, left, right))
+}
+
+/// Both sides' changes merged by the codec, by commuting the right side's
+/// diff past the left's or by merge3.
+fn merge_contents(codec: &dyn Codec, base: &[u8], left: &[u8], right: &[u8]) -> Option<Vec<u8>> {
+    if let (Ok(left_ops), Ok(right_ops)) = (codec.diff(base, left), codec.diff(base, right)) {
+        if let Ok((rebased_right, _)) = codec.commute(&left_ops, &right_ops) {
+            if let Ok(merged) = codec.apply(left, &rebased_right) {
+                return Some(merged);
+            }
+        }
+    }
+    codec.merge3(base, left, right).ok()
+}
+
+/// Both sides merged with a `.clawattributes` strategy other than a driver.
+fn merge_with_strategy(
+    registry: &CodecRegistry,
+    strategy: &MergeStrategy,
+    base: &[u8],
+    left: &[u8],
+    right: &[u8],
+) -> Option<Vec<u8>> {
+    match strategy {
+        MergeStrategy::Ours => Some(left.to_vec()),
+        MergeStrategy::Theirs => Some(right.to_vec()),
+        MergeStrategy::Union => union(base, left, right),
+        MergeStrategy::Codec(other) => registry
+            .get(other)
+            .ok()
+            .and_then(|codec| codec.merge3(base, left, right).ok()),
+        MergeStrategy::Driver(_) => None,
+    }
+}
+
+/// The conflicting line hunks, if all three versions are text.
+fn hunks(base: &[u8], left: &[u8], right: &[u8]) -> Vec<PreviewHunk> {
+    let (Ok(base), Ok(left), Ok(right)) = (
+        std::str::from_utf8(base),
+        std::str::from_utf8(left),
+        std::str::from_utf8(right),
+    ) else {
+        return vec![];
+    };
+    merge3_regions(base, left, right)
+        .into_iter()
+        .filter_map(|region| match region {
+            MergeRegion::Conflict {
+                base_start,
+                base,
+                left,
+                right,
+            } => Some(PreviewHunk {
+                base_line: base_start + 1,
+                base,
+                left,
+                right,
+            }),
+            MergeRegion::Clean(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emit::merge;
+    use crate::test_support::{commit, commit_files};
+    use claw_core::object::Object;
+    use claw_store::fsck::fsck;
+    use claw_store::repo::{read_config, write_config, MergeDriver};
+
+    #[test]
+    fn predicts_merge_without_writing_anything() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+        let mut config = read_config(store.layout()).unwrap();
+        config.merge.drivers.insert(
+            "regen".to_string(),
+            MergeDriver {
+                command: "touch".to_string(),
+                args: vec!["driver-ran".to_string()],
+            },
+        );
+        write_config(store.layout(), &config).unwrap();
+
+        let attributes = (ATTRIBUTES_FILE, "d.txt driver=regen\n");
+        let base = commit_files(
+            &store,
+            &registry,
+            None,
+            &[
+                attributes,
+                ("a.txt", "1\n2\n3\n"),
+                ("b.txt", "1\n2\n3\n4\n5\n"),
+                ("c.txt", "x\n"),
+                ("d.txt", "d\n"),
+            ],
+        );
+        let left = commit_files(
+            &store,
+            &registry,
+            Some(base),
+            &[
+                attributes,
+                ("a.txt", "one\n2\n3\n"),
+                ("b.txt", "L\n2\n3\n4\n5\n"),
+                ("c.txt", "x\n"),
+                ("d.txt", "left\n"),
+            ],
+        );
+        let right = commit_files(
+            &store,
+            &registry,
+            Some(base),
+            &[
+                attributes,
+                ("a.txt", "R\n2\n3\n"),
+                ("b.txt", "1\n2\n3\n4\nR\n"),
+                ("c.txt", "y\n"),
+                ("d.txt", "right\n"),
+            ],
+        );
+        // Start without a revision graph, so the merge base search has to
+        // compute entries it must not record.
+        drop(store);
+        let graph = tmp.path().join(".claw/revision-graph");
+        std::fs::remove_file(&graph).unwrap();
+        let store = ClawStore::open(tmp.path()).unwrap();
+
+        let objects = fsck(&store).unwrap().loose_objects;
+        let preview = preview_merge(&store, &registry, &left, &right).unwrap();
+        assert_eq!(fsck(&store).unwrap().loose_objects, objects);
+        assert!(!graph.exists());
+        assert!(!tmp.path().join("driver-ran").exists());
+
+        assert_eq!(preview.bases, vec![base]);
+        assert_eq!(preview.clean, vec!["c.txt"]);
+        assert_eq!(preview.auto_merged, vec!["b.txt"]);
+        assert_eq!(preview.driver_resolved, vec!["d.txt"]);
+        assert_eq!(preview.conflicts.len(), 1);
+        let conflict = &preview.conflicts[0];
+        assert_eq!(
+            (conflict.path.as_str(), conflict.kind),
+            ("a.txt", ConflictKind::Content)
+        );
+        assert_eq!(
+            conflict.hunks,
+            vec![PreviewHunk {
+                base_line: 1,
+                base: vec!["1".to_string()],
+                left: vec!["one".to_string()],
+                right: vec!["R".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn criss_cross_uses_virtual_base() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let root = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let x = commit(&store, &registry, Some(root), "x\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let y = commit(&store, &registry, Some(root), "1\n2\n3\n4\n5\n6\n7\n8\ny\n");
+        let [m1, m2] = [(x, y), (y, x)].map(|(l, r)| {
+            let merged = merge(&store, &registry, &l, &r, "test", "m").unwrap();
+            assert!(merged.conflicts.is_empty());
+            store
+                .store_object(&Object::Revision(merged.revision))
+                .unwrap()
+        });
+        // Against either base alone, each side's change conflicts with
+        // the other base's.
+        let left = commit(&store, &registry, Some(m1), "L\n2\n3\n4\n5\n6\n7\n8\ny\n");
+        let right = commit(&store, &registry, Some(m2), "x\n2\n3\n4\n5\n6\n7\n8\nR\n");
+
+        let preview = preview_merge(&store, &registry, &left, &right).unwrap();
+        assert_eq!(preview.bases.len(), 2);
+        assert!(preview.conflicts.is_empty());
+        assert_eq!(preview.auto_merged, vec!["a.txt"]);
+        let merged = merge(&store, &registry, &left, &right, "test", "m").unwrap();
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn preview_follows_moves_like_the_merge() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n");
+        let moved = commit_files(
+            &store,
+            &registry,
+            Some(base),
+            &[("b.txt", "one\n2\n3\n4\n5\n6\n")],
+        );
+        let edited = commit(&store, &registry, Some(base), "1\n2\n3\n4\n5\nsix\n");
+
+        for (left, right) in [(moved, edited), (edited, moved)] {
+            let preview = preview_merge(&store, &registry, &left, &right).unwrap();
+            let merged = merge(&store, &registry, &left, &right, "test", "m").unwrap();
+            assert!(preview.conflicts.is_empty(), "{:?}", preview.conflicts);
+            assert!(merged.conflicts.is_empty(), "{:?}", merged.conflicts);
+            assert_eq!(preview.auto_merged, vec!["b.txt"]);
+        }
+    }
+}
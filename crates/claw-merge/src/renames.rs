@@ -0,0 +1,189 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use claw_core::chunking::CHUNKING_THRESHOLD;
+use claw_core::id::ObjectId;
+use claw_core::types::FileMode;
+use claw_patch::CodecRegistry;
+use claw_store::tree_diff::{
+    detect_renames, ChangeKind, RenameOptions, SimilarityScorer, TreeChange,
+};
+use claw_store::ClawStore;
+
+/// Scores rename candidates with the patch codec for the new path.
+///
+/// Content comes from the store, or from the worktree under `worktree` for
+/// files that were only hashed. Chunked and unreadable files are skipped.
+pub struct CodecScorer<'a> {
+    store: &'a ClawStore,
+    registry: &'a CodecRegistry,
+    worktree: Option<&'a Path>,
+    cache: HashMap<ObjectId, Option<Vec<u8>>>,
+}
+
+impl<'a> CodecScorer<'a> {
+    pub fn new(
+        store: &'a ClawStore,
+        registry: &'a CodecRegistry,
+        worktree: Option<&'a Path>,
+    ) -> Self {
+        Self {
+            store,
+            registry,
+            worktree,
+            cache: HashMap::new(),
+        }
+    }
+
+    fn content(&mut self, path: &str, id: &ObjectId) -> Option<Vec<u8>> {
+        if let Some(cached) = self.cache.get(id) {
+            return cached.clone();
+        }
+        let content = if self.store.has_object(id) {
+            match self.store.is_chunked_blob(id) {
+                Ok(false) => self.store.read_blob(id).ok(),
+                _ => None,
+            }
+        } else {
+            self.worktree.and_then(|root| {
+                let file = root.join(path);
+                let len = std::fs::metadata(&file).ok()?.len();
+                (len as usize <= CHUNKING_THRESHOLD)
+                    .then(|| std::fs::read(file).ok())
+                    .flatten()
+            })
+        };
+        self.cache.insert(*id, content.clone());
+        content
+    }
+}
+
+impl SimilarityScorer for CodecScorer<'_> {
+    fn score(
+        &mut self,
+        old_path: &str,
+        old: &ObjectId,
+        new_path: &str,
+        new: &ObjectId,
+    ) -> Option<u8> {
+        let old = self.content(old_path, old)?;
+        let new = self.content(new_path, new)?;
+        Some(self.registry.similarity(new_path, &old, &new))
+    }
+}
+
+/// Run rename and copy detection over `changes` with the default options.
+pub fn detect(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    old_files: &BTreeMap<String, (ObjectId, FileMode)>,
+    changes: Vec<TreeChange>,
+    worktree: Option<&Path>,
+) -> Vec<TreeChange> {
+    let mut scorer = CodecScorer::new(store, registry, worktree);
+    detect_renames(
+        changes,
+        old_files,
+        &RenameOptions::default(),
+        Some(&mut scorer),
+    )
+}
+
+/// A file one side moved that the other side's edits follow to its new
+/// path, and the codec of both sides' patches there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FollowedMove {
+    pub old: String,
+    pub new: String,
+    pub codec_id: String,
+}
+
+/// One side of a merge: its files, and the `(path, codec)` pairs it has
+/// patches for.
+pub(crate) type MergeSide<'a> = (
+    &'a BTreeMap<String, (ObjectId, FileMode)>,
+    &'a BTreeSet<(String, String)>,
+);
+
+/// The moves whose edits from the other side follow them: the left side's
+/// moves (the right side's edits follow) and the right side's.
+///
+/// The other side must have kept the file where it was, edited it with the
+/// codec the mover's patches at the new path use, and not have touched the
+/// new path itself. Both the merge and its preview decide with this.
+pub(crate) fn followed_moves(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    base: &BTreeMap<String, (ObjectId, FileMode)>,
+    (left, left_changed): MergeSide,
+    (right, right_changed): MergeSide,
+) -> (Vec<FollowedMove>, Vec<FollowedMove>) {
+    let left_moves = side_moves(store, registry, base, left);
+    let right_moves = side_moves(store, registry, base, right);
+    let follow = |mover_moves: &BTreeMap<String, String>,
+                  mover_changed: &BTreeSet<(String, String)>,
+                  other_moves: &BTreeMap<String, String>,
+                  other_changed: &BTreeSet<(String, String)>| {
+        let mut followed = Vec::new();
+        for (old, new) in mover_moves {
+            if other_moves.contains_key(old) || !base.contains_key(old) {
+                continue;
+            }
+            let Some((_, codec_id)) = mover_changed.iter().find(|(path, _)| path == new) else {
+                continue;
+            };
+            if other_changed.iter().any(|(path, _)| path == new)
+                || !other_changed.contains(&(old.clone(), codec_id.clone()))
+            {
+                continue;
+            }
+            followed.push(FollowedMove {
+                old: old.clone(),
+                new: new.clone(),
+                codec_id: codec_id.clone(),
+            });
+        }
+        followed
+    };
+    (
+        follow(&left_moves, left_changed, &right_moves, right_changed),
+        follow(&right_moves, right_changed, &left_moves, left_changed),
+    )
+}
+
+/// The files a side renamed since the base, old path to new path.
+fn side_moves(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    base: &BTreeMap<String, (ObjectId, FileMode)>,
+    side: &BTreeMap<String, (ObjectId, FileMode)>,
+) -> BTreeMap<String, String> {
+    let change = |path: &str,
+                  kind,
+                  old: Option<&(ObjectId, FileMode)>,
+                  new: Option<&(ObjectId, FileMode)>| {
+        TreeChange {
+            path: path.to_string(),
+            kind,
+            old_id: old.map(|(id, _)| *id),
+            new_id: new.map(|(id, _)| *id),
+            old_mode: old.map(|(_, mode)| *mode),
+            new_mode: new.map(|(_, mode)| *mode),
+            old_path: None,
+            similarity: None,
+        }
+    };
+    let deleted = base
+        .iter()
+        .filter(|(path, _)| !side.contains_key(*path))
+        .map(|(path, entry)| change(path, ChangeKind::Deleted, Some(entry), None));
+    let added = side
+        .iter()
+        .filter(|(path, _)| !base.contains_key(*path))
+        .map(|(path, entry)| change(path, ChangeKind::Added, None, Some(entry)));
+    detect(store, registry, base, deleted.chain(added).collect(), None)
+        .into_iter()
+        .filter(|change| change.kind == ChangeKind::Renamed)
+        .filter_map(|change| Some((change.old_path?, change.path)))
+        .collect()
+}
@@ -0,0 +1,347 @@
+//! Reuse of recorded conflict resolutions ("rerere").
+//!
+//! A resolution is keyed by a hash of the file's conflicting hunks: the
+//! base lines and the two sides, normalized so that trailing whitespace and
+//! which side is left do not matter. The file's clean regions are not part
+//! of the key, so the same conflict recurring in an otherwise different
+//! file is still recognized. What is recorded is the text that replaced
+//! each conflicting hunk, stored as a blob under `rerere/<key>` along with
+//! the time it was recorded, so that old resolutions can be expired.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{Blob, Patch};
+use claw_patch::text_line::{merge3_regions, MergeRegion};
+use claw_patch::CodecRegistry;
+use claw_store::ClawStore;
+
+use crate::emit::group_contents;
+use crate::MergeError;
+
+/// Ref namespace holding recorded resolutions.
+pub const RERERE_PREFIX: &str = "rerere";
+
+/// How long a recorded resolution is kept before `claw gc` expires it.
+pub const DEFAULT_EXPIRY: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+/// A conflicting hunk: base, left and right lines.
+type Hunk = (Vec<String>, Vec<String>, Vec<String>);
+
+/// Clean stretches and conflicting hunks of a file, alternating, with
+/// adjacent conflicts merged into one.
+enum Part {
+    Clean(Vec<String>),
+    Conflict(Hunk),
+}
+
+fn parts(base: &str, left: &str, right: &str) -> Vec<Part> {
+    let mut parts: Vec<Part> = Vec::new();
+    for region in merge3_regions(base, left, right) {
+        match (region, parts.last_mut()) {
+            (
+                MergeRegion::Conflict {
+                    base, left, right, ..
+                },
+                Some(Part::Conflict(hunk)),
+            ) => {
+                hunk.0.extend(base);
+                hunk.1.extend(left);
+                hunk.2.extend(right);
+            }
+            (
+                MergeRegion::Conflict {
+                    base, left, right, ..
+                },
+                _,
+            ) => parts.push(Part::Conflict((base, left, right))),
+            (MergeRegion::Clean(lines), _) => parts.push(Part::Clean(lines)),
+        }
+    }
+    parts
+}
+
+/// Key of a file's conflicting hunks, or `None` if it has none.
+fn conflict_key(parts: &[Part]) -> Option<String> {
+    let normalize = |lines: &[String]| {
+        lines
+            .iter()
+            .map(|l| l.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let mut hasher = blake3::Hasher::new();
+    let mut any = false;
+    for part in parts {
+        if let Part::Conflict((base, left, right)) = part {
+            let mut sides = [normalize(left), normalize(right)];
+            sides.sort();
+            for text in [normalize(base), sides[0].clone(), sides[1].clone()] {
+                hasher.update(text.as_bytes());
+                hasher.update(&[0]);
+            }
+            hasher.update(&[0x1e]);
+            any = true;
+        }
+    }
+    any.then(|| ObjectId::from_bytes(*hasher.finalize().as_bytes()).to_hex())
+}
+
+/// Split a resolved file into the text that replaced each conflicting hunk,
+/// using the clean stretches around them as anchors. `None` if a clean
+/// stretch was edited as well.
+fn resolutions(parts: &[Part], resolved: &[&str]) -> Option<Vec<Vec<String>>> {
+    let mut found = Vec::new();
+    let mut cursor = 0;
+    let mut pending = false;
+    for part in parts {
+        match part {
+            Part::Conflict(_) => pending = true,
+            Part::Clean(lines) => {
+                let matches_at = |at: usize| {
+                    resolved
+                        .get(at..at + lines.len())
+                        .is_some_and(|window| window.iter().eq(lines.iter()))
+                };
+                let at = if pending {
+                    (cursor..=resolved.len()).find(|&at| matches_at(at))?
+                } else if matches_at(cursor) {
+                    cursor
+                } else {
+                    return None;
+                };
+                if pending {
+                    found.push(resolved[cursor..at].iter().map(|l| l.to_string()).collect());
+                    pending = false;
+                }
+                cursor = at + lines.len();
+            }
+        }
+    }
+    if pending {
+        found.push(resolved[cursor..].iter().map(|l| l.to_string()).collect());
+    } else if cursor != resolved.len() {
+        return None;
+    }
+    Some(found)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// The first line is the time of recording in milliseconds, followed by
+/// each hunk's line count and lines.
+fn encode(recorded_at_ms: u64, resolutions: &[Vec<String>]) -> Vec<u8> {
+    let mut out = format!("{recorded_at_ms}\n");
+    for lines in resolutions {
+        out.push_str(&format!("{}\n", lines.len()));
+        for line in lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.into_bytes()
+}
+
+fn decode(data: &[u8]) -> Option<(u64, Vec<Vec<String>>)> {
+    let text = std::str::from_utf8(data).ok()?;
+    let mut lines = text.lines();
+    let recorded_at_ms = lines.next()?.parse().ok()?;
+    let mut resolutions = Vec::new();
+    while let Some(count) = lines.next() {
+        let count: usize = count.parse().ok()?;
+        let hunk: Vec<String> = lines.by_ref().take(count).map(str::to_string).collect();
+        if hunk.len() != count {
+            return None;
+        }
+        resolutions.push(hunk);
+    }
+    Some((recorded_at_ms, resolutions))
+}
+
+/// Remember how a conflicted file was resolved. Returns whether a
+/// resolution was recorded; nothing is recorded for files without line
+/// conflicts or whose clean regions were edited too.
+pub fn record(
+    store: &ClawStore,
+    path: &str,
+    base: &[u8],
+    left: &[u8],
+    right: &[u8],
+    resolved: &[u8],
+) -> Result<bool, MergeError> {
+    let (Ok(base), Ok(left), Ok(right), Ok(resolved)) = (
+        std::str::from_utf8(base),
+        std::str::from_utf8(left),
+        std::str::from_utf8(right),
+        std::str::from_utf8(resolved),
+    ) else {
+        return Ok(false);
+    };
+    let parts = parts(base, left, right);
+    let Some(key) = conflict_key(&parts) else {
+        return Ok(false);
+    };
+    let resolved_lines: Vec<&str> = resolved.lines().collect();
+    let Some(found) = resolutions(&parts, &resolved_lines) else {
+        return Ok(false);
+    };
+    // Stored like the file itself, so an encrypted path stays encrypted.
+    let blob = store.store_object_for_path(
+        path,
+        &Object::Blob(Blob {
+            data: encode(now_ms(), &found),
+            media_type: None,
+        }),
+    )?;
+    store.set_ref(&format!("{RERERE_PREFIX}/{key}"), &blob)?;
+    Ok(true)
+}
+
+/// The file merged with a recorded resolution of its conflicts, if one
+/// was recorded for exactly these hunks.
+pub fn replay(
+    store: &ClawStore,
+    base: &[u8],
+    left: &[u8],
+    right: &[u8],
+) -> Result<Option<Vec<u8>>, MergeError> {
+    let (Ok(base), Ok(left), Ok(right)) = (
+        std::str::from_utf8(base),
+        std::str::from_utf8(left),
+        std::str::from_utf8(right),
+    ) else {
+        return Ok(None);
+    };
+    let parts = parts(base, left, right);
+    let Some(key) = conflict_key(&parts) else {
+        return Ok(None);
+    };
+    let Some(blob) = store.get_ref(&format!("{RERERE_PREFIX}/{key}"))? else {
+        return Ok(None);
+    };
+    let Some((_, recorded)) = decode(&store.read_blob(&blob)?) else {
+        return Ok(None);
+    };
+    let conflicts = parts
+        .iter()
+        .filter(|p| matches!(p, Part::Conflict(_)))
+        .count();
+    if recorded.len() != conflicts {
+        return Ok(None);
+    }
+
+    let mut recorded = recorded.into_iter();
+    let mut lines = Vec::new();
+    for part in parts {
+        match part {
+            Part::Clean(clean) => lines.extend(clean),
+            Part::Conflict(_) => lines.extend(recorded.next().unwrap_or_default()),
+        }
+    }
+    let mut merged = lines.join("\n");
+    if !merged.is_empty() && (left.ends_with('\n') || right.ends_with('\n')) {
+        merged.push('\n');
+    }
+    Ok(Some(merged.into_bytes()))
+}
+
+/// Refs of recorded resolutions older than `max_age`. Entries that can't
+/// be read, such as sealed ones without the key, are left alone.
+pub fn expired(store: &ClawStore, max_age: Duration) -> Result<Vec<String>, MergeError> {
+    let cutoff = now_ms().saturating_sub(max_age.as_millis() as u64);
+    let mut expired = Vec::new();
+    for (name, blob) in store.list_refs(&format!("{RERERE_PREFIX}/"))? {
+        let Ok(data) = store.read_blob(&blob) else {
+            continue;
+        };
+        if decode(&data).is_none_or(|(recorded_at_ms, _)| recorded_at_ms < cutoff) {
+            expired.push(name);
+        }
+    }
+    Ok(expired)
+}
+
+/// Resolve a conflicting `(path, codec)` group with a recorded resolution,
+/// returning the patch that takes the base to the result.
+pub(crate) fn replay_group(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    ancestor: &ObjectId,
+    codec_id: &str,
+    path: &str,
+    left_ids: &[ObjectId],
+    right_ids: &[ObjectId],
+) -> Result<Option<ObjectId>, MergeError> {
+    let Ok((base, left, right)) = group_contents(
+        store, registry, ancestor, codec_id, path, left_ids, right_ids,
+    ) else {
+        return Ok(None);
+    };
+    let Some(merged) = replay(store, &base, &left, &right)? else {
+        return Ok(None);
+    };
+    // A line-wise resolution may not parse under a structured codec.
+    let Ok(ops) = registry.get(codec_id)?.diff(&base, &merged) else {
+        return Ok(None);
+    };
+    let patch = Patch {
+        target_path: path.to_string(),
+        codec_id: codec_id.to_string(),
+        base_object: None,
+        result_object: None,
+        ops,
+        codec_payload: None,
+    };
+    Ok(Some(
+        store.store_object_for_path(path, &Object::Patch(patch))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_resolution_replays_in_another_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        let base = b"a\nb\nc\n";
+        let left = b"a\nleft\nc\n";
+        let right = b"a\nright\nc\n";
+        assert_eq!(replay(&store, base, left, right).unwrap(), None);
+        assert!(record(&store, "f.txt", base, left, right, b"a\nboth\nc\n").unwrap());
+
+        // Same hunk with the sides swapped and different clean lines.
+        let merged = replay(&store, b"x\nb\nz\n", b"x\nright\nz\n", b"x\nleft\nz\n").unwrap();
+        assert_eq!(merged, Some(b"x\nboth\nz\n".to_vec()));
+
+        // Editing outside the conflict is not a resolution of it.
+        assert!(!record(&store, "f.txt", base, left, right, b"A\nboth\nc\n").unwrap());
+    }
+
+    #[test]
+    fn old_resolutions_expire() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        assert!(record(&store, "f.txt", b"b\n", b"l\n", b"r\n", b"lr\n").unwrap());
+        let old = store
+            .store_object(&Object::Blob(Blob {
+                data: encode(0, &[vec!["x".to_string()]]),
+                media_type: None,
+            }))
+            .unwrap();
+        store
+            .set_ref(&format!("{RERERE_PREFIX}/old"), &old)
+            .unwrap();
+
+        let expired = expired(&store, DEFAULT_EXPIRY).unwrap();
+        assert_eq!(expired, vec![format!("{RERERE_PREFIX}/old")]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Rebasing a stack of changes onto a new base.
+//!
+//! Each change owns the revisions between the head of the change below it
+//! (or the stack's base, for the first) and its own head. Those revisions
+//! are replayed one at a time onto the new base in stack order, each as a
+//! cherry-pick, so every file is merged by commutation with merge3 as the
+//! fallback.
+
+use claw_core::id::{ChangeId, ObjectId};
+use claw_core::object::Object;
+use claw_patch::CodecRegistry;
+use claw_store::ClawStore;
+
+use crate::collect::revisions_between;
+use crate::emit::MergeResult;
+use crate::pick::cherry_pick;
+use crate::MergeError;
+
+pub struct StackRebase {
+    /// New heads of the changes rebased cleanly, in stack order.
+    pub rebased: Vec<(ChangeId, ObjectId)>,
+    /// Set when a change conflicted; the changes from it on were not moved.
+    pub stopped: Option<StackStop>,
+    /// Paths whose conflicts were resolved with a recorded resolution.
+    pub reused_resolutions: Vec<String>,
+}
+
+/// Where a stack rebase stopped.
+pub struct StackStop {
+    /// Position in the stack of the conflicting change.
+    pub index: usize,
+    pub change: ChangeId,
+    /// The original revision whose replay conflicted.
+    pub revision: ObjectId,
+    /// The rebased revision it was replayed onto.
+    pub onto: ObjectId,
+    pub result: MergeResult,
+}
+
+/// Move the changes in `stack`, given as `(change, head)` pairs bottom
+/// first, from `base` onto `onto`.
+///
+/// To resume after resolving a conflict, call again with the stack from the
+/// stopped change on, the conflicting revision as `base` and its resolved
+/// replacement as `onto`.
+pub fn rebase_stack(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    stack: &[(ChangeId, ObjectId)],
+    base: &ObjectId,
+    onto: &ObjectId,
+) -> Result<StackRebase, MergeError> {
+    let mut rebased = Vec::with_capacity(stack.len());
+    let mut old_base = *base;
+    let mut tip = *onto;
+    let mut reused_resolutions = Vec::new();
+
+    for (index, (change, head)) in stack.iter().enumerate() {
+        for id in revisions_between(store, &old_base, head)? {
+            let Object::Revision(rev) = store.load_object(&id)? else {
+                continue;
+            };
+            // Already on the new base; keep it rather than rewrite it.
+            if rev.parents == [tip] {
+                tip = id;
+                continue;
+            }
+            let mut result = cherry_pick(store, registry, &tip, &id, &rev.author, &rev.summary)?;
+            reused_resolutions.append(&mut result.reused_resolutions);
+            if !result.conflicts.is_empty() {
+                return Ok(StackRebase {
+                    rebased,
+                    stopped: Some(StackStop {
+                        index,
+                        change: *change,
+                        revision: id,
+                        onto: tip,
+                        result,
+                    }),
+                    reused_resolutions,
+                });
+            }
+            result.revision.change_id = rev.change_id;
+            result.revision.created_at_ms = rev.created_at_ms;
+            tip = store.store_object(&Object::Revision(result.revision))?;
+        }
+        rebased.push((*change, tip));
+        old_base = *head;
+    }
+
+    Ok(StackRebase {
+        rebased,
+        stopped: None,
+        reused_resolutions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collect::revision_tree;
+    use crate::test_support::{commit, first_file};
+
+    #[test]
+    fn restacks_changes_and_stops_at_conflict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let registry = CodecRegistry::default();
+
+        let base = commit(&store, &registry, None, "1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let lower = commit(&store, &registry, Some(base), "1\n2\nc\n4\n5\n6\n7\n8\n9\n");
+        let upper = commit(
+            &store,
+            &registry,
+            Some(lower),
+            "1\n2\nc\n4\n5\n6\ng\n8\n9\n",
+        );
+        let (first, second) = (ChangeId::new(), ChangeId::new());
+        let stack = [(first, lower), (second, upper)];
+
+        let onto = commit(&store, &registry, Some(base), "a\n2\n3\n4\n5\n6\n7\n8\n9\n");
+        let done = rebase_stack(&store, &registry, &stack, &base, &onto).unwrap();
+        assert!(done.stopped.is_none());
+        assert_eq!(done.rebased.len(), 2);
+        let (_, new_upper) = done.rebased[1];
+        assert_eq!(
+            first_file(&store, &revision_tree(&store, &new_upper).unwrap().unwrap()),
+            "a\n2\nc\n4\n5\n6\ng\n8\n9\n"
+        );
+
+        let clash = commit(&store, &registry, Some(base), "1\n2\n3\n4\n5\n6\nG\n8\n9\n");
+        let stopped = rebase_stack(&store, &registry, &stack, &base, &clash).unwrap();
+        assert_eq!(stopped.rebased.len(), 1);
+        let stop = stopped.stopped.unwrap();
+        assert_eq!((stop.index, stop.change, stop.revision), (1, second, upper));
+        assert_eq!(stop.onto, stopped.rebased[0].1);
+    }
+}
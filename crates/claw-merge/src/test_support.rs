@@ -0,0 +1,67 @@
+//! Fixtures shared by the merge tests.
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{Blob, FileMode, Revision, Tree, TreeEntry};
+use claw_patch::CodecRegistry;
+use claw_store::ClawStore;
+
+use crate::collect::{revision_tree, tree_diff_patches};
+
+/// A revision whose tree holds `a.txt` with `content`, with patches from
+/// its parent's tree.
+pub(crate) fn commit(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    parent: Option<ObjectId>,
+    content: &str,
+) -> ObjectId {
+    commit_files(store, registry, parent, &[("a.txt", content)])
+}
+
+/// A revision whose flat tree holds `files`, with patches from its
+/// parent's tree.
+pub(crate) fn commit_files(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    parent: Option<ObjectId>,
+    files: &[(&str, &str)],
+) -> ObjectId {
+    let entries = files
+        .iter()
+        .map(|(name, data)| TreeEntry {
+            name: name.to_string(),
+            mode: FileMode::Regular,
+            object_id: store
+                .store_object(&Object::Blob(Blob {
+                    data: data.as_bytes().to_vec(),
+                    media_type: None,
+                }))
+                .unwrap(),
+        })
+        .collect();
+    let tree = store.store_object(&Object::Tree(Tree { entries })).unwrap();
+    let parent_tree = parent.and_then(|p| revision_tree(store, &p).unwrap());
+    let patches = tree_diff_patches(store, registry, parent_tree.as_ref(), Some(&tree)).unwrap();
+    let rev = Revision {
+        change_id: None,
+        parents: parent.into_iter().collect(),
+        patches,
+        snapshot_base: None,
+        tree: Some(tree),
+        capsule_id: None,
+        author: "test".to_string(),
+        created_at_ms: 0,
+        summary: files.iter().map(|(_, data)| *data).collect(),
+        policy_evidence: vec![],
+    };
+    store.store_object(&Object::Revision(rev)).unwrap()
+}
+
+/// The content of the first file in `tree`.
+pub(crate) fn first_file(store: &ClawStore, tree: &ObjectId) -> String {
+    let Object::Tree(tree) = store.load_object(tree).unwrap() else {
+        panic!("expected a tree");
+    };
+    String::from_utf8(store.read_blob(&tree.entries[0].object_id).unwrap()).unwrap()
+}
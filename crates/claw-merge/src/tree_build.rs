@@ -2,10 +2,11 @@ use std::collections::BTreeMap;
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
-use claw_core::types::{Blob, FileMode, Patch, Tree, TreeEntry};
+use claw_core::types::{FileMode, Patch, Tree, TreeEntry};
 use claw_patch::CodecRegistry;
-use claw_store::ClawStore;
+use claw_store::{ClawStore, StoreError};
 
+use crate::emit::moved_from;
 use crate::MergeError;
 
 /// Build a merged tree from base tree + merged patches.
@@ -27,6 +28,9 @@ pub fn build_merged_tree(
     if let Some(base_id) = base_tree_id {
         flatten_tree(store, base_id, "", &mut file_map)?;
     }
+    let base_map = file_map.clone();
+    let left_map = flatten_side(store, left_tree_id)?;
+    let right_map = flatten_side(store, right_tree_id)?;
 
     // Collect patches by target_path and apply them in order
     let mut patches_by_path: BTreeMap<String, Vec<&Patch>> = BTreeMap::new();
@@ -51,10 +55,10 @@ pub fn build_merged_tree(
         let codec_id = &patches[0].codec_id;
         let codec = _registry.get(codec_id)?;
 
-        let base_content = file_map
-            .get(path)
-            .map(|(data, _)| data.clone())
-            .unwrap_or_default();
+        let base_content = match file_map.get(path) {
+            Some((data, _)) => data.clone(),
+            None => moved_from(store, patches.first().copied())?,
+        };
 
         let mut content = base_content;
         for patch in patches {
@@ -74,38 +78,80 @@ pub fn build_merged_tree(
         file_map.insert(path.clone(), (content, mode));
     }
 
+    // Drop what either side deleted. Had the other side changed it, that
+    // would have been a delete/modify conflict.
+    for path in base_map.keys() {
+        if !left_map.contains_key(path) || !right_map.contains_key(path) {
+            file_map.remove(path);
+        }
+    }
+
+    // Take a mode change from whichever side made it
+    for (path, (_, mode)) in file_map.iter_mut() {
+        let base_mode = base_map.get(path).map(|(_, m)| *m);
+        let changed = [left_map.get(path), right_map.get(path)]
+            .into_iter()
+            .flatten()
+            .map(|(_, m)| *m)
+            .find(|m| Some(*m) != base_mode);
+        if let Some(changed) = changed {
+            *mode = changed;
+        }
+    }
+
     // Also incorporate files from left and right trees that had no patches
     // (i.e. files that exist in one side but not in the base)
-    add_unique_files(store, left_tree_id, base_tree_id, &mut file_map)?;
-    add_unique_files(store, right_tree_id, base_tree_id, &mut file_map)?;
+    add_unique_files(&left_map, &base_map, &mut file_map);
+    add_unique_files(&right_map, &base_map, &mut file_map);
 
     // Build tree from flat file map
-    build_tree_from_flat(store, &file_map)
+    build_tree_from_flat(store, "", &file_map)
 }
 
-fn add_unique_files(
+/// `tree` with everything at or under each of `paths` taken from `from`
+/// instead.
+pub(crate) fn overlay_paths(
     store: &ClawStore,
-    tree_id: Option<&ObjectId>,
-    base_tree_id: Option<&ObjectId>,
-    file_map: &mut BTreeMap<String, (Vec<u8>, FileMode)>,
-) -> Result<(), MergeError> {
-    let Some(tid) = tree_id else {
-        return Ok(());
+    tree: Option<&ObjectId>,
+    from: Option<&ObjectId>,
+    paths: &[String],
+) -> Result<ObjectId, MergeError> {
+    let covered = |file: &str| {
+        paths
+            .iter()
+            .any(|p| file == p || file.starts_with(&format!("{p}/")))
     };
-    let mut side_map: BTreeMap<String, (Vec<u8>, FileMode)> = BTreeMap::new();
-    flatten_tree(store, tid, "", &mut side_map)?;
-
-    let mut base_map: BTreeMap<String, (Vec<u8>, FileMode)> = BTreeMap::new();
-    if let Some(bid) = base_tree_id {
-        flatten_tree(store, bid, "", &mut base_map)?;
+    let mut file_map = flatten_side(store, tree)?;
+    file_map.retain(|file, _| !covered(file));
+    for (file, entry) in flatten_side(store, from)? {
+        if covered(&file) {
+            file_map.insert(file, entry);
+        }
     }
+    build_tree_from_flat(store, "", &file_map)
+}
 
-    for (path, (data, mode)) in side_map {
-        if !base_map.contains_key(&path) && !file_map.contains_key(&path) {
-            file_map.insert(path, (data, mode));
+fn add_unique_files(
+    side_map: &BTreeMap<String, (Vec<u8>, FileMode)>,
+    base_map: &BTreeMap<String, (Vec<u8>, FileMode)>,
+    file_map: &mut BTreeMap<String, (Vec<u8>, FileMode)>,
+) {
+    for (path, entry) in side_map {
+        if !base_map.contains_key(path) && !file_map.contains_key(path) {
+            file_map.insert(path.clone(), entry.clone());
         }
     }
-    Ok(())
+}
+
+fn flatten_side(
+    store: &ClawStore,
+    tree_id: Option<&ObjectId>,
+) -> Result<BTreeMap<String, (Vec<u8>, FileMode)>, MergeError> {
+    let mut side_map = BTreeMap::new();
+    if let Some(tid) = tree_id {
+        flatten_tree(store, tid, "", &mut side_map)?;
+    }
+    Ok(side_map)
 }
 
 fn flatten_tree(
@@ -131,12 +177,13 @@ fn flatten_tree(
             FileMode::Directory => {
                 flatten_tree(store, &entry.object_id, &path, out)?;
             }
-            _ => {
-                let blob_obj = store.load_object(&entry.object_id)?;
-                if let Object::Blob(b) = blob_obj {
-                    out.insert(path, (b.data, entry.mode));
+            _ => match store.read_blob(&entry.object_id) {
+                Ok(data) => {
+                    out.insert(path, (data, entry.mode));
                 }
-            }
+                Err(StoreError::NotABlob(_)) => {}
+                Err(e) => return Err(e.into()),
+            },
         }
     }
     Ok(())
@@ -144,6 +191,7 @@ fn flatten_tree(
 
 fn build_tree_from_flat(
     store: &ClawStore,
+    prefix: &str,
     file_map: &BTreeMap<String, (Vec<u8>, FileMode)>,
 ) -> Result<ObjectId, MergeError> {
     // Group by first path component
@@ -165,7 +213,7 @@ fn build_tree_from_flat(
 
     // Create subtrees
     for (name, sub_map) in &children {
-        let sub_tree_id = build_tree_from_flat(store, sub_map)?;
+        let sub_tree_id = build_tree_from_flat(store, &join_path(prefix, name), sub_map)?;
         entries.push(TreeEntry {
             name: name.clone(),
             mode: FileMode::Directory,
@@ -175,11 +223,7 @@ fn build_tree_from_flat(
 
     // Create blobs
     for (name, (data, mode)) in &direct_files {
-        let blob = Blob {
-            data: data.clone(),
-            media_type: None,
-        };
-        let blob_id = store.store_object(&Object::Blob(blob))?;
+        let blob_id = store.store_blob_for_path(&join_path(prefix, name), data.as_slice())?;
         entries.push(TreeEntry {
             name: name.clone(),
             mode: *mode,
@@ -192,3 +236,11 @@ fn build_tree_from_flat(
     let id = store.store_object(&Object::Tree(tree))?;
     Ok(id)
 }
+
+fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}/{name}")
+    }
+}
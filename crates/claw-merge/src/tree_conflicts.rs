@@ -0,0 +1,218 @@
+//! Conflicts in the shape of the tree rather than in file contents.
+//!
+//! Patches only describe content, so these are found by comparing the
+//! entries of the base, left and right trees directly: a file one side
+//! deleted and the other modified, disagreeing modes, symlinks changed on
+//! both sides, and a path that is a file on one side and a directory on the
+//! other.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{ConflictKind, FileMode};
+use claw_store::ClawStore;
+
+use crate::MergeError;
+
+/// Blob id and mode of every file in a tree, by path.
+pub(crate) type Entries = BTreeMap<String, (ObjectId, FileMode)>;
+
+/// The tree conflicts between two sides, by path, in path order. Content
+/// merges of a conflicting path, or of anything under it, must be skipped.
+pub fn tree_conflicts(
+    store: &ClawStore,
+    base_tree: Option<&ObjectId>,
+    left_tree: Option<&ObjectId>,
+    right_tree: Option<&ObjectId>,
+) -> Result<Vec<(String, ConflictKind)>, MergeError> {
+    let [base, left, right] = [base_tree, left_tree, right_tree].map(|tree| {
+        let mut entries = Entries::new();
+        match tree {
+            Some(tree) => flatten_entries(store, tree, "", &mut entries).map(|_| entries),
+            None => Ok(entries),
+        }
+    });
+    Ok(entry_conflicts(&base?, &left?, &right?))
+}
+
+/// [`tree_conflicts`] between already flattened trees.
+pub(crate) fn entry_conflicts(
+    base: &Entries,
+    left: &Entries,
+    right: &Entries,
+) -> Vec<(String, ConflictKind)> {
+    let mut conflicts = BTreeMap::new();
+    // A file one side changed where the other changed anything below it.
+    for (side, other) in [(left, right), (right, left)] {
+        for (path, entry) in side {
+            if base.get(path) != Some(entry)
+                && subtree(other, path) != subtree(base, path)
+                && !subtree(other, path).is_empty()
+            {
+                conflicts.insert(path.clone(), ConflictKind::FileDirectory);
+            }
+        }
+    }
+
+    let paths: BTreeSet<&String> = base.keys().chain(left.keys()).chain(right.keys()).collect();
+    for path in paths {
+        if conflicts
+            .keys()
+            .any(|dir| path == dir || path.starts_with(&format!("{dir}/")))
+        {
+            continue;
+        }
+        let (b, l, r) = (base.get(path), left.get(path), right.get(path));
+        if l == r || l == b || r == b {
+            continue;
+        }
+        let kind = match (l, r) {
+            (Some((_, left_mode)), Some((_, right_mode))) => {
+                let modes = [
+                    b.map(|(_, mode)| *mode),
+                    Some(*left_mode),
+                    Some(*right_mode),
+                ];
+                if modes.contains(&Some(FileMode::Symlink)) {
+                    ConflictKind::Symlink
+                } else if left_mode != right_mode {
+                    ConflictKind::Mode
+                } else {
+                    // Same kind of file with different contents; the patches
+                    // merge those.
+                    continue;
+                }
+            }
+            _ => ConflictKind::DeleteModify,
+        };
+        conflicts.insert(path.clone(), kind);
+    }
+    conflicts.into_iter().collect()
+}
+
+/// The entries below `dir`.
+fn subtree<'a>(entries: &'a Entries, dir: &str) -> Vec<(&'a String, &'a (ObjectId, FileMode))> {
+    let prefix = format!("{dir}/");
+    entries
+        .range(prefix.clone()..)
+        .take_while(|(path, _)| path.starts_with(&prefix))
+        .collect()
+}
+
+pub(crate) fn flatten_entries(
+    store: &ClawStore,
+    tree_id: &ObjectId,
+    prefix: &str,
+    out: &mut Entries,
+) -> Result<(), MergeError> {
+    let Object::Tree(tree) = store.load_object(tree_id)? else {
+        return Ok(());
+    };
+    for entry in tree.entries {
+        let path = if prefix.is_empty() {
+            entry.name
+        } else {
+            format!("{prefix}/{}", entry.name)
+        };
+        match entry.mode {
+            FileMode::Directory => flatten_entries(store, &entry.object_id, &path, out)?,
+            mode => {
+                out.insert(path, (entry.object_id, mode));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::types::{Blob, Tree, TreeEntry};
+
+    fn tree(store: &ClawStore, files: &[(&str, &str, FileMode)]) -> ObjectId {
+        let mut dirs: BTreeMap<&str, Vec<(&str, &str, FileMode)>> = BTreeMap::new();
+        let mut entries = Vec::new();
+        for &(path, data, mode) in files {
+            if let Some((dir, rest)) = path.split_once('/') {
+                dirs.entry(dir).or_default().push((rest, data, mode));
+                continue;
+            }
+            let blob = store
+                .store_object(&Object::Blob(Blob {
+                    data: data.as_bytes().to_vec(),
+                    media_type: None,
+                }))
+                .unwrap();
+            entries.push(TreeEntry {
+                name: path.to_string(),
+                mode,
+                object_id: blob,
+            });
+        }
+        for (dir, files) in dirs {
+            entries.push(TreeEntry {
+                name: dir.to_string(),
+                mode: FileMode::Directory,
+                object_id: tree(store, &files),
+            });
+        }
+        store.store_object(&Object::Tree(Tree { entries })).unwrap()
+    }
+
+    #[test]
+    fn finds_each_kind_of_tree_conflict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        use FileMode::{Executable, Regular, Symlink};
+
+        let base = tree(
+            &store,
+            &[
+                ("deleted.txt", "a", Regular),
+                ("run.sh", "echo", Regular),
+                ("link", "target", Symlink),
+                ("dir", "file", Regular),
+                ("untouched.txt", "a", Regular),
+                ("edited.txt", "a", Regular),
+            ],
+        );
+        let left = tree(
+            &store,
+            &[
+                ("run.sh", "echo", Executable),
+                ("link", "elsewhere", Symlink),
+                ("dir", "file changed", Regular),
+                ("untouched.txt", "a", Regular),
+                ("edited.txt", "left", Regular),
+            ],
+        );
+        let right = tree(
+            &store,
+            &[
+                ("deleted.txt", "b", Regular),
+                ("run.sh", "echo hi", Regular),
+                ("link", "other", Symlink),
+                ("dir/inner.txt", "x", Regular),
+                ("untouched.txt", "a", Regular),
+                ("edited.txt", "right", Regular),
+            ],
+        );
+
+        let conflicts = tree_conflicts(&store, Some(&base), Some(&left), Some(&right)).unwrap();
+        assert_eq!(
+            conflicts,
+            vec![
+                ("deleted.txt".to_string(), ConflictKind::DeleteModify),
+                ("dir".to_string(), ConflictKind::FileDirectory),
+                ("link".to_string(), ConflictKind::Symlink),
+                ("run.sh".to_string(), ConflictKind::Mode),
+            ]
+        );
+        assert!(
+            tree_conflicts(&store, Some(&base), Some(&left), Some(&base))
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -18,4 +18,21 @@ pub trait Codec: Send + Sync {
     ) -> Result<(Vec<PatchOp>, Vec<PatchOp>), PatchError>;
 
     fn merge3(&self, base: &[u8], left: &[u8], right: &[u8]) -> Result<Vec<u8>, PatchError>;
+
+    /// How alike two versions are, from 0 to 100, judged by how much of
+    /// either side the diff between them touches.
+    fn similarity(&self, old: &[u8], new: &[u8]) -> Result<u8, PatchError> {
+        let total = old.len() + new.len();
+        if total == 0 {
+            return Ok(100);
+        }
+        let changed: usize = self
+            .diff(old, new)?
+            .iter()
+            .map(|op| {
+                op.old_data.as_ref().map_or(0, Vec::len) + op.new_data.as_ref().map_or(0, Vec::len)
+            })
+            .sum();
+        Ok((100 * total.saturating_sub(changed) / total) as u8)
+    }
 }
@@ -1,10 +1,45 @@
+use std::collections::{HashMap, HashSet};
+
 use claw_core::types::PatchOp;
 use serde_json::Value;
+use similar::{capture_diff_slices, Algorithm, DiffOp};
 
 use crate::codec::Codec;
 use crate::PatchError;
 
-pub struct JsonTreeCodec;
+/// Structural diff and merge of JSON documents.
+///
+/// Ops address values by path, `/deps/serde` or `/items/2`. Arrays are
+/// diffed element-wise: by the longest common subsequence of their
+/// elements, or, when every element on both sides is an object with a
+/// unique scalar value under one of the identity keys, by that key. Edits
+/// inside, moves and deletes of a matched element then address it by
+/// identity, as in `/items/[id=7]/price`, so they stay valid whatever
+/// happens around it; inserts address indices, which `commute` adjusts.
+/// There are no identity keys unless configured.
+pub struct JsonTreeCodec {
+    identity_keys: Vec<String>,
+}
+
+impl JsonTreeCodec {
+    /// A codec matching array elements by the given keys, tried in order;
+    /// with none, arrays are always diffed by LCS.
+    pub fn with_identity_keys<I, S>(keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            identity_keys: keys.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Default for JsonTreeCodec {
+    fn default() -> Self {
+        Self::with_identity_keys(Vec::<String>::new())
+    }
+}
 
 impl Codec for JsonTreeCodec {
     fn id(&self) -> &str {
@@ -18,7 +53,7 @@ impl Codec for JsonTreeCodec {
             serde_json::from_slice(new).map_err(|e| PatchError::InvalidJson(e.to_string()))?;
 
         let mut ops = Vec::new();
-        diff_values("", &old_val, &new_val, &mut ops);
+        diff_values(&self.identity_keys, "", &old_val, &new_val, &mut ops);
         Ok(ops)
     }
 
@@ -44,13 +79,26 @@ impl Codec for JsonTreeCodec {
                     new_data: None,
                     context_hash: None,
                 },
-                "delete" => PatchOp {
-                    address: op.address.clone(),
-                    op_type: "insert".to_string(),
-                    old_data: None,
-                    new_data: op.old_data.clone(),
-                    context_hash: None,
-                },
+                "delete" => {
+                    // A delete by identity is undone at the index it recorded.
+                    let mut segments = split_address(&op.address);
+                    let at = op
+                        .new_data
+                        .as_deref()
+                        .and_then(|d| std::str::from_utf8(d).ok());
+                    if let (Some(last), Some(at)) = (segments.last_mut(), at) {
+                        if parse_identity(last).is_some() {
+                            *last = at;
+                        }
+                    }
+                    PatchOp {
+                        address: join_address(&segments),
+                        op_type: "insert".to_string(),
+                        old_data: None,
+                        new_data: op.old_data.clone(),
+                        context_hash: None,
+                    }
+                }
                 "replace" => PatchOp {
                     address: op.address.clone(),
                     op_type: "replace".to_string(),
@@ -58,6 +106,26 @@ impl Codec for JsonTreeCodec {
                     new_data: op.old_data.clone(),
                     context_hash: None,
                 },
+                "move" => {
+                    // An element addressed by index is found at its new one.
+                    let mut segments = split_address(&op.address);
+                    let to = op
+                        .new_data
+                        .as_deref()
+                        .and_then(|d| std::str::from_utf8(d).ok());
+                    if let (Some(last), Some(to)) = (segments.last_mut(), to) {
+                        if last.parse::<usize>().is_ok() {
+                            *last = to;
+                        }
+                    }
+                    PatchOp {
+                        address: join_address(&segments),
+                        op_type: "move".to_string(),
+                        old_data: op.new_data.clone(),
+                        new_data: op.old_data.clone(),
+                        context_hash: None,
+                    }
+                }
                 _ => op.clone(),
             })
             .collect();
@@ -70,25 +138,20 @@ impl Codec for JsonTreeCodec {
         left: &[PatchOp],
         right: &[PatchOp],
     ) -> Result<(Vec<PatchOp>, Vec<PatchOp>), PatchError> {
-        // JSON tree commutation: independent paths commute, same path = conflict
-        for l in left {
-            for r in right {
-                let rel = path_relationship(&l.address, &r.address);
-                match rel {
-                    PathRelation::Equal | PathRelation::AncestorOf | PathRelation::DescendantOf => {
-                        return Err(PatchError::CommuteFailed);
-                    }
-                    PathRelation::Independent => {}
-                    PathRelation::SiblingArrayElements => {
-                        // Array index adjustment would be needed for full impl
-                        // For MVP, treat as non-commutable if they affect the same array
-                        return Err(PatchError::CommuteFailed);
-                    }
-                }
+        // Operational transform: each right op is moved past every left op
+        // and each left op past every right op, adjusting array indices.
+        let mut new_left = left.to_vec();
+        let mut new_right = Vec::with_capacity(right.len());
+        for r in right {
+            let mut r = r.clone();
+            for l in &mut new_left {
+                let r_after = transform(&r, l, false)?;
+                *l = transform(l, &r, true)?;
+                r = r_after;
             }
+            new_right.push(r);
         }
-        // Independent paths: they commute as-is
-        Ok((right.to_vec(), left.to_vec()))
+        Ok((new_right, new_left))
     }
 
     fn merge3(&self, base: &[u8], left: &[u8], right: &[u8]) -> Result<Vec<u8>, PatchError> {
@@ -99,12 +162,12 @@ impl Codec for JsonTreeCodec {
         let right_val: Value =
             serde_json::from_slice(right).map_err(|e| PatchError::InvalidJson(e.to_string()))?;
 
-        let merged = merge3_values(&base_val, &left_val, &right_val)?;
+        let merged = merge3_values(&self.identity_keys, &base_val, &left_val, &right_val)?;
         serde_json::to_vec_pretty(&merged).map_err(|e| PatchError::Merge3Failed(e.to_string()))
     }
 }
 
-fn diff_values(path: &str, old: &Value, new: &Value, ops: &mut Vec<PatchOp>) {
+fn diff_values(keys: &[String], path: &str, old: &Value, new: &Value, ops: &mut Vec<PatchOp>) {
     if old == new {
         return;
     }
@@ -141,25 +204,14 @@ fn diff_values(path: &str, old: &Value, new: &Value, ops: &mut Vec<PatchOp>) {
             for key in old_map.keys() {
                 if let Some(new_val) = new_map.get(key) {
                     let child_path = format!("{path}/{key}");
-                    diff_values(&child_path, &old_map[key], new_val, ops);
+                    diff_values(keys, &child_path, &old_map[key], new_val, ops);
                 }
             }
         }
         (Value::Array(old_arr), Value::Array(new_arr)) => {
-            // Simple array diff: if lengths differ or elements differ, replace the whole array
-            if old_arr.len() != new_arr.len() {
-                ops.push(PatchOp {
-                    address: path.to_string(),
-                    op_type: "replace".to_string(),
-                    old_data: Some(serde_json::to_vec(old).unwrap()),
-                    new_data: Some(serde_json::to_vec(new).unwrap()),
-                    context_hash: None,
-                });
-            } else {
-                for (i, (o, n)) in old_arr.iter().zip(new_arr.iter()).enumerate() {
-                    let child_path = format!("{path}/{i}");
-                    diff_values(&child_path, o, n, ops);
-                }
+            match identity_key(keys, &[old_arr, new_arr]) {
+                Some(key) => diff_keyed_array(keys, path, key, old_arr, new_arr, ops),
+                None => diff_array(keys, path, old_arr, new_arr, ops),
             }
         }
         _ => {
@@ -175,8 +227,203 @@ fn diff_values(path: &str, old: &Value, new: &Value, ops: &mut Vec<PatchOp>) {
     }
 }
 
+fn patch_op(address: String, op_type: &str, old: Option<&Value>, new: Option<&Value>) -> PatchOp {
+    PatchOp {
+        address,
+        op_type: op_type.to_string(),
+        old_data: old.map(|v| serde_json::to_vec(v).unwrap()),
+        new_data: new.map(|v| serde_json::to_vec(v).unwrap()),
+        context_hash: None,
+    }
+}
+
+/// Diff two arrays by the LCS of their elements. Elements replaced
+/// one-for-one are diffed in place. Indices are those of the array as
+/// the preceding ops leave it.
+fn diff_array(keys: &[String], path: &str, old: &[Value], new: &[Value], ops: &mut Vec<PatchOp>) {
+    let old_texts: Vec<String> = old.iter().map(Value::to_string).collect();
+    let new_texts: Vec<String> = new.iter().map(Value::to_string).collect();
+    let mut pos = 0;
+    for change in capture_diff_slices(Algorithm::Myers, &old_texts, &new_texts) {
+        let (old_range, new_range) = (change.old_range(), change.new_range());
+        if let DiffOp::Equal { len, .. } = change {
+            pos += len;
+            continue;
+        }
+        let paired = old_range.len().min(new_range.len());
+        for k in 0..paired {
+            let child_path = format!("{path}/{pos}");
+            diff_values(
+                keys,
+                &child_path,
+                &old[old_range.start + k],
+                &new[new_range.start + k],
+                ops,
+            );
+            pos += 1;
+        }
+        for value in &old[old_range.start + paired..old_range.end] {
+            ops.push(patch_op(
+                format!("{path}/{pos}"),
+                "delete",
+                Some(value),
+                None,
+            ));
+        }
+        for value in &new[new_range.start + paired..new_range.end] {
+            ops.push(patch_op(
+                format!("{path}/{pos}"),
+                "insert",
+                None,
+                Some(value),
+            ));
+            pos += 1;
+        }
+    }
+}
+
+/// Diff two arrays whose elements are matched by `key`: deletes, then
+/// moves of the elements outside the longest run kept in order, then
+/// inserts, then edits inside the elements both sides have.
+fn diff_keyed_array(
+    keys: &[String],
+    path: &str,
+    key: &str,
+    old: &[Value],
+    new: &[Value],
+    ops: &mut Vec<PatchOp>,
+) {
+    let id = |value: &Value| value[key].to_string();
+    let new_ids: Vec<String> = new.iter().map(id).collect();
+    let mut current: Vec<String> = old.iter().map(id).collect();
+
+    // Deletes address the element by identity and record the index it had,
+    // from the end so that index is still the original one, for `invert`.
+    for (i, value) in old.iter().enumerate().rev() {
+        if !new_ids.contains(&current[i]) {
+            ops.push(patch_op(
+                format!("{path}/{}", identity_segment(key, &current[i])),
+                "delete",
+                Some(value),
+                Some(&Value::from(i)),
+            ));
+            current.remove(i);
+        }
+    }
+
+    // The kept elements in their new order; those outside the LCS with the
+    // old order move, each right after its new predecessor.
+    let target: Vec<String> = new_ids
+        .iter()
+        .filter(|id| current.contains(id))
+        .cloned()
+        .collect();
+    let mut in_place = vec![false; target.len()];
+    for change in capture_diff_slices(Algorithm::Myers, &current, &target) {
+        if let DiffOp::Equal { new_index, len, .. } = change {
+            in_place[new_index..new_index + len].fill(true);
+        }
+    }
+    for (t, moved) in target.iter().enumerate() {
+        if in_place[t] {
+            continue;
+        }
+        let from = current.iter().position(|id| id == moved).unwrap();
+        current.remove(from);
+        let to = match t {
+            0 => 0,
+            _ => current.iter().position(|id| *id == target[t - 1]).unwrap() + 1,
+        };
+        current.insert(to, moved.clone());
+        ops.push(patch_op(
+            format!("{path}/{}", identity_segment(key, moved)),
+            "move",
+            Some(&Value::from(from)),
+            Some(&Value::from(to)),
+        ));
+    }
+
+    for (i, value) in new.iter().enumerate() {
+        if !current.contains(&new_ids[i]) {
+            ops.push(patch_op(format!("{path}/{i}"), "insert", None, Some(value)));
+            current.insert(i, new_ids[i].clone());
+        }
+    }
+
+    let new_by_id: HashMap<&String, &Value> = new_ids.iter().zip(new).collect();
+    for old_value in old {
+        let old_id = id(old_value);
+        if let Some(new_value) = new_by_id.get(&old_id) {
+            let child_path = format!("{path}/{}", identity_segment(key, &old_id));
+            diff_values(keys, &child_path, old_value, new_value, ops);
+        }
+    }
+}
+
+/// The first of `keys` under which every element of every array is an
+/// object with a scalar value, unique within its array.
+fn identity_key<'a>(keys: &'a [String], arrays: &[&[Value]]) -> Option<&'a str> {
+    if arrays.iter().all(|a| a.is_empty()) {
+        return None;
+    }
+    keys.iter().map(String::as_str).find(|key| {
+        arrays.iter().all(|array| {
+            let mut seen = HashSet::new();
+            array.iter().all(|element| match element.get(key) {
+                Some(v @ (Value::String(_) | Value::Number(_) | Value::Bool(_))) => {
+                    seen.insert(v.to_string())
+                }
+                _ => false,
+            })
+        })
+    })
+}
+
+/// Address segment of the element whose `key` has the JSON text `id`,
+/// with `~` and `/` escaped as in JSON Pointer.
+fn identity_segment(key: &str, id: &str) -> String {
+    format!("[{key}={id}]")
+        .replace('~', "~0")
+        .replace('/', "~1")
+}
+
+/// Key and value of an identity segment.
+fn parse_identity(segment: &str) -> Option<(String, Value)> {
+    let inner = segment.strip_prefix('[')?.strip_suffix(']')?;
+    let inner = inner.replace("~1", "/").replace("~0", "~");
+    let (key, value) = inner.split_once('=')?;
+    Some((key.to_string(), serde_json::from_str(value).ok()?))
+}
+
+fn split_address(address: &str) -> Vec<&str> {
+    address.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+fn join_address(segments: &[&str]) -> String {
+    segments.iter().map(|s| format!("/{s}")).collect()
+}
+
+/// Index of the element a segment addresses in `arr`.
+fn element_index(arr: &[Value], segment: &str) -> Result<usize, PatchError> {
+    if let Some((key, value)) = parse_identity(segment) {
+        return arr
+            .iter()
+            .position(|e| e.get(&key) == Some(&value))
+            .ok_or_else(|| PatchError::ApplyFailed(format!("no element {segment}")));
+    }
+    let idx: usize = segment
+        .parse()
+        .map_err(|_| PatchError::ApplyFailed(format!("invalid index: {segment}")))?;
+    if idx >= arr.len() {
+        return Err(PatchError::ApplyFailed(format!(
+            "index out of bounds: {idx}"
+        )));
+    }
+    Ok(idx)
+}
+
 fn apply_op(val: &mut Value, op: &PatchOp) -> Result<(), PatchError> {
-    let parts: Vec<&str> = op.address.split('/').filter(|s| !s.is_empty()).collect();
+    let parts = split_address(&op.address);
 
     if parts.is_empty() {
         // Root replacement
@@ -242,12 +489,7 @@ fn apply_op(val: &mut Value, op: &PatchOp) -> Result<(), PatchError> {
                 map.remove(last_key);
             }
             Value::Array(arr) => {
-                let idx: usize = last_key
-                    .parse()
-                    .map_err(|_| PatchError::ApplyFailed("invalid array index".into()))?;
-                if idx >= arr.len() {
-                    return Err(PatchError::ApplyFailed("array index out of bounds".into()));
-                }
+                let idx = element_index(arr, last_key)?;
                 arr.remove(idx);
             }
             _ => {
@@ -256,6 +498,23 @@ fn apply_op(val: &mut Value, op: &PatchOp) -> Result<(), PatchError> {
                 ))
             }
         },
+        "move" => {
+            let Value::Array(arr) = current else {
+                return Err(PatchError::ApplyFailed("cannot move in non-array".into()));
+            };
+            let to: usize = op
+                .new_data
+                .as_deref()
+                .and_then(|d| serde_json::from_slice(d).ok())
+                .ok_or_else(|| PatchError::ApplyFailed("move missing target index".into()))?;
+            let idx = element_index(arr, last_key)?;
+            let element = arr.remove(idx);
+            if to > arr.len() {
+                arr.insert(idx, element);
+                return Err(PatchError::ApplyFailed("array index out of bounds".into()));
+            }
+            arr.insert(to, element);
+        }
         "replace" => {
             let new_val: Value = serde_json::from_slice(
                 op.new_data
@@ -269,12 +528,7 @@ fn apply_op(val: &mut Value, op: &PatchOp) -> Result<(), PatchError> {
                     map.insert(last_key.to_string(), new_val);
                 }
                 Value::Array(arr) => {
-                    let idx: usize = last_key
-                        .parse()
-                        .map_err(|_| PatchError::ApplyFailed("invalid array index".into()))?;
-                    if idx >= arr.len() {
-                        return Err(PatchError::ApplyFailed("array index out of bounds".into()));
-                    }
+                    let idx = element_index(arr, last_key)?;
                     arr[idx] = new_val;
                 }
                 _ => {
@@ -296,11 +550,8 @@ fn navigate_mut<'a>(val: &'a mut Value, key: &str) -> Result<&'a mut Value, Patc
             .get_mut(key)
             .ok_or_else(|| PatchError::ApplyFailed(format!("key not found: {key}"))),
         Value::Array(arr) => {
-            let idx: usize = key
-                .parse()
-                .map_err(|_| PatchError::ApplyFailed(format!("invalid index: {key}")))?;
-            arr.get_mut(idx)
-                .ok_or_else(|| PatchError::ApplyFailed(format!("index out of bounds: {idx}")))
+            let idx = element_index(arr, key)?;
+            Ok(&mut arr[idx])
         }
         _ => Err(PatchError::ApplyFailed(format!(
             "cannot navigate into scalar at {key}"
@@ -344,7 +595,104 @@ fn path_relationship(a: &str, b: &str) -> PathRelation {
     PathRelation::Independent
 }
 
-fn merge3_values(base: &Value, left: &Value, right: &Value) -> Result<Value, PatchError> {
+/// Whether `op` inserts, deletes or moves an array element, shifting the
+/// elements after it.
+fn shifts_elements(op: &PatchOp) -> bool {
+    let last = split_address(&op.address).last().copied();
+    matches!(op.op_type.as_str(), "insert" | "delete" | "move")
+        && last.is_some_and(|s| s.parse::<usize>().is_ok() || parse_identity(s).is_some())
+}
+
+/// Rewrite `op` to apply after `against`, both made against the same
+/// document. `op_first` breaks the tie between two inserts at one index.
+fn transform(op: &PatchOp, against: &PatchOp, op_first: bool) -> Result<PatchOp, PatchError> {
+    let ours = split_address(&op.address);
+    let theirs = split_address(&against.address);
+
+    if shifts_elements(against) {
+        let depth = theirs.len() - 1;
+        if ours.len() > depth && ours[..depth] == theirs[..depth] {
+            let segment = ours[depth];
+            let inserts_here = ours.len() == depth + 1 && op.op_type == "insert";
+            let index = segment.parse::<usize>().ok();
+            let shifted = match (against.op_type.as_str(), theirs[depth].parse::<usize>()) {
+                ("insert", Ok(i)) => index.map(|j| {
+                    if j > i || (j == i && !(inserts_here && op_first)) {
+                        j + 1
+                    } else {
+                        j
+                    }
+                }),
+                ("delete", Ok(i)) => match index {
+                    Some(j) if j == i && !inserts_here => return Err(PatchError::CommuteFailed),
+                    Some(j) if j > i => Some(j - 1),
+                    Some(j) => Some(j),
+                    None if identifies(segment, against.old_data.as_deref()) => {
+                        return Err(PatchError::CommuteFailed)
+                    }
+                    None => None,
+                },
+                // A move, or a delete by identity: only edits inside other
+                // elements found by identity are unaffected.
+                (kind, _) => {
+                    let same_level = ours.len() == depth + 1;
+                    if index.is_some()
+                        || (same_level && op.op_type != "replace")
+                        || (kind == "delete" && segment == theirs[depth])
+                    {
+                        return Err(PatchError::CommuteFailed);
+                    }
+                    None
+                }
+            };
+            let mut address = ours.clone();
+            let shifted = shifted.map(|j| j.to_string());
+            if let Some(j) = &shifted {
+                address[depth] = j.as_str();
+            }
+            return Ok(PatchOp {
+                address: join_address(&address),
+                ..op.clone()
+            });
+        }
+    }
+    if shifts_elements(op) {
+        let depth = ours.len() - 1;
+        if theirs.len() > depth && theirs[..depth] == ours[..depth] {
+            // Transforming `against` past `op` adjusts it and finds conflicts.
+            return Ok(op.clone());
+        }
+    }
+
+    // An element one side finds by index and the other by identity may be
+    // the same one.
+    if let Some((a, b)) = ours.iter().zip(&theirs).find(|(a, b)| a != b) {
+        if a.parse::<usize>().is_ok() != b.parse::<usize>().is_ok() {
+            return Err(PatchError::CommuteFailed);
+        }
+    }
+    match path_relationship(&op.address, &against.address) {
+        PathRelation::Equal | PathRelation::AncestorOf | PathRelation::DescendantOf => {
+            Err(PatchError::CommuteFailed)
+        }
+        PathRelation::SiblingArrayElements | PathRelation::Independent => Ok(op.clone()),
+    }
+}
+
+/// Whether the identity segment names the element `data` holds.
+fn identifies(segment: &str, data: Option<&[u8]>) -> bool {
+    let (Some((key, value)), Some(data)) = (parse_identity(segment), data) else {
+        return false;
+    };
+    serde_json::from_slice::<Value>(data).is_ok_and(|element| element.get(&key) == Some(&value))
+}
+
+fn merge3_values(
+    keys: &[String],
+    base: &Value,
+    left: &Value,
+    right: &Value,
+) -> Result<Value, PatchError> {
     if left == right {
         return Ok(left.clone());
     }
@@ -371,7 +719,7 @@ fn merge3_values(base: &Value, left: &Value, right: &Value) -> Result<Value, Pat
 
                 match (b, l, r) {
                     (Some(bv), Some(lv), Some(rv)) => {
-                        merged.insert(key.clone(), merge3_values(bv, lv, rv)?);
+                        merged.insert(key.clone(), merge3_values(keys, bv, lv, rv)?);
                     }
                     (Some(_), Some(_lv), None) => {
                         // Right deleted, left kept or modified
@@ -417,6 +765,9 @@ fn merge3_values(base: &Value, left: &Value, right: &Value) -> Result<Value, Pat
             }
             Ok(Value::Object(merged))
         }
+        (Value::Array(base_arr), Value::Array(left_arr), Value::Array(right_arr)) => {
+            merge3_arrays(keys, base_arr, left_arr, right_arr).map(Value::Array)
+        }
         _ => {
             // Both changed differently - conflict
             Err(PatchError::Merge3Failed(
@@ -426,6 +777,255 @@ fn merge3_values(base: &Value, left: &Value, right: &Value) -> Result<Value, Pat
     }
 }
 
+/// Base elements `start..end` replaced by `values`.
+struct ArrayHunk {
+    start: usize,
+    end: usize,
+    values: Vec<Value>,
+}
+
+/// The changes from `base` to `side`, aligned by LCS of the elements.
+fn array_hunks(base: &[Value], side: &[Value]) -> Vec<ArrayHunk> {
+    let base_texts: Vec<String> = base.iter().map(Value::to_string).collect();
+    let side_texts: Vec<String> = side.iter().map(Value::to_string).collect();
+    let mut hunks = Vec::new();
+    for change in capture_diff_slices(Algorithm::Myers, &base_texts, &side_texts) {
+        let (old_range, new_range) = (change.old_range(), change.new_range());
+        if let DiffOp::Equal { .. } = change {
+            continue;
+        }
+        // As in the diff, elements replaced one-for-one are edits of their
+        // own, and only the surplus is a delete or an insert.
+        let paired = old_range.len().min(new_range.len());
+        for k in 0..paired {
+            hunks.push(ArrayHunk {
+                start: old_range.start + k,
+                end: old_range.start + k + 1,
+                values: vec![side[new_range.start + k].clone()],
+            });
+        }
+        if old_range.len() != new_range.len() {
+            hunks.push(ArrayHunk {
+                start: old_range.start + paired,
+                end: old_range.end,
+                values: side[new_range.start + paired..new_range.end].to_vec(),
+            });
+        }
+    }
+    hunks
+}
+
+/// Three-way merge of arrays. With an identity key, elements are matched
+/// by it; otherwise changes to separate stretches of the base both apply,
+/// elements both sides inserted at the same place go left first, and
+/// elements both sides edited one-for-one are merged in turn.
+fn merge3_arrays(
+    keys: &[String],
+    base: &[Value],
+    left: &[Value],
+    right: &[Value],
+) -> Result<Vec<Value>, PatchError> {
+    if let Some(key) = identity_key(keys, &[base, left, right]) {
+        return merge3_keyed_arrays(keys, key, base, left, right);
+    }
+    let left_hunks = array_hunks(base, left);
+    let right_hunks = array_hunks(base, right);
+
+    let mut merged = Vec::new();
+    let mut pos = 0;
+    let (mut li, mut ri) = (0, 0);
+    loop {
+        let hunk = match (left_hunks.get(li), right_hunks.get(ri)) {
+            (None, None) => break,
+            (Some(l), Some(r)) if l.start < r.end && r.start < l.end => {
+                if (l.start, l.end) != (r.start, r.end) {
+                    return Err(PatchError::Merge3Failed(format!(
+                        "conflict at array elements {}..{}",
+                        l.start.min(r.start),
+                        l.end.max(r.end)
+                    )));
+                }
+                let values = if l.values == r.values {
+                    l.values.clone()
+                } else if l.values.len() == l.end - l.start && r.values.len() == l.values.len() {
+                    (l.start..l.end)
+                        .zip(l.values.iter().zip(&r.values))
+                        .map(|(i, (lv, rv))| merge3_values(keys, &base[i], lv, rv))
+                        .collect::<Result<_, _>>()?
+                } else {
+                    return Err(PatchError::Merge3Failed(format!(
+                        "conflict at array elements {}..{}",
+                        l.start, l.end
+                    )));
+                };
+                li += 1;
+                ri += 1;
+                ArrayHunk {
+                    start: l.start,
+                    end: l.end,
+                    values,
+                }
+            }
+            (Some(l), Some(r)) if l.start == l.end && r.start == r.end && l.start == r.start => {
+                // Both inserted at the same place: left first, once if equal.
+                let mut values = l.values.clone();
+                if r.values != l.values {
+                    values.extend(r.values.iter().cloned());
+                }
+                li += 1;
+                ri += 1;
+                ArrayHunk {
+                    start: l.start,
+                    end: l.end,
+                    values,
+                }
+            }
+            (Some(l), Some(r)) => {
+                // Inserts go before a change starting at the same index.
+                let left_first = (l.start, l.end > l.start) <= (r.start, r.end > r.start);
+                if left_first {
+                    li += 1;
+                    ArrayHunk {
+                        start: l.start,
+                        end: l.end,
+                        values: l.values.clone(),
+                    }
+                } else {
+                    ri += 1;
+                    ArrayHunk {
+                        start: r.start,
+                        end: r.end,
+                        values: r.values.clone(),
+                    }
+                }
+            }
+            (Some(h), None) => {
+                li += 1;
+                ArrayHunk {
+                    start: h.start,
+                    end: h.end,
+                    values: h.values.clone(),
+                }
+            }
+            (None, Some(h)) => {
+                ri += 1;
+                ArrayHunk {
+                    start: h.start,
+                    end: h.end,
+                    values: h.values.clone(),
+                }
+            }
+        };
+        merged.extend_from_slice(&base[pos..hunk.start]);
+        merged.extend(hunk.values);
+        pos = hunk.end;
+    }
+    merged.extend_from_slice(&base[pos..]);
+    Ok(merged)
+}
+
+/// Three-way merge of arrays whose elements are matched by `key`. An
+/// element both sides kept is merged with its base version; deleting an
+/// element the other side edited, both sides adding one with different
+/// content, and both sides reordering differently are conflicts. Added
+/// elements go after the element they follow on their side, left first.
+fn merge3_keyed_arrays(
+    keys: &[String],
+    key: &str,
+    base: &[Value],
+    left: &[Value],
+    right: &[Value],
+) -> Result<Vec<Value>, PatchError> {
+    let id = |value: &Value| value[key].to_string();
+    let by_id = |array: &[Value]| -> HashMap<String, Value> {
+        array.iter().map(|v| (id(v), v.clone())).collect()
+    };
+    let (left_by_id, right_by_id) = (by_id(left), by_id(right));
+    let conflict = |id: &str, what: &str| {
+        PatchError::Merge3Failed(format!(
+            "conflict at element {}: {what}",
+            identity_segment(key, id)
+        ))
+    };
+
+    // Elements of the base that survive, merged.
+    let mut values: HashMap<String, Value> = HashMap::new();
+    for base_value in base {
+        let element = id(base_value);
+        match (left_by_id.get(&element), right_by_id.get(&element)) {
+            (Some(l), Some(r)) => {
+                values.insert(element, merge3_values(keys, base_value, l, r)?);
+            }
+            (Some(l), None) if l != base_value => {
+                return Err(conflict(&element, "left modified, right deleted"))
+            }
+            (None, Some(r)) if r != base_value => {
+                return Err(conflict(&element, "left deleted, right modified"))
+            }
+            _ => {}
+        }
+    }
+
+    // Their order: whichever side reordered them, unless both did.
+    let order = |array: &[Value]| -> Vec<String> {
+        array
+            .iter()
+            .map(id)
+            .filter(|id| values.contains_key(id))
+            .collect()
+    };
+    let (base_order, left_order, right_order) = (order(base), order(left), order(right));
+    let mut merged = if left_order == right_order || left_order == base_order {
+        right_order
+    } else if right_order == base_order {
+        left_order
+    } else {
+        return Err(PatchError::Merge3Failed(
+            "conflict: both sides moved array elements differently".to_string(),
+        ));
+    };
+
+    // Added elements, after the one they follow on their side. One both
+    // sides added is placed by the left and only followed by the right.
+    let base_ids: HashSet<String> = base.iter().map(id).collect();
+    let mut left_added = HashSet::new();
+    for (side, other, is_left) in [(left, &right_by_id, true), (right, &left_by_id, false)] {
+        let mut anchor: Option<String> = None;
+        for value in side {
+            let element = id(value);
+            if values.contains_key(&element) {
+                anchor = Some(element);
+                continue;
+            }
+            if base_ids.contains(&element) {
+                continue;
+            }
+            if other.get(&element).is_some_and(|o| o != value) {
+                return Err(conflict(&element, "both sides added different values"));
+            }
+            let mut at = match &anchor {
+                Some(a) => merged.iter().position(|m| m == a).unwrap() + 1,
+                None => 0,
+            };
+            if is_left {
+                left_added.insert(element.clone());
+            } else {
+                while merged.get(at).is_some_and(|m| left_added.contains(m)) {
+                    at += 1;
+                }
+            }
+            merged.insert(at, element.clone());
+            values.insert(element.clone(), value.clone());
+            anchor = Some(element);
+        }
+    }
+
+    Ok(merged
+        .into_iter()
+        .map(|element| values.remove(&element).unwrap())
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -433,7 +1033,7 @@ mod tests {
 
     #[test]
     fn diff_and_apply_json() {
-        let codec = JsonTreeCodec;
+        let codec = JsonTreeCodec::default();
         let old = serde_json::to_vec(&json!({"a": 1, "b": 2})).unwrap();
         let new = serde_json::to_vec(&json!({"a": 1, "b": 3, "c": 4})).unwrap();
         let ops = codec.diff(&old, &new).unwrap();
@@ -444,7 +1044,7 @@ mod tests {
 
     #[test]
     fn json_merge3_no_conflict() {
-        let codec = JsonTreeCodec;
+        let codec = JsonTreeCodec::default();
         let base = serde_json::to_vec(&json!({"a": 1, "b": 2, "c": 3})).unwrap();
         let left = serde_json::to_vec(&json!({"a": 10, "b": 2, "c": 3})).unwrap();
         let right = serde_json::to_vec(&json!({"a": 1, "b": 20, "c": 3})).unwrap();
@@ -455,7 +1055,7 @@ mod tests {
 
     #[test]
     fn json_merge3_both_add_same_key_different_value() {
-        let codec = JsonTreeCodec;
+        let codec = JsonTreeCodec::default();
         let base = serde_json::to_vec(&json!({"a": 1})).unwrap();
         let left = serde_json::to_vec(&json!({"a": 1, "new": "left"})).unwrap();
         let right = serde_json::to_vec(&json!({"a": 1, "new": "right"})).unwrap();
@@ -464,7 +1064,7 @@ mod tests {
 
     #[test]
     fn json_invert_roundtrip() {
-        let codec = JsonTreeCodec;
+        let codec = JsonTreeCodec::default();
         let old = serde_json::to_vec(&json!({"x": 1, "y": 2})).unwrap();
         let new = serde_json::to_vec(&json!({"x": 10, "y": 2, "z": 3})).unwrap();
         let ops = codec.diff(&old, &new).unwrap();
@@ -478,6 +1078,169 @@ mod tests {
         assert_eq!(restored_val, json!({"x": 1, "y": 2}));
     }
 
+    fn apply_json(codec: &JsonTreeCodec, base: &Value, ops: &[PatchOp]) -> Value {
+        let applied = codec
+            .apply(&serde_json::to_vec(base).unwrap(), ops)
+            .unwrap();
+        serde_json::from_slice(&applied).unwrap()
+    }
+
+    #[test]
+    fn array_diff_is_element_wise() {
+        let codec = JsonTreeCodec::default();
+        let old = json!({"xs": [1, 2, 3, {"a": 1}]});
+        let new = json!({"xs": [0, 1, 3, {"a": 2}, 4]});
+        let ops = codec
+            .diff(
+                &serde_json::to_vec(&old).unwrap(),
+                &serde_json::to_vec(&new).unwrap(),
+            )
+            .unwrap();
+        let addresses: Vec<(&str, &str)> = ops
+            .iter()
+            .map(|op| (op.address.as_str(), op.op_type.as_str()))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("/xs/0", "insert"),
+                ("/xs/2", "delete"),
+                ("/xs/3/a", "replace"),
+                ("/xs/4", "insert"),
+            ]
+        );
+        assert_eq!(apply_json(&codec, &old, &ops), new);
+        let applied = apply_json(&codec, &old, &ops);
+        assert_eq!(
+            apply_json(&codec, &applied, &codec.invert(&ops).unwrap()),
+            old
+        );
+    }
+
+    #[test]
+    fn identity_keys_match_moved_and_edited_elements() {
+        let codec = JsonTreeCodec::with_identity_keys(["id"]);
+        let old = json!([{"id": 1, "v": "a"}, {"id": 2}, {"id": 3}]);
+        let new = json!([{"id": 3}, {"id": 1, "v": "b"}, {"id": 4}]);
+        let ops = codec
+            .diff(
+                &serde_json::to_vec(&old).unwrap(),
+                &serde_json::to_vec(&new).unwrap(),
+            )
+            .unwrap();
+        let addresses: Vec<(&str, &str)> = ops
+            .iter()
+            .map(|op| (op.address.as_str(), op.op_type.as_str()))
+            .collect();
+        assert_eq!(
+            addresses,
+            vec![
+                ("/[id=2]", "delete"),
+                ("/[id=3]", "move"),
+                ("/2", "insert"),
+                ("/[id=1]/v", "replace"),
+            ]
+        );
+        let applied = apply_json(&codec, &old, &ops);
+        assert_eq!(applied, new);
+        assert_eq!(
+            apply_json(&codec, &applied, &codec.invert(&ops).unwrap()),
+            old
+        );
+
+        // Without identity keys the edited element is matched by position.
+        let lcs = JsonTreeCodec::default();
+        let ops = lcs
+            .diff(
+                &serde_json::to_vec(&old).unwrap(),
+                &serde_json::to_vec(&new).unwrap(),
+            )
+            .unwrap();
+        assert!(ops.iter().all(|op| !op.address.contains('[')));
+        assert_eq!(apply_json(&lcs, &old, &ops), new);
+    }
+
+    #[test]
+    fn concurrent_array_changes_commute_and_merge() {
+        let codec = JsonTreeCodec::default();
+        let base = json!({"items": [{"n": 1}, {"n": 2}]});
+        let left = json!({"items": [{"n": 0}, {"n": 1}, {"n": 2}, {"n": "left"}]});
+        let right = json!({"items": [{"n": 1}, {"n": 2, "done": true}, {"n": "right"}]});
+        let [base_b, left_b, right_b] =
+            [&base, &left, &right].map(|v| serde_json::to_vec(v).unwrap());
+        let expected = json!({"items": [
+            {"n": 0}, {"n": 1}, {"n": 2, "done": true}, {"n": "left"}, {"n": "right"}
+        ]});
+
+        let left_ops = codec.diff(&base_b, &left_b).unwrap();
+        let right_ops = codec.diff(&base_b, &right_b).unwrap();
+        let (right_after, left_after) = codec.commute(&left_ops, &right_ops).unwrap();
+        let via_left = apply_json(&codec, &left, &right_after);
+        assert_eq!(via_left, expected);
+        assert_eq!(apply_json(&codec, &right, &left_after), expected);
+
+        let merged = codec.merge3(&base_b, &left_b, &right_b).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&merged).unwrap(), expected);
+    }
+
+    fn merge_json(codec: &JsonTreeCodec, base: Value, left: Value, right: Value) -> Option<Value> {
+        let [base, left, right] = [base, left, right].map(|v| serde_json::to_vec(&v).unwrap());
+        let merged = codec.merge3(&base, &left, &right).ok()?;
+        Some(serde_json::from_slice(&merged).unwrap())
+    }
+
+    #[test]
+    fn keyed_merge_matches_elements_by_identity() {
+        let codec = JsonTreeCodec::with_identity_keys(["id"]);
+        let base = json!([{"id": 1, "a": 0}, {"id": 2, "a": 0}]);
+        let left = json!([{"id": 2, "a": 0}, {"id": 1, "a": 1}]);
+        let right = json!([{"id": 0}, {"id": 1, "a": 0, "b": 1}, {"id": 2, "a": 0}]);
+        assert_eq!(
+            merge_json(&codec, base, left, right),
+            Some(json!([{"id": 0}, {"id": 2, "a": 0}, {"id": 1, "a": 1, "b": 1}]))
+        );
+    }
+
+    #[test]
+    fn keyed_merge_conflicts() {
+        let codec = JsonTreeCodec::with_identity_keys(["id"]);
+        let ids = |ids: &[u32]| Value::from_iter(ids.iter().map(|id| json!({"id": id})));
+
+        // Both sides moved the same element to different places.
+        let (base, left, right) = (ids(&[1, 2, 3]), ids(&[3, 1, 2]), ids(&[1, 3, 2]));
+        assert_eq!(
+            merge_json(&codec, base.clone(), left.clone(), right.clone()),
+            None
+        );
+        let [base_b, left_b, right_b] =
+            [&base, &left, &right].map(|v| serde_json::to_vec(v).unwrap());
+        let left_ops = codec.diff(&base_b, &left_b).unwrap();
+        let right_ops = codec.diff(&base_b, &right_b).unwrap();
+        assert!(codec.commute(&left_ops, &right_ops).is_err());
+
+        // Both sides appended the same id with different bodies.
+        let base = json!([{"id": 1}]);
+        let left = json!([{"id": 1}, {"id": 2, "v": "left"}]);
+        let right = json!([{"id": 1}, {"id": 2, "v": "right"}]);
+        assert_eq!(merge_json(&codec, base.clone(), left.clone(), right), None);
+        assert_eq!(
+            merge_json(&codec, base, left.clone(), left.clone()),
+            Some(left)
+        );
+    }
+
+    #[test]
+    fn editing_a_deleted_element_conflicts() {
+        let codec = JsonTreeCodec::default();
+        let base = serde_json::to_vec(&json!([{"k": 1}, {"k": 2}])).unwrap();
+        let left = serde_json::to_vec(&json!([{"k": 1}])).unwrap();
+        let right = serde_json::to_vec(&json!([{"k": 1}, {"k": 3}])).unwrap();
+        let left_ops = codec.diff(&base, &left).unwrap();
+        let right_ops = codec.diff(&base, &right).unwrap();
+        assert!(codec.commute(&left_ops, &right_ops).is_err());
+        assert!(codec.merge3(&base, &left, &right).is_err());
+    }
+
     #[test]
     fn path_relation_tests() {
         assert_eq!(path_relationship("/a/b", "/a/b"), PathRelation::Equal);
@@ -49,6 +49,18 @@ impl CodecRegistry {
         self.get_by_extension(ext).or(self.fallback.as_ref())
     }
 
+    /// Similarity (0-100) of two versions of the file at `path`, using the
+    /// codec for its extension. Content the codec can't parse scores 0
+    /// unless identical.
+    pub fn similarity(&self, path: &str, old: &[u8], new: &[u8]) -> u8 {
+        if old == new {
+            return 100;
+        }
+        self.get_for_path(path)
+            .and_then(|codec| codec.similarity(old, new).ok())
+            .unwrap_or(0)
+    }
+
     pub fn default_registry() -> Self {
         use crate::binary::BinaryCodec;
         use crate::json_tree::JsonTreeCodec;
@@ -62,10 +74,23 @@ impl CodecRegistry {
                 "yaml", "yml",
             ],
         );
-        reg.register(Arc::new(JsonTreeCodec), &["json"]);
+        reg.register(Arc::new(JsonTreeCodec::default()), &["json"]);
         reg.set_fallback(Arc::new(BinaryCodec));
         reg
     }
+
+    /// The default registry with elements of JSON arrays matched by the
+    /// first of `keys` that identifies them; see [`crate::json_tree`].
+    pub fn with_json_identity_keys(keys: &[String]) -> Self {
+        use crate::json_tree::JsonTreeCodec;
+
+        let mut reg = Self::default_registry();
+        reg.register(
+            Arc::new(JsonTreeCodec::with_identity_keys(keys.iter().cloned())),
+            &["json"],
+        );
+        reg
+    }
 }
 
 impl Default for CodecRegistry {
@@ -378,6 +378,134 @@ impl Codec for TextLineCodec {
     }
 }
 
+/// One stretch of a three-way line merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeRegion {
+    /// Lines both sides agree on after merging.
+    Clean(Vec<String>),
+    /// Base lines both sides changed differently, with each side's version.
+    Conflict {
+        /// Index of the first base line in the region.
+        base_start: usize,
+        base: Vec<String>,
+        left: Vec<String>,
+        right: Vec<String>,
+    },
+}
+
+/// A change one side made: base lines `start..end` replaced by `lines`.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn hunks<'a>(base: &'a str, side: &'a str) -> Vec<Hunk<'a>> {
+    let diff = TextDiff::from_lines(base, side);
+    let new_lines: Vec<&str> = side.lines().collect();
+    diff.ops()
+        .iter()
+        .filter(|op| op.tag() != DiffTag::Equal)
+        .map(|op| Hunk {
+            start: op.old_range().start,
+            end: op.old_range().end,
+            lines: new_lines[op.new_range()].to_vec(),
+        })
+        .collect()
+}
+
+/// Whether `hunk` must be merged together with base lines `start..end`:
+/// the ranges intersect, or an insertion sits at the edge of the other.
+fn overlaps(hunk: &Hunk, start: usize, end: usize) -> bool {
+    (hunk.start < end && hunk.end > start)
+        || (hunk.start == hunk.end && (start..=end).contains(&hunk.start))
+        || (start == end && (hunk.start..=hunk.end).contains(&start))
+}
+
+/// One side's version of base lines `start..end`, given its hunks there.
+fn side_lines(base: &[&str], hunks: &[&Hunk], start: usize, end: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        lines.extend(base[pos..hunk.start].iter().map(|l| l.to_string()));
+        lines.extend(hunk.lines.iter().map(|l| l.to_string()));
+        pos = hunk.end;
+    }
+    lines.extend(base[pos..end].iter().map(|l| l.to_string()));
+    lines
+}
+
+/// Align both sides' changes against the base and split the result into
+/// clean and conflicting regions, in file order. Overlapping changes form
+/// one region; it is clean when both sides made the same change.
+pub fn merge3_regions(base: &str, left: &str, right: &str) -> Vec<MergeRegion> {
+    let base_lines: Vec<&str> = base.lines().collect();
+    let left_hunks = hunks(base, left);
+    let right_hunks = hunks(base, right);
+
+    let mut regions = Vec::new();
+    let mut clean: Vec<String> = Vec::new();
+    let mut pos = 0;
+    let (mut li, mut ri) = (0, 0);
+    while li < left_hunks.len() || ri < right_hunks.len() {
+        // Start from whichever side changes first, then absorb every hunk
+        // from either side that overlaps the growing region.
+        let first_is_left = match (left_hunks.get(li), right_hunks.get(ri)) {
+            (Some(l), Some(r)) => l.start <= r.start,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        let first = if first_is_left {
+            &left_hunks[li]
+        } else {
+            &right_hunks[ri]
+        };
+        let (start, mut end) = (first.start, first.end);
+        let (left_from, right_from) = (li, ri);
+        loop {
+            if let Some(h) = left_hunks.get(li).filter(|h| overlaps(h, start, end)) {
+                end = end.max(h.end);
+                li += 1;
+            } else if let Some(h) = right_hunks.get(ri).filter(|h| overlaps(h, start, end)) {
+                end = end.max(h.end);
+                ri += 1;
+            } else {
+                break;
+            }
+        }
+
+        clean.extend(base_lines[pos..start].iter().map(|l| l.to_string()));
+        let left_part: Vec<&Hunk> = left_hunks[left_from..li].iter().collect();
+        let right_part: Vec<&Hunk> = right_hunks[right_from..ri].iter().collect();
+        let left_version = side_lines(&base_lines, &left_part, start, end);
+        let right_version = side_lines(&base_lines, &right_part, start, end);
+        if right_part.is_empty() || left_version == right_version {
+            clean.extend(left_version);
+        } else if left_part.is_empty() {
+            clean.extend(right_version);
+        } else {
+            if !clean.is_empty() {
+                regions.push(MergeRegion::Clean(std::mem::take(&mut clean)));
+            }
+            regions.push(MergeRegion::Conflict {
+                base_start: start,
+                base: base_lines[start..end]
+                    .iter()
+                    .map(|l| l.to_string())
+                    .collect(),
+                left: left_version,
+                right: right_version,
+            });
+        }
+        pos = end;
+    }
+    clean.extend(base_lines[pos..].iter().map(|l| l.to_string()));
+    if !clean.is_empty() {
+        regions.push(MergeRegion::Clean(clean));
+    }
+    regions
+}
+
 fn collect_changes<'a>(
     diff: &TextDiff<'a, 'a, 'a, str>,
     changes: &mut std::collections::HashMap<usize, Vec<&'a str>>,
@@ -457,6 +585,18 @@ mod tests {
         assert_eq!(restored, old);
     }
 
+    #[test]
+    fn similarity_tracks_share_of_changed_lines() {
+        let codec = TextLineCodec;
+        let old = b"one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\n";
+        let edited = b"one\ntwo\nthree\nFOUR\nfive\nsix\nseven\neight\n";
+        let unrelated = b"alpha\nbeta\n";
+        let close = codec.similarity(old, edited).unwrap();
+        assert!(close >= 80, "{close}");
+        assert_eq!(codec.similarity(old, unrelated).unwrap(), 0);
+        assert_eq!(codec.similarity(old, old).unwrap(), 100);
+    }
+
     #[test]
     fn merge3_no_conflict() {
         let codec = TextLineCodec;
@@ -469,6 +609,27 @@ mod tests {
         assert!(merged_str.contains("right_change"));
     }
 
+    #[test]
+    fn merge3_regions_isolate_conflicts() {
+        let base = "a\nb\nc\nd\ne\n";
+        let left = "A\nb\nc\nd\ne\nf\n";
+        let right = "a2\nb\nc\nD\ne\n";
+        let regions = merge3_regions(base, left, right);
+        let lines = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            regions,
+            vec![
+                MergeRegion::Conflict {
+                    base_start: 0,
+                    base: lines(&["a"]),
+                    left: lines(&["A"]),
+                    right: lines(&["a2"]),
+                },
+                MergeRegion::Clean(lines(&["b", "c", "D", "e", "f"])),
+            ]
+        );
+    }
+
     #[test]
     fn merge3_conflict() {
         let codec = TextLineCodec;
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+
+use claw_core::cof::{decode_uvarint, encode_uvarint};
+
+use crate::pack::MAX_OBJECT_SIZE;
+use crate::StoreError;
+
+/// Delta format:
+/// [uvarint base_len][uvarint result_len]
+/// [ops: 0x01 uvarint offset, uvarint len (copy from base)
+///     | 0x02 uvarint len, bytes (insert literal)]*
+const OP_COPY: u8 = 0x01;
+const OP_INSERT: u8 = 0x02;
+
+/// Matches shorter than a block are emitted as literals.
+const BLOCK: usize = 16;
+/// Cap on candidate positions tracked per block to bound worst-case work.
+const MAX_CANDIDATES: usize = 8;
+
+/// Compute a delta that rebuilds `target` from `base`.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_uvarint(base.len() as u64, &mut out);
+    encode_uvarint(target.len() as u64, &mut out);
+
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    let mut pos = 0;
+    while pos + BLOCK <= base.len() {
+        let candidates = blocks.entry(&base[pos..pos + BLOCK]).or_default();
+        if candidates.len() < MAX_CANDIDATES {
+            candidates.push(pos);
+        }
+        pos += BLOCK;
+    }
+
+    let mut literal: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < target.len() {
+        let mut best: Option<(usize, usize)> = None;
+        if i + BLOCK <= target.len() {
+            if let Some(candidates) = blocks.get(&target[i..i + BLOCK]) {
+                for &start in candidates {
+                    let len = base[start..]
+                        .iter()
+                        .zip(&target[i..])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if best.is_none_or(|(_, best_len)| len > best_len) {
+                        best = Some((start, len));
+                    }
+                }
+            }
+        }
+
+        match best {
+            Some((mut start, forward)) if forward >= BLOCK => {
+                let mut len = forward;
+                // Pull matching bytes back out of the pending literal.
+                while start > 0
+                    && !literal.is_empty()
+                    && base[start - 1] == *literal.last().unwrap()
+                {
+                    literal.pop();
+                    start -= 1;
+                    len += 1;
+                }
+                flush_literal(&mut literal, &mut out);
+                out.push(OP_COPY);
+                encode_uvarint(start as u64, &mut out);
+                encode_uvarint(len as u64, &mut out);
+                i += forward;
+            }
+            _ => {
+                literal.push(target[i]);
+                i += 1;
+            }
+        }
+    }
+    flush_literal(&mut literal, &mut out);
+    out
+}
+
+fn flush_literal(literal: &mut Vec<u8>, out: &mut Vec<u8>) {
+    if literal.is_empty() {
+        return;
+    }
+    out.push(OP_INSERT);
+    encode_uvarint(literal.len() as u64, out);
+    out.extend_from_slice(literal);
+    literal.clear();
+}
+
+/// Rebuild the target bytes from `base` and a delta produced by [`create_delta`].
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, StoreError> {
+    let corrupt = |msg: &str| StoreError::Config(format!("corrupt delta: {msg}"));
+
+    let mut pos = 0;
+    let base_len = decode_uvarint(delta, &mut pos)? as usize;
+    if base_len != base.len() {
+        return Err(corrupt("base length mismatch"));
+    }
+    let result_len = decode_uvarint(delta, &mut pos)?;
+    if result_len > MAX_OBJECT_SIZE {
+        return Err(corrupt("result too large"));
+    }
+    let result_len = result_len as usize;
+
+    // Copies can repeat base bytes, so this is only a hint.
+    let mut out = Vec::with_capacity(result_len.min(base.len().saturating_add(delta.len())));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        match op {
+            OP_COPY => {
+                let offset = decode_uvarint(delta, &mut pos)? as usize;
+                let len = decode_uvarint(delta, &mut pos)? as usize;
+                let end = offset
+                    .checked_add(len)
+                    .filter(|end| *end <= base.len())
+                    .ok_or_else(|| corrupt("copy out of range"))?;
+                out.extend_from_slice(&base[offset..end]);
+            }
+            OP_INSERT => {
+                let len = decode_uvarint(delta, &mut pos)? as usize;
+                let end = pos
+                    .checked_add(len)
+                    .filter(|end| *end <= delta.len())
+                    .ok_or_else(|| corrupt("insert out of range"))?;
+                out.extend_from_slice(&delta[pos..end]);
+                pos = end;
+            }
+            _ => return Err(corrupt("unknown opcode")),
+        }
+        if out.len() > result_len {
+            return Err(corrupt("result length mismatch"));
+        }
+    }
+
+    if out.len() != result_len {
+        return Err(corrupt("result length mismatch"));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delta_roundtrip_with_edits() {
+        let base: Vec<u8> = (0..4000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let mut target = base.clone();
+        target.splice(100..120, b"inserted text here".iter().copied());
+        target.extend_from_slice(b"appended tail");
+        target.drain(5000..5200);
+
+        let delta = create_delta(&base, &target);
+        assert!(delta.len() < target.len() / 10);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn delta_roundtrip_unrelated_inputs() {
+        let base = b"completely different base content".to_vec();
+        let target = b"nothing in common with it at all!".to_vec();
+        let delta = create_delta(&base, &target);
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+    }
+
+    #[test]
+    fn apply_rejects_wrong_base() {
+        let delta = create_delta(b"base one", b"target");
+        assert!(apply_delta(b"other base", &delta).is_err());
+    }
+
+    #[test]
+    fn apply_rejects_huge_result_length() {
+        let mut delta = Vec::new();
+        encode_uvarint(4, &mut delta);
+        encode_uvarint(u64::MAX, &mut delta);
+        assert!(apply_delta(b"base", &delta).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! Zstd dictionaries trained on small objects.
+//!
+//! Each dictionary lives in `.claw/dicts/<id>.zdict`, where the id is derived
+//! from its bytes so the same dictionary has the same id in every clone.
+//! `active.toml` names the dictionary new objects of each type are compressed
+//! with; older dictionaries stay around for the objects that still use them.
+
+use std::collections::{BTreeMap, HashMap};
+
+use claw_core::cof::{
+    cof_decode_with, cof_dictionary_id, cof_encode_with_dictionary, cof_is_encrypted,
+    cof_peek_type_tag, CofContext, DictionarySource,
+};
+use claw_core::object::TypeTag;
+use serde::{Deserialize, Serialize};
+
+use crate::layout::RepoLayout;
+use crate::{ClawStore, StoreError};
+
+/// Objects with payloads up to this size are sampled for training and
+/// compressed with their type's dictionary.
+pub const SMALL_OBJECT_MAX: usize = 16 * 1024;
+
+/// Types with fewer small objects than this get no dictionary.
+pub const MIN_SAMPLES: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct TrainOptions {
+    /// Samples taken per type tag.
+    pub max_samples: usize,
+    /// Upper bound on the size of each dictionary.
+    pub max_dict_size: usize,
+}
+
+impl Default for TrainOptions {
+    fn default() -> Self {
+        Self {
+            max_samples: 2000,
+            max_dict_size: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrainedDictionary {
+    pub type_tag: TypeTag,
+    pub id: u32,
+    pub size: usize,
+    pub samples: usize,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ActiveFile {
+    #[serde(default)]
+    active: BTreeMap<String, String>,
+}
+
+/// Every dictionary in `.claw/dicts` and the active one per type.
+#[derive(Debug, Default)]
+pub struct Dictionaries {
+    by_id: HashMap<u32, Vec<u8>>,
+    active: HashMap<TypeTag, u32>,
+}
+
+impl Dictionaries {
+    pub fn load(layout: &RepoLayout) -> Result<Self, StoreError> {
+        let dir = layout.dicts_dir();
+        let mut dicts = Self::default();
+        if !dir.exists() {
+            return Ok(dicts);
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "zdict") {
+                continue;
+            }
+            let Some(id) = path
+                .file_stem()
+                .and_then(|stem| u32::from_str_radix(&stem.to_string_lossy(), 16).ok())
+            else {
+                continue;
+            };
+            dicts.by_id.insert(id, std::fs::read(&path)?);
+        }
+        for (name, id) in read_active(layout)?.active {
+            let (Some(tag), Ok(id)) = (tag_from_name(&name), u32::from_str_radix(&id, 16)) else {
+                continue;
+            };
+            if dicts.by_id.contains_key(&id) {
+                dicts.active.insert(tag, id);
+            }
+        }
+        Ok(dicts)
+    }
+
+    /// The dictionary new objects of `type_tag` are compressed with.
+    pub fn active(&self, type_tag: TypeTag) -> Option<(u32, &[u8])> {
+        let id = *self.active.get(&type_tag)?;
+        Some((id, self.by_id.get(&id)?.as_slice()))
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.by_id.get(&id).map(Vec::as_slice)
+    }
+
+    /// Active dictionaries by type, sorted by type tag.
+    pub fn active_entries(&self) -> Vec<(TypeTag, u32, usize)> {
+        let mut out: Vec<_> = self
+            .active
+            .iter()
+            .map(|(tag, id)| (*tag, *id, self.by_id.get(id).map_or(0, Vec::len)))
+            .collect();
+        out.sort_by_key(|(tag, _, _)| *tag as u8);
+        out
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.by_id.iter().map(|(id, dict)| (*id, dict.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+}
+
+impl DictionarySource for Dictionaries {
+    fn dictionary(&self, id: u32) -> Option<&[u8]> {
+        self.get(id)
+    }
+}
+
+/// Content-derived id of a dictionary.
+pub fn dictionary_id(dict: &[u8]) -> u32 {
+    let hash = blake3::hash(dict);
+    let bytes = hash.as_bytes();
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Save `dict` and make it the active dictionary for `type_tag`.
+pub fn install_dictionary(
+    layout: &RepoLayout,
+    type_tag: TypeTag,
+    dict: &[u8],
+) -> Result<u32, StoreError> {
+    let id = dictionary_id(dict);
+    save_dictionary(layout, id, dict)?;
+    let mut active = read_active(layout)?;
+    active
+        .active
+        .insert(type_tag.name().to_string(), format!("{id:08x}"));
+    let toml_str =
+        toml::to_string_pretty(&active).map_err(|e| StoreError::Config(e.to_string()))?;
+    std::fs::write(layout.dicts_dir().join("active.toml"), toml_str)?;
+    Ok(id)
+}
+
+/// Save `dict` under `id` without activating it, e.g. one carried in a pack.
+pub fn save_dictionary(layout: &RepoLayout, id: u32, dict: &[u8]) -> Result<(), StoreError> {
+    let dir = layout.dicts_dir();
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{id:08x}.zdict"));
+    if !path.exists() {
+        let tmp = tempfile::NamedTempFile::new_in(&dir)?;
+        std::fs::write(tmp.path(), dict)?;
+        tmp.persist(&path).map_err(|e| StoreError::Io(e.error))?;
+    }
+    Ok(())
+}
+
+/// Train one dictionary per type tag from a sample of small objects.
+///
+/// Encrypted objects are never sampled, so no dictionary holds their plaintext.
+pub fn train(store: &ClawStore, opts: &TrainOptions) -> Result<Vec<TrainedDictionary>, StoreError> {
+    let mut samples: BTreeMap<u8, Vec<Vec<u8>>> = BTreeMap::new();
+    for id in store.list_object_ids()? {
+        // The type comes from the meta index when there is one, so objects
+        // of a type with enough samples are never read.
+        let type_tag = store.object_type(&id)?;
+        let bucket = samples.entry(type_tag as u8).or_default();
+        if bucket.len() >= opts.max_samples {
+            continue;
+        }
+        let cof_data = store.load_cof_bytes(&id)?;
+        if cof_is_encrypted(&cof_data) {
+            continue;
+        }
+        let (_, payload) = store.decode_cof(&id, &cof_data)?;
+        if !payload.is_empty() && payload.len() <= SMALL_OBJECT_MAX {
+            bucket.push(payload);
+        }
+    }
+
+    let mut trained = Vec::new();
+    for (tag, bucket) in samples {
+        let Some(type_tag) = TypeTag::from_u8(tag) else {
+            continue;
+        };
+        if bucket.len() < MIN_SAMPLES {
+            continue;
+        }
+        let total: usize = bucket.iter().map(Vec::len).sum();
+        let size = (total / 10).clamp(1024, opts.max_dict_size.max(1024));
+        // Too little or too uniform input makes the trainer give up; that
+        // type just keeps plain zstd.
+        let dict = match zstd::dict::from_samples(&bucket, size) {
+            Ok(dict) => dict,
+            Err(e) => {
+                tracing::debug!("no dictionary for {}: {e}", type_tag.name());
+                continue;
+            }
+        };
+        let id = install_dictionary(store.layout(), type_tag, &dict)?;
+        trained.push(TrainedDictionary {
+            type_tag,
+            id,
+            size: dict.len(),
+            samples: bucket.len(),
+        });
+    }
+    store.reload_dictionaries()?;
+    Ok(trained)
+}
+
+/// Re-encode a small object with its type's active dictionary when that
+/// makes it smaller. Encrypted objects are returned unchanged.
+pub fn recompress(dicts: &Dictionaries, cof_data: Vec<u8>) -> Result<Vec<u8>, StoreError> {
+    if cof_is_encrypted(&cof_data) {
+        return Ok(cof_data);
+    }
+    let Some((dict_id, dict)) = dicts.active(cof_peek_type_tag(&cof_data)?) else {
+        return Ok(cof_data);
+    };
+    if cof_dictionary_id(&cof_data) == Some(dict_id) {
+        return Ok(cof_data);
+    }
+    let ctx = CofContext {
+        dictionaries: Some(dicts),
+        ..Default::default()
+    };
+    let (type_tag, payload) = cof_decode_with(&cof_data, ctx)?;
+    if payload.len() > SMALL_OBJECT_MAX {
+        return Ok(cof_data);
+    }
+    let recompressed = cof_encode_with_dictionary(type_tag, &payload, dict_id, dict)?;
+    Ok(if recompressed.len() < cof_data.len() {
+        recompressed
+    } else {
+        cof_data
+    })
+}
+
+fn read_active(layout: &RepoLayout) -> Result<ActiveFile, StoreError> {
+    match std::fs::read_to_string(layout.dicts_dir().join("active.toml")) {
+        Ok(content) => toml::from_str(&content).map_err(|e| StoreError::Config(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ActiveFile::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn tag_from_name(name: &str) -> Option<TypeTag> {
+    (1..=u8::MAX)
+        .map_while(TypeTag::from_u8)
+        .find(|tag| tag.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gc::{run_gc, GcOptions};
+    use claw_core::object::Object;
+    use claw_core::types::{Blob, FileMode, Tree, TreeEntry};
+
+    fn tree(store: &ClawStore, i: usize) -> Object {
+        let blob = store
+            .store_object(&Object::Blob(Blob {
+                data: format!("{i}").into_bytes(),
+                media_type: None,
+            }))
+            .unwrap();
+        Object::Tree(Tree {
+            entries: ["Cargo.toml", "README.md", "src", "tests"]
+                .iter()
+                .map(|name| TreeEntry {
+                    name: format!("{name}_{}", i % 7),
+                    mode: FileMode::Regular,
+                    object_id: blob,
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn trained_dictionary_compresses_and_travels_in_packs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        for i in 0..200 {
+            store.store_object(&tree(&store, i)).unwrap();
+        }
+        let trained = train(&store, &TrainOptions::default()).unwrap();
+        let (dict_id, _) = store.dictionaries().active(TypeTag::Tree).unwrap();
+        assert!(trained.iter().any(|d| d.id == dict_id));
+
+        let obj = tree(&store, 1000);
+        let id = store.store_object(&obj).unwrap();
+        let cof_data = store.load_cof_bytes(&id).unwrap();
+        assert_eq!(cof_dictionary_id(&cof_data), Some(dict_id));
+        let plain = claw_core::cof::cof_encode(TypeTag::Tree, &obj.serialize_payload().unwrap());
+        assert!(cof_data.len() < plain.unwrap().len());
+        assert_eq!(
+            store.load_object(&id).unwrap().serialize_payload().unwrap(),
+            obj.serialize_payload().unwrap()
+        );
+
+        store.set_ref("heads/main", &id).unwrap();
+        run_gc(&store, &GcOptions::default()).unwrap();
+        drop(store);
+
+        // The pack carries the dictionary, so it still decodes without `.claw/dicts`.
+        std::fs::remove_dir_all(RepoLayout::new(tmp.path()).dicts_dir()).unwrap();
+        let store = ClawStore::open(tmp.path()).unwrap();
+        assert!(store.dictionaries().is_empty());
+        assert_eq!(
+            store.load_object(&id).unwrap().serialize_payload().unwrap(),
+            obj.serialize_payload().unwrap()
+        );
+        let portable = store.load_portable_cof_bytes(&id).unwrap();
+        assert_eq!(cof_dictionary_id(&portable), None);
+    }
+}
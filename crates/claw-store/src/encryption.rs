@@ -0,0 +1,165 @@
+use claw_core::cof::PayloadCipher;
+use claw_core::CoreError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::layout::RepoLayout;
+use crate::StoreError;
+
+/// Environment variable holding the hex data key; overrides the key file.
+pub const DATA_KEY_ENV: &str = "CLAW_DATA_KEY";
+
+/// Symmetric key sealing encrypted objects with XChaCha20-Poly1305.
+#[derive(Clone)]
+pub struct DataKey([u8; 32]);
+
+impl DataKey {
+    pub fn from_hex(hex_key: &str) -> Result<Self, StoreError> {
+        let bytes = hex::decode(hex_key.trim())
+            .map_err(|e| StoreError::Config(format!("invalid data key: {e}")))?;
+        let key: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| StoreError::Config("invalid data key: expected 32 bytes".into()))?;
+        Ok(Self(key))
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DataKey(..)")
+    }
+}
+
+impl PayloadCipher for DataKey {
+    fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, CoreError> {
+        claw_crypto::encrypt::encrypt(&self.0, plaintext)
+            .map_err(|e| CoreError::Encryption(e.to_string()))
+    }
+
+    fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CoreError> {
+        claw_crypto::encrypt::decrypt(&self.0, sealed)
+            .map_err(|e| CoreError::Encryption(e.to_string()))
+    }
+}
+
+/// Load the data key from `CLAW_DATA_KEY` or `.claw/keys/data.key`.
+pub fn load_data_key(layout: &RepoLayout) -> Result<Option<DataKey>, StoreError> {
+    if let Ok(hex_key) = std::env::var(DATA_KEY_ENV) {
+        if !hex_key.trim().is_empty() {
+            return DataKey::from_hex(&hex_key).map(Some);
+        }
+    }
+    match std::fs::read_to_string(layout.data_key_file()) {
+        Ok(hex_key) => DataKey::from_hex(&hex_key).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create a random data key file. Fails if one already exists.
+pub fn generate_data_key(layout: &RepoLayout) -> Result<DataKey, StoreError> {
+    let path = layout.data_key_file();
+    if path.exists() {
+        return Err(StoreError::Config(format!(
+            "data key already exists at {}",
+            path.display()
+        )));
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut key = [0u8; 32];
+    rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut key);
+    let key = DataKey(key);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+    std::io::Write::write_all(&mut file, key.to_hex().as_bytes())?;
+    Ok(key)
+}
+
+/// Worktree paths whose objects are stored encrypted.
+#[derive(Debug, Clone)]
+pub struct EncryptionRules {
+    globs: GlobSet,
+    patterns: Vec<String>,
+}
+
+impl Default for EncryptionRules {
+    fn default() -> Self {
+        Self {
+            globs: GlobSet::empty(),
+            patterns: Vec::new(),
+        }
+    }
+}
+
+impl EncryptionRules {
+    /// Build rules from glob patterns; `secrets/` is shorthand for `secrets/**`.
+    pub fn new(patterns: &[String]) -> Result<Self, StoreError> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = match pattern.strip_suffix('/') {
+                Some(dir) => format!("{dir}/**"),
+                None => pattern.clone(),
+            };
+            builder.add(Glob::new(&glob).map_err(|e| {
+                StoreError::Config(format!("invalid encryption pattern '{pattern}': {e}"))
+            })?);
+        }
+        let globs = builder
+            .build()
+            .map_err(|e| StoreError::Config(e.to_string()))?;
+        Ok(Self {
+            globs,
+            patterns: patterns.to_vec(),
+        })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.globs.is_match(path)
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_match_directories_and_globs() {
+        let rules = EncryptionRules::new(&["secrets/".into(), "*.pem".into()]).unwrap();
+        assert!(rules.matches("secrets/api.txt"));
+        assert!(rules.matches("secrets/nested/token"));
+        assert!(rules.matches("certs/server.pem"));
+        assert!(!rules.matches("src/secrets.rs"));
+        assert!(!EncryptionRules::default().matches("secrets/api.txt"));
+    }
+
+    #[test]
+    fn generated_key_roundtrips_through_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+        let key = generate_data_key(&layout).unwrap();
+        let loaded = load_data_key(&layout).unwrap().unwrap();
+        assert_eq!(key.to_hex(), loaded.to_hex());
+        assert!(generate_data_key(&layout).is_err());
+
+        let sealed = key.seal(b"secret").unwrap();
+        assert_eq!(loaded.open(&sealed).unwrap(), b"secret");
+    }
+}
@@ -7,8 +7,12 @@ pub enum StoreError {
     NotARepository(PathBuf),
     #[error("object not found: {0}")]
     ObjectNotFound(claw_core::id::ObjectId),
+    #[error("not a blob: {0}")]
+    NotABlob(claw_core::id::ObjectId),
     #[error("ref not found: {0}")]
     RefNotFound(String),
+    #[error("invalid revision {0}")]
+    InvalidRevision(String),
     #[error("lock contention on {0}")]
     LockContention(PathBuf),
     #[error("io error: {0}")]
@@ -19,6 +23,10 @@ pub enum StoreError {
     Config(String),
     #[error("index error: {0}")]
     Index(String),
+    #[error("ref transaction failed: {0}")]
+    RefTransaction(String),
+    #[error("{0}: no data key (set CLAW_DATA_KEY or restore .claw/keys/data.key)")]
+    MissingDataKey(String),
     #[error("ref CAS conflict: expected {expected}, actual {actual}")]
     RefCasConflict { expected: String, actual: String },
 }
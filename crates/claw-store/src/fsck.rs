@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use claw_core::hash::content_hash;
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+
+use crate::gc::reflog_names;
+use crate::pack::PackHandle;
+use crate::pack_index::MultiPackIndex;
+use crate::{loose, reflog, ClawStore, HeadState, StoreError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsckIssueKind {
+    /// The object bytes could not be decoded (bad header, CRC, length or payload).
+    Corrupt,
+    /// The object decodes but hashes to a different id.
+    HashMismatch,
+    /// An object references a dependency that is not in the store.
+    MissingDependency,
+    /// A ref (or detached HEAD) points at a missing object.
+    DanglingRef,
+    /// A reflog entry points at a missing object.
+    DanglingReflog,
+    /// A pack, pack index or multi-pack index failed to open or verify.
+    CorruptPack,
+}
+
+impl FsckIssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Corrupt => "corrupt",
+            Self::HashMismatch => "hash_mismatch",
+            Self::MissingDependency => "missing_dependency",
+            Self::DanglingRef => "dangling_ref",
+            Self::DanglingReflog => "dangling_reflog",
+            Self::CorruptPack => "corrupt_pack",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FsckIssue {
+    pub kind: FsckIssueKind,
+    /// Hex id of the object the issue is about, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>,
+    /// Where the problem was found: a ref name, reflog, pack file or object id.
+    pub location: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct FsckReport {
+    pub loose_objects: usize,
+    pub packed_objects: usize,
+    pub packs: usize,
+    pub refs: usize,
+    pub reflog_entries: usize,
+    /// Encrypted objects that could not be checked because no data key is set.
+    pub encrypted_unverified: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn push(
+        &mut self,
+        kind: FsckIssueKind,
+        object: Option<&ObjectId>,
+        location: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.issues.push(FsckIssue {
+            kind,
+            object: object.map(|id| id.to_hex()),
+            location: location.into(),
+            message: message.into(),
+        });
+    }
+}
+
+/// Verify every object, pack, ref and reflog entry in the store.
+pub fn fsck(store: &ClawStore) -> Result<FsckReport, StoreError> {
+    let layout = store.layout();
+    let mut report = FsckReport::default();
+    let mut checked: HashSet<ObjectId> = HashSet::new();
+    let mut dependencies: Vec<(ObjectId, ObjectId)> = Vec::new();
+
+    for id in loose::list_loose_object_ids(layout)? {
+        report.loose_objects += 1;
+        let location = format!("loose {}", id.to_hex());
+        match loose::read_loose_object(layout, &id) {
+            Ok(cof_data) => check_object(
+                store,
+                &id,
+                &cof_data,
+                &location,
+                &mut report,
+                &mut dependencies,
+            ),
+            Err(e) => report.push(FsckIssueKind::Corrupt, Some(&id), location, e.to_string()),
+        }
+        checked.insert(id);
+    }
+
+    for pack_path in pack_paths(store)? {
+        let name = pack_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let pack = match PackHandle::open(&pack_path) {
+            Ok(pack) => pack,
+            Err(e) => {
+                report.push(FsckIssueKind::CorruptPack, None, name, e.to_string());
+                continue;
+            }
+        };
+        report.packs += 1;
+        if let Err(e) = pack.index().verify() {
+            report.push(FsckIssueKind::CorruptPack, None, &name, e.to_string());
+        }
+        for entry in pack.index().entries() {
+            report.packed_objects += 1;
+            let location = format!("{name} @ {}", entry.offset);
+            match pack.read_cof_at(entry.offset) {
+                Ok(cof_data) => check_object(
+                    store,
+                    &entry.id,
+                    &cof_data,
+                    &location,
+                    &mut report,
+                    &mut dependencies,
+                ),
+                Err(e) => report.push(
+                    FsckIssueKind::Corrupt,
+                    Some(&entry.id),
+                    location,
+                    e.to_string(),
+                ),
+            }
+            checked.insert(entry.id);
+        }
+    }
+    match MultiPackIndex::open(layout) {
+        Ok(Some(midx)) => {
+            if let Err(e) = midx.verify() {
+                report.push(
+                    FsckIssueKind::CorruptPack,
+                    None,
+                    "multi-pack-index",
+                    e.to_string(),
+                );
+            }
+        }
+        Ok(None) => {}
+        Err(e) => report.push(
+            FsckIssueKind::CorruptPack,
+            None,
+            "multi-pack-index",
+            e.to_string(),
+        ),
+    }
+
+    let mut reported_missing = HashSet::new();
+    for (referrer, dep) in dependencies {
+        if !checked.contains(&dep) && reported_missing.insert((referrer, dep)) {
+            report.push(
+                FsckIssueKind::MissingDependency,
+                Some(&dep),
+                referrer.to_hex(),
+                format!("{} references missing object {}", referrer, dep),
+            );
+        }
+    }
+
+    for (name, target) in store.list_refs("")? {
+        report.refs += 1;
+        if !checked.contains(&target) {
+            report.push(
+                FsckIssueKind::DanglingRef,
+                Some(&target),
+                format!("refs/{name}"),
+                format!("ref points at missing object {target}"),
+            );
+        }
+    }
+    if let HeadState::Detached { target } = store.read_head()? {
+        if !checked.contains(&target) {
+            report.push(
+                FsckIssueKind::DanglingRef,
+                Some(&target),
+                "HEAD",
+                format!("detached HEAD points at missing object {target}"),
+            );
+        }
+    }
+
+    for name in reflog_names(store)? {
+        for (line_no, line) in reflog::read_reflog(layout, &name)?.iter().enumerate() {
+            report.reflog_entries += 1;
+            for target in [line.old, line.new] {
+                if !reflog::is_null_id(&target) && !checked.contains(&target) {
+                    report.push(
+                        FsckIssueKind::DanglingReflog,
+                        Some(&target),
+                        format!("reflogs/{name}:{}", line_no + 1),
+                        format!("reflog entry points at missing object {target}"),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Decode one object, confirm its hash and queue its dependency edges.
+fn check_object(
+    store: &ClawStore,
+    id: &ObjectId,
+    cof_data: &[u8],
+    location: &str,
+    report: &mut FsckReport,
+    dependencies: &mut Vec<(ObjectId, ObjectId)>,
+) {
+    let (type_tag, payload) = match store.decode_cof(id, cof_data) {
+        Ok(decoded) => decoded,
+        Err(StoreError::MissingDataKey(_)) => {
+            report.encrypted_unverified += 1;
+            return;
+        }
+        Err(e) => {
+            report.push(FsckIssueKind::Corrupt, Some(id), location, e.to_string());
+            return;
+        }
+    };
+    let actual = content_hash(type_tag, &payload);
+    if actual != *id {
+        report.push(
+            FsckIssueKind::HashMismatch,
+            Some(id),
+            location,
+            format!("content hashes to {actual}"),
+        );
+        return;
+    }
+    match Object::deserialize_payload(type_tag, &payload) {
+        Ok(obj) => dependencies.extend(obj.dependencies().into_iter().map(|dep| (*id, dep))),
+        Err(e) => report.push(FsckIssueKind::Corrupt, Some(id), location, e.to_string()),
+    }
+}
+
+fn pack_paths(store: &ClawStore) -> Result<Vec<std::path::PathBuf>, StoreError> {
+    let packs_dir = store.layout().packs_dir();
+    let mut paths = Vec::new();
+    if packs_dir.exists() {
+        for entry in std::fs::read_dir(&packs_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "clwpack") {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::cof::cof_encode;
+    use claw_core::object::TypeTag;
+    use claw_core::types::{Blob, FileMode, Tree, TreeEntry};
+
+    fn blob(data: &str) -> Object {
+        Object::Blob(Blob {
+            data: data.as_bytes().to_vec(),
+            media_type: None,
+        })
+    }
+
+    #[test]
+    fn clean_store_has_no_issues() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let blob_id = store.store_object(&blob("hello")).unwrap();
+        store.set_ref("heads/main", &blob_id).unwrap();
+
+        let report = fsck(&store).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.loose_objects, 1);
+        assert_eq!(report.refs, 1);
+    }
+
+    #[test]
+    fn detects_corruption_and_dangling_edges() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        let missing_blob = claw_core::hash::content_hash(TypeTag::Blob, b"never stored");
+        let tree = store
+            .store_object(&Object::Tree(Tree {
+                entries: vec![TreeEntry {
+                    name: "gone.txt".into(),
+                    mode: FileMode::Regular,
+                    object_id: missing_blob,
+                }],
+            }))
+            .unwrap();
+        store.set_ref("heads/dangling", &missing_blob).unwrap();
+
+        // A well-formed object stored under the wrong id.
+        let wrong_id = claw_core::hash::content_hash(TypeTag::Blob, b"expected");
+        let cof = cof_encode(TypeTag::Blob, b"actual").unwrap();
+        loose::write_loose_object(store.layout(), &wrong_id, &cof).unwrap();
+
+        let report = fsck(&store).unwrap();
+        let kinds: Vec<FsckIssueKind> = report.issues.iter().map(|i| i.kind).collect();
+        assert!(kinds.contains(&FsckIssueKind::HashMismatch));
+        assert!(kinds.contains(&FsckIssueKind::MissingDependency));
+        assert!(kinds.contains(&FsckIssueKind::DanglingRef));
+        assert!(report
+            .issues
+            .iter()
+            .any(|i| i.kind == FsckIssueKind::MissingDependency && i.location == tree.to_hex()));
+    }
+}
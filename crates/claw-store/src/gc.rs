@@ -0,0 +1,503 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use claw_core::cof::cof_is_encrypted;
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+
+use crate::pack::PackWriter;
+use crate::{dictionary, loose, reflog, ClawStore, HeadState, StoreError};
+
+/// Default grace period for unreachable objects (two weeks).
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    /// Unreachable loose objects younger than this are kept.
+    pub grace_period: Duration,
+    /// Report what would happen without touching the store.
+    pub dry_run: bool,
+    /// Additional roots to keep alive (e.g. revisions of an in-progress merge).
+    pub extra_roots: Vec<ObjectId>,
+}
+
+impl Default for GcOptions {
+    fn default() -> Self {
+        Self {
+            grace_period: DEFAULT_GRACE_PERIOD,
+            dry_run: false,
+            extra_roots: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GcReport {
+    /// Objects reachable from refs, HEAD and reflogs.
+    pub reachable: usize,
+    /// Reachable objects referenced but absent from the store (partial clones).
+    pub missing: usize,
+    /// Loose objects written into the new pack.
+    pub packed: usize,
+    /// Objects carried over into the new pack from the packs it replaces.
+    pub repacked: usize,
+    /// Old packs removed after their live objects were repacked.
+    pub packs_removed: usize,
+    /// Path of the pack written, if any.
+    pub pack_path: Option<PathBuf>,
+    /// Unreachable objects removed, loose or packed.
+    pub pruned: usize,
+    /// Unreachable objects kept because they are within the grace period.
+    /// Packed ones are written back out loose.
+    pub kept_recent: usize,
+    /// Unreachable sealed objects kept because reachable sealed objects
+    /// could not be opened to see what they reference.
+    pub kept_sealed: usize,
+    /// Loose refs moved into `packed-refs`.
+    pub packed_refs: usize,
+}
+
+/// Objects reachable from the store's roots.
+#[derive(Debug, Default)]
+pub struct Reachability {
+    pub ids: HashSet<ObjectId>,
+    /// Worktree path each tree entry was first reached at, used as a delta hint.
+    pub paths: HashMap<ObjectId, String>,
+    /// Referenced objects absent from the store (partial clones).
+    pub missing: usize,
+    /// Reachable sealed objects that could not be opened without the data
+    /// key, so their dependencies are unknown.
+    pub sealed: usize,
+}
+
+/// Collect every object reachable from refs, HEAD, reflogs and `extra_roots`.
+pub fn reachable_objects(
+    store: &ClawStore,
+    extra_roots: &[ObjectId],
+) -> Result<Reachability, StoreError> {
+    let mut stack: Vec<(ObjectId, Option<String>)> =
+        extra_roots.iter().map(|id| (*id, None)).collect();
+    for (_, id) in store.list_refs("")? {
+        stack.push((id, None));
+    }
+    if let HeadState::Detached { target } = store.read_head()? {
+        stack.push((target, None));
+    }
+    for name in reflog_names(store)? {
+        for line in reflog::read_reflog(store.layout(), &name)? {
+            for id in [line.old, line.new] {
+                if !reflog::is_null_id(&id) {
+                    stack.push((id, None));
+                }
+            }
+        }
+    }
+
+    let mut result = Reachability::default();
+    while let Some((id, path)) = stack.pop() {
+        if !result.ids.insert(id) {
+            continue;
+        }
+        if let Some(path) = &path {
+            if !path.is_empty() {
+                result.paths.insert(id, path.clone());
+            }
+        }
+        let cof_data = match store.load_cof_bytes(&id) {
+            Ok(data) => data,
+            Err(StoreError::ObjectNotFound(_)) => {
+                result.missing += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if cof_is_encrypted(&cof_data) && !store.has_data_key() {
+            // Only objects at encrypted paths are sealed, and what they
+            // reference (chunks, patch bases) is sealed too; `run_gc` keeps
+            // every sealed object when any could not be walked.
+            result.sealed += 1;
+            continue;
+        }
+        let (type_tag, payload) = store.decode_cof(&id, &cof_data)?;
+        let obj = Object::deserialize_payload(type_tag, &payload)?;
+        if let Object::Tree(tree) = &obj {
+            let prefix = path.unwrap_or_default();
+            for entry in &tree.entries {
+                if !result.ids.contains(&entry.object_id) {
+                    let child = if prefix.is_empty() {
+                        entry.name.clone()
+                    } else {
+                        format!("{prefix}/{}", entry.name)
+                    };
+                    stack.push((entry.object_id, Some(child)));
+                }
+            }
+            continue;
+        }
+        let root_tree = match &obj {
+            Object::Revision(rev) => rev.tree,
+            Object::Snapshot(snapshot) => Some(snapshot.tree_root),
+            _ => None,
+        };
+        for dep in obj.dependencies() {
+            if !result.ids.contains(&dep) {
+                let hint = (root_tree == Some(dep)).then(String::new);
+                stack.push((dep, hint));
+            }
+        }
+    }
+    result.ids.retain(|id| store.has_object(id));
+    Ok(result)
+}
+
+/// Repack every reachable object, loose or packed, into one new pack and
+/// prune unreachable ones past the grace period.
+///
+/// Unreachable packed objects still within the grace period are written back
+/// out loose with their pack's modification time, so their age keeps
+/// counting from when they were packed rather than restarting.
+pub fn run_gc(store: &ClawStore, options: &GcOptions) -> Result<GcReport, StoreError> {
+    let layout = store.layout();
+    let reachable = reachable_objects(store, &options.extra_roots)?;
+    let mut report = GcReport {
+        reachable: reachable.ids.len(),
+        missing: reachable.missing,
+        ..GcReport::default()
+    };
+
+    let now = SystemTime::now();
+    let mut to_pack = Vec::new();
+    let mut to_prune = Vec::new();
+    for id in loose::list_loose_object_ids(layout)? {
+        if reachable.ids.contains(&id) {
+            to_pack.push(id);
+            continue;
+        }
+        if reachable.sealed > 0 && cof_is_encrypted(&loose::read_loose_object(layout, &id)?) {
+            report.kept_sealed += 1;
+            continue;
+        }
+        let path = loose::loose_object_path(layout, &id);
+        let age = std::fs::metadata(&path)?
+            .modified()
+            .ok()
+            .and_then(|mtime| now.duration_since(mtime).ok())
+            .unwrap_or_default();
+        if age >= options.grace_period {
+            to_prune.push(path);
+        } else {
+            report.kept_recent += 1;
+        }
+    }
+    to_pack.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+
+    // Sort the packed objects the same way. A loose copy wins over a packed
+    // one, and the first pack holding an object wins over later ones.
+    let packs = store.packs()?;
+    let mut seen: HashSet<ObjectId> = loose::list_loose_object_ids(layout)?.into_iter().collect();
+    let mut to_repack = Vec::new();
+    let mut to_loosen = Vec::new();
+    let mut pruned_packed = false;
+    for pack in &packs {
+        let mtime = std::fs::metadata(pack.pack_path())?.modified().ok();
+        let age = mtime
+            .and_then(|mtime| now.duration_since(mtime).ok())
+            .unwrap_or_default();
+        for id in pack.object_ids() {
+            if !seen.insert(id) {
+                continue;
+            }
+            if reachable.ids.contains(&id) {
+                to_repack.push((id, Arc::clone(pack)));
+                continue;
+            }
+            if reachable.sealed > 0 {
+                let cof_data = pack.read_cof_bytes(&id)?.unwrap_or_default();
+                if cof_is_encrypted(&cof_data) {
+                    report.kept_sealed += 1;
+                    to_repack.push((id, Arc::clone(pack)));
+                    continue;
+                }
+            }
+            if age >= options.grace_period {
+                report.pruned += 1;
+                pruned_packed = true;
+            } else {
+                report.kept_recent += 1;
+                to_loosen.push((id, Arc::clone(pack), mtime));
+            }
+        }
+    }
+
+    report.packed = to_pack.len();
+    report.repacked = to_repack.len();
+    report.pruned += to_prune.len();
+    // A single pack with nothing to add or drop is already as packed as it
+    // gets.
+    let rewrite = !to_pack.is_empty() || packs.len() > 1 || pruned_packed || !to_loosen.is_empty();
+    if rewrite {
+        report.packs_removed = packs.len();
+    }
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    if rewrite {
+        for (id, pack, mtime) in &to_loosen {
+            if let Some(cof_data) = pack.read_cof_bytes(id)? {
+                loose::write_loose_object(layout, id, &cof_data)?;
+                if let Some(mtime) = mtime {
+                    let file = std::fs::File::options()
+                        .write(true)
+                        .open(loose::loose_object_path(layout, id))?;
+                    file.set_modified(*mtime)?;
+                }
+            }
+        }
+
+        let dictionaries = store.dictionaries();
+        let mut writer = PackWriter::new();
+        for (dict_id, dict) in dictionaries.iter() {
+            writer.add_dictionary(dict_id, dict.to_vec());
+        }
+        for pack in &packs {
+            for (dict_id, dict) in pack.dictionaries() {
+                writer.add_dictionary(*dict_id, dict.clone());
+            }
+        }
+        for id in &to_pack {
+            let cof_data = loose::read_loose_object(layout, id)?;
+            writer.add_cof_bytes(*id, dictionary::recompress(&dictionaries, cof_data)?);
+            if let Some(path) = reachable.paths.get(id) {
+                writer.set_path_hint(id, path);
+            }
+        }
+        // Packed objects keep their encoding, which may use a dictionary
+        // only their pack carries.
+        for (id, pack) in &to_repack {
+            if let Some(cof_data) = pack.read_cof_bytes(id)? {
+                writer.add_cof_bytes(*id, cof_data);
+                if let Some(path) = reachable.paths.get(id) {
+                    writer.set_path_hint(id, path);
+                }
+            }
+        }
+
+        let pack_path = if writer.is_empty() {
+            None
+        } else {
+            Some(writer.write_pack(layout)?.0)
+        };
+        for pack in &packs {
+            if Some(pack.pack_path()) != pack_path.as_deref() {
+                std::fs::remove_file(pack.pack_path())?;
+                std::fs::remove_file(pack.pack_path().with_extension("idx"))?;
+            }
+        }
+        store.write_multi_pack_index()?;
+        for id in &to_pack {
+            remove_file_and_empty_parent(&loose::loose_object_path(layout, id))?;
+        }
+        report.pack_path = pack_path;
+    }
+    for path in &to_prune {
+        remove_file_and_empty_parent(path)?;
+    }
+    if report.pruned > 0 {
+        store.revision_graph().retain(|id| store.has_object(id))?;
+    }
+    report.packed_refs = store.pack_refs()?;
+
+    Ok(report)
+}
+
+pub(crate) fn reflog_names(store: &ClawStore) -> Result<Vec<String>, StoreError> {
+    let root = store.layout().reflogs_dir();
+    let mut names = Vec::new();
+    collect_files(&root, &root, &mut names)?;
+    Ok(names)
+}
+
+fn collect_files(dir: &Path, root: &Path, out: &mut Vec<String>) -> Result<(), StoreError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, root, out)?;
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            out.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    Ok(())
+}
+
+fn remove_file_and_empty_parent(path: &Path) -> Result<(), StoreError> {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(parent) = path.parent() {
+        // Only succeeds when the shard directory is now empty.
+        let _ = std::fs::remove_dir(parent);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::types::{Blob, FileMode, Tree, TreeEntry};
+
+    fn blob(data: &str) -> Object {
+        Object::Blob(Blob {
+            data: data.as_bytes().to_vec(),
+            media_type: None,
+        })
+    }
+
+    #[test]
+    fn gc_packs_reachable_and_prunes_unreachable() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        let kept_blob = store.store_object(&blob("kept")).unwrap();
+        let tree = store
+            .store_object(&Object::Tree(Tree {
+                entries: vec![TreeEntry {
+                    name: "a.txt".into(),
+                    mode: FileMode::Regular,
+                    object_id: kept_blob,
+                }],
+            }))
+            .unwrap();
+        store.set_ref("heads/main", &tree).unwrap();
+        let garbage = store.store_object(&blob("garbage")).unwrap();
+
+        let report = run_gc(
+            &store,
+            &GcOptions {
+                grace_period: Duration::ZERO,
+                ..GcOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(report.reachable, 2);
+        assert_eq!(report.packed, 2);
+        assert_eq!(report.pruned, 1);
+        assert!(!loose::loose_object_path(store.layout(), &kept_blob).exists());
+        assert!(store.has_object(&kept_blob));
+        assert!(store.has_object(&tree));
+        assert!(!store.has_object(&garbage));
+    }
+
+    #[test]
+    fn gc_walks_past_sealed_objects_without_the_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        crate::encryption::generate_data_key(store.layout()).unwrap();
+        let mut config = crate::repo::read_config(store.layout()).unwrap();
+        config.encryption.paths.push("secret.txt".into());
+        crate::repo::write_config(store.layout(), &config).unwrap();
+        drop(store);
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        let secret = store
+            .store_blob_for_path("secret.txt", &b"hunter2"[..])
+            .unwrap();
+        let tree = store
+            .store_object(&Object::Tree(Tree {
+                entries: vec![TreeEntry {
+                    name: "secret.txt".into(),
+                    mode: FileMode::Regular,
+                    object_id: secret,
+                }],
+            }))
+            .unwrap();
+        store.set_ref("heads/main", &tree).unwrap();
+        let old_secret = store
+            .store_blob_for_path("secret.txt", &b"old"[..])
+            .unwrap();
+        drop(store);
+
+        std::fs::remove_file(crate::layout::RepoLayout::new(tmp.path()).data_key_file()).unwrap();
+        let store = ClawStore::open(tmp.path()).unwrap();
+        let report = run_gc(
+            &store,
+            &GcOptions {
+                grace_period: Duration::ZERO,
+                ..GcOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(report.reachable, 2);
+        assert_eq!(report.kept_sealed, 1);
+        assert!(store.has_object(&secret));
+        assert!(store.has_object(&old_secret));
+    }
+
+    #[test]
+    fn gc_keeps_recent_unreachable_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let young = store.store_object(&blob("young")).unwrap();
+
+        let report = run_gc(&store, &GcOptions::default()).unwrap();
+        assert_eq!(report.pruned, 0);
+        assert_eq!(report.kept_recent, 1);
+        assert!(store.has_object(&young));
+    }
+
+    #[test]
+    fn gc_repacks_packed_objects_and_prunes_dead_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let pack_count = || {
+            std::fs::read_dir(store.layout().packs_dir())
+                .unwrap()
+                .filter(|e| {
+                    e.as_ref()
+                        .unwrap()
+                        .path()
+                        .extension()
+                        .is_some_and(|ext| ext == "clwpack")
+                })
+                .count()
+        };
+
+        let old = store.store_object(&blob("old")).unwrap();
+        store.set_ref("heads/main", &old).unwrap();
+        run_gc(&store, &GcOptions::default()).unwrap();
+
+        // `old` is packed but no longer reachable. Still young, it comes back
+        // out loose, and its pack is replaced by one holding `new`.
+        let new = store.store_object(&blob("new")).unwrap();
+        store.set_ref("heads/main", &new).unwrap();
+        let report = run_gc(&store, &GcOptions::default()).unwrap();
+        assert_eq!((report.packed, report.packs_removed), (1, 1));
+        assert_eq!(report.kept_recent, 1);
+        assert!(loose::loose_object_path(store.layout(), &old).exists());
+        assert_eq!(pack_count(), 1);
+
+        let third = store.store_object(&blob("third")).unwrap();
+        store.set_ref("heads/main", &third).unwrap();
+        let report = run_gc(
+            &store,
+            &GcOptions {
+                grace_period: Duration::ZERO,
+                ..GcOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(report.pruned, 2);
+        assert!(!store.has_object(&old));
+        assert!(!store.has_object(&new));
+        assert!(store.has_object(&third));
+        assert_eq!(pack_count(), 1);
+    }
+}
@@ -30,12 +30,16 @@ pub fn read_head(layout: &RepoLayout) -> Result<HeadState, StoreError> {
 }
 
 pub fn write_head(layout: &RepoLayout, state: &HeadState) -> Result<(), StoreError> {
-    let content = match state {
+    std::fs::write(layout.head_file(), head_content(state))?;
+    Ok(())
+}
+
+/// The contents of a HEAD file holding `state`.
+pub(crate) fn head_content(state: &HeadState) -> String {
+    match state {
         HeadState::Symbolic { ref_name } => format!("ref: {}\n", ref_name),
         HeadState::Detached { target } => format!("{}\n", target.to_hex()),
-    };
-    std::fs::write(layout.head_file(), content)?;
-    Ok(())
+    }
 }
 
 pub fn resolve_head(layout: &RepoLayout) -> Result<Option<ObjectId>, StoreError> {
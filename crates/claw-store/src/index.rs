@@ -1,55 +1,302 @@
+use claw_core::id::ObjectId;
+use redb::{MultimapTableDefinition, ReadableTableMetadata, TableDefinition};
+
 use crate::StoreError;
 
 /// Index operations backed by redb.
-/// In MVP, the index is optional - we can operate purely on loose objects and ref files.
-/// This provides an acceleration layer for type lookups and ref searches.
+/// The index is optional - everything it answers can be recomputed from loose
+/// objects, packs and ref files. It is an acceleration layer for type, size and
+/// reverse-dependency lookups.
 pub struct MetaIndex {
     _db: redb::Database,
 }
 
-const OBJECT_TYPE_TABLE: redb::TableDefinition<&[u8], u8> =
-    redb::TableDefinition::new("object_types");
+const OBJECT_TYPE_TABLE: TableDefinition<&[u8], u8> = TableDefinition::new("object_types");
+
+const OBJECT_SIZE_TABLE: TableDefinition<&[u8], u64> = TableDefinition::new("object_sizes");
+
+/// dependency id -> ids of the objects that reference it
+const REFERRERS_TABLE: MultimapTableDefinition<&[u8], &[u8]> =
+    MultimapTableDefinition::new("referrers");
+
+const META_TABLE: TableDefinition<&str, u64> = TableDefinition::new("meta");
+
+/// Set once the index covers every object in the store.
+const COMPLETE_KEY: &str = "complete";
+
+/// redb holds `meta.db` exclusively; `open` retries this many times, waiting a
+/// little longer each time, before giving up on a database held elsewhere.
+const OPEN_RETRIES: u32 = 4;
+const OPEN_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// What the index records about one object.
+#[derive(Debug, Clone)]
+pub struct IndexedObject {
+    pub id: ObjectId,
+    pub type_tag: u8,
+    pub size: u64,
+    pub dependencies: Vec<ObjectId>,
+}
+
+fn index_err(e: impl std::fmt::Display) -> StoreError {
+    StoreError::Index(e.to_string())
+}
+
+fn to_id(bytes: &[u8]) -> Option<ObjectId> {
+    let arr: [u8; 32] = bytes.try_into().ok()?;
+    Some(ObjectId::from_bytes(arr))
+}
 
 impl MetaIndex {
     pub fn open(path: &std::path::Path) -> Result<Self, StoreError> {
-        let db = redb::Database::create(path).map_err(|e| StoreError::Index(e.to_string()))?;
-        Ok(Self { _db: db })
+        let mut attempt = 0;
+        loop {
+            match redb::Database::create(path) {
+                Ok(db) => return Ok(Self { _db: db }),
+                Err(redb::DatabaseError::DatabaseAlreadyOpen) if attempt < OPEN_RETRIES => {
+                    attempt += 1;
+                    std::thread::sleep(OPEN_RETRY_DELAY * attempt);
+                }
+                Err(e) => return Err(index_err(e)),
+            }
+        }
     }
 
-    pub fn record_object(
-        &self,
-        id: &claw_core::id::ObjectId,
-        type_tag: u8,
-    ) -> Result<(), StoreError> {
-        let write_txn = self
-            ._db
-            .begin_write()
-            .map_err(|e| StoreError::Index(e.to_string()))?;
+    pub fn record_object(&self, id: &ObjectId, type_tag: u8) -> Result<(), StoreError> {
+        let write_txn = self._db.begin_write().map_err(index_err)?;
         {
-            let mut table = write_txn
-                .open_table(OBJECT_TYPE_TABLE)
-                .map_err(|e| StoreError::Index(e.to_string()))?;
+            let mut table = write_txn.open_table(OBJECT_TYPE_TABLE).map_err(index_err)?;
             table
                 .insert(id.as_bytes().as_slice(), type_tag)
-                .map_err(|e| StoreError::Index(e.to_string()))?;
+                .map_err(index_err)?;
+        }
+        write_txn.commit().map_err(index_err)?;
+        Ok(())
+    }
+
+    /// Record type, size and dependency edges for a batch of objects in one
+    /// transaction. `durable` forces an fsync; otherwise the write is flushed
+    /// by a later durable commit.
+    pub fn record_objects(
+        &self,
+        objects: &[IndexedObject],
+        durable: bool,
+    ) -> Result<(), StoreError> {
+        let mut write_txn = self._db.begin_write().map_err(index_err)?;
+        if !durable {
+            write_txn.set_durability(redb::Durability::Eventual);
         }
-        write_txn
-            .commit()
-            .map_err(|e| StoreError::Index(e.to_string()))?;
+        {
+            let mut types = write_txn.open_table(OBJECT_TYPE_TABLE).map_err(index_err)?;
+            let mut sizes = write_txn.open_table(OBJECT_SIZE_TABLE).map_err(index_err)?;
+            let mut referrers = write_txn
+                .open_multimap_table(REFERRERS_TABLE)
+                .map_err(index_err)?;
+            for obj in objects {
+                let key = obj.id.as_bytes().as_slice();
+                types.insert(key, obj.type_tag).map_err(index_err)?;
+                sizes.insert(key, obj.size).map_err(index_err)?;
+                for dep in &obj.dependencies {
+                    referrers
+                        .insert(dep.as_bytes().as_slice(), key)
+                        .map_err(index_err)?;
+                }
+            }
+        }
+        write_txn.commit().map_err(index_err)?;
         Ok(())
     }
 
-    pub fn get_type(&self, id: &claw_core::id::ObjectId) -> Result<Option<u8>, StoreError> {
-        let read_txn = self
-            ._db
-            .begin_read()
-            .map_err(|e| StoreError::Index(e.to_string()))?;
-        let table = read_txn
-            .open_table(OBJECT_TYPE_TABLE)
-            .map_err(|e| StoreError::Index(e.to_string()))?;
-        let result = table
-            .get(id.as_bytes().as_slice())
-            .map_err(|e| StoreError::Index(e.to_string()))?;
+    pub fn get_type(&self, id: &ObjectId) -> Result<Option<u8>, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let table = match read_txn.open_table(OBJECT_TYPE_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(index_err(e)),
+        };
+        let result = table.get(id.as_bytes().as_slice()).map_err(index_err)?;
         Ok(result.map(|v| v.value()))
     }
+
+    pub fn get_size(&self, id: &ObjectId) -> Result<Option<u64>, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let table = match read_txn.open_table(OBJECT_SIZE_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(e) => return Err(index_err(e)),
+        };
+        let result = table.get(id.as_bytes().as_slice()).map_err(index_err)?;
+        Ok(result.map(|v| v.value()))
+    }
+
+    /// Ids of every indexed object that lists `id` as a dependency.
+    pub fn referrers(&self, id: &ObjectId) -> Result<Vec<ObjectId>, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let table = match read_txn.open_multimap_table(REFERRERS_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(Vec::new()),
+            Err(e) => return Err(index_err(e)),
+        };
+        let mut out = Vec::new();
+        for value in table.get(id.as_bytes().as_slice()).map_err(index_err)? {
+            if let Some(referrer) = to_id(value.map_err(index_err)?.value()) {
+                out.push(referrer);
+            }
+        }
+        Ok(out)
+    }
+
+    /// For each of `ids`, the referrers whose recorded type is `type_tag`.
+    /// Answered from a single read transaction.
+    pub fn referrers_of_type(
+        &self,
+        ids: &[ObjectId],
+        type_tag: u8,
+    ) -> Result<Vec<Vec<ObjectId>>, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let referrers = match read_txn.open_multimap_table(REFERRERS_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![Vec::new(); ids.len()]),
+            Err(e) => return Err(index_err(e)),
+        };
+        let types = match read_txn.open_table(OBJECT_TYPE_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(vec![Vec::new(); ids.len()]),
+            Err(e) => return Err(index_err(e)),
+        };
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            let mut matching = Vec::new();
+            for value in referrers.get(id.as_bytes().as_slice()).map_err(index_err)? {
+                let value = value.map_err(index_err)?;
+                let Some(referrer) = to_id(value.value()) else {
+                    continue;
+                };
+                let tag = types.get(value.value()).map_err(index_err)?;
+                if tag.is_some_and(|t| t.value() == type_tag) {
+                    matching.push(referrer);
+                }
+            }
+            out.push(matching);
+        }
+        Ok(out)
+    }
+
+    pub fn is_complete(&self) -> Result<bool, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let table = match read_txn.open_table(META_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(false),
+            Err(e) => return Err(index_err(e)),
+        };
+        let value = table.get(COMPLETE_KEY).map_err(index_err)?;
+        Ok(value.is_some_and(|v| v.value() == 1))
+    }
+
+    pub fn set_complete(&self, complete: bool) -> Result<(), StoreError> {
+        let write_txn = self._db.begin_write().map_err(index_err)?;
+        {
+            let mut table = write_txn.open_table(META_TABLE).map_err(index_err)?;
+            table
+                .insert(COMPLETE_KEY, u64::from(complete))
+                .map_err(index_err)?;
+        }
+        write_txn.commit().map_err(index_err)?;
+        Ok(())
+    }
+
+    /// Drop every indexed object, marking the index incomplete.
+    pub fn clear(&self) -> Result<(), StoreError> {
+        let write_txn = self._db.begin_write().map_err(index_err)?;
+        {
+            write_txn
+                .delete_table(OBJECT_TYPE_TABLE)
+                .map_err(index_err)?;
+            write_txn
+                .delete_table(OBJECT_SIZE_TABLE)
+                .map_err(index_err)?;
+            write_txn
+                .delete_multimap_table(REFERRERS_TABLE)
+                .map_err(index_err)?;
+            let mut meta = write_txn.open_table(META_TABLE).map_err(index_err)?;
+            meta.insert(COMPLETE_KEY, 0).map_err(index_err)?;
+        }
+        write_txn.commit().map_err(index_err)?;
+        Ok(())
+    }
+
+    /// Number of objects with a recorded type.
+    pub fn object_count(&self) -> Result<u64, StoreError> {
+        let read_txn = self._db.begin_read().map_err(index_err)?;
+        let table = match read_txn.open_table(OBJECT_TYPE_TABLE) {
+            Ok(table) => table,
+            Err(redb::TableError::TableDoesNotExist(_)) => return Ok(0),
+            Err(e) => return Err(index_err(e)),
+        };
+        table.len().map_err(index_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::hash::content_hash;
+    use claw_core::object::TypeTag;
+
+    #[test]
+    fn records_types_sizes_and_referrers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index = MetaIndex::open(&tmp.path().join("meta.db")).unwrap();
+        assert!(!index.is_complete().unwrap());
+
+        let blob = content_hash(TypeTag::Blob, b"blob");
+        let tree_a = content_hash(TypeTag::Tree, b"a");
+        let tree_b = content_hash(TypeTag::Tree, b"b");
+        index
+            .record_objects(
+                &[
+                    IndexedObject {
+                        id: blob,
+                        type_tag: TypeTag::Blob as u8,
+                        size: 4,
+                        dependencies: vec![],
+                    },
+                    IndexedObject {
+                        id: tree_a,
+                        type_tag: TypeTag::Tree as u8,
+                        size: 40,
+                        dependencies: vec![blob],
+                    },
+                    IndexedObject {
+                        id: tree_b,
+                        type_tag: TypeTag::Tree as u8,
+                        size: 41,
+                        dependencies: vec![blob],
+                    },
+                ],
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(index.get_type(&blob).unwrap(), Some(TypeTag::Blob as u8));
+        assert_eq!(index.get_size(&tree_b).unwrap(), Some(41));
+        let mut referrers = index.referrers(&blob).unwrap();
+        referrers.sort_by_key(|id| id.to_hex());
+        let mut expected = vec![tree_a, tree_b];
+        expected.sort_by_key(|id| id.to_hex());
+        assert_eq!(referrers, expected);
+        assert_eq!(index.object_count().unwrap(), 3);
+        let by_type = index
+            .referrers_of_type(&[blob, tree_a], TypeTag::Tree as u8)
+            .unwrap();
+        assert_eq!(by_type[0].len(), 2);
+        assert!(by_type[1].is_empty());
+
+        index.set_complete(true).unwrap();
+        assert!(index.is_complete().unwrap());
+        index.clear().unwrap();
+        assert!(!index.is_complete().unwrap());
+        assert_eq!(index.get_type(&blob).unwrap(), None);
+        assert!(index.referrers(&blob).unwrap().is_empty());
+    }
 }
@@ -30,6 +30,10 @@ impl RepoLayout {
         self.claw_dir().join("refs")
     }
 
+    pub fn packed_refs_file(&self) -> PathBuf {
+        self.claw_dir().join("packed-refs")
+    }
+
     pub fn config_file(&self) -> PathBuf {
         self.claw_dir().join("repo.toml")
     }
@@ -38,6 +42,36 @@ impl RepoLayout {
         self.claw_dir().join("meta.db")
     }
 
+    /// Present when objects were stored without being recorded in `meta.db`.
+    pub fn index_stale_file(&self) -> PathBuf {
+        self.claw_dir().join("meta.db.stale")
+    }
+
+    /// Holds one marker per store handle with unflushed `meta.db` entries.
+    pub fn index_pending_dir(&self) -> PathBuf {
+        self.claw_dir().join("index-pending")
+    }
+
+    /// Stat cache of worktree files and their blob ids.
+    pub fn worktree_index_file(&self) -> PathBuf {
+        self.claw_dir().join("worktree-index")
+    }
+
+    /// Parents and generation numbers of every revision.
+    pub fn revision_graph_file(&self) -> PathBuf {
+        self.claw_dir().join("revision-graph")
+    }
+
+    /// Repository data key for at-rest encryption. Never synced.
+    pub fn data_key_file(&self) -> PathBuf {
+        self.claw_dir().join("keys").join("data.key")
+    }
+
+    /// Trained zstd dictionaries and the per-type selection in `active.toml`.
+    pub fn dicts_dir(&self) -> PathBuf {
+        self.claw_dir().join("dicts")
+    }
+
     pub fn packs_dir(&self) -> PathBuf {
         self.claw_dir().join("packs")
     }
@@ -1,31 +1,76 @@
+pub mod delta;
+pub mod dictionary;
+pub mod encryption;
 pub mod error;
+pub mod fsck;
+pub mod gc;
 pub mod head;
 pub mod index;
 pub mod layout;
 pub mod lockfile;
 pub mod loose;
+pub mod oplog;
 pub mod pack;
+pub mod pack_cache;
+pub mod pack_index;
+pub mod ref_transaction;
 pub mod reflog;
 pub mod refs;
 pub mod repo;
+pub mod revision_graph;
+pub mod revparse;
 pub mod tree_diff;
+pub mod worktree_index;
 
 pub use error::StoreError;
 pub use head::HeadState;
 
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
-use claw_core::cof::{cof_decode, cof_encode};
+use claw_core::chunking::{self, ChunkManifest};
+use claw_core::cof::{
+    cof_decode_with, cof_dictionary_id, cof_encode, cof_encode_sealed, cof_encode_with_dictionary,
+    cof_is_encrypted, cof_peek_type_tag, CofContext,
+};
 use claw_core::hash::content_hash;
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
+use claw_core::object::{Object, TypeTag};
+use claw_core::types::Blob;
+use claw_core::CoreError;
 
+use crate::dictionary::Dictionaries;
+use crate::encryption::{DataKey, EncryptionRules};
+use crate::index::{IndexedObject, MetaIndex};
 use crate::layout::RepoLayout;
+use crate::pack_cache::PackCache;
+use crate::revision_graph::{GraphEntry, RevisionGraph};
 
 pub struct ClawStore {
     layout: RepoLayout,
+    packs: PackCache,
+    index: Option<MetaIndex>,
+    /// Index entries for objects stored by this handle, written in batches.
+    pending_index: Mutex<Vec<IndexedObject>>,
+    /// Marks the index as having unflushed entries until this handle flushes.
+    pending_marker: PathBuf,
+    cipher: Option<DataKey>,
+    encryption: EncryptionRules,
+    /// Why the encryption rules or data key could not be loaded. Only
+    /// operations that need them fail with it.
+    encryption_error: Option<String>,
+    dictionaries: RwLock<Arc<Dictionaries>>,
+    revision_graph: RevisionGraph,
 }
 
+/// Pending index entries are flushed once this many accumulate.
+const INDEX_BATCH: usize = 256;
+
+static STORE_HANDLES: AtomicU64 = AtomicU64::new(0);
+
 impl ClawStore {
     pub fn init(root: &Path) -> Result<Self, StoreError> {
         let layout = RepoLayout::new(root);
@@ -37,7 +82,12 @@ impl ClawStore {
                 ref_name: "heads/main".to_string(),
             },
         )?;
-        Ok(Self { layout })
+        let index = open_index(&layout);
+        if let Some(index) = &index {
+            // An empty repository is trivially fully indexed.
+            index.set_complete(true)?;
+        }
+        Self::with_index(layout, index)
     }
 
     pub fn open(root: &Path) -> Result<Self, StoreError> {
@@ -57,7 +107,55 @@ impl ClawStore {
         if !layout.reflogs_dir().exists() {
             std::fs::create_dir_all(layout.reflogs_dir())?;
         }
-        Ok(Self { layout })
+        let index = open_index(&layout);
+        let store = Self::with_index(layout, index)?;
+        if store.index.is_some() && store.layout.index_stale_file().exists() {
+            // Left by a process that stored objects while it couldn't open the index.
+            if let Err(e) = store.rebuild_index() {
+                tracing::warn!("meta index rebuild failed: {e}");
+            }
+        }
+        Ok(store)
+    }
+
+    fn with_index(layout: RepoLayout, index: Option<MetaIndex>) -> Result<Self, StoreError> {
+        let marker = format!(
+            "{}-{}",
+            std::process::id(),
+            STORE_HANDLES.fetch_add(1, Ordering::Relaxed)
+        );
+        let pending_marker = layout.index_pending_dir().join(marker);
+        let mut encryption_error = None;
+        let encryption = match repo::read_config(&layout)
+            .and_then(|config| EncryptionRules::new(&config.encryption.paths))
+        {
+            Ok(rules) => rules,
+            Err(StoreError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                EncryptionRules::default()
+            }
+            Err(e) => {
+                encryption_error = Some(format!("cannot load encryption rules: {e}"));
+                EncryptionRules::default()
+            }
+        };
+        let cipher = encryption::load_data_key(&layout).unwrap_or_else(|e| {
+            encryption_error = Some(format!("cannot load data key: {e}"));
+            None
+        });
+        let dictionaries = Dictionaries::load(&layout)?;
+        let revision_graph = RevisionGraph::new(layout.revision_graph_file());
+        Ok(Self {
+            layout,
+            packs: PackCache::new(),
+            index,
+            pending_index: Mutex::new(Vec::new()),
+            pending_marker,
+            cipher,
+            encryption,
+            encryption_error,
+            dictionaries: RwLock::new(Arc::new(dictionaries)),
+            revision_graph,
+        })
     }
 
     pub fn root(&self) -> &Path {
@@ -69,31 +167,559 @@ impl ClawStore {
     }
 
     pub fn store_object(&self, obj: &Object) -> Result<ObjectId, StoreError> {
+        self.write_object(obj, None)
+    }
+
+    /// Store an object belonging to worktree `path`, encrypted when the path
+    /// matches an encryption rule.
+    pub fn store_object_for_path(&self, path: &str, obj: &Object) -> Result<ObjectId, StoreError> {
+        self.check_encryption()?;
+        if self.encrypts_path(path) {
+            self.store_object_encrypted(obj)
+        } else {
+            self.store_object(obj)
+        }
+    }
+
+    /// Store `obj` with its payload sealed under the repository data key.
+    /// The id is still the hash of the plaintext.
+    pub fn store_object_encrypted(&self, obj: &Object) -> Result<ObjectId, StoreError> {
+        self.check_encryption()?;
+        let cipher = self
+            .cipher
+            .as_ref()
+            .ok_or_else(|| StoreError::MissingDataKey("cannot store encrypted object".into()))?;
+        self.write_object(obj, Some(cipher))
+    }
+
+    /// Store an object received as COF bytes, returning the id it hashes
+    /// to. Sealed payloads are kept exactly as received, so content sealed
+    /// elsewhere never lands on disk in the clear; they are only written if
+    /// they hash to `expected`. Without the data key that can't be checked:
+    /// the object is stored but the meta index is marked incomplete, and a
+    /// later copy that does verify replaces it.
+    pub fn store_received_cof(
+        &self,
+        expected: &ObjectId,
+        cof_data: &[u8],
+    ) -> Result<ObjectId, StoreError> {
+        if !cof_is_encrypted(cof_data) {
+            let (type_tag, payload) = self.decode_cof(expected, cof_data)?;
+            let obj = Object::deserialize_payload(type_tag, &payload)?;
+            if content_hash(type_tag, &payload) == *expected && self.loose_copy_unverified(expected)
+            {
+                loose::replace_loose_object(&self.layout, expected, cof_data)?;
+                self.index_object(IndexedObject {
+                    id: *expected,
+                    type_tag: type_tag as u8,
+                    size: payload.len() as u64,
+                    dependencies: obj.dependencies(),
+                });
+            }
+            return self.store_object(&obj);
+        }
+        let type_tag = cof_peek_type_tag(cof_data)?;
+        if self.cipher.is_none() {
+            if loose::write_loose_object(&self.layout, expected, cof_data)? {
+                self.index_object(IndexedObject {
+                    id: *expected,
+                    type_tag: type_tag as u8,
+                    size: cof_data.len() as u64,
+                    dependencies: Vec::new(),
+                });
+                // Its dependencies are unknown until it can be decoded.
+                self.mark_index_incomplete();
+            }
+            return Ok(*expected);
+        }
+        let (type_tag, payload) = self.decode_cof(expected, cof_data)?;
+        let obj = Object::deserialize_payload(type_tag, &payload)?;
+        let id = content_hash(type_tag, &payload);
+        if id != *expected {
+            return Ok(id);
+        }
+        if !loose::write_loose_object(&self.layout, &id, cof_data)? {
+            if !self.loose_copy_unverified(&id) {
+                return Ok(id);
+            }
+            loose::replace_loose_object(&self.layout, &id, cof_data)?;
+        }
+        self.index_object(IndexedObject {
+            id,
+            type_tag: type_tag as u8,
+            size: payload.len() as u64,
+            dependencies: obj.dependencies(),
+        });
+        Ok(id)
+    }
+
+    /// Whether a loose copy of `id` exists that can't be shown to hash to
+    /// `id`, e.g. a sealed object received before the data key was available.
+    fn loose_copy_unverified(&self, id: &ObjectId) -> bool {
+        let Ok(existing) = loose::read_loose_object(&self.layout, id) else {
+            return false;
+        };
+        match self.decode_cof(id, &existing) {
+            Ok((type_tag, payload)) => content_hash(type_tag, &payload) != *id,
+            Err(_) => true,
+        }
+    }
+
+    /// The deferred error from loading the encryption rules or data key.
+    fn check_encryption(&self) -> Result<(), StoreError> {
+        match &self.encryption_error {
+            Some(reason) => Err(StoreError::Config(reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn write_object(&self, obj: &Object, cipher: Option<&DataKey>) -> Result<ObjectId, StoreError> {
         let payload = obj.serialize_payload()?;
         let type_tag = obj.type_tag();
         let id = content_hash(type_tag, &payload);
-        let cof_data = cof_encode(type_tag, &payload)?;
-        loose::write_loose_object(&self.layout, &id, &cof_data)?;
+        let dictionaries = self.dictionaries();
+        let cof_data = match (cipher, dictionaries.active(type_tag)) {
+            (Some(cipher), _) => cof_encode_sealed(type_tag, &payload, cipher)?,
+            (None, Some((dict_id, dict))) if payload.len() <= dictionary::SMALL_OBJECT_MAX => {
+                cof_encode_with_dictionary(type_tag, &payload, dict_id, dict)?
+            }
+            (None, _) => cof_encode(type_tag, &payload)?,
+        };
+        if loose::write_loose_object(&self.layout, &id, &cof_data)? {
+            self.index_object(IndexedObject {
+                id,
+                type_tag: type_tag as u8,
+                size: payload.len() as u64,
+                dependencies: obj.dependencies(),
+            });
+        } else if cipher.is_some()
+            && !cof_is_encrypted(&loose::read_loose_object(&self.layout, &id)?)
+        {
+            // Stored in the clear before its path matched an encryption rule.
+            loose::replace_loose_object(&self.layout, &id, &cof_data)?;
+        }
+        if let Object::Revision(rev) = obj {
+            // Like the meta index, the graph can be rebuilt; don't fail the write.
+            if let Err(e) = revision_graph::record(self, &id, rev) {
+                tracing::warn!("revision graph update failed: {e}");
+            }
+        }
         Ok(id)
     }
 
+    /// Queue a newly stored object for the meta index. Failures only mark the
+    /// index stale; the object itself is already safely on disk.
+    fn index_object(&self, entry: IndexedObject) {
+        if self.index.is_none() {
+            self.mark_index_stale();
+            return;
+        }
+        let Ok(mut pending) = self.pending_index.lock() else {
+            self.mark_index_stale();
+            return;
+        };
+        if pending.is_empty() {
+            // Until the batch is flushed, other processes must not trust the index.
+            let created = std::fs::create_dir_all(self.layout.index_pending_dir())
+                .and_then(|_| std::fs::write(&self.pending_marker, b""));
+            if created.is_err() {
+                self.mark_index_stale();
+            }
+        }
+        pending.push(entry);
+        if pending.len() >= INDEX_BATCH {
+            self.flush_pending(&mut pending);
+        }
+    }
+
+    fn flush_pending(&self, pending: &mut Vec<IndexedObject>) {
+        if pending.is_empty() {
+            return;
+        }
+        let result = match &self.index {
+            Some(index) => index.record_objects(pending, true),
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                let _ = std::fs::remove_file(&self.pending_marker);
+            }
+            Err(e) => {
+                tracing::warn!("meta index update failed: {e}");
+                self.mark_index_stale();
+            }
+        }
+        pending.clear();
+    }
+
+    /// Write any queued meta index entries.
+    pub fn flush_index(&self) {
+        if let Ok(mut pending) = self.pending_index.lock() {
+            self.flush_pending(&mut pending);
+        }
+    }
+
+    /// Stop trusting the meta index until it is rebuilt, without forcing a
+    /// rebuild on the next open.
+    fn mark_index_incomplete(&self) {
+        let marked = match &self.index {
+            Some(index) => index.set_complete(false).is_ok(),
+            None => false,
+        };
+        if !marked {
+            self.mark_index_stale();
+        }
+    }
+
+    fn mark_index_stale(&self) {
+        let stale = self.layout.index_stale_file();
+        if !stale.exists() {
+            let _ = std::fs::write(stale, b"");
+        }
+    }
+
+    /// Whether the meta index is open, complete and has no unflushed writers.
+    pub fn index_is_usable(&self) -> bool {
+        self.meta_index().is_some()
+    }
+
+    /// The meta index, if it is open and known to cover every object.
+    pub fn meta_index(&self) -> Option<&MetaIndex> {
+        let index = self.index.as_ref()?;
+        self.flush_index();
+        if self.layout.index_stale_file().exists() || !index.is_complete().unwrap_or(false) {
+            return None;
+        }
+        // Another handle with unflushed entries (or one that crashed) leaves a marker.
+        if let Ok(entries) = std::fs::read_dir(self.layout.index_pending_dir()) {
+            if entries.flatten().any(|e| e.path() != self.pending_marker) {
+                return None;
+            }
+        }
+        Some(index)
+    }
+
+    /// Drop and rebuild the meta index from every loose and packed object.
+    /// Returns the number of objects indexed.
+    pub fn rebuild_index(&self) -> Result<usize, StoreError> {
+        let index = self.index.as_ref().ok_or_else(|| {
+            StoreError::Index("meta index is unavailable (in use by another process?)".into())
+        })?;
+        self.flush_index();
+        // Clear the markers first: a writer that can't reach the index while
+        // the rebuild runs sets them again and the next open rebuilds once more.
+        let stale = self.layout.index_stale_file();
+        if stale.exists() {
+            std::fs::remove_file(stale)?;
+        }
+        let pending_dir = self.layout.index_pending_dir();
+        if pending_dir.exists() {
+            std::fs::remove_dir_all(pending_dir)?;
+        }
+        index.clear()?;
+        let ids = self.list_object_ids()?;
+        for chunk in ids.chunks(1024) {
+            let mut batch = Vec::with_capacity(chunk.len());
+            for id in chunk {
+                let (type_tag, payload) = self.decode_cof(id, &self.load_cof_bytes(id)?)?;
+                let obj = Object::deserialize_payload(type_tag, &payload)?;
+                batch.push(IndexedObject {
+                    id: *id,
+                    type_tag: type_tag as u8,
+                    size: payload.len() as u64,
+                    dependencies: obj.dependencies(),
+                });
+            }
+            index.record_objects(&batch, false)?;
+        }
+        index.set_complete(true)?;
+        Ok(ids.len())
+    }
+
+    /// The type of an object, from the meta index when possible.
+    pub fn object_type(&self, id: &ObjectId) -> Result<TypeTag, StoreError> {
+        if let Some(index) = &self.index {
+            // Type entries never go stale, so even a partial index can answer.
+            if let Ok(Some(tag)) = index.get_type(id) {
+                if let Some(tag) = TypeTag::from_u8(tag) {
+                    return Ok(tag);
+                }
+            }
+        }
+        Ok(cof_peek_type_tag(&self.load_cof_bytes(id)?)?)
+    }
+
+    /// Objects that reference `id`, or `None` when the meta index can't answer.
+    /// Referrers pruned by gc since they were indexed are skipped.
+    pub fn referrers(&self, id: &ObjectId) -> Result<Option<Vec<ObjectId>>, StoreError> {
+        let Some(index) = self.meta_index() else {
+            return Ok(None);
+        };
+        let mut out = index.referrers(id)?;
+        out.retain(|referrer| self.has_object(referrer));
+        Ok(Some(out))
+    }
+
+    /// Objects of type `type_tag` that reference `id`, or `None` when the meta
+    /// index can't answer.
+    pub fn referrers_of_type(
+        &self,
+        id: &ObjectId,
+        type_tag: TypeTag,
+    ) -> Result<Option<Vec<ObjectId>>, StoreError> {
+        Ok(self
+            .referrers_of_type_many(std::slice::from_ref(id), type_tag)?
+            .and_then(|mut all| all.pop()))
+    }
+
+    /// [`referrers_of_type`](Self::referrers_of_type) for many ids at once,
+    /// in the same order as `ids`.
+    pub fn referrers_of_type_many(
+        &self,
+        ids: &[ObjectId],
+        type_tag: TypeTag,
+    ) -> Result<Option<Vec<Vec<ObjectId>>>, StoreError> {
+        let Some(index) = self.meta_index() else {
+            return Ok(None);
+        };
+        let mut out = index.referrers_of_type(ids, type_tag as u8)?;
+        for referrers in &mut out {
+            referrers.retain(|referrer| self.has_object(referrer));
+        }
+        Ok(Some(out))
+    }
+
+    pub fn revision_graph(&self) -> &RevisionGraph {
+        &self.revision_graph
+    }
+
+    /// Parents, generation and timestamp of revision `id` from the revision
+    /// graph, computed on a miss. `None` if `id` is not a revision.
+    pub fn revision_entry(&self, id: &ObjectId) -> Result<Option<GraphEntry>, StoreError> {
+        revision_graph::resolve(self, id)
+    }
+
     pub fn load_object(&self, id: &ObjectId) -> Result<Object, StoreError> {
-        let cof_data = loose::read_loose_object(&self.layout, id)?;
-        let (type_tag, payload) = cof_decode(&cof_data)?;
+        let cof_data = self.load_cof_bytes(id)?;
+        let (type_tag, payload) = self.decode_cof(id, &cof_data)?;
         let obj = Object::deserialize_payload(type_tag, &payload)?;
         Ok(obj)
     }
 
+    /// Decode COF bytes for `id`, opening encrypted payloads with the data key.
+    pub fn decode_cof(
+        &self,
+        id: &ObjectId,
+        cof_data: &[u8],
+    ) -> Result<(TypeTag, Vec<u8>), StoreError> {
+        let dictionaries = self.dictionaries();
+        let mut ctx = CofContext {
+            cipher: self
+                .cipher
+                .as_ref()
+                .map(|c| c as &dyn claw_core::cof::PayloadCipher),
+            dictionaries: Some(dictionaries.as_ref()),
+        };
+        let mut result = cof_decode_with(cof_data, ctx);
+        // Objects from a pack may use a dictionary only that pack carries.
+        let carried;
+        if let Err(CoreError::UnknownDictionary(dict_id)) = result {
+            carried = self.pack_dictionary(dict_id)?;
+            ctx.dictionaries = Some(&carried);
+            result = cof_decode_with(cof_data, ctx);
+        }
+        result.map_err(|e| match (e, &self.encryption_error) {
+            (CoreError::Encrypted, Some(reason)) => {
+                StoreError::Config(format!("object {id} is encrypted; {reason}"))
+            }
+            (CoreError::Encrypted, None) => {
+                StoreError::MissingDataKey(format!("object {id} is encrypted"))
+            }
+            (other, _) => other.into(),
+        })
+    }
+
+    /// The trained dictionaries loaded from `.claw/dicts`.
+    pub fn dictionaries(&self) -> Arc<Dictionaries> {
+        match self.dictionaries.read() {
+            Ok(dicts) => Arc::clone(&dicts),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// Re-read `.claw/dicts`, e.g. after training new dictionaries.
+    pub fn reload_dictionaries(&self) -> Result<(), StoreError> {
+        let dicts = Arc::new(Dictionaries::load(&self.layout)?);
+        match self.dictionaries.write() {
+            Ok(mut current) => *current = dicts,
+            Err(poisoned) => *poisoned.into_inner() = dicts,
+        }
+        Ok(())
+    }
+
+    /// Dictionary `dict_id` as carried by one of the packs, if any.
+    fn pack_dictionary(&self, dict_id: u32) -> Result<HashMap<u32, Vec<u8>>, StoreError> {
+        let mut found = HashMap::new();
+        for pack in self.packs()? {
+            if let Some(dict) = pack.dictionaries().get(&dict_id) {
+                found.insert(dict_id, dict.clone());
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    /// The bytes of dictionary `dict_id`, from `.claw/dicts` or a pack.
+    pub fn dictionary_bytes(&self, dict_id: u32) -> Result<Option<Vec<u8>>, StoreError> {
+        if let Some(dict) = self.dictionaries().get(dict_id) {
+            return Ok(Some(dict.to_vec()));
+        }
+        Ok(self.pack_dictionary(dict_id)?.remove(&dict_id))
+    }
+
+    /// Keep a dictionary a peer sent along with objects compressed with it,
+    /// without activating it. Its id must match its content.
+    pub fn store_received_dictionary(&self, dict_id: u32, dict: &[u8]) -> Result<(), StoreError> {
+        if dictionary::dictionary_id(dict) != dict_id {
+            return Err(StoreError::Config(format!(
+                "received dictionary does not match its id {dict_id:08x}"
+            )));
+        }
+        if self.dictionaries().get(dict_id).is_none() {
+            dictionary::save_dictionary(&self.layout, dict_id, dict)?;
+            self.reload_dictionaries()?;
+        }
+        Ok(())
+    }
+
+    /// Whether objects at worktree `path` are stored encrypted.
+    pub fn encrypts_path(&self, path: &str) -> bool {
+        self.encryption.matches(path)
+    }
+
+    /// Whether the repository data key is available to this handle.
+    pub fn has_data_key(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Store the contents of `reader` as a blob, chunking it when it is large.
+    /// At most one chunk is held in memory at a time.
+    pub fn store_blob_from_reader(&self, reader: impl Read) -> Result<ObjectId, StoreError> {
+        chunking::build_blob(reader, |obj| self.store_object(obj))
+    }
+
+    /// Like [`store_blob_from_reader`](Self::store_blob_from_reader), but the
+    /// blob and its chunks are encrypted when `path` matches an encryption rule.
+    pub fn store_blob_for_path(
+        &self,
+        path: &str,
+        reader: impl Read,
+    ) -> Result<ObjectId, StoreError> {
+        self.check_encryption()?;
+        if self.encrypts_path(path) {
+            chunking::build_blob(reader, |obj| self.store_object_encrypted(obj))
+        } else {
+            self.store_blob_from_reader(reader)
+        }
+    }
+
+    /// Stream a blob's content into `out`, reassembling chunked blobs one
+    /// chunk at a time. Returns the number of bytes written.
+    pub fn write_blob_to(&self, id: &ObjectId, out: &mut impl Write) -> Result<u64, StoreError> {
+        let blob = self.load_blob(id)?;
+        match ChunkManifest::from_blob(&blob) {
+            Some(manifest) => {
+                let mut written = 0;
+                for chunk in manifest?.chunks {
+                    let chunk_blob = self.load_blob(&chunk.id)?;
+                    out.write_all(&chunk_blob.data)?;
+                    written += chunk_blob.data.len() as u64;
+                }
+                Ok(written)
+            }
+            None => {
+                out.write_all(&blob.data)?;
+                Ok(blob.data.len() as u64)
+            }
+        }
+    }
+
+    /// A blob's full content, reassembled if it is chunked.
+    pub fn read_blob(&self, id: &ObjectId) -> Result<Vec<u8>, StoreError> {
+        let mut data = Vec::new();
+        self.write_blob_to(id, &mut data)?;
+        Ok(data)
+    }
+
+    /// Whether `id` is the manifest of a chunked blob.
+    pub fn is_chunked_blob(&self, id: &ObjectId) -> Result<bool, StoreError> {
+        Ok(self.load_blob(id)?.is_chunk_manifest())
+    }
+
+    /// A blob's content length, read from the manifest of a chunked blob
+    /// rather than by reassembling it.
+    pub fn blob_size(&self, id: &ObjectId) -> Result<u64, StoreError> {
+        let blob = self.load_blob(id)?;
+        match ChunkManifest::from_blob(&blob) {
+            Some(manifest) => Ok(manifest?.total_size),
+            None => Ok(blob.data.len() as u64),
+        }
+    }
+
+    fn load_blob(&self, id: &ObjectId) -> Result<Blob, StoreError> {
+        match self.load_object(id)? {
+            Object::Blob(blob) => Ok(blob),
+            _ => Err(StoreError::NotABlob(*id)),
+        }
+    }
+
     /// Read the raw COF-encoded bytes for an object without decoding.
     ///
     /// This avoids the decode → re-encode cycle when the COF bytes will be
     /// sent over the wire unmodified (e.g., pack uploads, inline batch uploads).
+    ///
+    /// Loose objects are checked first, then every pack in `.claw/packs`.
     pub fn load_cof_bytes(&self, id: &ObjectId) -> Result<Vec<u8>, StoreError> {
-        loose::read_loose_object(&self.layout, id)
+        match loose::read_loose_object(&self.layout, id) {
+            Err(StoreError::ObjectNotFound(_)) => self
+                .packs
+                .read_cof_bytes(&self.layout, id)?
+                .ok_or(StoreError::ObjectNotFound(*id)),
+            other => other,
+        }
+    }
+
+    /// Raw COF bytes that decode without this repository's dictionaries.
+    /// Dictionary-compressed objects are re-encoded with plain zstd.
+    pub fn load_portable_cof_bytes(&self, id: &ObjectId) -> Result<Vec<u8>, StoreError> {
+        let cof_data = self.load_cof_bytes(id)?;
+        if cof_dictionary_id(&cof_data).is_none() {
+            return Ok(cof_data);
+        }
+        let (type_tag, payload) = self.decode_cof(id, &cof_data)?;
+        Ok(cof_encode(type_tag, &payload)?)
     }
 
     pub fn has_object(&self, id: &ObjectId) -> bool {
         loose::loose_object_path(&self.layout, id).exists()
+            || matches!(self.packs.find(&self.layout, id), Ok(Some(_)))
+    }
+
+    /// Rescan `.claw/packs` so newly written or removed packs are picked up.
+    pub fn refresh_packs(&self) -> Result<(), StoreError> {
+        self.packs.refresh(&self.layout)
+    }
+
+    /// All packs currently in `.claw/packs`.
+    pub fn packs(&self) -> Result<Vec<std::sync::Arc<pack::PackHandle>>, StoreError> {
+        self.packs.packs(&self.layout)
+    }
+
+    /// Rebuild the multi-pack index over every pack and start using it.
+    pub fn write_multi_pack_index(&self) -> Result<std::path::PathBuf, StoreError> {
+        let path = pack_index::write_multi_pack_index(&self.layout)?;
+        self.packs.refresh(&self.layout)?;
+        Ok(path)
     }
 
     pub fn set_ref(&self, name: &str, target: &ObjectId) -> Result<(), StoreError> {
@@ -112,6 +738,22 @@ impl ClawStore {
         refs::delete_ref(&self.layout, name)
     }
 
+    /// Start a transaction that applies several ref changes atomically.
+    pub fn transaction(&self) -> ref_transaction::RefTransaction<'_> {
+        ref_transaction::RefTransaction::new(&self.layout)
+    }
+
+    /// Move all loose refs into `packed-refs`.
+    pub fn pack_refs(&self) -> Result<usize, StoreError> {
+        refs::pack_refs(&self.layout)
+    }
+
+    /// Resolve a revision expression such as `main~2`, `HEAD@{1}` or an
+    /// abbreviated id. See [`revparse`] for the full syntax.
+    pub fn rev_parse(&self, spec: &str) -> Result<ObjectId, StoreError> {
+        revparse::resolve(self, spec)
+    }
+
     pub fn read_head(&self) -> Result<HeadState, StoreError> {
         head::read_head(&self.layout)
     }
@@ -142,7 +784,308 @@ impl ClawStore {
         )
     }
 
+    /// List every object id in the store, loose and packed, without duplicates.
     pub fn list_object_ids(&self) -> Result<Vec<ObjectId>, StoreError> {
-        loose::list_loose_object_ids(&self.layout)
+        let mut ids = loose::list_loose_object_ids(&self.layout)?;
+        let mut seen: HashSet<ObjectId> = ids.iter().copied().collect();
+        for id in self.packs.object_ids(&self.layout)? {
+            if seen.insert(id) {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+}
+
+impl Drop for ClawStore {
+    fn drop(&mut self) {
+        self.flush_index();
+    }
+}
+
+/// Open `meta.db`, or run without it if another process keeps it open. Objects
+/// stored without the index mark it stale, and the next handle that does open
+/// it rebuilds it.
+fn open_index(layout: &RepoLayout) -> Option<MetaIndex> {
+    match MetaIndex::open(&layout.index_file()) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            tracing::warn!("meta index unavailable, running without it: {e}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::PackWriter;
+    use claw_core::types::Blob;
+
+    fn blob_bytes(data: &[u8]) -> Object {
+        Object::Blob(Blob {
+            data: data.to_vec(),
+            media_type: None,
+        })
+    }
+
+    fn blob(data: &str) -> Object {
+        Object::Blob(Blob {
+            data: data.as_bytes().to_vec(),
+            media_type: None,
+        })
+    }
+
+    #[test]
+    fn packed_objects_are_visible_to_store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let loose_id = store.store_object(&blob("loose")).unwrap();
+
+        let mut writer = PackWriter::new();
+        let packed_id = writer.add_object(&blob("packed")).unwrap();
+        writer.write_pack(store.layout()).unwrap();
+
+        assert!(store.has_object(&packed_id));
+        match store.load_object(&packed_id).unwrap() {
+            Object::Blob(b) => assert_eq!(b.data, b"packed"),
+            _ => panic!("expected blob"),
+        }
+
+        let ids = store.list_object_ids().unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&loose_id));
+        assert!(ids.contains(&packed_id));
+    }
+
+    #[test]
+    fn large_blobs_are_chunked_and_streamed_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let data: Vec<u8> = (0..chunking::CHUNKING_THRESHOLD + 512 * 1024)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+
+        let id = store.store_blob_from_reader(data.as_slice()).unwrap();
+        assert!(store.is_chunked_blob(&id).unwrap());
+        assert_eq!(
+            id,
+            chunking::build_blob(data.as_slice(), chunking::hash_object).unwrap()
+        );
+        assert_eq!(store.read_blob(&id).unwrap(), data);
+
+        let small = store.store_blob_from_reader(&b"small"[..]).unwrap();
+        assert!(!store.is_chunked_blob(&small).unwrap());
+        assert_eq!(store.read_blob(&small).unwrap(), b"small");
+    }
+
+    #[test]
+    fn encrypted_paths_are_sealed_on_disk() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        encryption::generate_data_key(store.layout()).unwrap();
+        let mut config = repo::read_config(store.layout()).unwrap();
+        config.encryption.paths.push("secrets/".into());
+        repo::write_config(store.layout(), &config).unwrap();
+        drop(store);
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        let secret = b"api-token=hunter2hunter2hunter2";
+        let id = store
+            .store_blob_for_path("secrets/token.txt", &secret[..])
+            .unwrap();
+        let plain = store
+            .store_blob_for_path("README.md", &b"hello"[..])
+            .unwrap();
+        assert_eq!(id, chunking::hash_object(&blob_bytes(secret)).unwrap());
+
+        let on_disk = std::fs::read(loose::loose_object_path(store.layout(), &id)).unwrap();
+        assert!(claw_core::cof::cof_is_encrypted(&on_disk));
+        assert!(!on_disk.windows(7).any(|w| w == b"hunter2"));
+        assert_eq!(store.read_blob(&id).unwrap(), secret);
+        drop(store);
+
+        std::fs::remove_file(RepoLayout::new(tmp.path()).data_key_file()).unwrap();
+        let store = ClawStore::open(tmp.path()).unwrap();
+        assert!(matches!(
+            store.load_object(&id),
+            Err(StoreError::MissingDataKey(_))
+        ));
+        assert_eq!(store.read_blob(&plain).unwrap(), b"hello");
+        assert!(store
+            .store_blob_for_path("secrets/new.txt", &b"x"[..])
+            .is_err());
+
+        let report = fsck::fsck(&store).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.encrypted_unverified, 1);
+    }
+
+    #[test]
+    fn encrypted_write_reseals_plaintext_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let plain = store
+            .store_blob_for_path("secret.txt", &b"hunter2"[..])
+            .unwrap();
+        encryption::generate_data_key(store.layout()).unwrap();
+        let mut config = repo::read_config(store.layout()).unwrap();
+        config.encryption.paths.push("secret.txt".into());
+        repo::write_config(store.layout(), &config).unwrap();
+        drop(store);
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        let sealed = store
+            .store_blob_for_path("secret.txt", &b"hunter2"[..])
+            .unwrap();
+        assert_eq!(sealed, plain);
+        let on_disk = std::fs::read(loose::loose_object_path(store.layout(), &sealed)).unwrap();
+        assert!(claw_core::cof::cof_is_encrypted(&on_disk));
+    }
+
+    #[test]
+    fn unverifiable_sealed_copy_is_replaced_by_a_verified_one() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sender = ClawStore::init(&tmp.path().join("sender")).unwrap();
+        encryption::generate_data_key(sender.layout()).unwrap();
+        let mut config = repo::read_config(sender.layout()).unwrap();
+        config.encryption.paths.push("secrets/".into());
+        repo::write_config(sender.layout(), &config).unwrap();
+        drop(sender);
+        let sender = ClawStore::open(&tmp.path().join("sender")).unwrap();
+        let genuine = sender
+            .store_blob_for_path("secrets/a.txt", &b"genuine"[..])
+            .unwrap();
+        let other = sender
+            .store_blob_for_path("secrets/b.txt", &b"forged"[..])
+            .unwrap();
+        let forged = std::fs::read(loose::loose_object_path(sender.layout(), &other)).unwrap();
+
+        // Without the data key the forged copy can't be told apart.
+        let receiver = ClawStore::init(&tmp.path().join("receiver")).unwrap();
+        assert!(receiver.index_is_usable());
+        assert_eq!(
+            receiver.store_received_cof(&genuine, &forged).unwrap(),
+            genuine
+        );
+        assert!(!receiver.index_is_usable());
+
+        let payload = blob_bytes(b"genuine").serialize_payload().unwrap();
+        let plain = claw_core::cof::cof_encode(TypeTag::Blob, &payload).unwrap();
+        receiver.store_received_cof(&genuine, &plain).unwrap();
+        assert_eq!(receiver.read_blob(&genuine).unwrap(), b"genuine");
+    }
+
+    #[test]
+    fn bad_encryption_config_only_fails_encryption() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        drop(store);
+        std::fs::write(RepoLayout::new(tmp.path()).config_file(), "version = [").unwrap();
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        assert!(store.store_object(&blob_bytes(b"fine")).is_ok());
+        assert!(matches!(
+            store.store_blob_for_path("a.txt", &b"x"[..]),
+            Err(StoreError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn missing_object_is_not_found() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let id = claw_core::hash::content_hash(claw_core::object::TypeTag::Blob, b"nope");
+        assert!(!store.has_object(&id));
+        assert!(matches!(
+            store.load_object(&id),
+            Err(StoreError::ObjectNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn multi_pack_index_covers_every_pack() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+
+        let mut ids = Vec::new();
+        for name in ["one", "two"] {
+            let mut writer = PackWriter::new();
+            ids.push(writer.add_object(&blob(name)).unwrap());
+            writer.write_pack(store.layout()).unwrap();
+        }
+        store.write_multi_pack_index().unwrap();
+        let midx = pack_index::MultiPackIndex::open(store.layout())
+            .unwrap()
+            .unwrap();
+        assert_eq!(midx.pack_names().len(), 2);
+        assert_eq!(midx.len(), 2);
+        midx.verify().unwrap();
+
+        // A pack written after the multi-pack index is still found.
+        let mut writer = PackWriter::new();
+        ids.push(writer.add_object(&blob("three")).unwrap());
+        writer.write_pack(store.layout()).unwrap();
+
+        for id in &ids {
+            assert!(store.has_object(id));
+            store.load_object(id).unwrap();
+        }
+    }
+
+    #[test]
+    fn meta_index_tracks_referrers_and_rebuilds() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let blob_id = store.store_object(&blob("content")).unwrap();
+        let tree_id = store
+            .store_object(&Object::Tree(claw_core::types::Tree {
+                entries: vec![claw_core::types::TreeEntry {
+                    name: "f.txt".into(),
+                    mode: claw_core::types::FileMode::Regular,
+                    object_id: blob_id,
+                }],
+            }))
+            .unwrap();
+
+        assert_eq!(store.object_type(&tree_id).unwrap(), TypeTag::Tree);
+        assert_eq!(store.referrers(&blob_id).unwrap(), Some(vec![tree_id]));
+        assert_eq!(
+            store
+                .referrers_of_type(&blob_id, TypeTag::Revision)
+                .unwrap(),
+            Some(vec![])
+        );
+
+        // Objects stored while the index is missing leave it stale until rebuilt.
+        std::fs::write(store.layout().index_stale_file(), b"").unwrap();
+        assert_eq!(store.referrers(&blob_id).unwrap(), None);
+        assert_eq!(store.rebuild_index().unwrap(), 2);
+        assert_eq!(store.referrers(&blob_id).unwrap(), Some(vec![tree_id]));
+    }
+
+    #[test]
+    fn objects_stored_without_the_index_are_indexed_on_next_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let holder = ClawStore::init(tmp.path()).unwrap();
+        let blob_id = holder.store_object(&blob("content")).unwrap();
+
+        // redb is exclusive, so a second handle runs without the index.
+        let second = ClawStore::open(tmp.path()).unwrap();
+        assert!(!second.index_is_usable());
+        let other_id = second.store_object(&blob("other")).unwrap();
+        drop(second);
+        assert!(!holder.index_is_usable());
+        drop(holder);
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        assert!(store.index_is_usable());
+        assert_eq!(store.object_type(&other_id).unwrap(), TypeTag::Blob);
+        assert_eq!(
+            store
+                .referrers_of_type_many(&[blob_id, other_id], TypeTag::Tree)
+                .unwrap(),
+            Some(vec![vec![], vec![]])
+        );
     }
 }
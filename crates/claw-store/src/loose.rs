@@ -10,16 +10,28 @@ pub fn loose_object_path(layout: &RepoLayout, id: &ObjectId) -> PathBuf {
     dir.join(id.shard_suffix())
 }
 
+/// Write a loose object, returning `false` if it was already present.
 pub fn write_loose_object(
     layout: &RepoLayout,
     id: &ObjectId,
     data: &[u8],
-) -> Result<(), StoreError> {
+) -> Result<bool, StoreError> {
     let path = loose_object_path(layout, id);
 
     if path.exists() {
-        return Ok(());
+        return Ok(false);
     }
+    replace_loose_object(layout, id, data)?;
+    Ok(true)
+}
+
+/// Write a loose object, atomically replacing any existing copy.
+pub fn replace_loose_object(
+    layout: &RepoLayout,
+    id: &ObjectId,
+    data: &[u8],
+) -> Result<(), StoreError> {
+    let path = loose_object_path(layout, id);
 
     // Create shard directory
     if let Some(parent) = path.parent() {
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::{Operation, OperationRef};
+
+use crate::ref_transaction::RefExpectation;
+use crate::{reflog, ClawStore, HeadState, StoreError};
+
+/// Ref pointing at the newest operation. Everything under `ops/` is excluded
+/// from recorded and restored state, as are recorded conflict resolutions
+/// under `rerere/` and virtual merge bases pinned under `merge-bases/`,
+/// which are caches rather than repository state.
+pub const OPS_REF: &str = "ops/head";
+const UNTRACKED_PREFIXES: [&str; 3] = ["ops/", "rerere/", "merge-bases/"];
+
+/// How long an operation is kept before `claw gc` expires it.
+pub const DEFAULT_EXPIRY: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// The repository state an operation captures: every ref and HEAD.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoState {
+    pub refs: BTreeMap<String, ObjectId>,
+    pub head: HeadState,
+}
+
+impl RepoState {
+    pub fn capture(store: &ClawStore) -> Result<Self, StoreError> {
+        let refs = store
+            .list_refs("")?
+            .into_iter()
+            .filter(|(name, _)| !UNTRACKED_PREFIXES.iter().any(|p| name.starts_with(p)))
+            .collect();
+        Ok(Self {
+            refs,
+            head: store.read_head()?,
+        })
+    }
+
+    pub fn from_operation(op: &Operation) -> Self {
+        let head = match (&op.head_ref, op.head_target) {
+            (_, Some(target)) => HeadState::Detached { target },
+            (Some(ref_name), None) => HeadState::Symbolic {
+                ref_name: ref_name.clone(),
+            },
+            (None, None) => HeadState::Symbolic {
+                ref_name: "heads/main".to_string(),
+            },
+        };
+        Self {
+            refs: op.refs.iter().map(|r| (r.name.clone(), r.target)).collect(),
+            head,
+        }
+    }
+}
+
+/// The newest operation id, if any operation has been recorded.
+pub fn current_operation(store: &ClawStore) -> Result<Option<ObjectId>, StoreError> {
+    store.get_ref(OPS_REF)
+}
+
+pub fn load_operation(store: &ClawStore, id: &ObjectId) -> Result<Operation, StoreError> {
+    match store.load_object(id)? {
+        Object::Operation(op) => Ok(op),
+        other => Err(StoreError::InvalidRevision(format!(
+            "{id} is a {}, not an operation",
+            other.type_tag().name()
+        ))),
+    }
+}
+
+/// Append an operation recording `state` and move `ops/head` to it.
+///
+/// Fails with a CAS conflict if another process recorded an operation since
+/// `ops/head` was read.
+pub fn record_operation(
+    store: &ClawStore,
+    state: &RepoState,
+    command: &str,
+    author: &str,
+) -> Result<ObjectId, StoreError> {
+    let parent = current_operation(store)?;
+    let (head_ref, head_target) = match &state.head {
+        HeadState::Symbolic { ref_name } => (Some(ref_name.clone()), None),
+        HeadState::Detached { target } => (None, Some(*target)),
+    };
+    let op = Operation {
+        parent,
+        refs: state
+            .refs
+            .iter()
+            .map(|(name, target)| OperationRef {
+                name: name.clone(),
+                target: *target,
+            })
+            .collect(),
+        head_ref,
+        head_target,
+        command: command.to_string(),
+        author: author.to_string(),
+        timestamp: now_ms(),
+    };
+    let id = store.store_object(&Object::Operation(op))?;
+    let expected = parent.map_or(RefExpectation::Absent, RefExpectation::Value);
+    let mut tx = store.transaction();
+    tx.stage(OPS_REF, expected, Some(id));
+    tx.commit(author, command)?;
+    Ok(id)
+}
+
+/// Operations from newest to oldest, at most `limit` of them.
+pub fn list_operations(
+    store: &ClawStore,
+    limit: usize,
+) -> Result<Vec<(ObjectId, Operation)>, StoreError> {
+    let mut out = Vec::new();
+    let mut next = current_operation(store)?;
+    while let Some(id) = next {
+        if out.len() >= limit {
+            break;
+        }
+        let op = load_operation(store, &id)?;
+        next = op.parent;
+        out.push((id, op));
+    }
+    Ok(out)
+}
+
+/// Put every ref and HEAD back to `target`, then record the restore itself
+/// as a new operation.
+///
+/// Ref changes go through one transaction, each checked against the value
+/// read just before, so a concurrent writer makes the restore fail instead
+/// of being silently overwritten.
+pub fn restore(
+    store: &ClawStore,
+    target: &RepoState,
+    command: &str,
+    author: &str,
+) -> Result<ObjectId, StoreError> {
+    let current = RepoState::capture(store)?;
+    let mut tx = store.transaction();
+    for (name, old) in &current.refs {
+        match target.refs.get(name) {
+            Some(new) if new == old => {}
+            Some(new) => {
+                tx.update(name, old, new);
+            }
+            None => {
+                tx.delete(name, Some(old));
+            }
+        }
+    }
+    for (name, new) in &target.refs {
+        if !current.refs.contains_key(name) {
+            tx.create(name, new);
+        }
+    }
+    if current.head != target.head {
+        tx.set_head(&target.head);
+    }
+    tx.commit(author, command)?;
+    record_operation(store, target, command, author)
+}
+
+/// Drop operations older than `max_age` from the log, always keeping the
+/// newest, so the states they captured can be pruned. Returns how many were
+/// dropped; with `dry_run` nothing changes.
+///
+/// Each operation names its parent, so the ones kept are rewritten with the
+/// oldest as the new start of the log and get new ids. The `ops/head`
+/// reflog, which would still reach the old chain, is removed.
+pub fn expire(store: &ClawStore, max_age: Duration, dry_run: bool) -> Result<usize, StoreError> {
+    let Some(head) = current_operation(store)? else {
+        return Ok(0);
+    };
+    let cutoff = now_ms().saturating_sub(max_age.as_millis() as u64);
+    let mut kept = Vec::new();
+    let mut expired = 0;
+    let mut next = Some(head);
+    while let Some(id) = next {
+        let op = load_operation(store, &id)?;
+        next = op.parent;
+        if kept.is_empty() || op.timestamp >= cutoff {
+            kept.push(op);
+        } else {
+            expired += 1;
+        }
+    }
+    if expired == 0 || dry_run {
+        return Ok(expired);
+    }
+
+    let mut parent = None;
+    for mut op in kept.into_iter().rev() {
+        op.parent = parent;
+        parent = Some(store.store_object(&Object::Operation(op))?);
+    }
+    let new_head = parent.expect("the newest operation is always kept");
+    let mut tx = store.transaction();
+    tx.update(OPS_REF, &head, &new_head);
+    tx.commit("claw", "expire operations")?;
+    reflog::remove_reflog(store.layout(), OPS_REF)?;
+    Ok(expired)
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::types::Blob;
+
+    fn blob(store: &ClawStore, data: &str) -> ObjectId {
+        store
+            .store_object(&Object::Blob(Blob {
+                data: data.as_bytes().to_vec(),
+                media_type: None,
+            }))
+            .unwrap()
+    }
+
+    #[test]
+    fn restore_puts_refs_and_head_back() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = blob(&store, "a");
+        let b = blob(&store, "b");
+        store.set_ref("heads/main", &a).unwrap();
+        store.set_ref("heads/gone", &a).unwrap();
+        let before = RepoState::capture(&store).unwrap();
+        let first = record_operation(&store, &before, "claw snapshot", "alice").unwrap();
+
+        store.set_ref("heads/main", &b).unwrap();
+        store.delete_ref("heads/gone").unwrap();
+        store.set_ref("heads/new", &b).unwrap();
+        store
+            .write_head(&HeadState::Detached { target: b })
+            .unwrap();
+        let after = RepoState::capture(&store).unwrap();
+        record_operation(&store, &after, "claw integrate", "alice").unwrap();
+        store.set_ref("rerere/key", &b).unwrap();
+        store.set_ref("merge-bases/pin", &b).unwrap();
+
+        let op = load_operation(&store, &first).unwrap();
+        restore(
+            &store,
+            &RepoState::from_operation(&op),
+            "claw undo",
+            "alice",
+        )
+        .unwrap();
+        assert_eq!(RepoState::capture(&store).unwrap(), before);
+        assert_eq!(store.get_ref("rerere/key").unwrap(), Some(b));
+        assert_eq!(store.get_ref("merge-bases/pin").unwrap(), Some(b));
+
+        let ops = list_operations(&store, 10).unwrap();
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops[0].1.command, "claw undo");
+        assert_eq!(ops[2].0, first);
+        assert!(!RepoState::capture(&store)
+            .unwrap()
+            .refs
+            .contains_key(OPS_REF));
+    }
+
+    #[test]
+    fn expire_drops_old_operations_and_keeps_the_newest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = blob(&store, "a");
+        store.set_ref("heads/main", &a).unwrap();
+        let state = RepoState::capture(&store).unwrap();
+        record_operation(&store, &state, "claw snapshot", "alice").unwrap();
+        record_operation(&store, &state, "claw integrate", "alice").unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        // Everything is older than a zero age, but the newest stays.
+        assert_eq!(expire(&store, Duration::ZERO, true).unwrap(), 1);
+        assert_eq!(list_operations(&store, 10).unwrap().len(), 2);
+        assert_eq!(expire(&store, Duration::ZERO, false).unwrap(), 1);
+        let ops = list_operations(&store, 10).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert_eq!(ops[0].1.command, "claw integrate");
+        assert_eq!(ops[0].1.parent, None);
+        assert!(reflog::read_reflog(store.layout(), OPS_REF)
+            .unwrap()
+            .is_empty());
+        assert_eq!(expire(&store, Duration::ZERO, false).unwrap(), 0);
+    }
+}
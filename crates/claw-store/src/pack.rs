@@ -1,24 +1,86 @@
-use claw_core::cof::{cof_decode, cof_encode};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use claw_core::cof::{
+    cof_decode_with, cof_dictionary_id, cof_encode, cof_is_encrypted, cof_peek_type_tag,
+    CofContext, DictionarySource,
+};
 use claw_core::hash::content_hash;
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
+use claw_core::object::{Object, TypeTag};
 
+use crate::delta::{apply_delta, create_delta};
 use crate::layout::RepoLayout;
+use crate::pack_index::{write_atomic, write_index_v2, IndexEntry, PackIndex};
 use crate::StoreError;
 
-/// Pack format (MVP - no delta compression):
+pub use crate::pack_index::read_pack_index;
+
+/// Pack format v1 (no delta compression):
 /// [4B "CLPK"][4B version=1][4B object_count]
 /// [object entries: 4B length, COF bytes]*
+///
+/// Pack format v2 adds a kind byte to every entry:
+/// [4B "CLPK"][4B version=2][4B object_count]
+/// [object entries: 1B kind, then
+///     kind 0 (full):  4B length, COF bytes
+///   | kind 1 (delta): 8B base entry offset, 1B type_tag, 4B length,
+///                     zstd-compressed delta against the base payload]*
+///
+/// A delta base always precedes the entries that refer to it. Zstd
+/// dictionaries used by full entries are carried right after the header as
+/// kind 2 entries (4B dictionary id, 4B length, dictionary bytes); they are
+/// not counted in `object_count`.
 const PACK_MAGIC: &[u8; 4] = b"CLPK";
-const PACK_VERSION: u32 = 1;
+pub(crate) const PACK_VERSION_V1: u32 = 1;
+const PACK_VERSION: u32 = 2;
+
+const ENTRY_FULL: u8 = 0;
+const ENTRY_DELTA: u8 = 1;
+const ENTRY_DICTIONARY: u8 = 2;
+
+/// Objects smaller than this are always stored whole.
+const MIN_DELTA_SIZE: usize = 256;
+/// Upper bound on delta chain walks when reading, guarding against corrupt packs.
+const MAX_READ_DEPTH: usize = 256;
+/// Largest entry or delta result a pack may describe; bigger claims are corrupt.
+pub(crate) const MAX_OBJECT_SIZE: u64 = 1 << 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaOptions {
+    /// Longest allowed chain of deltas before an object is stored whole.
+    pub max_depth: usize,
+    /// How many preceding candidates to try as a base for each object.
+    pub window: usize,
+}
+
+impl Default for DeltaOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            window: 10,
+        }
+    }
+}
 
-/// Index format (separate .idx file):
-/// [4B "CLIX"][4B entry_count]
-/// [entries: 32B ObjectId, 8B offset]*
-const IDX_MAGIC: &[u8; 4] = b"CLIX";
+struct PackEntry {
+    id: ObjectId,
+    cof_data: Vec<u8>,
+    path_hint: Option<String>,
+}
+
+enum EncodedEntry {
+    Full,
+    Delta { base: usize, data: Vec<u8> },
+}
 
 pub struct PackWriter {
-    objects: Vec<(ObjectId, Vec<u8>)>,
+    objects: Vec<PackEntry>,
+    delta: DeltaOptions,
+    dictionaries: HashMap<u32, Vec<u8>>,
 }
 
 impl Default for PackWriter {
@@ -31,41 +93,59 @@ impl PackWriter {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            delta: DeltaOptions::default(),
+            dictionaries: HashMap::new(),
         }
     }
 
+    pub fn with_delta_options(mut self, delta: DeltaOptions) -> Self {
+        self.delta = delta;
+        self
+    }
+
     pub fn add_object(&mut self, obj: &Object) -> Result<ObjectId, StoreError> {
         let payload = obj.serialize_payload()?;
         let type_tag = obj.type_tag();
         let id = content_hash(type_tag, &payload);
         let cof_data = cof_encode(type_tag, &payload)?;
-        self.objects.push((id, cof_data));
+        self.add_cof_bytes(id, cof_data);
         Ok(id)
     }
 
-    /// Write pack and index as separate files with hash-based naming.
-    /// Returns (pack_path, idx_path).
-    pub fn write_pack(
-        &self,
-        layout: &RepoLayout,
-    ) -> Result<(std::path::PathBuf, std::path::PathBuf), StoreError> {
-        // Build pack data
-        let mut data = Vec::new();
+    /// Add an already COF-encoded object without decoding it.
+    pub fn add_cof_bytes(&mut self, id: ObjectId, cof_data: Vec<u8>) {
+        self.objects.push(PackEntry {
+            id,
+            cof_data,
+            path_hint: None,
+        });
+    }
 
-        // Header
-        data.extend_from_slice(PACK_MAGIC);
-        data.extend_from_slice(&PACK_VERSION.to_le_bytes());
-        data.extend_from_slice(&(self.objects.len() as u32).to_le_bytes());
+    /// Make a zstd dictionary available to entries added as raw COF bytes.
+    /// Only dictionaries some entry uses are written into the pack.
+    pub fn add_dictionary(&mut self, id: u32, dict: Vec<u8>) {
+        self.dictionaries.insert(id, dict);
+    }
 
-        // Object entries
-        let mut index_entries = Vec::new();
-        for (id, cof_data) in &self.objects {
-            let offset = data.len() as u64;
-            let len = cof_data.len() as u32;
-            data.extend_from_slice(&len.to_le_bytes());
-            data.extend_from_slice(cof_data);
-            index_entries.push((*id, offset));
+    /// Record the worktree path an object was seen at, used to pick delta bases.
+    pub fn set_path_hint(&mut self, id: &ObjectId, path: &str) {
+        for entry in self.objects.iter_mut().filter(|e| e.id == *id) {
+            entry.path_hint = Some(path.to_string());
         }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Write pack and index as separate files with hash-based naming.
+    /// Returns (pack_path, idx_path).
+    pub fn write_pack(&self, layout: &RepoLayout) -> Result<(PathBuf, PathBuf), StoreError> {
+        let (data, index_entries) = self.encode()?;
 
         // Hash the pack data for naming
         let pack_hash = blake3::hash(&data);
@@ -74,153 +154,632 @@ impl PackWriter {
         let pack_path = layout.packs_dir().join(format!("{hash_hex}.clwpack"));
         let idx_path = layout.packs_dir().join(format!("{hash_hex}.idx"));
 
-        // Write pack file
-        std::fs::write(&pack_path, &data)?;
-
-        // Write separate index file
-        let mut idx_data = Vec::new();
-        idx_data.extend_from_slice(IDX_MAGIC);
-        idx_data.extend_from_slice(&(index_entries.len() as u32).to_le_bytes());
-        for (id, offset) in &index_entries {
-            idx_data.extend_from_slice(id.as_bytes());
-            idx_data.extend_from_slice(&offset.to_le_bytes());
-        }
-        std::fs::write(&idx_path, &idx_data)?;
+        write_pack_files(&pack_path, &idx_path, &data, &index_entries)?;
 
         Ok((pack_path, idx_path))
     }
 
+    /// The pack's bytes without writing anything, e.g. to upload it.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, StoreError> {
+        Ok(self.encode()?.0)
+    }
+
     /// Legacy write with explicit name (for backward compat).
     pub fn write_pack_named(
         &self,
         layout: &RepoLayout,
         pack_name: &str,
-    ) -> Result<std::path::PathBuf, StoreError> {
+    ) -> Result<PathBuf, StoreError> {
+        let (data, index_entries) = self.encode()?;
+
         let pack_path = layout.packs_dir().join(format!("{pack_name}.clwpack"));
+        let idx_path = layout.packs_dir().join(format!("{pack_name}.idx"));
+        write_pack_files(&pack_path, &idx_path, &data, &index_entries)?;
 
-        let mut data = Vec::new();
+        Ok(pack_path)
+    }
+
+    /// Serialize the pack, returning its bytes and index entries.
+    fn encode(&self) -> Result<(Vec<u8>, Vec<IndexEntry>), StoreError> {
+        let order = self.write_order();
+        let encoded = self.select_deltas(&order)?;
 
-        // Header
+        let mut data = Vec::new();
         data.extend_from_slice(PACK_MAGIC);
         data.extend_from_slice(&PACK_VERSION.to_le_bytes());
         data.extend_from_slice(&(self.objects.len() as u32).to_le_bytes());
 
-        // Object entries
-        let mut index_entries = Vec::new();
-        for (id, cof_data) in &self.objects {
+        let mut used: Vec<u32> = self
+            .objects
+            .iter()
+            .filter_map(|entry| cof_dictionary_id(&entry.cof_data))
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+        for id in used {
+            let dict = self.dictionaries.get(&id).ok_or_else(|| {
+                StoreError::Config(format!("pack entry uses unknown dictionary {id:08x}"))
+            })?;
+            data.push(ENTRY_DICTIONARY);
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&(dict.len() as u32).to_le_bytes());
+            data.extend_from_slice(dict);
+        }
+
+        let mut offsets = vec![0u64; self.objects.len()];
+        let mut index_entries = Vec::with_capacity(self.objects.len());
+        for &i in &order {
+            let entry = &self.objects[i];
             let offset = data.len() as u64;
-            let len = cof_data.len() as u32;
-            data.extend_from_slice(&len.to_le_bytes());
-            data.extend_from_slice(cof_data);
-            index_entries.push((*id, offset));
+            offsets[i] = offset;
+            match &encoded[i] {
+                EncodedEntry::Full => {
+                    data.push(ENTRY_FULL);
+                    data.extend_from_slice(&(entry.cof_data.len() as u32).to_le_bytes());
+                    data.extend_from_slice(&entry.cof_data);
+                }
+                EncodedEntry::Delta { base, data: delta } => {
+                    data.push(ENTRY_DELTA);
+                    data.extend_from_slice(&offsets[*base].to_le_bytes());
+                    data.push(cof_peek_type_tag(&entry.cof_data)? as u8);
+                    data.extend_from_slice(&(delta.len() as u32).to_le_bytes());
+                    data.extend_from_slice(delta);
+                }
+            }
+            index_entries.push(IndexEntry {
+                id: entry.id,
+                offset,
+                crc32: crc32fast::hash(&data[offset as usize..]),
+            });
         }
 
-        std::fs::write(&pack_path, &data)?;
+        Ok((data, index_entries))
+    }
 
-        // Write separate .idx
-        let idx_path = layout.packs_dir().join(format!("{pack_name}.idx"));
-        let mut idx_data = Vec::new();
-        idx_data.extend_from_slice(IDX_MAGIC);
-        idx_data.extend_from_slice(&(index_entries.len() as u32).to_le_bytes());
-        for (id, offset) in &index_entries {
-            idx_data.extend_from_slice(id.as_bytes());
-            idx_data.extend_from_slice(&offset.to_le_bytes());
+    /// Order entries so likely delta pairs sit next to each other:
+    /// grouped by type and path, largest first within a group.
+    fn write_order(&self) -> Vec<usize> {
+        let tags: Vec<u8> = self
+            .objects
+            .iter()
+            .map(|entry| cof_peek_type_tag(&entry.cof_data).map_or(0, |t| t as u8))
+            .collect();
+        let mut order: Vec<usize> = (0..self.objects.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (ea, eb) = (&self.objects[a], &self.objects[b]);
+            tags[a]
+                .cmp(&tags[b])
+                .then_with(|| ea.path_hint.is_none().cmp(&eb.path_hint.is_none()))
+                .then_with(|| ea.path_hint.cmp(&eb.path_hint))
+                .then_with(|| eb.cof_data.len().cmp(&ea.cof_data.len()))
+        });
+        order
+    }
+
+    /// Pick a delta base for each entry from the window of entries written before it.
+    fn select_deltas(&self, order: &[usize]) -> Result<Vec<EncodedEntry>, StoreError> {
+        let mut encoded: Vec<EncodedEntry> =
+            self.objects.iter().map(|_| EncodedEntry::Full).collect();
+        if self.delta.max_depth == 0 || self.delta.window == 0 {
+            return Ok(encoded);
         }
-        std::fs::write(&idx_path, &idx_data)?;
 
-        Ok(pack_path)
+        // Encrypted entries are stored as-is: a delta would leak plaintext.
+        let payloads = self
+            .objects
+            .iter()
+            .map(|entry| {
+                if cof_is_encrypted(&entry.cof_data) {
+                    Ok(None)
+                } else {
+                    let ctx = CofContext {
+                        dictionaries: Some(&self.dictionaries),
+                        ..Default::default()
+                    };
+                    cof_decode_with(&entry.cof_data, ctx).map(Some)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut depth = vec![0usize; self.objects.len()];
+
+        for (pos, &i) in order.iter().enumerate() {
+            let Some((type_tag, target)) = &payloads[i] else {
+                continue;
+            };
+            if target.len() < MIN_DELTA_SIZE {
+                continue;
+            }
+            let path = self.objects[i].path_hint.as_deref();
+
+            // Same-path candidates first, then anything of similar size.
+            let mut candidates: Vec<usize> = order[pos.saturating_sub(self.delta.window)..pos]
+                .iter()
+                .rev()
+                .copied()
+                .filter(|&b| depth[b] < self.delta.max_depth)
+                .filter(|&b| {
+                    payloads[b].as_ref().is_some_and(|(tag, base)| {
+                        tag == type_tag && similar_size(base.len(), target.len())
+                    })
+                })
+                .collect();
+            candidates.sort_by_key(|&b| self.objects[b].path_hint.as_deref() != path);
+
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            for b in candidates {
+                let Some((_, base)) = &payloads[b] else {
+                    continue;
+                };
+                let delta = zstd::encode_all(create_delta(base, target).as_slice(), 3)
+                    .map_err(|e| StoreError::Config(format!("delta compression: {e}")))?;
+                if best.as_ref().is_none_or(|(_, d)| delta.len() < d.len()) {
+                    best = Some((b, delta));
+                }
+            }
+
+            if let Some((base, delta)) = best {
+                // Only worth it if the delta beats the standalone COF encoding.
+                if delta.len() + 9 < self.objects[i].cof_data.len() {
+                    depth[i] = depth[base] + 1;
+                    encoded[i] = EncodedEntry::Delta { base, data: delta };
+                }
+            }
+        }
+
+        Ok(encoded)
     }
 }
 
-pub fn read_pack_index(idx_path: &std::path::Path) -> Result<Vec<(ObjectId, u64)>, StoreError> {
-    let data = std::fs::read(idx_path)?;
+/// Write the index, then move the pack into place, so a concurrent scan never
+/// sees a partial pack or one whose index is still missing.
+fn write_pack_files(
+    pack_path: &Path,
+    idx_path: &Path,
+    data: &[u8],
+    index_entries: &[IndexEntry],
+) -> Result<(), StoreError> {
+    write_index_v2(idx_path, index_entries)?;
+    write_atomic(pack_path, data)
+}
 
-    // Try new separate index format first
-    if data.len() >= 8 && &data[..4] == IDX_MAGIC {
-        let entry_count = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
-        let mut entries = Vec::with_capacity(entry_count);
-        let mut pos = 8;
-        for _ in 0..entry_count {
-            if pos + 40 > data.len() {
-                break;
-            }
-            let mut id_bytes = [0u8; 32];
-            id_bytes.copy_from_slice(&data[pos..pos + 32]);
-            pos += 32;
-            let offset = u64::from_le_bytes([
-                data[pos],
-                data[pos + 1],
-                data[pos + 2],
-                data[pos + 3],
-                data[pos + 4],
-                data[pos + 5],
-                data[pos + 6],
-                data[pos + 7],
-            ]);
-            pos += 8;
-            entries.push((ObjectId::from_bytes(id_bytes), offset));
-        }
-        return Ok(entries);
-    }
-
-    // Fall back to reading inline index from pack file
-    read_pack_index_inline(idx_path)
-}
-
-/// Read inline index from legacy pack files that embed the index.
-fn read_pack_index_inline(pack_path: &std::path::Path) -> Result<Vec<(ObjectId, u64)>, StoreError> {
-    let data = std::fs::read(pack_path)?;
-    if data.len() < 16 || &data[..4] != PACK_MAGIC {
+fn similar_size(base: usize, target: usize) -> bool {
+    base <= target.saturating_mul(2) && target <= base.saturating_mul(2)
+}
+
+/// Read the pack header and return its format version.
+pub fn read_pack_version(file: &mut File) -> Result<u32, StoreError> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    if &header[..4] != PACK_MAGIC {
         return Err(StoreError::Config("invalid pack file".into()));
     }
+    let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if version != PACK_VERSION_V1 && version != PACK_VERSION {
+        return Err(StoreError::Config(format!(
+            "unsupported pack version {version}"
+        )));
+    }
+    Ok(version)
+}
 
-    // Read index count from end
-    let idx_count_offset = data.len() - 4;
-    let idx_count = u32::from_le_bytes([
-        data[idx_count_offset],
-        data[idx_count_offset + 1],
-        data[idx_count_offset + 2],
-        data[idx_count_offset + 3],
-    ]) as usize;
-
-    let idx_start = idx_count_offset - (idx_count * 40); // 32 bytes id + 8 bytes offset
-    let mut entries = Vec::with_capacity(idx_count);
-    let mut pos = idx_start;
-    for _ in 0..idx_count {
-        let mut id_bytes = [0u8; 32];
-        id_bytes.copy_from_slice(&data[pos..pos + 32]);
-        pos += 32;
-        let offset = u64::from_le_bytes([
-            data[pos],
-            data[pos + 1],
-            data[pos + 2],
-            data[pos + 3],
-            data[pos + 4],
-            data[pos + 5],
-            data[pos + 6],
-            data[pos + 7],
-        ]);
-        pos += 8;
-        entries.push((ObjectId::from_bytes(id_bytes), offset));
-    }
-
-    Ok(entries)
+fn read_u32(file: &mut File) -> Result<u32, StoreError> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read `len` bytes at the current position, rejecting lengths that run past
+/// the end of the file or exceed [`MAX_OBJECT_SIZE`] before allocating.
+fn read_bytes(file: &mut File, len: usize) -> Result<Vec<u8>, StoreError> {
+    let remaining = file
+        .metadata()?
+        .len()
+        .saturating_sub(file.stream_position()?);
+    if len as u64 > remaining.min(MAX_OBJECT_SIZE) {
+        return Err(StoreError::Config(format!(
+            "corrupt pack: entry of {len} bytes runs past the end of the file"
+        )));
+    }
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read the dictionary entries that follow the pack header.
+pub fn read_pack_dictionaries(
+    file: &mut File,
+    version: u32,
+) -> Result<HashMap<u32, Vec<u8>>, StoreError> {
+    let mut dictionaries = HashMap::new();
+    if version == PACK_VERSION_V1 {
+        return Ok(dictionaries);
+    }
+    let file_len = file.metadata()?.len();
+    let mut pos = 12;
+    while pos < file_len {
+        file.seek(SeekFrom::Start(pos))?;
+        let mut kind = [0u8; 1];
+        file.read_exact(&mut kind)?;
+        if kind[0] != ENTRY_DICTIONARY {
+            break;
+        }
+        let id = read_u32(file)?;
+        let len = read_u32(file)? as usize;
+        if len as u64 > file_len.saturating_sub(pos + 9) {
+            return Err(StoreError::Config(format!(
+                "corrupt pack: dictionary {id:08x} runs past the end of the file"
+            )));
+        }
+        dictionaries.insert(id, read_bytes(file, len)?);
+        pos += 9 + len as u64;
+    }
+    Ok(dictionaries)
+}
+
+/// Read the raw COF bytes of the entry at `offset`, resolving delta chains.
+pub fn read_cof_at(
+    file: &mut File,
+    version: u32,
+    offset: u64,
+    dictionaries: &dyn DictionarySource,
+) -> Result<Vec<u8>, StoreError> {
+    file.seek(SeekFrom::Start(offset))?;
+    if version == PACK_VERSION_V1 {
+        let len = read_u32(file)? as usize;
+        return read_bytes(file, len);
+    }
+
+    let mut kind = [0u8; 1];
+    file.read_exact(&mut kind)?;
+    if kind[0] == ENTRY_FULL {
+        let len = read_u32(file)? as usize;
+        return read_bytes(file, len);
+    }
+    let (type_tag, payload) = read_payload_at(file, version, offset, dictionaries)?;
+    Ok(cof_encode(type_tag, &payload)?)
+}
+
+/// Read and fully decode the entry at `offset`, applying any delta chain.
+pub fn read_payload_at(
+    file: &mut File,
+    version: u32,
+    offset: u64,
+    dictionaries: &dyn DictionarySource,
+) -> Result<(TypeTag, Vec<u8>), StoreError> {
+    let ctx = CofContext {
+        dictionaries: Some(dictionaries),
+        ..Default::default()
+    };
+    if version == PACK_VERSION_V1 {
+        let cof_data = read_cof_at(file, version, offset, dictionaries)?;
+        return Ok(cof_decode_with(&cof_data, ctx)?);
+    }
+
+    // Walk down to the first full entry, remembering the deltas on the way.
+    let mut deltas: Vec<(TypeTag, Vec<u8>)> = Vec::new();
+    let mut cursor = offset;
+    let (mut type_tag, mut payload) = loop {
+        if deltas.len() > MAX_READ_DEPTH {
+            return Err(StoreError::Config("pack delta chain too deep".into()));
+        }
+        file.seek(SeekFrom::Start(cursor))?;
+        let mut kind = [0u8; 1];
+        file.read_exact(&mut kind)?;
+        match kind[0] {
+            ENTRY_FULL => {
+                let len = read_u32(file)? as usize;
+                break cof_decode_with(&read_bytes(file, len)?, ctx)?;
+            }
+            ENTRY_DELTA => {
+                let mut base_offset = [0u8; 8];
+                file.read_exact(&mut base_offset)?;
+                let base_offset = u64::from_le_bytes(base_offset);
+                if base_offset >= cursor {
+                    return Err(StoreError::Config("pack delta base follows entry".into()));
+                }
+                let mut tag = [0u8; 1];
+                file.read_exact(&mut tag)?;
+                let type_tag =
+                    TypeTag::from_u8(tag[0]).ok_or(claw_core::CoreError::UnknownTypeTag(tag[0]))?;
+                let len = read_u32(file)? as usize;
+                let compressed = read_bytes(file, len)?;
+                let delta = zstd::decode_all(compressed.as_slice())
+                    .map_err(|e| StoreError::Config(format!("delta decompression: {e}")))?;
+                deltas.push((type_tag, delta));
+                cursor = base_offset;
+            }
+            other => {
+                return Err(StoreError::Config(format!(
+                    "unknown pack entry kind {other}"
+                )))
+            }
+        }
+    };
+
+    while let Some((tag, delta)) = deltas.pop() {
+        payload = apply_delta(&payload, &delta)?;
+        type_tag = tag;
+    }
+    Ok((type_tag, payload))
 }
 
 pub fn read_object_from_pack(
     pack_path: &std::path::Path,
     offset: u64,
 ) -> Result<Object, StoreError> {
-    let data = std::fs::read(pack_path)?;
-    let offset = offset as usize;
-    let len = u32::from_le_bytes([
-        data[offset],
-        data[offset + 1],
-        data[offset + 2],
-        data[offset + 3],
-    ]) as usize;
-    let cof_data = &data[offset + 4..offset + 4 + len];
-    let (type_tag, payload) = cof_decode(cof_data)?;
+    let mut file = File::open(pack_path)?;
+    let version = read_pack_version(&mut file)?;
+    let dictionaries = read_pack_dictionaries(&mut file, version)?;
+    let (type_tag, payload) = read_payload_at(&mut file, version, offset, &dictionaries)?;
     let obj = Object::deserialize_payload(type_tag, &payload)?;
     Ok(obj)
 }
+
+/// An open pack file together with its parsed index.
+pub struct PackHandle {
+    pack_path: PathBuf,
+    version: u32,
+    file: Mutex<File>,
+    index: PackIndex,
+    /// Whether the index was read from the pack itself for lack of an `.idx`.
+    inline_index: bool,
+    dictionaries: HashMap<u32, Vec<u8>>,
+}
+
+impl PackHandle {
+    /// Open a `.clwpack` and map the matching `.idx` next to it.
+    pub fn open(pack_path: &Path) -> Result<Self, StoreError> {
+        let inline_index = !pack_path.with_extension("idx").exists();
+        let index = PackIndex::open_for_pack(pack_path)?;
+        let mut file = File::open(pack_path)?;
+        let version = read_pack_version(&mut file)?;
+        let dictionaries = read_pack_dictionaries(&mut file, version)?;
+        Ok(Self {
+            pack_path: pack_path.to_path_buf(),
+            version,
+            file: Mutex::new(file),
+            index,
+            inline_index,
+            dictionaries,
+        })
+    }
+
+    /// Whether this handle fell back to the legacy inline index, so it
+    /// should be reopened once an `.idx` shows up.
+    pub fn has_inline_index(&self) -> bool {
+        self.inline_index
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    /// Zstd dictionaries carried in this pack.
+    pub fn dictionaries(&self) -> &HashMap<u32, Vec<u8>> {
+        &self.dictionaries
+    }
+
+    pub fn contains(&self, id: &ObjectId) -> bool {
+        self.index.lookup(id).is_some()
+    }
+
+    pub fn object_ids(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.index.entries().map(|e| e.id)
+    }
+
+    /// Read the raw COF bytes for `id`, or `None` if this pack doesn't hold it.
+    ///
+    /// Delta entries are resolved and re-encoded as standalone COF.
+    pub fn read_cof_bytes(&self, id: &ObjectId) -> Result<Option<Vec<u8>>, StoreError> {
+        let Some(offset) = self.index.lookup(id) else {
+            return Ok(None);
+        };
+        self.read_cof_at(offset).map(Some)
+    }
+
+    /// Read the raw COF bytes of the entry at `offset`.
+    pub fn read_cof_at(&self, offset: u64) -> Result<Vec<u8>, StoreError> {
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| StoreError::Config("pack handle lock poisoned".into()))?;
+        read_cof_at(&mut file, self.version, offset, &self.dictionaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::cof::cof_decode;
+    use claw_core::types::Blob;
+
+    #[test]
+    fn pack_handle_reads_written_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+
+        let mut writer = PackWriter::new();
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            let blob = Object::Blob(Blob {
+                data: format!("packed blob {i}").into_bytes(),
+                media_type: None,
+            });
+            ids.push(writer.add_object(&blob).unwrap());
+        }
+        let (pack_path, _) = writer.write_pack(&layout).unwrap();
+
+        let handle = PackHandle::open(&pack_path).unwrap();
+        for (i, id) in ids.iter().enumerate() {
+            let cof = handle.read_cof_bytes(id).unwrap().unwrap();
+            let (_, payload) = cof_decode(&cof).unwrap();
+            let obj =
+                Object::deserialize_payload(claw_core::object::TypeTag::Blob, &payload).unwrap();
+            match obj {
+                Object::Blob(b) => assert_eq!(b.data, format!("packed blob {i}").into_bytes()),
+                _ => panic!("expected blob"),
+            }
+        }
+        let missing = claw_core::hash::content_hash(claw_core::object::TypeTag::Blob, b"x");
+        assert!(handle.read_cof_bytes(&missing).unwrap().is_none());
+    }
+
+    #[test]
+    fn v2_pack_deltas_similar_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+
+        // Pseudo-random text so zstd alone can't shrink each version much.
+        let mut seed = 7u64;
+        let mut text = String::new();
+        for _ in 0..400 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            text.push_str(&format!("line {:x}\n", seed >> 20));
+        }
+
+        let mut writer = PackWriter::new();
+        let mut versions = Vec::new();
+        for i in 0..6 {
+            let data = format!("{text}revision {i}\n").into_bytes();
+            let id = writer
+                .add_object(&Object::Blob(Blob {
+                    data: data.clone(),
+                    media_type: None,
+                }))
+                .unwrap();
+            writer.set_path_hint(&id, "src/big.txt");
+            versions.push((id, data));
+        }
+        let full_size: usize = writer.objects.iter().map(|e| e.cof_data.len()).sum();
+        let (pack_path, _) = writer.write_pack(&layout).unwrap();
+        let pack_size = std::fs::metadata(&pack_path).unwrap().len() as usize;
+        assert!(pack_size * 3 < full_size);
+
+        let handle = PackHandle::open(&pack_path).unwrap();
+        assert_eq!(handle.version(), 2);
+        for (id, data) in &versions {
+            let cof = handle.read_cof_bytes(id).unwrap().unwrap();
+            let (tag, payload) = cof_decode(&cof).unwrap();
+            match Object::deserialize_payload(tag, &payload).unwrap() {
+                Object::Blob(b) => assert_eq!(&b.data, data),
+                _ => panic!("expected blob"),
+            }
+            assert_eq!(content_hash(tag, &payload), *id);
+        }
+    }
+
+    #[test]
+    fn delta_chains_respect_depth_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+
+        let base: String = (0..300)
+            .map(|i| format!("row {i} of the table\n"))
+            .collect();
+        let mut writer = PackWriter::new().with_delta_options(DeltaOptions {
+            max_depth: 2,
+            window: 10,
+        });
+        for i in 0..8 {
+            let blob = Object::Blob(Blob {
+                data: format!("{base}{}", "x".repeat(i)).into_bytes(),
+                media_type: None,
+            });
+            writer.add_object(&blob).unwrap();
+        }
+        let order = writer.write_order();
+        let encoded = writer.select_deltas(&order).unwrap();
+        let depth_of = |mut i: usize| {
+            let mut d = 0;
+            while let EncodedEntry::Delta { base, .. } = &encoded[i] {
+                d += 1;
+                i = *base;
+            }
+            d
+        };
+        assert!((0..encoded.len()).all(|i| depth_of(i) <= 2));
+        assert!(encoded
+            .iter()
+            .any(|e| matches!(e, EncodedEntry::Delta { .. })));
+    }
+
+    #[test]
+    fn dictionary_length_past_the_end_is_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("bad.clwpack");
+        let mut data = Vec::new();
+        data.extend_from_slice(PACK_MAGIC);
+        data.extend_from_slice(&PACK_VERSION.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.push(ENTRY_DICTIONARY);
+        data.extend_from_slice(&7u32.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(b"short");
+        std::fs::write(&path, &data).unwrap();
+
+        let mut file = File::open(&path).unwrap();
+        let version = read_pack_version(&mut file).unwrap();
+        assert!(read_pack_dictionaries(&mut file, version).is_err());
+    }
+
+    #[test]
+    fn entry_lengths_past_the_end_are_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+
+        let base: String = (0..300)
+            .map(|i| format!("row {i} of the table\n"))
+            .collect();
+        let mut writer = PackWriter::new();
+        let mut ids = Vec::new();
+        for i in 0..2 {
+            ids.push(
+                writer
+                    .add_object(&Object::Blob(Blob {
+                        data: format!("{base}{i}").into_bytes(),
+                        media_type: None,
+                    }))
+                    .unwrap(),
+            );
+        }
+        let (pack_path, _) = writer.write_pack(&layout).unwrap();
+        let handle = PackHandle::open(&pack_path).unwrap();
+        let offsets: Vec<u64> = ids
+            .iter()
+            .map(|id| handle.index().lookup(id).unwrap())
+            .collect();
+        drop(handle);
+        let pristine = std::fs::read(&pack_path).unwrap();
+
+        let mut kinds = Vec::new();
+        for (id, offset) in ids.iter().zip(offsets) {
+            let offset = offset as usize;
+            let kind = pristine[offset];
+            // Full: kind, length. Delta: kind, base offset, type tag, length.
+            let len_at = match kind {
+                ENTRY_FULL => offset + 1,
+                _ => offset + 10,
+            };
+            let mut corrupt = pristine.clone();
+            corrupt[len_at..len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            std::fs::write(&pack_path, &corrupt).unwrap();
+            let handle = PackHandle::open(&pack_path).unwrap();
+            assert!(handle.read_cof_bytes(id).is_err());
+            kinds.push(kind);
+        }
+        assert!(kinds.contains(&ENTRY_FULL) && kinds.contains(&ENTRY_DELTA));
+    }
+}
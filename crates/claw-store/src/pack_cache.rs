@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use claw_core::id::ObjectId;
+
+use crate::layout::RepoLayout;
+use crate::pack::PackHandle;
+use crate::pack_index::MultiPackIndex;
+use crate::StoreError;
+
+/// A change to the packs directory this soon after its last one may share
+/// its timestamp on coarse-grained filesystems, so the mtime can't be trusted.
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+#[derive(Default)]
+struct CacheState {
+    /// Open packs keyed by file stem.
+    packs: HashMap<String, Arc<PackHandle>>,
+    midx: Option<Arc<MultiPackIndex>>,
+    /// The packs directory's mtime at the last scan (`None` if it didn't
+    /// exist), and when that scan started.
+    scanned: Option<(Option<SystemTime>, SystemTime)>,
+}
+
+/// Cache of open pack handles and the multi-pack index.
+///
+/// Packs are discovered lazily: a lookup that misses every cached pack
+/// rescans `.claw/packs` once so packs written by other processes show up,
+/// unless the directory hasn't changed since the last scan.
+#[derive(Default)]
+pub struct PackCache {
+    state: RwLock<CacheState>,
+}
+
+impl PackCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rescan the packs directory, opening new packs and dropping removed ones.
+    pub fn refresh(&self, layout: &RepoLayout) -> Result<(), StoreError> {
+        let packs_dir = layout.packs_dir();
+        // Taken before the scan, so a pack added during it changes the mtime.
+        let scanned = (dir_mtime(layout), SystemTime::now());
+        let mut on_disk = HashMap::new();
+        if packs_dir.exists() {
+            for entry in std::fs::read_dir(&packs_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|ext| ext == "clwpack") {
+                    if let Some(stem) = path.file_stem() {
+                        on_disk.insert(stem.to_string_lossy().to_string(), path);
+                    }
+                }
+            }
+        }
+
+        let midx = match MultiPackIndex::open(layout) {
+            Ok(midx) => midx.map(Arc::new),
+            Err(e) => {
+                tracing::warn!("ignoring unreadable multi-pack index: {e}");
+                None
+            }
+        };
+
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| StoreError::Config("pack cache lock poisoned".into()))?;
+        state.packs.retain(|stem, _| on_disk.contains_key(stem));
+        for (stem, path) in on_disk {
+            let stale = state.packs.get(&stem).map(|pack| {
+                pack.has_inline_index() && pack.pack_path().with_extension("idx").exists()
+            });
+            if stale == Some(false) {
+                continue;
+            }
+            match PackHandle::open(&path) {
+                Ok(handle) => {
+                    state.packs.insert(stem, Arc::new(handle));
+                }
+                Err(e) => {
+                    tracing::warn!("skipping unreadable pack {}: {e}", path.display());
+                }
+            }
+        }
+        state.midx = midx;
+        state.scanned = Some(scanned);
+        Ok(())
+    }
+
+    /// Whether the packs directory is unchanged since the last scan.
+    fn is_current(&self, layout: &RepoLayout) -> bool {
+        let Ok(state) = self.state.read() else {
+            return false;
+        };
+        let Some((mtime, scanned_at)) = state.scanned else {
+            return false;
+        };
+        let settled = match mtime {
+            Some(mtime) => scanned_at
+                .duration_since(mtime)
+                .is_ok_and(|age| age >= RACY_WINDOW),
+            None => true,
+        };
+        settled && dir_mtime(layout) == mtime
+    }
+
+    /// Look up `id` in the cached packs: the multi-pack index first, then
+    /// any pack it doesn't cover.
+    fn find_cached(&self, id: &ObjectId) -> Option<(Arc<PackHandle>, u64)> {
+        let state = self.state.read().ok()?;
+        let mut covered = HashSet::new();
+        if let Some(midx) = &state.midx {
+            if let Some((stem, offset)) = midx.lookup(id) {
+                if let Some(pack) = state.packs.get(stem) {
+                    return Some((pack.clone(), offset));
+                }
+            }
+            covered.extend(midx.pack_names().iter().map(String::as_str));
+        }
+        state
+            .packs
+            .iter()
+            .filter(|(stem, _)| !covered.contains(stem.as_str()))
+            .find_map(|(_, pack)| pack.index().lookup(id).map(|offset| (pack.clone(), offset)))
+    }
+
+    /// Locate the pack and offset holding `id`, rescanning once on a miss if
+    /// the packs directory changed.
+    pub fn find(
+        &self,
+        layout: &RepoLayout,
+        id: &ObjectId,
+    ) -> Result<Option<(Arc<PackHandle>, u64)>, StoreError> {
+        if let Some(found) = self.find_cached(id) {
+            return Ok(Some(found));
+        }
+        if self.is_current(layout) {
+            return Ok(None);
+        }
+        self.refresh(layout)?;
+        Ok(self.find_cached(id))
+    }
+
+    /// Read the raw COF bytes for `id` from whichever pack holds it.
+    pub fn read_cof_bytes(
+        &self,
+        layout: &RepoLayout,
+        id: &ObjectId,
+    ) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.find(layout, id)? {
+            Some((pack, offset)) => pack.read_cof_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// All open packs, after a rescan.
+    pub fn packs(&self, layout: &RepoLayout) -> Result<Vec<Arc<PackHandle>>, StoreError> {
+        self.refresh(layout)?;
+        let state = self
+            .state
+            .read()
+            .map_err(|_| StoreError::Config("pack cache lock poisoned".into()))?;
+        Ok(state.packs.values().cloned().collect())
+    }
+
+    /// All object ids held in any pack.
+    pub fn object_ids(&self, layout: &RepoLayout) -> Result<Vec<ObjectId>, StoreError> {
+        let mut ids = Vec::new();
+        for pack in self.packs(layout)? {
+            ids.extend(pack.object_ids());
+        }
+        Ok(ids)
+    }
+}
+
+fn dir_mtime(layout: &RepoLayout) -> Option<SystemTime> {
+    std::fs::metadata(layout.packs_dir())
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rescans_only_when_the_packs_directory_changes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        std::fs::create_dir_all(layout.packs_dir()).unwrap();
+        let cache = PackCache::new();
+
+        // Just modified: the mtime may not reflect a change made right after.
+        cache.refresh(&layout).unwrap();
+        assert!(!cache.is_current(&layout));
+
+        let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        std::fs::File::open(layout.packs_dir())
+            .unwrap()
+            .set_modified(hour_ago)
+            .unwrap();
+        cache.refresh(&layout).unwrap();
+        assert!(cache.is_current(&layout));
+
+        std::fs::write(layout.packs_dir().join("new.clwpack"), b"").unwrap();
+        assert!(!cache.is_current(&layout));
+    }
+
+    #[test]
+    fn packs_are_not_opened_until_their_index_lands() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+        let mut writer = crate::pack::PackWriter::new();
+        let id = writer
+            .add_object(&claw_core::object::Object::Blob(claw_core::types::Blob {
+                data: b"packed".to_vec(),
+                media_type: None,
+            }))
+            .unwrap();
+        let (pack_path, idx_path) = writer.write_pack(&layout).unwrap();
+        let mut names: Vec<_> = std::fs::read_dir(layout.packs_dir())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        names.sort();
+        let mut expected = vec![pack_path.clone(), idx_path.clone()];
+        expected.sort();
+        assert_eq!(names, expected);
+
+        // As seen mid-write by another process before the index exists.
+        let aside = tmp.path().join("aside.idx");
+        std::fs::rename(&idx_path, &aside).unwrap();
+        assert!(PackHandle::open(&pack_path).is_err());
+        let cache = PackCache::new();
+        cache.refresh(&layout).unwrap();
+        assert!(cache.find_cached(&id).is_none());
+
+        std::fs::rename(&aside, &idx_path).unwrap();
+        assert!(cache.find(&layout, &id).unwrap().is_some());
+    }
+}
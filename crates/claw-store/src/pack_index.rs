@@ -0,0 +1,568 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use claw_core::id::ObjectId;
+use memmap2::Mmap;
+
+use crate::layout::RepoLayout;
+use crate::pack::{read_pack_version, PACK_VERSION_V1};
+use crate::StoreError;
+
+/// Index format v1 (separate .idx file):
+/// [4B "CLIX"][4B entry_count]
+/// [entries: 32B ObjectId, 8B offset]*
+///
+/// Index format v2:
+/// [4B "CLIX"][4B 0xFFFFFFFF][4B version=2][4B entry_count]
+/// [256 x 4B fanout: number of ids whose first byte is <= i]
+/// [entry_count x 32B ObjectId, sorted]
+/// [entry_count x 4B CRC32 of the pack entry bytes]
+/// [entry_count x 8B pack offset]
+/// [32B BLAKE3 checksum of everything above]
+const IDX_MAGIC: &[u8; 4] = b"CLIX";
+const IDX_V2_MARKER: u32 = u32::MAX;
+const IDX_VERSION: u32 = 2;
+const IDX_V2_HEADER: usize = 16;
+
+/// Multi-pack index format (`.claw/packs/multi-pack-index`):
+/// [4B "CLMX"][4B version=1][4B pack_count][4B entry_count]
+/// [pack names: 2B length, UTF-8 file stem]*
+/// [256 x 4B fanout][entry_count x 32B ObjectId, sorted]
+/// [entry_count x 4B pack number][entry_count x 8B pack offset]
+/// [32B BLAKE3 checksum of everything above]
+const MIDX_MAGIC: &[u8; 4] = b"CLMX";
+const MIDX_VERSION: u32 = 1;
+pub const MIDX_FILE_NAME: &str = "multi-pack-index";
+
+const FANOUT_LEN: usize = 256 * 4;
+const CHECKSUM_LEN: usize = 32;
+
+/// One object in a pack index.
+#[derive(Debug, Clone, Copy)]
+pub struct IndexEntry {
+    pub id: ObjectId,
+    pub offset: u64,
+    pub crc32: u32,
+}
+
+/// Write a v2 index for a pack. Entries may be given in any order.
+pub fn write_index_v2(path: &Path, entries: &[IndexEntry]) -> Result<(), StoreError> {
+    let mut sorted = entries.to_vec();
+    sorted.sort_by(|a, b| a.id.as_bytes().cmp(b.id.as_bytes()));
+    sorted.dedup_by(|a, b| a.id == b.id);
+
+    let mut data = Vec::with_capacity(IDX_V2_HEADER + FANOUT_LEN + sorted.len() * 44 + 32);
+    data.extend_from_slice(IDX_MAGIC);
+    data.extend_from_slice(&IDX_V2_MARKER.to_le_bytes());
+    data.extend_from_slice(&IDX_VERSION.to_le_bytes());
+    data.extend_from_slice(&(sorted.len() as u32).to_le_bytes());
+    write_fanout(&mut data, sorted.iter().map(|e| &e.id));
+    for entry in &sorted {
+        data.extend_from_slice(entry.id.as_bytes());
+    }
+    for entry in &sorted {
+        data.extend_from_slice(&entry.crc32.to_le_bytes());
+    }
+    for entry in &sorted {
+        data.extend_from_slice(&entry.offset.to_le_bytes());
+    }
+    let checksum = blake3::hash(&data);
+    data.extend_from_slice(checksum.as_bytes());
+
+    write_atomic(path, &data)
+}
+
+fn write_fanout<'a>(data: &mut Vec<u8>, sorted_ids: impl Iterator<Item = &'a ObjectId>) {
+    let mut counts = [0u32; 256];
+    for id in sorted_ids {
+        counts[id.as_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0u32;
+    for count in counts {
+        total += count;
+        data.extend_from_slice(&total.to_le_bytes());
+    }
+}
+
+pub(crate) fn write_atomic(path: &Path, data: &[u8]) -> Result<(), StoreError> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| StoreError::Config(format!("invalid index path {}", path.display())))?;
+    let temp = tempfile::NamedTempFile::new_in(dir)?;
+    std::fs::write(temp.path(), data)?;
+    temp.persist(path).map_err(|e| StoreError::Io(e.error))?;
+    Ok(())
+}
+
+fn read_u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn read_u64_at(data: &[u8], pos: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&data[pos..pos + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// Whether the fanout table at `fanout_pos` never decreases and stays within
+/// `count`, so searches bounded by it stay inside the id table.
+fn fanout_is_valid(data: &[u8], fanout_pos: usize, count: usize) -> bool {
+    let mut prev = 0;
+    for i in 0..256 {
+        let value = read_u32_at(data, fanout_pos + i * 4) as usize;
+        if value < prev || value > count {
+            return false;
+        }
+        prev = value;
+    }
+    true
+}
+
+/// Binary search a fanout-partitioned table of sorted ids.
+fn fanout_search(data: &[u8], fanout_pos: usize, ids_pos: usize, id: &ObjectId) -> Option<usize> {
+    let first = id.as_bytes()[0] as usize;
+    let lo = if first == 0 {
+        0
+    } else {
+        read_u32_at(data, fanout_pos + (first - 1) * 4) as usize
+    };
+    let hi = read_u32_at(data, fanout_pos + first * 4) as usize;
+    let (mut lo, mut hi) = (lo, hi);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let start = ids_pos + mid * 32;
+        match data[start..start + 32].cmp(id.as_bytes().as_slice()) {
+            std::cmp::Ordering::Equal => return Some(mid),
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+        }
+    }
+    None
+}
+
+fn id_at(data: &[u8], ids_pos: usize, pos: usize) -> ObjectId {
+    let mut bytes = [0u8; 32];
+    bytes.copy_from_slice(&data[ids_pos + pos * 32..ids_pos + pos * 32 + 32]);
+    ObjectId::from_bytes(bytes)
+}
+
+fn verify_checksum(data: &[u8], what: &str) -> Result<(), StoreError> {
+    let body = &data[..data.len() - CHECKSUM_LEN];
+    if blake3::hash(body).as_bytes() != &data[data.len() - CHECKSUM_LEN..] {
+        return Err(StoreError::Index(format!("{what} checksum mismatch")));
+    }
+    Ok(())
+}
+
+/// A pack index, memory-mapped when it is in the v2 format.
+pub enum PackIndex {
+    Mapped {
+        map: Mmap,
+        count: usize,
+    },
+    /// v1 and inline indices, decoded and sorted in memory.
+    Legacy(Vec<(ObjectId, u64)>),
+}
+
+impl PackIndex {
+    /// Open the index for `pack_path`, falling back to the inline index of
+    /// v1 packs. Current packs without an `.idx` are still being written.
+    pub fn open_for_pack(pack_path: &Path) -> Result<Self, StoreError> {
+        let idx_path = pack_path.with_extension("idx");
+        if !idx_path.exists() {
+            let version = read_pack_version(&mut File::open(pack_path)?)?;
+            if version != PACK_VERSION_V1 {
+                return Err(StoreError::Index(format!(
+                    "pack {} has no index",
+                    pack_path.display()
+                )));
+            }
+            return Ok(Self::legacy(read_pack_index_inline(pack_path)?));
+        }
+        Self::open(&idx_path)
+    }
+
+    pub fn open(idx_path: &Path) -> Result<Self, StoreError> {
+        let file = File::open(idx_path)?;
+        // SAFETY: index files are written once via rename and never modified in place.
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() >= IDX_V2_HEADER
+            && &map[..4] == IDX_MAGIC
+            && read_u32_at(&map, 4) == IDX_V2_MARKER
+        {
+            let version = read_u32_at(&map, 8);
+            if version != IDX_VERSION {
+                return Err(StoreError::Index(format!(
+                    "unsupported pack index version {version}"
+                )));
+            }
+            let count = read_u32_at(&map, 12) as usize;
+            let expected = IDX_V2_HEADER + FANOUT_LEN + count * 44 + CHECKSUM_LEN;
+            if map.len() != expected {
+                return Err(StoreError::Index(format!(
+                    "truncated pack index {}",
+                    idx_path.display()
+                )));
+            }
+            if !fanout_is_valid(&map, IDX_V2_HEADER, count) {
+                return Err(StoreError::Index(format!(
+                    "corrupt fanout table in pack index {}",
+                    idx_path.display()
+                )));
+            }
+            return Ok(Self::Mapped { map, count });
+        }
+        Ok(Self::legacy(read_index_v1(&map, idx_path)?))
+    }
+
+    fn legacy(mut entries: Vec<(ObjectId, u64)>) -> Self {
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Self::Legacy(entries)
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Mapped { count, .. } => *count,
+            Self::Legacy(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn ids_pos() -> usize {
+        IDX_V2_HEADER + FANOUT_LEN
+    }
+
+    /// Offset of `id` in the pack, found by binary search.
+    pub fn lookup(&self, id: &ObjectId) -> Option<u64> {
+        match self {
+            Self::Mapped { map, count } => {
+                let pos = fanout_search(map, IDX_V2_HEADER, Self::ids_pos(), id)?;
+                Some(read_u64_at(map, Self::ids_pos() + count * 36 + pos * 8))
+            }
+            Self::Legacy(entries) => entries
+                .binary_search_by(|(probe, _)| probe.as_bytes().cmp(id.as_bytes()))
+                .ok()
+                .map(|pos| entries[pos].1),
+        }
+    }
+
+    /// The entry at sorted position `pos`. Legacy indices carry no CRC.
+    pub fn entry(&self, pos: usize) -> IndexEntry {
+        match self {
+            Self::Mapped { map, count } => IndexEntry {
+                id: id_at(map, Self::ids_pos(), pos),
+                crc32: read_u32_at(map, Self::ids_pos() + count * 32 + pos * 4),
+                offset: read_u64_at(map, Self::ids_pos() + count * 36 + pos * 8),
+            },
+            Self::Legacy(entries) => IndexEntry {
+                id: entries[pos].0,
+                offset: entries[pos].1,
+                crc32: 0,
+            },
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = IndexEntry> + '_ {
+        (0..self.len()).map(|pos| self.entry(pos))
+    }
+
+    pub fn has_crc(&self) -> bool {
+        matches!(self, Self::Mapped { .. })
+    }
+
+    /// Check the trailing checksum of a v2 index.
+    pub fn verify(&self) -> Result<(), StoreError> {
+        match self {
+            Self::Mapped { map, .. } => verify_checksum(map, "pack index"),
+            Self::Legacy(_) => Ok(()),
+        }
+    }
+}
+
+fn read_index_v1(data: &[u8], idx_path: &Path) -> Result<Vec<(ObjectId, u64)>, StoreError> {
+    if data.len() < 8 || &data[..4] != IDX_MAGIC {
+        // Fall back to reading inline index from pack file
+        return read_pack_index_inline(idx_path);
+    }
+    let entry_count = read_u32_at(data, 4) as usize;
+    let mut entries = Vec::with_capacity(entry_count);
+    let mut pos = 8;
+    for _ in 0..entry_count {
+        if pos + 40 > data.len() {
+            break;
+        }
+        let mut id_bytes = [0u8; 32];
+        id_bytes.copy_from_slice(&data[pos..pos + 32]);
+        let offset = read_u64_at(data, pos + 32);
+        pos += 40;
+        entries.push((ObjectId::from_bytes(id_bytes), offset));
+    }
+    Ok(entries)
+}
+
+/// Read an index file of any version into (id, offset) pairs sorted by id.
+pub fn read_pack_index(idx_path: &Path) -> Result<Vec<(ObjectId, u64)>, StoreError> {
+    let index = PackIndex::open(idx_path)?;
+    Ok(index.entries().map(|e| (e.id, e.offset)).collect())
+}
+
+/// Read inline index from legacy pack files that embed the index.
+fn read_pack_index_inline(pack_path: &Path) -> Result<Vec<(ObjectId, u64)>, StoreError> {
+    let data = std::fs::read(pack_path)?;
+    if data.len() < 16 || &data[..4] != b"CLPK" {
+        return Err(StoreError::Config("invalid pack file".into()));
+    }
+
+    // Read index count from end
+    let idx_count_offset = data.len() - 4;
+    let idx_count = read_u32_at(&data, idx_count_offset) as usize;
+
+    let idx_start = idx_count_offset
+        .checked_sub(idx_count * 40) // 32 bytes id + 8 bytes offset
+        .ok_or_else(|| StoreError::Config("invalid inline pack index".into()))?;
+    let mut entries = Vec::with_capacity(idx_count);
+    let mut pos = idx_start;
+    for _ in 0..idx_count {
+        let mut id_bytes = [0u8; 32];
+        id_bytes.copy_from_slice(&data[pos..pos + 32]);
+        let offset = read_u64_at(&data, pos + 32);
+        pos += 40;
+        entries.push((ObjectId::from_bytes(id_bytes), offset));
+    }
+
+    Ok(entries)
+}
+
+/// An index over every pack in `.claw/packs`, mapping ids to (pack, offset).
+pub struct MultiPackIndex {
+    map: Mmap,
+    pack_names: Vec<String>,
+    count: usize,
+    fanout_pos: usize,
+}
+
+impl MultiPackIndex {
+    pub fn path(layout: &RepoLayout) -> PathBuf {
+        layout.packs_dir().join(MIDX_FILE_NAME)
+    }
+
+    /// Open the multi-pack index if one exists.
+    pub fn open(layout: &RepoLayout) -> Result<Option<Self>, StoreError> {
+        let path = Self::path(layout);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path)?;
+        // SAFETY: the multi-pack index is replaced via rename, never modified in place.
+        let map = unsafe { Mmap::map(&file)? };
+        let invalid = || StoreError::Index("invalid multi-pack index".into());
+        if map.len() < 16 || &map[..4] != MIDX_MAGIC {
+            return Err(invalid());
+        }
+        let version = read_u32_at(&map, 4);
+        if version != MIDX_VERSION {
+            return Err(StoreError::Index(format!(
+                "unsupported multi-pack index version {version}"
+            )));
+        }
+        let pack_count = read_u32_at(&map, 8) as usize;
+        let count = read_u32_at(&map, 12) as usize;
+
+        let mut pos = 16;
+        let mut pack_names = Vec::with_capacity(pack_count);
+        for _ in 0..pack_count {
+            if pos + 2 > map.len() {
+                return Err(invalid());
+            }
+            let len = u16::from_le_bytes([map[pos], map[pos + 1]]) as usize;
+            pos += 2;
+            let name = map
+                .get(pos..pos + len)
+                .and_then(|b| std::str::from_utf8(b).ok())
+                .ok_or_else(invalid)?;
+            pack_names.push(name.to_string());
+            pos += len;
+        }
+        if map.len() != pos + FANOUT_LEN + count * 44 + CHECKSUM_LEN
+            || !fanout_is_valid(&map, pos, count)
+        {
+            return Err(invalid());
+        }
+
+        Ok(Some(Self {
+            map,
+            pack_names,
+            count,
+            fanout_pos: pos,
+        }))
+    }
+
+    fn ids_pos(&self) -> usize {
+        self.fanout_pos + FANOUT_LEN
+    }
+
+    /// File stems of the packs this index covers.
+    pub fn pack_names(&self) -> &[String] {
+        &self.pack_names
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Find the pack (by file stem) and offset holding `id`.
+    pub fn lookup(&self, id: &ObjectId) -> Option<(&str, u64)> {
+        let pos = fanout_search(&self.map, self.fanout_pos, self.ids_pos(), id)?;
+        let pack = read_u32_at(&self.map, self.ids_pos() + self.count * 32 + pos * 4) as usize;
+        let offset = read_u64_at(&self.map, self.ids_pos() + self.count * 36 + pos * 8);
+        Some((self.pack_names.get(pack)?.as_str(), offset))
+    }
+
+    pub fn verify(&self) -> Result<(), StoreError> {
+        verify_checksum(&self.map, "multi-pack index")
+    }
+}
+
+/// Rebuild the multi-pack index from every pack in `.claw/packs`.
+///
+/// When an object appears in several packs the first pack (by name) wins.
+pub fn write_multi_pack_index(layout: &RepoLayout) -> Result<PathBuf, StoreError> {
+    let packs_dir = layout.packs_dir();
+    let mut pack_paths = Vec::new();
+    if packs_dir.exists() {
+        for entry in std::fs::read_dir(&packs_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "clwpack") {
+                pack_paths.push(path);
+            }
+        }
+    }
+    pack_paths.sort();
+
+    let mut pack_names = Vec::with_capacity(pack_paths.len());
+    let mut entries: Vec<(ObjectId, u32, u64)> = Vec::new();
+    for (pack_no, path) in pack_paths.iter().enumerate() {
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        pack_names.push(stem);
+        let index = PackIndex::open_for_pack(path)?;
+        entries.extend(index.entries().map(|e| (e.id, pack_no as u32, e.offset)));
+    }
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()).then(a.1.cmp(&b.1)));
+    entries.dedup_by(|a, b| a.0 == b.0);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(MIDX_MAGIC);
+    data.extend_from_slice(&MIDX_VERSION.to_le_bytes());
+    data.extend_from_slice(&(pack_names.len() as u32).to_le_bytes());
+    data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for name in &pack_names {
+        data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        data.extend_from_slice(name.as_bytes());
+    }
+    write_fanout(&mut data, entries.iter().map(|e| &e.0));
+    for (id, _, _) in &entries {
+        data.extend_from_slice(id.as_bytes());
+    }
+    for (_, pack_no, _) in &entries {
+        data.extend_from_slice(&pack_no.to_le_bytes());
+    }
+    for (_, _, offset) in &entries {
+        data.extend_from_slice(&offset.to_le_bytes());
+    }
+    let checksum = blake3::hash(&data);
+    data.extend_from_slice(checksum.as_bytes());
+
+    let path = MultiPackIndex::path(layout);
+    write_atomic(&path, &data)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::hash::content_hash;
+    use claw_core::object::TypeTag;
+
+    fn entries(n: u32) -> Vec<IndexEntry> {
+        (0..n)
+            .map(|i| IndexEntry {
+                id: content_hash(TypeTag::Blob, &i.to_le_bytes()),
+                offset: 12 + i as u64 * 100,
+                crc32: i,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn v2_index_lookup_by_binary_search() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("p.idx");
+        let written = entries(500);
+        write_index_v2(&path, &written).unwrap();
+
+        let index = PackIndex::open(&path).unwrap();
+        assert!(index.has_crc());
+        index.verify().unwrap();
+        assert_eq!(index.len(), 500);
+        for e in &written {
+            assert_eq!(index.lookup(&e.id), Some(e.offset));
+        }
+        let missing = content_hash(TypeTag::Blob, b"missing");
+        assert_eq!(index.lookup(&missing), None);
+
+        let sorted: Vec<_> = index.entries().collect();
+        assert!(sorted
+            .windows(2)
+            .all(|w| w[0].id.as_bytes() < w[1].id.as_bytes()));
+    }
+
+    #[test]
+    fn v2_index_detects_corruption() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("p.idx");
+        write_index_v2(&path, &entries(10)).unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        data[IDX_V2_HEADER + FANOUT_LEN + 3] ^= 0xFF;
+        std::fs::write(&path, &data).unwrap();
+        assert!(PackIndex::open(&path).unwrap().verify().is_err());
+    }
+
+    #[test]
+    fn corrupt_fanout_is_rejected_on_open() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+        let idx_path = layout.packs_dir().join("p.idx");
+        write_index_v2(&idx_path, &entries(10)).unwrap();
+        std::fs::write(layout.packs_dir().join("p.clwpack"), b"").unwrap();
+        write_multi_pack_index(&layout).unwrap();
+
+        // A fanout entry past the entry count, then one that decreases.
+        let midx_path = MultiPackIndex::path(&layout);
+        let midx_fanout = 16 + 2 + "p".len();
+        for (path, fanout_pos) in [(&idx_path, IDX_V2_HEADER), (&midx_path, midx_fanout)] {
+            let original = std::fs::read(path).unwrap();
+            for (slot, value) in [(255, 11u32), (254, 0)] {
+                let mut data = original.clone();
+                let pos = fanout_pos + slot * 4;
+                data[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+                std::fs::write(path, &data).unwrap();
+                if path == &idx_path {
+                    assert!(PackIndex::open(path).is_err());
+                } else {
+                    assert!(MultiPackIndex::open(&layout).is_err());
+                }
+            }
+        }
+    }
+}
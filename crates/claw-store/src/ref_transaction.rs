@@ -0,0 +1,415 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use claw_core::id::ObjectId;
+
+use crate::head::{head_content, HeadState};
+use crate::layout::RepoLayout;
+use crate::lockfile::LockFile;
+use crate::{reflog, refs, StoreError};
+
+/// What a ref must currently hold for a staged update to apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefExpectation {
+    /// No check.
+    Any,
+    /// The ref must not exist.
+    Absent,
+    /// The ref must point at this id.
+    Value(ObjectId),
+}
+
+#[derive(Debug, Clone)]
+struct StagedRef {
+    name: String,
+    expected: RefExpectation,
+    /// `None` deletes the ref.
+    new: Option<ObjectId>,
+}
+
+/// A set of ref creates, updates and deletes applied all-or-nothing.
+///
+/// `commit` locks every ref involved, checks all expectations, and only then
+/// writes. Each applied change gets a reflog entry; deletions are logged with
+/// the null id as their new value. HEAD can be moved in the same transaction.
+pub struct RefTransaction<'a> {
+    layout: &'a RepoLayout,
+    updates: Vec<StagedRef>,
+    head: Option<HeadState>,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub fn new(layout: &'a RepoLayout) -> Self {
+        Self {
+            layout,
+            updates: Vec::new(),
+            head: None,
+        }
+    }
+
+    /// Stage an arbitrary change; `new = None` deletes the ref.
+    pub fn stage(
+        &mut self,
+        name: &str,
+        expected: RefExpectation,
+        new: Option<ObjectId>,
+    ) -> &mut Self {
+        self.updates.push(StagedRef {
+            name: name.to_string(),
+            expected,
+            new,
+        });
+        self
+    }
+
+    /// Create a ref that must not exist yet.
+    pub fn create(&mut self, name: &str, target: &ObjectId) -> &mut Self {
+        self.stage(name, RefExpectation::Absent, Some(*target))
+    }
+
+    /// Move a ref from `old` to `target`.
+    pub fn update(&mut self, name: &str, old: &ObjectId, target: &ObjectId) -> &mut Self {
+        self.stage(name, RefExpectation::Value(*old), Some(*target))
+    }
+
+    /// Point a ref at `target` whatever it holds now.
+    pub fn set(&mut self, name: &str, target: &ObjectId) -> &mut Self {
+        self.stage(name, RefExpectation::Any, Some(*target))
+    }
+
+    /// Delete a ref, optionally checking its current value.
+    pub fn delete(&mut self, name: &str, old: Option<&ObjectId>) -> &mut Self {
+        let expected = old.map_or(RefExpectation::Any, |id| RefExpectation::Value(*id));
+        self.stage(name, expected, None)
+    }
+
+    /// Point HEAD at `state` along with the staged ref changes.
+    pub fn set_head(&mut self, state: &HeadState) -> &mut Self {
+        self.head = Some(state.clone());
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.updates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty() && self.head.is_none()
+    }
+
+    /// Apply every staged change, or none of them.
+    pub fn commit(mut self, author: &str, message: &str) -> Result<(), StoreError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        self.updates.sort_by(|a, b| a.name.cmp(&b.name));
+        let mut seen = HashSet::new();
+        for update in &self.updates {
+            validate_ref_name(&update.name)?;
+            if !seen.insert(update.name.as_str()) {
+                return Err(StoreError::RefTransaction(format!(
+                    "ref '{}' staged more than once",
+                    update.name
+                )));
+            }
+        }
+
+        let refs_dir = self.layout.refs_dir();
+        let mut locks = Vec::with_capacity(self.updates.len() + 1);
+        for update in &self.updates {
+            let path = refs_dir.join(&update.name);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            locks.push(LockFile::acquire(&path)?);
+        }
+        let deletes: Vec<&str> = self
+            .updates
+            .iter()
+            .filter(|u| u.new.is_none())
+            .map(|u| u.name.as_str())
+            .collect();
+        if !deletes.is_empty() {
+            locks.push(LockFile::acquire(&self.layout.packed_refs_file())?);
+        }
+        let head_file = self.layout.head_file();
+        if self.head.is_some() {
+            locks.push(LockFile::acquire(&head_file)?);
+        }
+
+        // Check every expectation before touching anything.
+        let mut current = Vec::with_capacity(self.updates.len());
+        for update in &self.updates {
+            let actual = refs::read_ref(self.layout, &update.name)?;
+            let ok = match update.expected {
+                RefExpectation::Any => true,
+                RefExpectation::Absent => actual.is_none(),
+                RefExpectation::Value(expected) => actual == Some(expected),
+            };
+            if !ok {
+                return Err(StoreError::RefCasConflict {
+                    expected: match update.expected {
+                        RefExpectation::Value(id) => id.to_hex(),
+                        _ => "none".to_string(),
+                    },
+                    actual: actual.map_or_else(|| "none".to_string(), |id| id.to_hex()),
+                });
+            }
+            current.push(actual);
+        }
+
+        // Stage new loose values next to their targets, then rename them in.
+        let mut staged = Vec::new();
+        for update in &self.updates {
+            if let Some(new) = update.new {
+                let path = refs_dir.join(&update.name);
+                let dir = path.parent().unwrap_or(&refs_dir);
+                let temp = tempfile::NamedTempFile::new_in(dir)?;
+                std::fs::write(temp.path(), new.to_hex())?;
+                staged.push((path, temp));
+            }
+        }
+        let mut old_head = None;
+        if let Some(state) = &self.head {
+            old_head = std::fs::read(&head_file).ok();
+            let dir = head_file.parent().unwrap_or(&refs_dir);
+            let temp = tempfile::NamedTempFile::new_in(dir)?;
+            std::fs::write(temp.path(), head_content(state))?;
+            staged.push((head_file.clone(), temp));
+        }
+        let mut applied: Vec<PathBuf> = Vec::new();
+        let mut result = Ok(());
+        for (path, temp) in staged {
+            match temp.persist(&path) {
+                Ok(_) => applied.push(path),
+                Err(e) => {
+                    result = Err(StoreError::Io(e.error));
+                    break;
+                }
+            }
+        }
+        // Loose files go first: they can be written back from `current`,
+        // while the packed-refs rewrite is the last step that can fail.
+        if result.is_ok() {
+            for name in &deletes {
+                let path = refs_dir.join(name);
+                match std::fs::remove_file(&path) {
+                    Ok(()) => applied.push(path),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        result = Err(e.into());
+                        break;
+                    }
+                }
+            }
+        }
+        if result.is_ok() && !deletes.is_empty() {
+            result = remove_packed_unlocked(self.layout, &deletes);
+        }
+        if let Err(e) = result {
+            self.rollback(&applied, &current, old_head);
+            return Err(e);
+        }
+
+        let null = ObjectId::from_bytes([0; 32]);
+        for (update, old) in self.updates.iter().zip(&current) {
+            if update.new.is_none() && old.is_none() {
+                continue;
+            }
+            let new = update.new.unwrap_or(null);
+            reflog::append_reflog(
+                self.layout,
+                &update.name,
+                old.as_ref(),
+                &new,
+                author,
+                message,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Put back the previous loose value of every ref already written or
+    /// removed, and HEAD's previous contents if it was written.
+    fn rollback(
+        &self,
+        applied: &[PathBuf],
+        current: &[Option<ObjectId>],
+        old_head: Option<Vec<u8>>,
+    ) {
+        let head_file = self.layout.head_file();
+        if applied.contains(&head_file) {
+            let _ = match old_head {
+                Some(content) => std::fs::write(&head_file, content),
+                None => std::fs::remove_file(&head_file),
+            };
+        }
+        let refs_dir = self.layout.refs_dir();
+        for (update, old) in self.updates.iter().zip(current) {
+            let path = refs_dir.join(&update.name);
+            if !applied.contains(&path) {
+                continue;
+            }
+            let _ = match old {
+                Some(id) => std::fs::write(&path, id.to_hex()),
+                None => std::fs::remove_file(&path),
+            };
+        }
+    }
+}
+
+/// Like [`refs::remove_packed_refs`], for callers already holding the lock.
+fn remove_packed_unlocked(layout: &RepoLayout, names: &[&str]) -> Result<(), StoreError> {
+    let mut packed = refs::read_packed_refs(layout)?;
+    let before = packed.len();
+    for name in names {
+        packed.remove(*name);
+    }
+    if packed.len() != before {
+        refs::write_packed_refs(layout, &packed)?;
+    }
+    Ok(())
+}
+
+fn validate_ref_name(name: &str) -> Result<(), StoreError> {
+    let bad = name.is_empty()
+        || name.ends_with(".lock")
+        || name
+            .split('/')
+            .any(|part| part.is_empty() || part == "." || part == "..");
+    if bad {
+        return Err(StoreError::RefTransaction(format!(
+            "invalid ref name '{name}'"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::hash::content_hash;
+    use claw_core::object::TypeTag;
+
+    fn setup() -> (tempfile::TempDir, RepoLayout) {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+        (tmp, layout)
+    }
+
+    #[test]
+    fn commits_all_updates_with_reflogs() {
+        let (_tmp, layout) = setup();
+        let a = content_hash(TypeTag::Blob, b"a");
+        let b = content_hash(TypeTag::Blob, b"b");
+        refs::write_ref(&layout, "heads/main", &a).unwrap();
+        refs::write_ref(&layout, "heads/old", &a).unwrap();
+
+        let mut tx = RefTransaction::new(&layout);
+        tx.update("heads/main", &a, &b)
+            .create("capsules/x", &b)
+            .delete("heads/old", Some(&a));
+        tx.commit("alice", "ship").unwrap();
+
+        assert_eq!(refs::read_ref(&layout, "heads/main").unwrap(), Some(b));
+        assert_eq!(refs::read_ref(&layout, "capsules/x").unwrap(), Some(b));
+        assert_eq!(refs::read_ref(&layout, "heads/old").unwrap(), None);
+        let log = reflog::read_reflog(&layout, "heads/old").unwrap();
+        assert_eq!(log.len(), 1);
+        assert!(reflog::is_null_id(&log[0].new));
+        assert_eq!(reflog::read_reflog(&layout, "capsules/x").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn failed_expectation_applies_nothing() {
+        let (_tmp, layout) = setup();
+        let a = content_hash(TypeTag::Blob, b"a");
+        let b = content_hash(TypeTag::Blob, b"b");
+        refs::write_ref(&layout, "heads/main", &a).unwrap();
+
+        let mut tx = RefTransaction::new(&layout);
+        tx.set("heads/other", &b).create("heads/main", &b);
+        assert!(matches!(
+            tx.commit("alice", "clash"),
+            Err(StoreError::RefCasConflict { .. })
+        ));
+        assert_eq!(refs::read_ref(&layout, "heads/other").unwrap(), None);
+        assert_eq!(refs::read_ref(&layout, "heads/main").unwrap(), Some(a));
+        assert!(!layout.refs_dir().join("heads/main.lock").exists());
+    }
+
+    #[test]
+    fn head_moves_with_the_refs_or_not_at_all() {
+        let (_tmp, layout) = setup();
+        let a = content_hash(TypeTag::Blob, b"a");
+        let b = content_hash(TypeTag::Blob, b"b");
+        refs::write_ref(&layout, "heads/main", &a).unwrap();
+        let detached = HeadState::Detached { target: b };
+
+        let mut tx = RefTransaction::new(&layout);
+        tx.update("heads/main", &a, &b).set_head(&detached);
+        tx.commit("alice", "move").unwrap();
+        assert_eq!(crate::head::read_head(&layout).unwrap(), detached);
+
+        // HEAD can't be replaced, so the ref update is undone too.
+        std::fs::remove_file(layout.head_file()).unwrap();
+        std::fs::create_dir(layout.head_file()).unwrap();
+        let mut tx = RefTransaction::new(&layout);
+        tx.update("heads/main", &b, &a).set_head(&detached);
+        assert!(tx.commit("alice", "move back").is_err());
+        assert_eq!(refs::read_ref(&layout, "heads/main").unwrap(), Some(b));
+        assert!(!layout.claw_dir().join("HEAD.lock").exists());
+    }
+
+    #[test]
+    fn failed_packed_refs_rewrite_restores_deleted_loose_refs() {
+        let (_tmp, layout) = setup();
+        let a = content_hash(TypeTag::Blob, b"a");
+        let b = content_hash(TypeTag::Blob, b"b");
+        refs::write_ref(&layout, "heads/main", &a).unwrap();
+        refs::write_ref(&layout, "heads/old", &a).unwrap();
+        std::fs::create_dir(layout.packed_refs_file()).unwrap();
+
+        let mut tx = RefTransaction::new(&layout);
+        tx.update("heads/main", &a, &b)
+            .delete("heads/old", Some(&a));
+        assert!(tx.commit("alice", "cleanup").is_err());
+        assert_eq!(refs::read_ref(&layout, "heads/main").unwrap(), Some(a));
+        assert_eq!(refs::read_ref(&layout, "heads/old").unwrap(), Some(a));
+    }
+
+    #[test]
+    fn packed_refs_are_read_listed_and_deleted() {
+        let (_tmp, layout) = setup();
+        let a = content_hash(TypeTag::Blob, b"a");
+        let b = content_hash(TypeTag::Blob, b"b");
+        refs::write_ref(&layout, "changes/one", &a).unwrap();
+        refs::write_ref(&layout, "changes/two", &a).unwrap();
+        refs::write_ref(&layout, "heads/main", &b).unwrap();
+
+        assert_eq!(refs::pack_refs(&layout).unwrap(), 3);
+        assert!(!layout.refs_dir().join("changes/one").exists());
+        assert_eq!(refs::read_ref(&layout, "changes/one").unwrap(), Some(a));
+        assert_eq!(refs::list_refs(&layout, "changes").unwrap().len(), 2);
+        assert_eq!(refs::list_refs(&layout, "").unwrap().len(), 3);
+
+        // A loose write overrides the packed value.
+        refs::write_ref(&layout, "changes/one", &b).unwrap();
+        assert_eq!(refs::read_ref(&layout, "changes/one").unwrap(), Some(b));
+        assert_eq!(
+            refs::list_refs(&layout, "changes/").unwrap(),
+            vec![
+                ("changes/one".to_string(), b),
+                ("changes/two".to_string(), a)
+            ]
+        );
+
+        let mut tx = RefTransaction::new(&layout);
+        tx.delete("changes/one", None)
+            .delete("changes/two", Some(&a));
+        tx.commit("alice", "cleanup").unwrap();
+        assert!(refs::list_refs(&layout, "changes").unwrap().is_empty());
+        assert_eq!(refs::read_ref(&layout, "heads/main").unwrap(), Some(b));
+    }
+}
@@ -14,6 +14,11 @@ pub struct RefLogLine {
 
 static ZERO_HEX: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 
+/// Whether `id` is the all-zero id reflogs use for "no value".
+pub fn is_null_id(id: &ObjectId) -> bool {
+    id.as_bytes().iter().all(|b| *b == 0)
+}
+
 pub fn append_reflog(
     layout: &RepoLayout,
     ref_name: &str,
@@ -84,6 +89,15 @@ pub fn read_reflog(layout: &RepoLayout, ref_name: &str) -> Result<Vec<RefLogLine
     Ok(entries)
 }
 
+/// Delete the reflog of `ref_name`, if it has one.
+pub fn remove_reflog(layout: &RepoLayout, ref_name: &str) -> Result<(), StoreError> {
+    match std::fs::remove_file(layout.reflogs_dir().join(ref_name)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,8 +1,16 @@
+use std::collections::BTreeMap;
+
 use claw_core::id::ObjectId;
 
 use crate::layout::RepoLayout;
+use crate::lockfile::LockFile;
+use crate::ref_transaction::{RefExpectation, RefTransaction};
 use crate::StoreError;
 
+/// `.claw/packed-refs` format: a header line, then `<hex> <name>` lines
+/// sorted by name. A loose ref file always takes precedence over its packed entry.
+const PACKED_REFS_HEADER: &str = "# claw packed-refs v1";
+
 pub fn write_ref(layout: &RepoLayout, name: &str, target: &ObjectId) -> Result<(), StoreError> {
     let path = layout.refs_dir().join(name);
     if let Some(parent) = path.parent() {
@@ -12,9 +20,9 @@ pub fn write_ref(layout: &RepoLayout, name: &str, target: &ObjectId) -> Result<(
     Ok(())
 }
 
-pub fn read_ref(layout: &RepoLayout, name: &str) -> Result<Option<ObjectId>, StoreError> {
+fn read_loose_ref(layout: &RepoLayout, name: &str) -> Result<Option<ObjectId>, StoreError> {
     let path = layout.refs_dir().join(name);
-    if !path.exists() {
+    if !path.is_file() {
         return Ok(None);
     }
     let content = std::fs::read_to_string(&path)?;
@@ -22,14 +30,121 @@ pub fn read_ref(layout: &RepoLayout, name: &str) -> Result<Option<ObjectId>, Sto
     Ok(Some(id))
 }
 
+pub fn read_ref(layout: &RepoLayout, name: &str) -> Result<Option<ObjectId>, StoreError> {
+    if let Some(id) = read_loose_ref(layout, name)? {
+        return Ok(Some(id));
+    }
+    Ok(read_packed_refs(layout)?.get(name).copied())
+}
+
 pub fn delete_ref(layout: &RepoLayout, name: &str) -> Result<(), StoreError> {
     let path = layout.refs_dir().join(name);
-    if path.exists() {
+    if path.is_file() {
         std::fs::remove_file(&path)?;
     }
+    remove_packed_refs(layout, &[name])
+}
+
+/// Read every entry of the packed-refs file.
+pub fn read_packed_refs(layout: &RepoLayout) -> Result<BTreeMap<String, ObjectId>, StoreError> {
+    let path = layout.packed_refs_file();
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut refs = BTreeMap::new();
+    for line in content.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        let Some((hex, name)) = line.split_once(' ') else {
+            continue; // skip corrupt line
+        };
+        if let Ok(id) = ObjectId::from_hex(hex) {
+            refs.insert(name.to_string(), id);
+        }
+    }
+    Ok(refs)
+}
+
+/// Atomically replace the packed-refs file. Callers must hold its lock.
+pub(crate) fn write_packed_refs(
+    layout: &RepoLayout,
+    refs: &BTreeMap<String, ObjectId>,
+) -> Result<(), StoreError> {
+    let mut content = String::with_capacity(refs.len() * 100);
+    content.push_str(PACKED_REFS_HEADER);
+    content.push('\n');
+    for (name, id) in refs {
+        content.push_str(&id.to_hex());
+        content.push(' ');
+        content.push_str(name);
+        content.push('\n');
+    }
+    let temp = tempfile::NamedTempFile::new_in(layout.claw_dir())?;
+    std::fs::write(temp.path(), content)?;
+    temp.persist(layout.packed_refs_file())
+        .map_err(|e| StoreError::Io(e.error))?;
+    Ok(())
+}
+
+/// Drop `names` from the packed-refs file, if any of them are packed.
+pub(crate) fn remove_packed_refs(layout: &RepoLayout, names: &[&str]) -> Result<(), StoreError> {
+    if !layout.packed_refs_file().exists() {
+        return Ok(());
+    }
+    let _lock = LockFile::acquire(&layout.packed_refs_file())?;
+    let mut packed = read_packed_refs(layout)?;
+    let before = packed.len();
+    for name in names {
+        packed.remove(*name);
+    }
+    if packed.len() != before {
+        write_packed_refs(layout, &packed)?;
+    }
     Ok(())
 }
 
+/// Move every loose ref into the packed-refs file, returning how many were packed.
+///
+/// A loose ref is only removed if it still holds the packed value once its
+/// lock is taken, so concurrent updates are never lost.
+pub fn pack_refs(layout: &RepoLayout) -> Result<usize, StoreError> {
+    let _lock = LockFile::acquire(&layout.packed_refs_file())?;
+    let mut loose = Vec::new();
+    collect_refs(&layout.refs_dir(), &layout.refs_dir(), &mut loose)?;
+    if loose.is_empty() {
+        return Ok(0);
+    }
+
+    let mut packed = read_packed_refs(layout)?;
+    for (name, id) in &loose {
+        packed.insert(name.clone(), *id);
+    }
+    write_packed_refs(layout, &packed)?;
+
+    let refs_dir = layout.refs_dir();
+    for (name, id) in &loose {
+        let path = refs_dir.join(name);
+        let Ok(_ref_lock) = LockFile::acquire(&path) else {
+            continue; // being updated; the loose value wins
+        };
+        if read_loose_ref(layout, name)? == Some(*id) {
+            std::fs::remove_file(&path)?;
+            // Prune now-empty directories, stopping at the top-level namespace.
+            let mut dir = path.parent();
+            while let Some(d) = dir {
+                if d.parent() == Some(refs_dir.as_path()) || std::fs::remove_dir(d).is_err() {
+                    break;
+                }
+                dir = d.parent();
+            }
+        }
+    }
+    Ok(loose.len())
+}
+
 pub fn update_ref_cas(
     layout: &RepoLayout,
     name: &str,
@@ -38,44 +153,34 @@ pub fn update_ref_cas(
     author: &str,
     message: &str,
 ) -> Result<(), StoreError> {
-    use crate::lockfile::LockFile;
-    use crate::reflog;
-
-    let ref_path = layout.refs_dir().join(name);
-    let _lock = LockFile::acquire(&ref_path)?;
-
-    let current = read_ref(layout, name)?;
-
-    match (expected_old, &current) {
-        (None, None) => {}
-        (Some(expected), Some(actual)) if expected == actual => {}
-        (expected, actual) => {
-            return Err(StoreError::RefCasConflict {
-                expected: expected
-                    .map(|id| id.to_hex())
-                    .unwrap_or_else(|| "none".to_string()),
-                actual: actual
-                    .as_ref()
-                    .map(|id| id.to_hex())
-                    .unwrap_or_else(|| "none".to_string()),
-            });
-        }
-    }
-
-    write_ref(layout, name, new_target)?;
-    reflog::append_reflog(layout, name, current.as_ref(), new_target, author, message)?;
-    Ok(())
+    let expected = match expected_old {
+        Some(id) => RefExpectation::Value(*id),
+        None => RefExpectation::Absent,
+    };
+    let mut tx = RefTransaction::new(layout);
+    tx.stage(name, expected, Some(*new_target));
+    tx.commit(author, message)
 }
 
 pub fn list_refs(layout: &RepoLayout, prefix: &str) -> Result<Vec<(String, ObjectId)>, StoreError> {
+    let prefix = prefix.trim_end_matches('/');
+    let mut merged: BTreeMap<String, ObjectId> = read_packed_refs(layout)?
+        .into_iter()
+        .filter(|(name, _)| {
+            prefix.is_empty()
+                || name
+                    .strip_prefix(prefix)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+        .collect();
+
     let base = layout.refs_dir().join(prefix);
-    if !base.exists() {
-        return Ok(Vec::new());
+    if base.exists() {
+        let mut loose = Vec::new();
+        collect_refs(&base, &layout.refs_dir(), &mut loose)?;
+        merged.extend(loose);
     }
-
-    let mut results = Vec::new();
-    collect_refs(&base, &layout.refs_dir(), &mut results)?;
-    Ok(results)
+    Ok(merged.into_iter().collect())
 }
 
 fn collect_refs(
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 use crate::layout::RepoLayout;
@@ -7,6 +9,12 @@ use crate::StoreError;
 pub struct RepoConfig {
     pub version: u32,
     pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "EncryptionConfig::is_empty")]
+    pub encryption: EncryptionConfig,
+    #[serde(default, skip_serializing_if = "MergeConfig::is_empty")]
+    pub merge: MergeConfig,
+    #[serde(default, skip_serializing_if = "CodecsConfig::is_empty")]
+    pub codecs: CodecsConfig,
 }
 
 impl Default for RepoConfig {
@@ -14,14 +22,83 @@ impl Default for RepoConfig {
         Self {
             version: 1,
             name: None,
+            encryption: EncryptionConfig::default(),
+            merge: MergeConfig::default(),
+            codecs: CodecsConfig::default(),
         }
     }
 }
 
+/// `[encryption]` in `repo.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Worktree paths whose blobs are stored encrypted. Glob patterns; a
+    /// trailing `/` covers everything under a directory.
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+impl EncryptionConfig {
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+}
+
+/// `[merge]` in `repo.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MergeConfig {
+    /// Merge drivers a `.clawattributes` file may name with `driver=`.
+    /// They are only ever read from this local file, never from a tree.
+    #[serde(default)]
+    pub drivers: BTreeMap<String, MergeDriver>,
+}
+
+impl MergeConfig {
+    pub fn is_empty(&self) -> bool {
+        self.drivers.is_empty()
+    }
+}
+
+/// `[merge.drivers.<name>]` in `repo.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeDriver {
+    /// Program to run, relative to the repository root if it contains a `/`.
+    pub command: String,
+    /// Arguments; `%O`, `%A` and `%B` are replaced with files holding the
+    /// base, left and right contents and `%P` with the path.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// `[codecs]` in `repo.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CodecsConfig {
+    #[serde(default)]
+    pub json: JsonCodecConfig,
+}
+
+impl CodecsConfig {
+    pub fn is_empty(&self) -> bool {
+        self.json.identity_keys.is_empty()
+    }
+}
+
+/// `[codecs.json]` in `repo.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JsonCodecConfig {
+    /// Object keys, tried in order, that identify the elements of an array
+    /// so they are matched by identity rather than by position. None by
+    /// default.
+    #[serde(default)]
+    pub identity_keys: Vec<String>,
+}
+
 pub fn write_default_config(layout: &RepoLayout) -> Result<(), StoreError> {
-    let config = RepoConfig::default();
-    let toml_str =
-        toml::to_string_pretty(&config).map_err(|e| StoreError::Config(e.to_string()))?;
+    write_config(layout, &RepoConfig::default())
+}
+
+pub fn write_config(layout: &RepoLayout, config: &RepoConfig) -> Result<(), StoreError> {
+    let toml_str = toml::to_string_pretty(config).map_err(|e| StoreError::Config(e.to_string()))?;
     std::fs::write(layout.config_file(), toml_str)?;
     Ok(())
 }
@@ -0,0 +1,489 @@
+//! Persisted revision graph: parents, generation number and timestamp of
+//! every revision, so ancestry queries don't have to decode revisions.
+//!
+//! `.claw/revision-graph` is an append-only file of checksummed records.
+//! Entries are added as revisions are stored and filled in lazily for
+//! revisions that arrived some other way. A revision's generation is one more
+//! than the highest generation of its parents (roots are 1), so a walk
+//! towards an ancestor can stop at anything with a generation at or below
+//! the ancestor's.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_core::types::Revision;
+
+use crate::{ClawStore, StoreError};
+
+const MAGIC: &[u8; 4] = b"CLRG";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 5;
+/// Id, generation, timestamp and parent count; parents and a CRC32 follow.
+const RECORD_FIXED_LEN: usize = 32 + 8 + 8 + 4;
+
+/// What the graph records about one revision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEntry {
+    pub parents: Vec<ObjectId>,
+    pub generation: u64,
+    pub created_at_ms: u64,
+}
+
+/// In-memory view of the revision graph file, loaded on first use.
+///
+/// Entries never change once written, so a handle that misses entries
+/// appended by another process only recomputes them.
+pub struct RevisionGraph {
+    path: PathBuf,
+    entries: RwLock<Option<HashMap<ObjectId, GraphEntry>>>,
+}
+
+impl RevisionGraph {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            entries: RwLock::new(None),
+        }
+    }
+
+    pub fn get(&self, id: &ObjectId) -> Option<GraphEntry> {
+        self.ensure_loaded();
+        let entries = self.entries.read().ok()?;
+        entries.as_ref()?.get(id).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ensure_loaded();
+        self.entries
+            .read()
+            .ok()
+            .and_then(|e| e.as_ref().map(HashMap::len))
+            .unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `new` to the file and the in-memory view.
+    pub fn insert(&self, new: &[(ObjectId, GraphEntry)]) -> Result<(), StoreError> {
+        if new.is_empty() {
+            return Ok(());
+        }
+        self.ensure_loaded();
+        let mut data = Vec::new();
+        for (id, entry) in new {
+            encode_record(&mut data, id, entry);
+        }
+        self.create_if_missing()?;
+        // One write per batch, so concurrent appenders don't interleave.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&self.path)?
+            .write_all(&data)?;
+        if let Ok(mut entries) = self.entries.write() {
+            let map = entries.get_or_insert_with(HashMap::new);
+            for (id, entry) in new {
+                map.insert(*id, entry.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Add `new` to the in-memory view only.
+    fn remember(&self, new: &[(ObjectId, GraphEntry)]) {
+        self.ensure_loaded();
+        if let Ok(mut entries) = self.entries.write() {
+            let map = entries.get_or_insert_with(HashMap::new);
+            for (id, entry) in new {
+                map.insert(*id, entry.clone());
+            }
+        }
+    }
+
+    /// Rewrite the file with only the entries `keep` accepts. Returns the
+    /// number of entries dropped.
+    pub fn retain(&self, keep: impl Fn(&ObjectId) -> bool) -> Result<usize, StoreError> {
+        self.ensure_loaded();
+        let Ok(mut guard) = self.entries.write() else {
+            return Ok(0);
+        };
+        let map = guard.get_or_insert_with(HashMap::new);
+        let before = map.len();
+        map.retain(|id, _| keep(id));
+        let dropped = before - map.len();
+        if dropped == 0 {
+            return Ok(0);
+        }
+        let mut data = header();
+        for (id, entry) in map.iter() {
+            encode_record(&mut data, id, entry);
+        }
+        let dir = self.path.parent().unwrap_or(std::path::Path::new("."));
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        std::fs::write(temp.path(), data)?;
+        temp.persist(&self.path)
+            .map_err(|e| StoreError::Io(e.error))?;
+        Ok(dropped)
+    }
+
+    fn ensure_loaded(&self) {
+        if matches!(self.entries.read().as_deref(), Ok(Some(_))) {
+            return;
+        }
+        let loaded = match std::fs::read(&self.path) {
+            Ok(data) => decode(&data),
+            Err(_) => HashMap::new(),
+        };
+        if let Ok(mut entries) = self.entries.write() {
+            entries.get_or_insert(loaded);
+        }
+    }
+
+    fn create_if_missing(&self) -> Result<(), StoreError> {
+        if self.path.exists() {
+            return Ok(());
+        }
+        let dir = self.path.parent().unwrap_or(std::path::Path::new("."));
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        std::fs::write(temp.path(), header())?;
+        match temp.persist_noclobber(&self.path) {
+            Ok(_) => Ok(()),
+            // Another process created it first.
+            Err(e) if e.error.kind() == std::io::ErrorKind::AlreadyExists => Ok(()),
+            Err(e) => Err(StoreError::Io(e.error)),
+        }
+    }
+}
+
+fn header() -> Vec<u8> {
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+    data
+}
+
+fn encode_record(out: &mut Vec<u8>, id: &ObjectId, entry: &GraphEntry) {
+    let start = out.len();
+    out.extend_from_slice(id.as_bytes());
+    out.extend_from_slice(&entry.generation.to_le_bytes());
+    out.extend_from_slice(&entry.created_at_ms.to_le_bytes());
+    out.extend_from_slice(&(entry.parents.len() as u32).to_le_bytes());
+    for parent in &entry.parents {
+        out.extend_from_slice(parent.as_bytes());
+    }
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// Parse the graph file, keeping every record before the first torn or
+/// corrupt one.
+fn decode(data: &[u8]) -> HashMap<ObjectId, GraphEntry> {
+    let mut entries = HashMap::new();
+    if data.len() < HEADER_LEN || &data[..4] != MAGIC || data[4] != VERSION {
+        tracing::warn!("ignoring unreadable revision graph");
+        return entries;
+    }
+    let mut pos = HEADER_LEN;
+    while pos < data.len() {
+        match decode_record(&data[pos..]) {
+            Some((id, entry, len)) => {
+                entries.insert(id, entry);
+                pos += len;
+            }
+            None => {
+                tracing::warn!("revision graph truncated at byte {pos}");
+                break;
+            }
+        }
+    }
+    entries
+}
+
+fn decode_record(data: &[u8]) -> Option<(ObjectId, GraphEntry, usize)> {
+    let id: [u8; 32] = data.get(..32)?.try_into().ok()?;
+    let generation = u64::from_le_bytes(data.get(32..40)?.try_into().ok()?);
+    let created_at_ms = u64::from_le_bytes(data.get(40..48)?.try_into().ok()?);
+    let count = u32::from_le_bytes(data.get(48..52)?.try_into().ok()?) as usize;
+    let body_len = RECORD_FIXED_LEN.checked_add(count.checked_mul(32)?)?;
+    let body = data.get(..body_len)?;
+    let crc = u32::from_le_bytes(data.get(body_len..body_len + 4)?.try_into().ok()?);
+    if crc32fast::hash(body) != crc {
+        return None;
+    }
+    let parents = body[RECORD_FIXED_LEN..]
+        .chunks_exact(32)
+        .map(|chunk| ObjectId::from_bytes(chunk.try_into().expect("32-byte chunk")))
+        .collect();
+    Some((
+        ObjectId::from_bytes(id),
+        GraphEntry {
+            parents,
+            generation,
+            created_at_ms,
+        },
+        body_len + 4,
+    ))
+}
+
+/// Parents and timestamp of `id`, or `None` if it is missing or not a revision.
+fn load_revision(
+    store: &ClawStore,
+    id: &ObjectId,
+) -> Result<Option<(Vec<ObjectId>, u64)>, StoreError> {
+    match store.load_object(id) {
+        Ok(Object::Revision(rev)) => Ok(Some((rev.parents, rev.created_at_ms))),
+        Ok(_) | Err(StoreError::ObjectNotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The graph entry for revision `id`, computing and recording it (and any
+/// ancestors the graph lacks) on a miss. `None` if `id` is not a revision.
+///
+/// Ancestors missing from the store count as absent parents; entries that
+/// depend on them are returned but not recorded.
+pub fn resolve(store: &ClawStore, id: &ObjectId) -> Result<Option<GraphEntry>, StoreError> {
+    resolve_entry(store, id, true)
+}
+
+/// Like [`resolve`], but entries computed on a miss are only kept in
+/// memory, so nothing under `.claw` is written.
+pub fn resolve_in_memory(
+    store: &ClawStore,
+    id: &ObjectId,
+) -> Result<Option<GraphEntry>, StoreError> {
+    resolve_entry(store, id, false)
+}
+
+fn resolve_entry(
+    store: &ClawStore,
+    id: &ObjectId,
+    persist: bool,
+) -> Result<Option<GraphEntry>, StoreError> {
+    let graph = store.revision_graph();
+    if let Some(entry) = graph.get(id) {
+        return Ok(Some(entry));
+    }
+
+    // id -> (entry, whether its whole ancestry is present)
+    let mut computed: HashMap<ObjectId, (GraphEntry, bool)> = HashMap::new();
+    let mut absent: HashSet<ObjectId> = HashSet::new();
+    let mut loaded: HashMap<ObjectId, (Vec<ObjectId>, u64)> = HashMap::new();
+    let mut fresh = Vec::new();
+    let mut stack = vec![*id];
+
+    while let Some(&top) = stack.last() {
+        if computed.contains_key(&top) || absent.contains(&top) {
+            stack.pop();
+            continue;
+        }
+        let (parents, created_at_ms) = match loaded.get(&top) {
+            Some(rev) => rev.clone(),
+            None => match load_revision(store, &top)? {
+                Some(rev) => {
+                    loaded.insert(top, rev.clone());
+                    rev
+                }
+                None => {
+                    absent.insert(top);
+                    stack.pop();
+                    continue;
+                }
+            },
+        };
+
+        let mut waiting = false;
+        for parent in &parents {
+            if computed.contains_key(parent) || absent.contains(parent) {
+                continue;
+            }
+            match graph.get(parent) {
+                Some(entry) => {
+                    computed.insert(*parent, (entry, true));
+                }
+                None => {
+                    stack.push(*parent);
+                    waiting = true;
+                }
+            }
+        }
+        if waiting {
+            continue;
+        }
+
+        let mut generation = 1;
+        let mut complete = true;
+        for parent in &parents {
+            match computed.get(parent) {
+                Some((entry, parent_complete)) => {
+                    generation = generation.max(entry.generation + 1);
+                    complete &= parent_complete;
+                }
+                None => complete = false,
+            }
+        }
+        let entry = GraphEntry {
+            parents,
+            generation,
+            created_at_ms,
+        };
+        if complete {
+            fresh.push((top, entry.clone()));
+        }
+        computed.insert(top, (entry, complete));
+        stack.pop();
+    }
+
+    if !persist {
+        graph.remember(&fresh);
+    } else if let Err(e) = graph.insert(&fresh) {
+        tracing::warn!("revision graph update failed: {e}");
+    }
+    Ok(computed.remove(id).map(|(entry, _)| entry))
+}
+
+/// Record a revision that was just stored. Skipped when a parent's entry
+/// can't be recorded; [`resolve`] fills it in later.
+pub(crate) fn record(store: &ClawStore, id: &ObjectId, rev: &Revision) -> Result<(), StoreError> {
+    let graph = store.revision_graph();
+    if graph.get(id).is_some() {
+        return Ok(());
+    }
+    let mut generation = 1;
+    for parent in &rev.parents {
+        resolve(store, parent)?;
+        match graph.get(parent) {
+            Some(entry) => generation = generation.max(entry.generation + 1),
+            None => return Ok(()),
+        }
+    }
+    graph.insert(&[(
+        *id,
+        GraphEntry {
+            parents: rev.parents.clone(),
+            generation,
+            created_at_ms: rev.created_at_ms,
+        },
+    )])
+}
+
+/// Whether `ancestor` is reachable from `descendant` (or is the same
+/// revision), walking only revisions with a higher generation.
+pub fn is_ancestor(
+    store: &ClawStore,
+    ancestor: &ObjectId,
+    descendant: &ObjectId,
+) -> Result<bool, StoreError> {
+    is_ancestor_by(ancestor, descendant, |id| resolve(store, id))
+}
+
+/// [`is_ancestor`] with graph entries looked up by `entry`.
+pub fn is_ancestor_by(
+    ancestor: &ObjectId,
+    descendant: &ObjectId,
+    mut entry: impl FnMut(&ObjectId) -> Result<Option<GraphEntry>, StoreError>,
+) -> Result<bool, StoreError> {
+    if ancestor == descendant {
+        return Ok(true);
+    }
+    let Some(target) = entry(ancestor)? else {
+        return Ok(false);
+    };
+
+    let mut visited = HashSet::new();
+    let mut stack = vec![*descendant];
+    while let Some(id) = stack.pop() {
+        if id == *ancestor {
+            return Ok(true);
+        }
+        if !visited.insert(id) {
+            continue;
+        }
+        let Some(current) = entry(&id)? else {
+            continue;
+        };
+        if current.generation <= target.generation {
+            continue;
+        }
+        stack.extend(current.parents);
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::RepoLayout;
+    use claw_core::types::Revision;
+
+    fn make_rev(store: &ClawStore, parents: Vec<ObjectId>, msg: &str) -> ObjectId {
+        let rev = Revision {
+            change_id: None,
+            parents,
+            patches: vec![],
+            snapshot_base: None,
+            tree: None,
+            capsule_id: None,
+            author: "test".to_string(),
+            created_at_ms: 7,
+            summary: msg.to_string(),
+            policy_evidence: vec![],
+        };
+        store.store_object(&Object::Revision(rev)).unwrap()
+    }
+
+    #[test]
+    fn generations_are_recorded_and_reloaded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = make_rev(&store, vec![], "A");
+        let b = make_rev(&store, vec![a], "B");
+        let c = make_rev(&store, vec![a], "C");
+        let m = make_rev(&store, vec![b, c], "M");
+
+        let reopened = ClawStore::open(tmp.path()).unwrap();
+        let graph = reopened.revision_graph();
+        assert_eq!(graph.len(), 4);
+        let entry = graph.get(&m).unwrap();
+        assert_eq!(entry.generation, 3);
+        assert_eq!(entry.parents, vec![b, c]);
+        assert_eq!(entry.created_at_ms, 7);
+
+        assert!(is_ancestor(&reopened, &a, &m).unwrap());
+        assert!(!is_ancestor(&reopened, &b, &c).unwrap());
+    }
+
+    #[test]
+    fn missing_entries_are_filled_lazily() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = make_rev(&store, vec![], "A");
+        let b = make_rev(&store, vec![a], "B");
+        drop(store);
+        std::fs::remove_file(RepoLayout::new(tmp.path()).revision_graph_file()).unwrap();
+
+        let store = ClawStore::open(tmp.path()).unwrap();
+        assert_eq!(resolve(&store, &b).unwrap().unwrap().generation, 2);
+        assert_eq!(store.revision_graph().len(), 2);
+    }
+
+    #[test]
+    fn torn_tail_is_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = make_rev(&store, vec![], "A");
+        drop(store);
+        let path = RepoLayout::new(tmp.path()).revision_graph_file();
+        let mut data = std::fs::read(&path).unwrap();
+        data.extend_from_slice(&[1, 2, 3]);
+        std::fs::write(&path, data).unwrap();
+
+        let graph = RevisionGraph::new(path);
+        assert_eq!(graph.len(), 1);
+        assert!(graph.get(&a).is_some());
+    }
+}
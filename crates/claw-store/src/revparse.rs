@@ -0,0 +1,425 @@
+//! Revision expressions.
+//!
+//! ```text
+//! expr     := base ( "~" [n] | "^" [n] )*
+//! base     := "HEAD" | "@" | ref [ "@{" n "}" ]
+//!           | "intent:" ulid | "change:" ulid | "capsule:" expr
+//!           | full or abbreviated hex id | full or abbreviated clw_ id
+//! ```
+//!
+//! Refs are tried as given and then under `heads/`, `tags/` and `remotes/`.
+
+use claw_core::id::{ChangeId, IntentId, ObjectId};
+use claw_core::object::{Object, TypeTag};
+use claw_core::types::Change;
+
+use crate::{reflog, ClawStore, HeadState, StoreError};
+
+/// Abbreviated ids shorter than this are not treated as id prefixes.
+pub const MIN_PREFIX_LEN: usize = 4;
+
+const REF_NAMESPACES: &[&str] = &["", "heads/", "tags/", "remotes/"];
+
+fn invalid(spec: &str, msg: impl std::fmt::Display) -> StoreError {
+    StoreError::InvalidRevision(format!("{spec}: {msg}"))
+}
+
+/// Resolve a revision expression to an object id.
+pub fn resolve(store: &ClawStore, spec: &str) -> Result<ObjectId, StoreError> {
+    let spec = spec.trim();
+    if spec.is_empty() {
+        return Err(invalid(spec, "empty revision"));
+    }
+    if let Some(rest) = spec.strip_prefix("capsule:") {
+        // The inner expression may carry its own suffixes.
+        let rev = resolve(store, rest)?;
+        return find_capsule(store, &rev)?
+            .ok_or_else(|| invalid(spec, format!("no capsule for revision {rev}")));
+    }
+
+    let (base, suffixes) = split_suffixes(spec);
+    let mut id = resolve_base(store, base, spec)?;
+
+    let mut chars = suffixes.chars().peekable();
+    while let Some(op) = chars.next() {
+        let mut digits = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            chars.next();
+        }
+        let n: usize = if digits.is_empty() {
+            1
+        } else {
+            digits.parse().map_err(|_| invalid(spec, "bad count"))?
+        };
+        match op {
+            '~' => {
+                for _ in 0..n {
+                    id = nth_parent(store, &id, 1, spec)?;
+                }
+            }
+            '^' if n == 0 => {}
+            '^' => id = nth_parent(store, &id, n, spec)?,
+            _ => return Err(invalid(spec, format!("unexpected '{op}'"))),
+        }
+    }
+    Ok(id)
+}
+
+/// The full ref name `name` refers to, if it names an existing ref.
+pub fn resolve_ref_name(store: &ClawStore, name: &str) -> Result<Option<String>, StoreError> {
+    if name.is_empty() || name.split('/').any(|part| part.is_empty() || part == "..") {
+        return Ok(None);
+    }
+    for ns in REF_NAMESPACES {
+        let full = format!("{ns}{name}");
+        if store.get_ref(&full)?.is_some() {
+            return Ok(Some(full));
+        }
+    }
+    Ok(None)
+}
+
+/// Split `base~2^1` into the base and its `~`/`^` suffix chain.
+fn split_suffixes(spec: &str) -> (&str, &str) {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '~' | '^' if depth == 0 => return (&spec[..i], &spec[i..]),
+            _ => {}
+        }
+    }
+    (spec, "")
+}
+
+fn resolve_base(store: &ClawStore, base: &str, spec: &str) -> Result<ObjectId, StoreError> {
+    if base == "HEAD" || base == "@" {
+        return store
+            .resolve_head()?
+            .ok_or_else(|| invalid(spec, "HEAD does not point at a revision yet"));
+    }
+    if let Some(ulid) = base.strip_prefix("intent:") {
+        return latest_intent_revision(store, ulid, spec);
+    }
+    if let Some(ulid) = base.strip_prefix("change:") {
+        let change_id = ChangeId::from_string(ulid).map_err(|e| invalid(spec, e))?;
+        let change =
+            load_change(store, &change_id)?.ok_or_else(|| invalid(spec, "unknown change"))?;
+        return change
+            .head_revision
+            .ok_or_else(|| invalid(spec, "change has no head revision"));
+    }
+    if let Some(open) = base.find("@{") {
+        let selector = base[open + 2..]
+            .strip_suffix('}')
+            .ok_or_else(|| invalid(spec, "unterminated reflog selector"))?;
+        let n: usize = selector
+            .parse()
+            .map_err(|_| invalid(spec, "reflog selector must be a number"))?;
+        return reflog_entry(store, &base[..open], n, spec);
+    }
+
+    if let Some(full) = resolve_ref_name(store, base)? {
+        if let Some(id) = store.get_ref(&full)? {
+            return Ok(id);
+        }
+    }
+    if let Ok(id) = ObjectId::from_hex(base) {
+        return Ok(id);
+    }
+    if let Ok(id) = ObjectId::from_display(base) {
+        return Ok(id);
+    }
+    if let Some(id) = resolve_prefix(store, base, spec)? {
+        return Ok(id);
+    }
+    Err(StoreError::RefNotFound(base.to_string()))
+}
+
+/// The target `n` updates back in the reflog of `name` (`@{0}` is the current value).
+fn reflog_entry(
+    store: &ClawStore,
+    name: &str,
+    n: usize,
+    spec: &str,
+) -> Result<ObjectId, StoreError> {
+    let ref_name = match name {
+        "" | "HEAD" | "@" => match store.read_head()? {
+            HeadState::Symbolic { ref_name } => ref_name,
+            HeadState::Detached { .. } => {
+                return Err(invalid(spec, "detached HEAD has no reflog"));
+            }
+        },
+        _ => resolve_ref_name(store, name)?
+            .ok_or_else(|| StoreError::RefNotFound(name.to_string()))?,
+    };
+    let entries = reflog::read_reflog(store.layout(), &ref_name)?;
+    entries
+        .iter()
+        .rev()
+        .nth(n)
+        .map(|line| line.new)
+        .ok_or_else(|| {
+            invalid(
+                spec,
+                format!("reflog of {ref_name} has only {} entries", entries.len()),
+            )
+        })
+}
+
+/// Match an abbreviated hex or `clw_` id against every stored object.
+fn resolve_prefix(
+    store: &ClawStore,
+    base: &str,
+    spec: &str,
+) -> Result<Option<ObjectId>, StoreError> {
+    let lower = base.to_ascii_lowercase();
+    let display = lower.starts_with("clw_");
+    let digits = lower.strip_prefix("clw_").unwrap_or(&lower);
+    if digits.len() < MIN_PREFIX_LEN || (!display && !digits.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        return Ok(None);
+    }
+
+    let mut found: Vec<ObjectId> = Vec::new();
+    for id in store.list_object_ids()? {
+        let text = if display { id.to_string() } else { id.to_hex() };
+        if text.starts_with(&lower) && !found.contains(&id) {
+            found.push(id);
+        }
+    }
+    match found.len() {
+        0 => Ok(None),
+        1 => Ok(Some(found[0])),
+        count => Err(invalid(
+            spec,
+            format!("ambiguous prefix {base} matches {count} objects"),
+        )),
+    }
+}
+
+fn nth_parent(
+    store: &ClawStore,
+    id: &ObjectId,
+    n: usize,
+    spec: &str,
+) -> Result<ObjectId, StoreError> {
+    match store.load_object(id)? {
+        Object::Revision(rev) => rev
+            .parents
+            .get(n - 1)
+            .copied()
+            .ok_or_else(|| invalid(spec, format!("{id} has no parent {n}"))),
+        other => Err(invalid(
+            spec,
+            format!("{id} is a {}, not a revision", other.type_tag().name()),
+        )),
+    }
+}
+
+fn load_change(store: &ClawStore, change_id: &ChangeId) -> Result<Option<Change>, StoreError> {
+    let Some(obj_id) = store.get_ref(&format!("changes/{change_id}"))? else {
+        return Ok(None);
+    };
+    match store.load_object(&obj_id)? {
+        Object::Change(change) => Ok(Some(change)),
+        _ => Ok(None),
+    }
+}
+
+/// The most recent head revision among the changes belonging to an intent.
+fn latest_intent_revision(
+    store: &ClawStore,
+    ulid: &str,
+    spec: &str,
+) -> Result<ObjectId, StoreError> {
+    let intent_id = IntentId::from_string(ulid).map_err(|e| invalid(spec, e))?;
+    if store.get_ref(&format!("intents/{intent_id}"))?.is_none() {
+        return Err(invalid(spec, "unknown intent"));
+    }
+
+    let mut latest: Option<(u64, ObjectId)> = None;
+    for (_, obj_id) in store.list_refs("changes")? {
+        let Object::Change(change) = store.load_object(&obj_id)? else {
+            continue;
+        };
+        let Some(head) = change
+            .head_revision
+            .filter(|_| change.intent_id == intent_id)
+        else {
+            continue;
+        };
+        let created = match store.load_object(&head) {
+            Ok(Object::Revision(rev)) => rev.created_at_ms,
+            _ => continue,
+        };
+        if latest.is_none_or(|(best, _)| created > best) {
+            latest = Some((created, head));
+        }
+    }
+    latest
+        .map(|(_, id)| id)
+        .ok_or_else(|| invalid(spec, "intent has no revisions yet"))
+}
+
+/// The capsule attached to a revision, via the object index or capsule refs.
+fn find_capsule(store: &ClawStore, rev: &ObjectId) -> Result<Option<ObjectId>, StoreError> {
+    if let Some(caps) = store.referrers_of_type(rev, TypeTag::Capsule)? {
+        if let Some(id) = caps.first() {
+            return Ok(Some(*id));
+        }
+    }
+    if let Some(id) = store.get_ref(&format!("capsules/{}", rev.to_hex()))? {
+        return Ok(Some(id));
+    }
+    store.get_ref(&format!("capsules/by-revision/{}", &rev.to_hex()[..16]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::types::{ChangeStatus, Intent, IntentStatus, Revision};
+
+    fn revision(store: &ClawStore, parents: Vec<ObjectId>, at: u64) -> ObjectId {
+        store
+            .store_object(&Object::Revision(Revision {
+                change_id: None,
+                parents,
+                patches: vec![],
+                snapshot_base: None,
+                tree: None,
+                capsule_id: None,
+                author: "test".into(),
+                created_at_ms: at,
+                summary: format!("rev {at}"),
+                policy_evidence: vec![],
+            }))
+            .unwrap()
+    }
+
+    #[test]
+    fn resolves_refs_ancestry_and_prefixes() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let a = revision(&store, vec![], 1);
+        let b = revision(&store, vec![a], 2);
+        let side = revision(&store, vec![a], 3);
+        let merge = revision(&store, vec![b, side], 4);
+        store
+            .update_ref_cas("heads/main", None, &b, "t", "b")
+            .unwrap();
+        store
+            .update_ref_cas("heads/main", Some(&b), &merge, "t", "merge")
+            .unwrap();
+
+        assert_eq!(resolve(&store, "HEAD").unwrap(), merge);
+        assert_eq!(resolve(&store, "main").unwrap(), merge);
+        assert_eq!(resolve(&store, "@~2").unwrap(), a);
+        assert_eq!(resolve(&store, "main^2").unwrap(), side);
+        assert_eq!(resolve(&store, "main^2~1").unwrap(), a);
+        assert_eq!(resolve(&store, "main^0").unwrap(), merge);
+        assert_eq!(resolve(&store, "main@{1}").unwrap(), b);
+        assert_eq!(resolve(&store, "@{0}").unwrap(), merge);
+        assert!(resolve(&store, "main@{2}").is_err());
+        assert!(resolve(&store, "main~5").is_err());
+
+        assert_eq!(resolve(&store, &merge.to_hex()[..12]).unwrap(), merge);
+        assert_eq!(resolve(&store, &side.to_string()[..14]).unwrap(), side);
+        assert!(matches!(
+            resolve(&store, "nope"),
+            Err(StoreError::RefNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn ambiguous_prefix_is_rejected() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let mut first = [0u8; 32];
+        first[..2].copy_from_slice(&[0xab, 0xcd]);
+        let mut second = first;
+        second[2] = 0x01;
+        for bytes in [first, second] {
+            let id = ObjectId::from_bytes(bytes);
+            crate::loose::write_loose_object(store.layout(), &id, b"placeholder").unwrap();
+        }
+
+        assert!(matches!(
+            resolve(&store, "abcd"),
+            Err(StoreError::InvalidRevision(_))
+        ));
+        assert_eq!(
+            resolve(&store, "abcd00").unwrap(),
+            ObjectId::from_bytes(first)
+        );
+        // Too short to be treated as an id prefix.
+        assert!(matches!(
+            resolve(&store, "abc"),
+            Err(StoreError::RefNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn resolves_domain_selectors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = ClawStore::init(tmp.path()).unwrap();
+        let old = revision(&store, vec![], 10);
+        let new = revision(&store, vec![old], 20);
+
+        let intent_id = IntentId::new();
+        let intent = store
+            .store_object(&Object::Intent(Intent {
+                id: intent_id,
+                title: "t".into(),
+                goal: "g".into(),
+                constraints: vec![],
+                acceptance_tests: vec![],
+                links: vec![],
+                policy_refs: vec![],
+                agents: vec![],
+                change_ids: vec![],
+                depends_on: vec![],
+                supersedes: vec![],
+                status: IntentStatus::Open,
+                created_at_ms: 0,
+                updated_at_ms: 0,
+            }))
+            .unwrap();
+        store
+            .set_ref(&format!("intents/{intent_id}"), &intent)
+            .unwrap();
+        for head in [old, new] {
+            let change_id = ChangeId::new();
+            let change = store
+                .store_object(&Object::Change(Change {
+                    id: change_id,
+                    intent_id,
+                    head_revision: Some(head),
+                    workstream_id: None,
+                    status: ChangeStatus::Open,
+                    created_at_ms: 0,
+                    updated_at_ms: 0,
+                }))
+                .unwrap();
+            store
+                .set_ref(&format!("changes/{change_id}"), &change)
+                .unwrap();
+        }
+
+        assert_eq!(
+            resolve(&store, &format!("intent:{intent_id}")).unwrap(),
+            new
+        );
+        assert!(resolve(&store, &format!("intent:{}", IntentId::new())).is_err());
+
+        let capsule_id = claw_core::hash::content_hash(TypeTag::Capsule, b"cap");
+        store
+            .set_ref(&format!("capsules/{}", new.to_hex()), &capsule_id)
+            .unwrap();
+        store.set_ref("heads/main", &new).unwrap();
+        assert_eq!(resolve(&store, "capsule:main").unwrap(), capsule_id);
+        assert!(resolve(&store, "capsule:main~1").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
@@ -13,6 +13,10 @@ pub enum ChangeKind {
     Deleted,
     Modified,
     TypeChanged,
+    /// Moved from `old_path`, possibly with edits.
+    Renamed,
+    /// Added as a copy of `old_path`, which still exists.
+    Copied,
 }
 
 #[derive(Debug, Clone)]
@@ -23,6 +27,10 @@ pub struct TreeChange {
     pub new_id: Option<ObjectId>,
     pub old_mode: Option<FileMode>,
     pub new_mode: Option<FileMode>,
+    /// Source path of a rename or copy.
+    pub old_path: Option<String>,
+    /// Content similarity to the source, 0-100, for renames and copies.
+    pub similarity: Option<u8>,
 }
 
 pub fn diff_trees(
@@ -66,6 +74,8 @@ pub fn diff_trees(
                         new_id: Some(*new_id),
                         old_mode: None,
                         new_mode: Some(*new_mode),
+                        old_path: None,
+                        similarity: None,
                     });
                 }
             }
@@ -81,6 +91,8 @@ pub fn diff_trees(
                         new_id: None,
                         old_mode: Some(*old_mode),
                         new_mode: None,
+                        old_path: None,
+                        similarity: None,
                     });
                 }
             }
@@ -100,6 +112,8 @@ pub fn diff_trees(
                             new_id: None,
                             old_mode: Some(*old_mode),
                             new_mode: None,
+                            old_path: None,
+                            similarity: None,
                         });
                     }
                     if *new_mode == FileMode::Directory {
@@ -113,6 +127,8 @@ pub fn diff_trees(
                             new_id: Some(*new_id),
                             old_mode: None,
                             new_mode: Some(*new_mode),
+                            old_path: None,
+                            similarity: None,
                         });
                     }
                 } else if *old_mode == FileMode::Directory && *new_mode == FileMode::Directory {
@@ -133,6 +149,8 @@ pub fn diff_trees(
                         new_id: Some(*new_id),
                         old_mode: Some(*old_mode),
                         new_mode: Some(*new_mode),
+                        old_path: None,
+                        similarity: None,
                     });
                 } else if old_mode != new_mode {
                     changes.push(TreeChange {
@@ -142,6 +160,8 @@ pub fn diff_trees(
                         new_id: Some(*new_id),
                         old_mode: Some(*old_mode),
                         new_mode: Some(*new_mode),
+                        old_path: None,
+                        similarity: None,
                     });
                 }
             }
@@ -167,3 +187,364 @@ fn flatten_tree_entries(
     }
     Ok(map)
 }
+
+/// Every non-directory entry under `tree_id`, keyed by its full path.
+pub fn flatten_tree(
+    store: &ClawStore,
+    tree_id: &ObjectId,
+) -> Result<BTreeMap<String, (ObjectId, FileMode)>, StoreError> {
+    let mut out = BTreeMap::new();
+    flatten_into(store, tree_id, "", &mut out)?;
+    Ok(out)
+}
+
+fn flatten_into(
+    store: &ClawStore,
+    tree_id: &ObjectId,
+    prefix: &str,
+    out: &mut BTreeMap<String, (ObjectId, FileMode)>,
+) -> Result<(), StoreError> {
+    for (name, (id, mode)) in flatten_tree_entries(store, tree_id)? {
+        let full_path = if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        if mode == FileMode::Directory {
+            flatten_into(store, &id, &full_path, out)?;
+        } else {
+            out.insert(full_path, (id, mode));
+        }
+    }
+    Ok(())
+}
+
+/// Diff two path -> (blob, mode) maps as produced by [`flatten_tree`].
+pub fn diff_flat(
+    old: &BTreeMap<String, (ObjectId, FileMode)>,
+    new: &BTreeMap<String, (ObjectId, FileMode)>,
+) -> Vec<TreeChange> {
+    let mut paths: Vec<&String> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut changes = Vec::new();
+    for path in paths {
+        let (kind, old_entry, new_entry) = match (old.get(path), new.get(path)) {
+            (None, Some(n)) => (ChangeKind::Added, None, Some(n)),
+            (Some(o), None) => (ChangeKind::Deleted, Some(o), None),
+            (Some(o), Some(n)) if o.1 != n.1 => (ChangeKind::TypeChanged, Some(o), Some(n)),
+            (Some(o), Some(n)) if o.0 != n.0 => (ChangeKind::Modified, Some(o), Some(n)),
+            _ => continue,
+        };
+        changes.push(TreeChange {
+            path: path.clone(),
+            kind,
+            old_id: old_entry.map(|e| e.0),
+            new_id: new_entry.map(|e| e.0),
+            old_mode: old_entry.map(|e| e.1),
+            new_mode: new_entry.map(|e| e.1),
+            old_path: None,
+            similarity: None,
+        });
+    }
+    changes
+}
+
+/// Scores how alike two files are for inexact rename and copy detection.
+pub trait SimilarityScorer {
+    /// Similarity of `old` at `old_path` to `new` at `new_path`, 0-100, or
+    /// `None` when they can't be compared (e.g. content too large to load).
+    fn score(
+        &mut self,
+        old_path: &str,
+        old: &ObjectId,
+        new_path: &str,
+        new: &ObjectId,
+    ) -> Option<u8>;
+}
+
+#[derive(Debug, Clone)]
+pub struct RenameOptions {
+    /// Minimum similarity for an inexact rename or copy.
+    pub threshold: u8,
+    /// Also look for added files that copy a file which still exists.
+    pub copies: bool,
+    /// Skip inexact matching when it would score more pairs than this.
+    pub max_pairs: usize,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 50,
+            copies: true,
+            max_pairs: 10_000,
+        }
+    }
+}
+
+/// Pair up deletions and additions in `changes` as renames, and additions
+/// with existing files as copies.
+///
+/// Identical blob ids are matched first; the remaining pairs are scored with
+/// `scorer` (when given) and matched best-first above the threshold. Copy
+/// sources are the files of `old_files` still in the new tree for exact
+/// matches, and modified files for inexact ones. Each deleted file is the
+/// source of at most one rename.
+pub fn detect_renames(
+    changes: Vec<TreeChange>,
+    old_files: &BTreeMap<String, (ObjectId, FileMode)>,
+    options: &RenameOptions,
+    mut scorer: Option<&mut dyn SimilarityScorer>,
+) -> Vec<TreeChange> {
+    let deleted: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].kind == ChangeKind::Deleted)
+        .collect();
+    let added: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].kind == ChangeKind::Added)
+        .collect();
+    if added.is_empty() {
+        return changes;
+    }
+
+    // added index -> (deleted index, similarity)
+    let mut renames: HashMap<usize, (usize, u8)> = HashMap::new();
+    let mut used = vec![false; changes.len()];
+
+    // Exact renames, preferring a source with the same file name.
+    let mut by_id: HashMap<ObjectId, Vec<usize>> = HashMap::new();
+    for &d in &deleted {
+        if let Some(id) = changes[d].old_id {
+            by_id.entry(id).or_default().push(d);
+        }
+    }
+    for &a in &added {
+        let Some(candidates) = changes[a].new_id.and_then(|id| by_id.get(&id)) else {
+            continue;
+        };
+        let name = file_name(&changes[a].path);
+        let free = candidates.iter().copied().filter(|&d| !used[d]);
+        let pick = free
+            .clone()
+            .find(|&d| file_name(&changes[d].path) == name)
+            .or_else(|| free.clone().next());
+        if let Some(d) = pick {
+            used[d] = true;
+            used[a] = true;
+            renames.insert(a, (d, 100));
+        }
+    }
+
+    // Inexact renames, best score first.
+    let open_deleted: Vec<usize> = deleted.iter().copied().filter(|&d| !used[d]).collect();
+    let open_added: Vec<usize> = added.iter().copied().filter(|&a| !used[a]).collect();
+    if let Some(scorer) = scorer.as_deref_mut() {
+        if open_deleted.len() * open_added.len() <= options.max_pairs {
+            let mut scored = Vec::new();
+            for &d in &open_deleted {
+                for &a in &open_added {
+                    let (Some(old), Some(new)) = (changes[d].old_id, changes[a].new_id) else {
+                        continue;
+                    };
+                    if let Some(score) =
+                        scorer.score(&changes[d].path, &old, &changes[a].path, &new)
+                    {
+                        if score >= options.threshold {
+                            scored.push((score, d, a));
+                        }
+                    }
+                }
+            }
+            scored.sort_by(|x, y| y.0.cmp(&x.0).then(x.1.cmp(&y.1)).then(x.2.cmp(&y.2)));
+            for (score, d, a) in scored {
+                if !used[d] && !used[a] {
+                    used[d] = true;
+                    used[a] = true;
+                    renames.insert(a, (d, score));
+                }
+            }
+        }
+    }
+
+    let mut copies: HashMap<usize, (String, ObjectId, FileMode, u8)> = HashMap::new();
+    if options.copies {
+        // Only files the new tree still has can be copied from; a deleted
+        // one is either a rename source or gone.
+        let gone: HashSet<&str> = deleted.iter().map(|&d| changes[d].path.as_str()).collect();
+        let mut old_by_id: HashMap<ObjectId, &String> = HashMap::new();
+        for (path, (id, _)) in old_files.iter().rev() {
+            if !gone.contains(path.as_str()) {
+                old_by_id.insert(*id, path);
+            }
+        }
+        let modified: Vec<usize> = (0..changes.len())
+            .filter(|&i| changes[i].kind == ChangeKind::Modified)
+            .collect();
+        for &a in added.iter().filter(|&&a| !used[a]) {
+            let Some(new_id) = changes[a].new_id else {
+                continue;
+            };
+            if let Some(path) = old_by_id.get(&new_id) {
+                let (id, mode) = old_files[*path];
+                copies.insert(a, ((*path).clone(), id, mode, 100));
+                continue;
+            }
+            let Some(scorer) = scorer.as_deref_mut() else {
+                continue;
+            };
+            if modified.len() * open_added.len() > options.max_pairs {
+                continue;
+            }
+            let mut best: Option<(u8, usize)> = None;
+            for &m in &modified {
+                let Some(old) = changes[m].old_id else {
+                    continue;
+                };
+                if let Some(score) = scorer.score(&changes[m].path, &old, &changes[a].path, &new_id)
+                {
+                    if score >= options.threshold && best.is_none_or(|(b, _)| score > b) {
+                        best = Some((score, m));
+                    }
+                }
+            }
+            if let Some((score, m)) = best {
+                let source = &changes[m];
+                copies.insert(
+                    a,
+                    (
+                        source.path.clone(),
+                        source.old_id.unwrap_or(new_id),
+                        source.old_mode.unwrap_or(FileMode::Regular),
+                        score,
+                    ),
+                );
+            }
+        }
+    }
+
+    let sources: Vec<(String, Option<ObjectId>, Option<FileMode>)> = changes
+        .iter()
+        .map(|c| (c.path.clone(), c.old_id, c.old_mode))
+        .collect();
+    let mut out = Vec::with_capacity(changes.len());
+    for (i, mut change) in changes.into_iter().enumerate() {
+        if let Some(&(d, score)) = renames.get(&i) {
+            let (path, old_id, old_mode) = sources[d].clone();
+            change.kind = ChangeKind::Renamed;
+            change.old_path = Some(path);
+            change.old_id = old_id;
+            change.old_mode = old_mode;
+            change.similarity = Some(score);
+        } else if let Some((path, id, mode, score)) = copies.remove(&i) {
+            change.kind = ChangeKind::Copied;
+            change.old_path = Some(path);
+            change.old_id = Some(id);
+            change.old_mode = Some(mode);
+            change.similarity = Some(score);
+        } else if change.kind == ChangeKind::Deleted && used[i] {
+            continue;
+        }
+        out.push(change);
+    }
+    out
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(data: &str) -> ObjectId {
+        claw_core::hash::content_hash(claw_core::object::TypeTag::Blob, data.as_bytes())
+    }
+
+    fn flat(files: &[(&str, &str)]) -> BTreeMap<String, (ObjectId, FileMode)> {
+        files
+            .iter()
+            .map(|(path, data)| (path.to_string(), (id(data), FileMode::Regular)))
+            .collect()
+    }
+
+    /// Scores by the length of the shared prefix of the two contents.
+    struct PrefixScorer(HashMap<ObjectId, &'static str>);
+
+    impl SimilarityScorer for PrefixScorer {
+        fn score(&mut self, _: &str, old: &ObjectId, _: &str, new: &ObjectId) -> Option<u8> {
+            let (old, new) = (self.0.get(old)?, self.0.get(new)?);
+            let shared = old
+                .bytes()
+                .zip(new.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            Some((100 * shared / old.len().max(new.len())) as u8)
+        }
+    }
+
+    #[test]
+    fn exact_and_edited_moves_become_renames() {
+        let contents = [
+            "fn main() {}",
+            "0123456789",
+            "012345678X",
+            "unrelated",
+            "copy me",
+        ];
+        let old = flat(&[
+            ("src/main.rs", contents[0]),
+            ("lib/a.txt", contents[1]),
+            ("gone.txt", contents[3]),
+            ("keep.txt", contents[4]),
+        ]);
+        let new = flat(&[
+            ("bin/main.rs", contents[0]),
+            // Its source was renamed away, so this is not a copy of it.
+            ("bin/other.rs", contents[0]),
+            ("lib/b.txt", contents[2]),
+            ("keep.txt", contents[4]),
+            ("keep2.txt", contents[4]),
+        ]);
+        let mut scorer = PrefixScorer(contents.iter().map(|c| (id(c), *c)).collect());
+        let changes = detect_renames(
+            diff_flat(&old, &new),
+            &old,
+            &RenameOptions::default(),
+            Some(&mut scorer),
+        );
+
+        let summary: Vec<(ChangeKind, Option<&str>, &str, Option<u8>)> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.kind.clone(),
+                    c.old_path.as_deref(),
+                    c.path.as_str(),
+                    c.similarity,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    ChangeKind::Renamed,
+                    Some("src/main.rs"),
+                    "bin/main.rs",
+                    Some(100)
+                ),
+                (ChangeKind::Added, None, "bin/other.rs", None),
+                (ChangeKind::Deleted, None, "gone.txt", None),
+                (ChangeKind::Copied, Some("keep.txt"), "keep2.txt", Some(100)),
+                (
+                    ChangeKind::Renamed,
+                    Some("lib/a.txt"),
+                    "lib/b.txt",
+                    Some(90)
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use claw_core::id::ObjectId;
+use claw_core::types::FileMode;
+
+use crate::layout::RepoLayout;
+use crate::StoreError;
+
+/// Worktree index format (`.claw/worktree-index`):
+/// [4B "CLWI"][4B version=1][4B entry_count]
+/// [entries, sorted by path:
+///   2B path length, UTF-8 path ('/'-separated, relative to the repo root)
+///   8B size, 8B mtime seconds, 4B mtime nanoseconds, 8B inode, 1B mode, 32B blob id]*
+/// [32B BLAKE3 checksum of everything above]
+const MAGIC: &[u8; 4] = b"CLWI";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 12;
+const ENTRY_FIXED_LEN: usize = 8 + 8 + 4 + 8 + 1 + 32;
+const CHECKSUM_LEN: usize = 32;
+
+/// The stat fields used to decide whether a file changed since it was hashed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime_secs: i64,
+    pub mtime_nanos: u32,
+    pub inode: u64,
+}
+
+impl FileStat {
+    pub fn from_metadata(meta: &Metadata) -> Self {
+        let (mtime_secs, mtime_nanos) = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or((0, 0), |d| (d.as_secs() as i64, d.subsec_nanos()));
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(meta);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Self {
+            size: meta.len(),
+            mtime_secs,
+            mtime_nanos,
+            inode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorktreeEntry {
+    pub stat: FileStat,
+    pub mode: FileMode,
+    pub blob_id: ObjectId,
+}
+
+/// Cached blob ids for worktree files, keyed by path.
+///
+/// A file whose size, mtime, inode and mode all match its entry is assumed
+/// unchanged and is not re-read. The cache is advisory: a missing or corrupt
+/// file simply means every file is hashed again.
+#[derive(Debug, Default)]
+pub struct WorktreeIndex {
+    entries: BTreeMap<String, WorktreeEntry>,
+    dirty: bool,
+}
+
+fn mode_to_u8(mode: FileMode) -> u8 {
+    match mode {
+        FileMode::Regular => 0,
+        FileMode::Executable => 1,
+        FileMode::Symlink => 2,
+        FileMode::Directory => 3,
+    }
+}
+
+fn mode_from_u8(v: u8) -> Option<FileMode> {
+    match v {
+        0 => Some(FileMode::Regular),
+        1 => Some(FileMode::Executable),
+        2 => Some(FileMode::Symlink),
+        3 => Some(FileMode::Directory),
+        _ => None,
+    }
+}
+
+impl WorktreeIndex {
+    /// Load the index, falling back to an empty one if it is missing or unreadable.
+    pub fn load(layout: &RepoLayout) -> Self {
+        let path = layout.worktree_index_file();
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+        match decode(&data) {
+            Ok(entries) => Self {
+                entries,
+                dirty: false,
+            },
+            Err(e) => {
+                tracing::warn!("ignoring unreadable worktree index: {e}");
+                Self {
+                    entries: BTreeMap::new(),
+                    dirty: true,
+                }
+            }
+        }
+    }
+
+    /// The cached blob id for `path`, if the file is unchanged since it was hashed.
+    pub fn lookup(&self, path: &str, stat: &FileStat, mode: FileMode) -> Option<ObjectId> {
+        self.entries
+            .get(path)
+            .filter(|e| e.stat == *stat && e.mode == mode)
+            .map(|e| e.blob_id)
+    }
+
+    pub fn insert(&mut self, path: String, entry: WorktreeEntry) {
+        if self.entries.get(&path) != Some(&entry) {
+            self.entries.insert(path, entry);
+            self.dirty = true;
+        }
+    }
+
+    /// Drop entries for paths not in `keep` (deleted or newly ignored files).
+    pub fn retain_paths(&mut self, keep: impl Fn(&str) -> bool) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| keep(path));
+        if self.entries.len() != before {
+            self.dirty = true;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the index back if anything changed.
+    ///
+    /// Files modified in the same second the index is written could change
+    /// again without their mtime moving, so those entries are left out and
+    /// get re-hashed next time.
+    pub fn save(&mut self, layout: &RepoLayout) -> Result<(), StoreError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let data = encode(
+            self.entries
+                .iter()
+                .filter(|(_, e)| e.stat.mtime_secs < now_secs),
+        );
+        let temp = tempfile::NamedTempFile::new_in(layout.claw_dir())?;
+        std::fs::write(temp.path(), data)?;
+        temp.persist(layout.worktree_index_file())
+            .map_err(|e| StoreError::Io(e.error))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn encode<'a>(entries: impl Iterator<Item = (&'a String, &'a WorktreeEntry)>) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut count = 0u32;
+    for (path, entry) in entries {
+        body.extend_from_slice(&(path.len() as u16).to_le_bytes());
+        body.extend_from_slice(path.as_bytes());
+        body.extend_from_slice(&entry.stat.size.to_le_bytes());
+        body.extend_from_slice(&entry.stat.mtime_secs.to_le_bytes());
+        body.extend_from_slice(&entry.stat.mtime_nanos.to_le_bytes());
+        body.extend_from_slice(&entry.stat.inode.to_le_bytes());
+        body.push(mode_to_u8(entry.mode));
+        body.extend_from_slice(entry.blob_id.as_bytes());
+        count += 1;
+    }
+
+    let mut data = Vec::with_capacity(HEADER_LEN + body.len() + CHECKSUM_LEN);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.extend_from_slice(&count.to_le_bytes());
+    data.extend_from_slice(&body);
+    let checksum = blake3::hash(&data);
+    data.extend_from_slice(checksum.as_bytes());
+    data
+}
+
+fn decode(data: &[u8]) -> Result<BTreeMap<String, WorktreeEntry>, StoreError> {
+    let corrupt = |msg: &str| StoreError::Index(format!("worktree index: {msg}"));
+    if data.len() < HEADER_LEN + CHECKSUM_LEN || &data[..4] != MAGIC {
+        return Err(corrupt("bad header"));
+    }
+    let body_end = data.len() - CHECKSUM_LEN;
+    if blake3::hash(&data[..body_end]).as_bytes() != &data[body_end..] {
+        return Err(corrupt("checksum mismatch"));
+    }
+    let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+    if version != VERSION {
+        return Err(corrupt(&format!("unsupported version {version}")));
+    }
+    let count = u32::from_le_bytes(data[8..12].try_into().unwrap()) as usize;
+
+    let mut entries = BTreeMap::new();
+    let mut pos = HEADER_LEN;
+    for _ in 0..count {
+        if pos + 2 > body_end {
+            return Err(corrupt("truncated entry"));
+        }
+        let path_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        pos += 2;
+        if pos + path_len + ENTRY_FIXED_LEN > body_end {
+            return Err(corrupt("truncated entry"));
+        }
+        let path = std::str::from_utf8(&data[pos..pos + path_len])
+            .map_err(|_| corrupt("path is not UTF-8"))?
+            .to_string();
+        pos += path_len;
+        let field = &data[pos..pos + ENTRY_FIXED_LEN];
+        let stat = FileStat {
+            size: u64::from_le_bytes(field[0..8].try_into().unwrap()),
+            mtime_secs: i64::from_le_bytes(field[8..16].try_into().unwrap()),
+            mtime_nanos: u32::from_le_bytes(field[16..20].try_into().unwrap()),
+            inode: u64::from_le_bytes(field[20..28].try_into().unwrap()),
+        };
+        let mode = mode_from_u8(field[28]).ok_or_else(|| corrupt("unknown mode"))?;
+        let blob_id = ObjectId::from_bytes(field[29..61].try_into().unwrap());
+        pos += ENTRY_FIXED_LEN;
+        entries.insert(
+            path,
+            WorktreeEntry {
+                stat,
+                mode,
+                blob_id,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::hash::content_hash;
+    use claw_core::object::TypeTag;
+
+    #[test]
+    fn roundtrip_and_lookup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+
+        let stat = FileStat {
+            size: 5,
+            mtime_secs: 1_000,
+            mtime_nanos: 7,
+            inode: 42,
+        };
+        let id = content_hash(TypeTag::Blob, b"hello");
+        let mut index = WorktreeIndex::load(&layout);
+        assert!(index.is_empty());
+        index.insert(
+            "src/a.txt".into(),
+            WorktreeEntry {
+                stat,
+                mode: FileMode::Regular,
+                blob_id: id,
+            },
+        );
+        index.save(&layout).unwrap();
+
+        let index = WorktreeIndex::load(&layout);
+        assert_eq!(index.len(), 1);
+        assert_eq!(
+            index.lookup("src/a.txt", &stat, FileMode::Regular),
+            Some(id)
+        );
+        let touched = FileStat {
+            mtime_secs: 1_001,
+            ..stat
+        };
+        assert_eq!(index.lookup("src/a.txt", &touched, FileMode::Regular), None);
+        assert_eq!(index.lookup("src/a.txt", &stat, FileMode::Executable), None);
+    }
+
+    #[test]
+    fn corrupt_index_is_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let layout = RepoLayout::new(tmp.path());
+        layout.create_dirs().unwrap();
+        std::fs::write(layout.worktree_index_file(), b"CLWI garbage").unwrap();
+        assert!(WorktreeIndex::load(&layout).is_empty());
+    }
+}
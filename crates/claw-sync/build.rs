@@ -17,6 +17,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         "claw/capsule.proto",
         "claw/workstream.proto",
         "claw/event.proto",
+        "claw/merge.proto",
     ];
 
     for proto in protos {
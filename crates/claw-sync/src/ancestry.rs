@@ -1,35 +1,14 @@
-use std::collections::HashSet;
-
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
 use claw_store::ClawStore;
 
-/// Check if `potential_ancestor` is an ancestor of `descendant` by BFS back from descendant.
+/// Check if `potential_ancestor` is an ancestor of `descendant`, using the
+/// revision graph's generation numbers to cut the walk short.
 pub fn is_ancestor(
     store: &ClawStore,
     potential_ancestor: &ObjectId,
     descendant: &ObjectId,
 ) -> bool {
-    if potential_ancestor == descendant {
-        return true;
-    }
-
-    let mut visited = HashSet::new();
-    let mut queue = vec![*descendant];
-
-    while let Some(id) = queue.pop() {
-        if id == *potential_ancestor {
-            return true;
-        }
-        if !visited.insert(id) {
-            continue;
-        }
-        if let Ok(Object::Revision(rev)) = store.load_object(&id) {
-            queue.extend_from_slice(&rev.parents);
-        }
-    }
-
-    false
+    claw_store::revision_graph::is_ancestor(store, potential_ancestor, descendant).unwrap_or(false)
 }
 
 #[cfg(test)]
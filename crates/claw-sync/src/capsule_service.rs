@@ -4,9 +4,9 @@ use tokio::sync::RwLock;
 use tonic::{Request, Response, Status};
 
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
+use claw_core::object::{Object, TypeTag};
 use claw_core::types::{Capsule, CapsulePublic, Evidence};
-use claw_store::ClawStore;
+use claw_store::{ClawStore, StoreError};
 
 use crate::proto::capsule::capsule_service_server::CapsuleService;
 use crate::proto::capsule::*;
@@ -21,6 +21,16 @@ impl CapsuleServer {
     }
 }
 
+/// Find the capsule attached to `revision_id`: the `capsules/` ref first,
+/// then any capsule the object index records as referencing the revision.
+fn find_capsule(store: &ClawStore, revision_id: &ObjectId) -> Result<Option<ObjectId>, StoreError> {
+    if let Some(id) = store.get_ref(&format!("capsules/{}", revision_id.to_hex()))? {
+        return Ok(Some(id));
+    }
+    let indexed = store.referrers_of_type(revision_id, TypeTag::Capsule)?;
+    Ok(indexed.and_then(|caps| caps.first().copied()))
+}
+
 fn capsule_to_proto(c: &Capsule) -> crate::proto::objects::Capsule {
     crate::proto::objects::Capsule {
         revision_id: Some(crate::proto::common::ObjectId {
@@ -166,8 +176,7 @@ impl CapsuleService for CapsuleServer {
         let revision_id = ObjectId::from_bytes(arr);
 
         let store = self.store.read().await;
-        let obj_id = store
-            .get_ref(&format!("capsules/{}", revision_id.to_hex()))
+        let obj_id = find_capsule(&store, &revision_id)
             .map_err(|e| Status::internal(e.to_string()))?
             .ok_or_else(|| Status::not_found("capsule not found"))?;
 
@@ -198,8 +207,7 @@ impl CapsuleService for CapsuleServer {
         let revision_id = ObjectId::from_bytes(arr);
 
         let store = self.store.read().await;
-        let obj_id = match store
-            .get_ref(&format!("capsules/{}", revision_id.to_hex()))
+        let obj_id = match find_capsule(&store, &revision_id)
             .map_err(|e| Status::internal(e.to_string()))?
         {
             Some(id) => id,
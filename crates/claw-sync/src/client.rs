@@ -1,9 +1,9 @@
 use async_trait::async_trait;
-use claw_core::cof::{cof_decode, cof_encode};
+use claw_core::cof::cof_peek_type_tag;
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
 use claw_store::ClawStore;
 
+use crate::dictionaries::{dictionary_chunk, DictionaryShipper, CAP_DICTIONARIES};
 use crate::http_client::HttpSyncClient;
 use crate::proto::sync::sync_service_client::SyncServiceClient;
 use crate::proto::sync::*;
@@ -74,12 +74,27 @@ impl SyncClient {
 
 pub struct GrpcSyncClient {
     client: SyncServiceClient<tonic::transport::Channel>,
+    /// What the server said it supports, once `hello` has been called.
+    server_capabilities: Option<Vec<String>>,
 }
 
 impl GrpcSyncClient {
     pub async fn connect(addr: &str) -> Result<Self, SyncError> {
         let client = SyncServiceClient::connect(addr.to_string()).await?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            server_capabilities: None,
+        })
+    }
+
+    async fn server_supports(&mut self, capability: &str) -> Result<bool, SyncError> {
+        if self.server_capabilities.is_none() {
+            self.hello().await?;
+        }
+        Ok(self
+            .server_capabilities
+            .as_ref()
+            .is_some_and(|caps| caps.iter().any(|c| c == capability)))
     }
 }
 
@@ -90,10 +105,12 @@ impl SyncTransport for GrpcSyncClient {
             .client
             .hello(HelloRequest {
                 client_version: "0.1.0".to_string(),
-                capabilities: vec!["partial-clone".to_string()],
+                capabilities: vec!["partial-clone".to_string(), CAP_DICTIONARIES.to_string()],
             })
             .await?;
-        Ok(resp.into_inner())
+        let resp = resp.into_inner();
+        self.server_capabilities = Some(resp.capabilities.clone());
+        Ok(resp)
     }
 
     async fn advertise_refs(&mut self, prefix: &str) -> Result<Vec<(String, ObjectId)>, SyncError> {
@@ -144,6 +161,7 @@ impl SyncTransport for GrpcSyncClient {
                 want: want_msgs,
                 have: have_msgs,
                 filter: None,
+                accept_dictionaries: true,
             })
             .await?;
 
@@ -154,9 +172,24 @@ impl SyncTransport for GrpcSyncClient {
             if chunk.is_last {
                 break;
             }
-            let (type_tag, payload) = cof_decode(&chunk.data)?;
-            let obj = Object::deserialize_payload(type_tag, &payload)?;
-            let id = store.store_object(&obj)?;
+            if let Some(dict) = &chunk.dictionary {
+                store.store_received_dictionary(dict.id, &dict.data)?;
+                continue;
+            }
+            let expected = chunk
+                .id
+                .as_ref()
+                .and_then(|id| <[u8; 32]>::try_from(id.hash.as_slice()).ok())
+                .map(ObjectId::from_bytes)
+                .ok_or_else(|| SyncError::TransferFailed("object chunk without an id".into()))?;
+            let id = store.store_received_cof(&expected, &chunk.data)?;
+            if id != expected {
+                return Err(SyncError::TransferFailed(format!(
+                    "object id mismatch: expected={} actual={}",
+                    expected.to_hex(),
+                    id.to_hex()
+                )));
+            }
             fetched.push(id);
         }
 
@@ -196,13 +229,15 @@ impl SyncTransport for GrpcSyncClient {
         store: &ClawStore,
         ids: &[ObjectId],
     ) -> Result<PushObjectsResponse, SyncError> {
+        let mut shipper = DictionaryShipper::new(self.server_supports(CAP_DICTIONARIES).await?);
         let mut chunks = Vec::new();
 
         for id in ids {
-            let obj = store.load_object(id)?;
-            let payload = obj.serialize_payload()?;
-            let type_tag = obj.type_tag();
-            let cof_data = cof_encode(type_tag, &payload)?;
+            let (cof_data, dict) = shipper.prepare(store, id)?;
+            if let Some((dict_id, data)) = dict {
+                chunks.push(dictionary_chunk(dict_id, data));
+            }
+            let type_tag = cof_peek_type_tag(&cof_data)?;
 
             chunks.push(ObjectChunk {
                 id: Some(crate::proto::common::ObjectId {
@@ -211,6 +246,7 @@ impl SyncTransport for GrpcSyncClient {
                 object_type: type_tag as i32,
                 data: cof_data,
                 is_last: false,
+                dictionary: None,
             });
         }
 
@@ -219,6 +255,7 @@ impl SyncTransport for GrpcSyncClient {
             object_type: 0,
             data: vec![],
             is_last: true,
+            dictionary: None,
         });
 
         let stream = tokio_stream::iter(chunks);
@@ -0,0 +1,133 @@
+//! Zstd dictionaries sent along with the objects compressed with them.
+//!
+//! Small objects are stored compressed with a per-type dictionary. A peer
+//! that takes dictionaries gets each one once, ahead of the first object
+//! using it, and the objects go out as stored; any other peer gets them
+//! re-encoded with plain zstd.
+
+use std::collections::HashSet;
+
+use claw_core::cof::cof_dictionary_id;
+use claw_core::id::ObjectId;
+use claw_store::{ClawStore, StoreError};
+
+use crate::proto::sync::{Dictionary, ObjectChunk};
+
+/// Capability of peers that take dictionary-compressed objects.
+pub const CAP_DICTIONARIES: &str = "zstd-dictionaries";
+
+/// A dictionary's id and bytes.
+pub type DictionaryEntry = (u32, Vec<u8>);
+
+/// Which dictionaries a peer has been sent during one transfer.
+#[derive(Debug, Default)]
+pub struct DictionaryShipper {
+    enabled: bool,
+    sent: HashSet<u32>,
+}
+
+impl DictionaryShipper {
+    /// A shipper for a peer that takes dictionaries when `enabled`.
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            sent: HashSet::new(),
+        }
+    }
+
+    /// The COF bytes to send for `id`, with the dictionary to send ahead of
+    /// them if the peer has not been sent it yet.
+    pub fn prepare(
+        &mut self,
+        store: &ClawStore,
+        id: &ObjectId,
+    ) -> Result<(Vec<u8>, Option<DictionaryEntry>), StoreError> {
+        if !self.enabled {
+            return Ok((store.load_portable_cof_bytes(id)?, None));
+        }
+        let cof_data = store.load_cof_bytes(id)?;
+        let Some(dict_id) = cof_dictionary_id(&cof_data) else {
+            return Ok((cof_data, None));
+        };
+        if self.sent.contains(&dict_id) {
+            return Ok((cof_data, None));
+        }
+        match store.dictionary_bytes(dict_id)? {
+            Some(dict) => {
+                self.sent.insert(dict_id);
+                Ok((cof_data, Some((dict_id, dict))))
+            }
+            None => Ok((store.load_portable_cof_bytes(id)?, None)),
+        }
+    }
+}
+
+/// The stream chunk carrying dictionary `id`.
+pub fn dictionary_chunk(id: u32, data: Vec<u8>) -> ObjectChunk {
+    ObjectChunk {
+        id: None,
+        object_type: 0,
+        data: vec![],
+        is_last: false,
+        dictionary: Some(Dictionary { id, data }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use claw_core::object::Object;
+    use claw_core::types::{FileMode, Tree, TreeEntry};
+    use claw_store::dictionary::{train, TrainOptions};
+
+    fn tree(i: usize) -> Object {
+        Object::Tree(Tree {
+            entries: ["Cargo.toml", "README.md", "src", "tests"]
+                .iter()
+                .map(|name| TreeEntry {
+                    name: format!("{name}_{}", i % 7),
+                    mode: FileMode::Regular,
+                    object_id: ObjectId::from_bytes([i as u8; 32]),
+                })
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn dictionaries_are_sent_once_and_received_before_their_objects() {
+        let tmp = tempfile::tempdir().unwrap();
+        let sender = ClawStore::init(&tmp.path().join("sender")).unwrap();
+        for i in 0..200 {
+            sender.store_object(&tree(i)).unwrap();
+        }
+        train(&sender, &TrainOptions::default()).unwrap();
+        let ids: Vec<_> = (1000..1003)
+            .map(|i| sender.store_object(&tree(i)).unwrap())
+            .collect();
+
+        let mut shipper = DictionaryShipper::new(true);
+        let (first, dict) = shipper.prepare(&sender, &ids[0]).unwrap();
+        let (dict_id, dict) = dict.expect("the first object brings its dictionary");
+        assert_eq!(cof_dictionary_id(&first), Some(dict_id));
+        let (_, again) = shipper.prepare(&sender, &ids[1]).unwrap();
+        assert!(again.is_none());
+
+        let (portable, none) = DictionaryShipper::new(false)
+            .prepare(&sender, &ids[0])
+            .unwrap();
+        assert!(none.is_none());
+        assert_eq!(cof_dictionary_id(&portable), None);
+
+        let receiver = ClawStore::init(&tmp.path().join("receiver")).unwrap();
+        assert!(receiver.store_received_cof(&ids[0], &first).is_err());
+        assert!(receiver
+            .store_received_dictionary(dict_id ^ 1, &dict)
+            .is_err());
+        receiver.store_received_dictionary(dict_id, &dict).unwrap();
+        assert_eq!(
+            receiver.store_received_cof(&ids[0], &first).unwrap(),
+            ids[0]
+        );
+        assert!(receiver.dictionaries().active_entries().is_empty());
+    }
+}
@@ -4,13 +4,15 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use base64::prelude::*;
-use claw_core::cof::{cof_decode, cof_peek_type_tag};
+use claw_core::cof::cof_peek_type_tag;
 use claw_core::id::ObjectId;
-use claw_core::object::{Object, TypeTag};
+use claw_core::object::TypeTag;
+use claw_store::pack::PackWriter;
 use claw_store::ClawStore;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
+use crate::dictionaries::DictionaryShipper;
 use crate::proto;
 use crate::proto::sync::{HelloResponse, PushObjectsResponse, UpdateRefsResponse};
 use crate::transport::SyncTransport;
@@ -34,6 +36,7 @@ const INLINE_OBJECT_MAX_BYTES: usize = 1_000_000;
 const INLINE_BATCH_MAX_BYTES: usize = 2_500_000;
 const CAP_CHUNKED_OBJECTS_V1: &str = "chunked-objects-v1";
 const CAP_PACK_UPLOAD_V1: &str = "pack-upload-v1";
+const CAP_PACK_UPLOAD_V2: &str = "pack-upload-v2";
 const CAP_BATCH_COMPLETE_V1: &str = "batch-complete-v1";
 const MAX_CONCURRENT_UPLOADS: usize = 8;
 const MAX_BATCH_SIZE: usize = 500;
@@ -50,13 +53,15 @@ struct PreparedObject {
 }
 
 /// Read raw COF bytes from the store without the decode → re-encode cycle.
+/// Only objects compressed with a local dictionary are re-encoded, since the
+/// remote has no copy of it.
 fn prepare_objects_raw(
     store: &ClawStore,
     ids: &[ObjectId],
 ) -> Result<Vec<PreparedObject>, SyncError> {
     let mut prepared = Vec::with_capacity(ids.len());
     for id in ids {
-        let cof_bytes = store.load_cof_bytes(id)?;
+        let cof_bytes = store.load_portable_cof_bytes(id)?;
         let type_tag = cof_peek_type_tag(&cof_bytes)?;
         prepared.push(PreparedObject {
             id: *id,
@@ -88,6 +93,23 @@ fn build_clpk_pack(objects: &[PreparedObject]) -> Vec<u8> {
     data
 }
 
+/// Build a CLPK v2 packfile, which carries the dictionaries its objects are
+/// compressed with, so they go out as stored, and delta-compresses similar
+/// objects.
+#[allow(clippy::result_large_err)]
+fn build_clpk_pack_v2(store: &ClawStore, ids: &[ObjectId]) -> Result<Vec<u8>, SyncError> {
+    let mut shipper = DictionaryShipper::new(true);
+    let mut writer = PackWriter::new();
+    for id in ids {
+        let (cof_bytes, dict) = shipper.prepare(store, id)?;
+        if let Some((dict_id, dict)) = dict {
+            writer.add_dictionary(dict_id, dict);
+        }
+        writer.add_cof_bytes(*id, cof_bytes);
+    }
+    Ok(writer.to_bytes()?)
+}
+
 fn ids_to_proto(ids: impl IntoIterator<Item = ObjectId>) -> Vec<proto::common::ObjectId> {
     ids.into_iter()
         .map(|id| proto::common::ObjectId {
@@ -513,8 +535,11 @@ impl HttpSyncClient {
         store: &ClawStore,
         ids: &[ObjectId],
     ) -> Result<PushObjectsResponse, SyncError> {
-        let prepared = prepare_objects_raw(store, ids)?;
-        let pack_data = build_clpk_pack(&prepared);
+        let pack_data = if self.server_capabilities.contains(CAP_PACK_UPLOAD_V2) {
+            build_clpk_pack_v2(store, ids)?
+        } else {
+            build_clpk_pack(&prepare_objects_raw(store, ids)?)
+        };
         let pack_size = pack_data.len();
 
         let accepted = if pack_size <= OBJECT_BYTES_CHUNK_SIZE {
@@ -896,7 +921,7 @@ impl HttpSyncClient {
 
                 let cof_bytes = self.fetch_object_bytes(&object_id, item.size_bytes).await?;
 
-                let (type_tag, payload) = cof_decode(&cof_bytes)?;
+                let type_tag = cof_peek_type_tag(&cof_bytes)?;
                 if type_tag != expected_type {
                     return Err(SyncError::TransferFailed(format!(
                         "type tag mismatch for {}: manifest={} cof={}",
@@ -906,8 +931,7 @@ impl HttpSyncClient {
                     )));
                 }
 
-                let object = Object::deserialize_payload(type_tag, &payload)?;
-                let id = store.store_object(&object)?;
+                let id = store.store_received_cof(&expected_id, &cof_bytes)?;
                 if id != expected_id {
                     return Err(SyncError::TransferFailed(format!(
                         "object id mismatch for {}: expected={} actual={}",
@@ -975,7 +999,7 @@ impl HttpSyncClient {
                 SyncError::TransferFailed(format!("invalid cofBase64 for {object_id}: {e}"))
             })?;
 
-            let (type_tag, payload) = cof_decode(&cof_bytes)?;
+            let type_tag = cof_peek_type_tag(&cof_bytes)?;
             if type_tag != expected_type {
                 return Err(SyncError::TransferFailed(format!(
                     "type tag mismatch for {}: manifest={} cof={}",
@@ -985,8 +1009,7 @@ impl HttpSyncClient {
                 )));
             }
 
-            let object = Object::deserialize_payload(type_tag, &payload)?;
-            let id = store.store_object(&object)?;
+            let id = store.store_received_cof(&expected_id, &cof_bytes)?;
             if id != expected_id {
                 return Err(SyncError::TransferFailed(format!(
                     "object id mismatch for {}: expected={} actual={}",
@@ -1413,7 +1436,9 @@ impl SyncTransport for HttpSyncClient {
 
         // Strategy 1: Pack upload (Tier 1) – single binary payload, fewest HTTP
         // requests. The server unpacks the CLPK and stores all objects at once.
-        if self.server_capabilities.contains(CAP_PACK_UPLOAD_V1) {
+        if self.server_capabilities.contains(CAP_PACK_UPLOAD_V1)
+            || self.server_capabilities.contains(CAP_PACK_UPLOAD_V2)
+        {
             return self.push_objects_pack(store, ids).await;
         }
 
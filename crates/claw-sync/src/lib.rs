@@ -2,10 +2,12 @@ pub mod ancestry;
 pub mod capsule_service;
 pub mod change_service;
 pub mod client;
+pub mod dictionaries;
 pub mod error;
 pub mod event_service;
 pub mod http_client;
 pub mod intent_service;
+pub mod merge_service;
 pub mod negotiation;
 pub mod partial_clone;
 pub mod server;
@@ -37,6 +39,9 @@ pub mod proto {
     pub mod event {
         tonic::include_proto!("claw.event");
     }
+    pub mod merge {
+        tonic::include_proto!("claw.merge");
+    }
 }
 
 pub use error::SyncError;
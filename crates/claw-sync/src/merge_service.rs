@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use tonic::{Request, Response, Status};
+
+use claw_core::id::ObjectId;
+use claw_merge::preview::preview_merge;
+use claw_patch::CodecRegistry;
+use claw_store::repo::read_config;
+use claw_store::ClawStore;
+
+use crate::proto::merge::merge_service_server::MergeService;
+use crate::proto::merge::*;
+
+pub struct MergeServer {
+    store: Arc<RwLock<ClawStore>>,
+}
+
+impl MergeServer {
+    pub fn new(store: Arc<RwLock<ClawStore>>) -> Self {
+        Self { store }
+    }
+}
+
+#[tonic::async_trait]
+impl MergeService for MergeServer {
+    async fn preview(
+        &self,
+        request: Request<PreviewMergeRequest>,
+    ) -> Result<Response<PreviewMergeResponse>, Status> {
+        let req = request.into_inner();
+        let store = self.store.read().await;
+
+        let left = store
+            .rev_parse(&req.left)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let right = store
+            .rev_parse(&req.right)
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        let config = read_config(store.layout()).map_err(|e| Status::internal(e.to_string()))?;
+        let registry = CodecRegistry::with_json_identity_keys(&config.codecs.json.identity_keys);
+        let preview = preview_merge(&store, &registry, &left, &right)
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        // Conflict hunks are plaintext, which must not leave the server for
+        // paths stored encrypted.
+        let conflicts = preview
+            .conflicts
+            .into_iter()
+            .map(|c| {
+                let redacted = store.encrypts_path(&c.path);
+                let hunks = if redacted { vec![] } else { c.hunks };
+                PreviewConflict {
+                    path: c.path,
+                    kind: c.kind.as_str().to_string(),
+                    hunks: hunks
+                        .into_iter()
+                        .map(|h| PreviewHunk {
+                            base_line: h.base_line as u32,
+                            base: h.base,
+                            left: h.left,
+                            right: h.right,
+                        })
+                        .collect(),
+                    redacted,
+                }
+            })
+            .collect();
+        let object_id = |id: &ObjectId| crate::proto::common::ObjectId {
+            hash: id.as_bytes().to_vec(),
+        };
+        Ok(Response::new(PreviewMergeResponse {
+            base: preview.bases.first().map(object_id),
+            clean: preview.clean,
+            auto_merged: preview.auto_merged,
+            conflicts,
+            driver_resolved: preview.driver_resolved,
+            bases: preview.bases.iter().map(object_id).collect(),
+        }))
+    }
+}
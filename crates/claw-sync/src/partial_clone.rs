@@ -1,5 +1,5 @@
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
+use claw_core::object::{Object, TypeTag};
 use claw_store::ClawStore;
 
 pub struct PartialCloneFilter {
@@ -12,6 +12,12 @@ pub struct PartialCloneFilter {
 
 impl PartialCloneFilter {
     pub fn matches_object(&self, store: &ClawStore, id: &ObjectId) -> bool {
+        // Only patches and revisions are filtered; skip decoding everything else.
+        match store.object_type(id) {
+            Ok(TypeTag::Patch | TypeTag::Revision) => {}
+            Ok(_) => return true,
+            Err(_) => return false,
+        }
         let obj = match store.load_object(id) {
             Ok(o) => o,
             Err(_) => return false,
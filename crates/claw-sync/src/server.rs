@@ -3,11 +3,13 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tonic::{Request, Response, Status};
 
-use claw_core::cof::cof_encode;
+use claw_core::cof::cof_peek_type_tag;
 use claw_core::id::ObjectId;
-use claw_store::ClawStore;
+use claw_store::ref_transaction::RefExpectation;
+use claw_store::{ClawStore, StoreError};
 
 use crate::ancestry::is_ancestor;
+use crate::dictionaries::{dictionary_chunk, DictionaryShipper, CAP_DICTIONARIES};
 use crate::negotiation::find_reachable_objects;
 use crate::partial_clone::PartialCloneFilter;
 use crate::proto::sync::sync_service_server::SyncService;
@@ -71,7 +73,7 @@ impl SyncService for SyncServer {
         let _req = request.into_inner();
         Ok(Response::new(HelloResponse {
             server_version: "0.1.0".to_string(),
-            capabilities: vec!["partial-clone".to_string()],
+            capabilities: vec!["partial-clone".to_string(), CAP_DICTIONARIES.to_string()],
         }))
     }
 
@@ -134,6 +136,7 @@ impl SyncService for SyncServer {
 
             let want_set = find_reachable_objects(&store, &want_ids);
             let have_set = find_reachable_objects(&store, &have_ids);
+            let mut shipper = DictionaryShipper::new(req.accept_dictionaries);
 
             // Send want_set - have_set
             for id in &want_set {
@@ -148,10 +151,16 @@ impl SyncService for SyncServer {
                     }
                 }
 
-                if let Ok(obj) = store.load_object(id) {
-                    let payload = obj.serialize_payload().unwrap_or_default();
-                    let type_tag = obj.type_tag();
-                    let cof_data = cof_encode(type_tag, &payload).unwrap_or_default();
+                // Sealed objects go out as stored; the client needs the key.
+                if let Ok((cof_data, dict)) = shipper.prepare(&store, id) {
+                    let Ok(type_tag) = cof_peek_type_tag(&cof_data) else {
+                        continue;
+                    };
+                    if let Some((dict_id, data)) = dict {
+                        if tx.send(Ok(dictionary_chunk(dict_id, data))).await.is_err() {
+                            break;
+                        }
+                    }
 
                     let chunk = ObjectChunk {
                         id: Some(crate::proto::common::ObjectId {
@@ -160,6 +169,7 @@ impl SyncService for SyncServer {
                         object_type: type_tag as i32,
                         data: cof_data,
                         is_last: false,
+                        dictionary: None,
                     };
                     if tx.send(Ok(chunk)).await.is_err() {
                         break;
@@ -173,6 +183,7 @@ impl SyncService for SyncServer {
                     object_type: 0,
                     data: vec![],
                     is_last: true,
+                    dictionary: None,
                 }))
                 .await;
         });
@@ -195,14 +206,27 @@ impl SyncService for SyncServer {
                 break;
             }
 
-            if let Some(_id_msg) = &chunk.id {
-                let (type_tag, payload) = claw_core::cof::cof_decode(&chunk.data)
-                    .map_err(|e| Status::internal(e.to_string()))?;
-                let obj = claw_core::object::Object::deserialize_payload(type_tag, &payload)
-                    .map_err(|e| Status::internal(e.to_string()))?;
+            if let Some(dict) = &chunk.dictionary {
+                store
+                    .store_received_dictionary(dict.id, &dict.data)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+                continue;
+            }
+
+            if let Some(id_msg) = &chunk.id {
+                let expected = <[u8; 32]>::try_from(id_msg.hash.as_slice())
+                    .map(ObjectId::from_bytes)
+                    .map_err(|_| Status::invalid_argument("invalid object id"))?;
                 let id = store
-                    .store_object(&obj)
+                    .store_received_cof(&expected, &chunk.data)
                     .map_err(|e| Status::internal(e.to_string()))?;
+                if id != expected {
+                    return Err(Status::invalid_argument(format!(
+                        "object id mismatch: expected={} actual={}",
+                        expected.to_hex(),
+                        id.to_hex()
+                    )));
+                }
                 accepted.push(crate::proto::common::ObjectId {
                     hash: id.as_bytes().to_vec(),
                 });
@@ -225,6 +249,7 @@ impl SyncService for SyncServer {
 
         // Two-pass: first verify all CAS conditions, then apply
         // Pass 1: verify
+        let mut tx = store.transaction();
         for update in &req.updates {
             let current = store
                 .get_ref(&update.name)
@@ -267,17 +292,26 @@ impl SyncService for SyncServer {
                         }));
                     }
                 }
+                // Re-check the value seen here when committing, so writers
+                // outside this server can't be clobbered in between.
+                let expected = match current {
+                    Some(id) => RefExpectation::Value(id),
+                    None => RefExpectation::Absent,
+                };
+                tx.stage(&update.name, expected, Some(new_id));
             }
         }
 
-        // Pass 2: apply all updates
-        for update in &req.updates {
-            if let Some(new_target) = &update.new_target {
-                let id = decode_object_id(new_target)?;
-                store
-                    .set_ref(&update.name, &id)
-                    .map_err(|e| Status::internal(e.to_string()))?;
+        // Pass 2: apply all updates atomically
+        match tx.commit("sync", "update refs") {
+            Ok(()) => {}
+            Err(e @ (StoreError::RefCasConflict { .. } | StoreError::LockContention(_))) => {
+                return Ok(Response::new(UpdateRefsResponse {
+                    success: false,
+                    message: e.to_string(),
+                }));
             }
+            Err(e) => return Err(Status::internal(e.to_string())),
         }
 
         Ok(Response::new(UpdateRefsResponse {
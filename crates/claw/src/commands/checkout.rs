@@ -1,6 +1,5 @@
 use clap::Args;
 
-use claw_core::id::ObjectId;
 use claw_core::object::Object;
 use claw_store::{ClawStore, HeadState};
 
@@ -9,7 +8,7 @@ use crate::worktree;
 
 #[derive(Args)]
 pub struct CheckoutArgs {
-    /// Branch name or ObjectId to checkout
+    /// Branch name or revision expression to checkout
     target: String,
     /// Force checkout even with uncommitted changes
     #[arg(long)]
@@ -20,7 +19,7 @@ pub fn run(args: CheckoutArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
 
-    // Resolve target: try branch ref first, then object ID
+    // Resolve target: a branch checks out symbolically, anything else detaches
     let (new_head_state, target_id) =
         if let Some(id) = store.get_ref(&format!("heads/{}", args.target))? {
             (
@@ -29,20 +28,12 @@ pub fn run(args: CheckoutArgs) -> anyhow::Result<()> {
                 },
                 id,
             )
-        } else if let Ok(id) = ObjectId::from_hex(&args.target) {
-            if store.has_object(&id) {
-                (HeadState::Detached { target: id }, id)
-            } else {
-                anyhow::bail!("object not found: {}", args.target);
-            }
-        } else if let Ok(id) = ObjectId::from_display(&args.target) {
-            if store.has_object(&id) {
-                (HeadState::Detached { target: id }, id)
-            } else {
+        } else {
+            let id = store.rev_parse(&args.target)?;
+            if !store.has_object(&id) {
                 anyhow::bail!("object not found: {}", args.target);
             }
-        } else {
-            anyhow::bail!("unknown branch or revision: {}", args.target);
+            (HeadState::Detached { target: id }, id)
         };
 
     // Load target revision
@@ -54,56 +45,22 @@ pub fn run(args: CheckoutArgs) -> anyhow::Result<()> {
         _ => anyhow::bail!("target is not a revision"),
     };
 
+    let head_tree = worktree::head_tree(&store)?;
     if !args.force {
         // Check for uncommitted changes: compare worktree to current HEAD's tree
-        if let Some(head_id) = store.resolve_head()? {
-            let head_obj = store.load_object(&head_id)?;
-            if let Object::Revision(ref rev) = head_obj {
-                if let Some(ref head_tree) = rev.tree {
-                    let ignore = crate::ignore::IgnoreRules::load(&root);
-                    let worktree_tree = worktree::scan_worktree(&store, &root, &ignore)?;
-                    if worktree_tree != *head_tree {
-                        let changes = claw_store::tree_diff::diff_trees(
-                            &store,
-                            Some(head_tree),
-                            Some(&worktree_tree),
-                            "",
-                        )?;
-                        if !changes.is_empty() {
-                            anyhow::bail!(
-                                "uncommitted changes ({} files). Use --force to override.",
-                                changes.len()
-                            );
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    // Remove files tracked in current tree but not in target tree
-    if let Some(head_id) = store.resolve_head()? {
-        let head_obj = store.load_object(&head_id)?;
-        if let Object::Revision(ref rev) = head_obj {
-            if let Some(ref old_tree_id) = rev.tree {
-                let old_paths = worktree::collect_tracked_paths(&store, old_tree_id, "")?;
-                let new_paths = worktree::collect_tracked_paths(&store, &target_tree, "")?;
-                for old_path in &old_paths {
-                    if !new_paths.contains(old_path) {
-                        let full = root.join(old_path);
-                        let _ = std::fs::remove_file(&full);
-                        // Clean empty parent dirs
-                        if let Some(parent) = full.parent() {
-                            let _ = remove_empty_dirs(parent, &root);
-                        }
-                    }
-                }
+        if let Some(ref head_tree) = head_tree {
+            let ignore = crate::ignore::IgnoreRules::load(&root);
+            let changed = worktree::count_uncommitted(&store, &root, &ignore, head_tree)?;
+            if changed > 0 {
+                anyhow::bail!(
+                    "uncommitted changes ({} files). Use --force to override.",
+                    changed
+                );
             }
         }
     }
 
-    // Materialize target tree
-    worktree::materialize_tree(&store, &target_tree, &root)?;
+    worktree::switch_tree(&store, &root, head_tree.as_ref(), &target_tree)?;
 
     // Update HEAD
     store.write_head(&new_head_state)?;
@@ -120,16 +77,3 @@ pub fn run(args: CheckoutArgs) -> anyhow::Result<()> {
 
     Ok(())
 }
-
-fn remove_empty_dirs(dir: &std::path::Path, stop_at: &std::path::Path) -> std::io::Result<()> {
-    if dir == stop_at || !dir.starts_with(stop_at) {
-        return Ok(());
-    }
-    if dir.is_dir() && std::fs::read_dir(dir)?.next().is_none() {
-        std::fs::remove_dir(dir)?;
-        if let Some(parent) = dir.parent() {
-            remove_empty_dirs(parent, stop_at)?;
-        }
-    }
-    Ok(())
-}
@@ -8,10 +8,12 @@ use claw_sync::capsule_service::CapsuleServer;
 use claw_sync::change_service::ChangeServer;
 use claw_sync::event_service::EventServer;
 use claw_sync::intent_service::IntentServer;
+use claw_sync::merge_service::MergeServer;
 use claw_sync::proto::capsule::capsule_service_server::CapsuleServiceServer;
 use claw_sync::proto::change::change_service_server::ChangeServiceServer;
 use claw_sync::proto::event::event_stream_service_server::EventStreamServiceServer;
 use claw_sync::proto::intent::intent_service_server::IntentServiceServer;
+use claw_sync::proto::merge::merge_service_server::MergeServiceServer;
 use claw_sync::proto::sync::sync_service_server::SyncServiceServer;
 use claw_sync::proto::workstream::workstream_service_server::WorkstreamServiceServer;
 use claw_sync::server::SyncServer;
@@ -87,6 +89,7 @@ pub async fn run(args: DaemonArgs) -> anyhow::Result<()> {
     let change_server = ChangeServer::new(shared_store.clone());
     let capsule_server = CapsuleServer::new(shared_store.clone());
     let workstream_server = WorkstreamServer::new(shared_store.clone());
+    let merge_server = MergeServer::new(shared_store.clone());
     let event_server = EventServer::new(shared_store);
 
     println!("Claw daemon listening on {}", addr);
@@ -96,6 +99,7 @@ pub async fn run(args: DaemonArgs) -> anyhow::Result<()> {
         .add_service(ChangeServiceServer::new(change_server))
         .add_service(CapsuleServiceServer::new(capsule_server))
         .add_service(WorkstreamServiceServer::new(workstream_server))
+        .add_service(MergeServiceServer::new(merge_server))
         .add_service(EventStreamServiceServer::new(event_server))
         .serve(addr)
         .await?;
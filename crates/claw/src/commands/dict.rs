@@ -0,0 +1,85 @@
+use clap::{Args, Subcommand};
+
+use claw_store::dictionary::{self, TrainOptions};
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+use crate::output;
+
+#[derive(Args)]
+pub struct DictArgs {
+    #[command(subcommand)]
+    command: DictCommand,
+}
+
+#[derive(Subcommand)]
+enum DictCommand {
+    /// Train a compression dictionary per object type from existing objects
+    Train {
+        /// Objects sampled per type
+        #[arg(long, default_value_t = TrainOptions::default().max_samples)]
+        max_samples: usize,
+        /// Maximum dictionary size in bytes
+        #[arg(long, default_value_t = TrainOptions::default().max_dict_size)]
+        max_size: usize,
+    },
+    /// List the active dictionary for each object type
+    List,
+}
+
+pub fn run(args: DictArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    match args.command {
+        DictCommand::Train {
+            max_samples,
+            max_size,
+        } => {
+            let opts = TrainOptions {
+                max_samples,
+                max_dict_size: max_size,
+            };
+            let trained = dictionary::train(&store, &opts)?;
+            println!("{}", output::header("dict train"));
+            if trained.is_empty() {
+                println!(
+                    "Not enough small objects to train on (need {} of a type).",
+                    dictionary::MIN_SAMPLES
+                );
+                return Ok(());
+            }
+            for dict in &trained {
+                println!(
+                    "{}",
+                    output::kv(
+                        dict.type_tag.name(),
+                        &format!(
+                            "{:08x} ({} bytes from {} samples)",
+                            dict.id, dict.size, dict.samples
+                        )
+                    )
+                );
+            }
+            println!();
+            println!("New objects use these now; `claw gc` recompresses loose ones.");
+        }
+        DictCommand::List => {
+            let dicts = store.dictionaries();
+            println!("{}", output::header("dictionaries"));
+            let active = dicts.active_entries();
+            if active.is_empty() {
+                println!("No dictionaries trained (run `claw dict train`).");
+            }
+            for (type_tag, id, size) in active {
+                println!(
+                    "{}",
+                    output::kv(type_tag.name(), &format!("{id:08x} ({size} bytes)"))
+                );
+            }
+            println!("{}", output::kv("Stored", &dicts.len().to_string()));
+        }
+    }
+
+    Ok(())
+}
@@ -2,21 +2,21 @@ use clap::Args;
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
-use claw_patch::CodecRegistry;
-use claw_store::tree_diff::{diff_trees, ChangeKind};
+use claw_merge::renames;
+use claw_store::tree_diff::{diff_trees, flatten_tree, ChangeKind};
 use claw_store::ClawStore;
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
 use crate::diff_render;
 use crate::ignore::IgnoreRules;
 use crate::worktree;
 
 #[derive(Args)]
 pub struct DiffArgs {
-    /// Source ref (default: HEAD)
+    /// Source revision expression (default: HEAD)
     #[arg(long)]
     from: Option<String>,
-    /// Target ref (default: working tree)
+    /// Target revision expression (default: working tree)
     #[arg(long)]
     to: Option<String>,
     /// Filter by path
@@ -30,7 +30,7 @@ pub struct DiffArgs {
 pub fn run(args: DiffArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
-    let registry = CodecRegistry::default();
+    let registry = codec_registry(&store)?;
 
     let from_tree = resolve_tree(&store, args.from.as_deref(), true)?;
     let to_tree = if args.to.is_some() {
@@ -42,12 +42,22 @@ pub fn run(args: DiffArgs) -> anyhow::Result<()> {
     };
 
     let changes = diff_trees(&store, from_tree.as_ref(), to_tree.as_ref(), "")?;
+    let old_files = match &from_tree {
+        Some(tree) => flatten_tree(&store, tree)?,
+        None => Default::default(),
+    };
+    let changes = renames::detect(&store, &registry, &old_files, changes, Some(&root));
 
     // Filter by path prefix if specified
     let changes: Vec<_> = if let Some(ref filter_path) = args.path {
         changes
             .into_iter()
-            .filter(|c| c.path.starts_with(filter_path.as_str()))
+            .filter(|c| {
+                c.path.starts_with(filter_path.as_str())
+                    || c.old_path
+                        .as_deref()
+                        .is_some_and(|p| p.starts_with(filter_path.as_str()))
+            })
             .collect()
     } else {
         changes
@@ -63,15 +73,40 @@ pub fn run(args: DiffArgs) -> anyhow::Result<()> {
     for change in &sorted {
         if args.name_only {
             let tag = match change.kind {
-                ChangeKind::Added => "A",
-                ChangeKind::Deleted => "D",
-                ChangeKind::Modified => "M",
-                ChangeKind::TypeChanged => "T",
+                ChangeKind::Added => "A".to_string(),
+                ChangeKind::Deleted => "D".to_string(),
+                ChangeKind::Modified => "M".to_string(),
+                ChangeKind::TypeChanged => "T".to_string(),
+                ChangeKind::Renamed => format!("R{:03}", change.similarity.unwrap_or(0)),
+                ChangeKind::Copied => format!("C{:03}", change.similarity.unwrap_or(0)),
             };
-            println!("{} {}", tag, change.path);
+            match &change.old_path {
+                Some(old_path) => println!("{} {} -> {}", tag, old_path, change.path),
+                None => println!("{} {}", tag, change.path),
+            }
             continue;
         }
 
+        let old_path = change.old_path.as_deref().unwrap_or(&change.path);
+        if let Some(kind) = match change.kind {
+            ChangeKind::Renamed => Some("rename"),
+            ChangeKind::Copied => Some("copy"),
+            _ => None,
+        } {
+            print!(
+                "{}",
+                diff_render::render_move_header(
+                    kind,
+                    old_path,
+                    &change.path,
+                    change.similarity.unwrap_or(0)
+                )
+            );
+            if change.old_id == change.new_id {
+                continue;
+            }
+        }
+
         let ext = change.path.rsplit('.').next().unwrap_or("");
         let codec = registry.get_by_extension(ext);
 
@@ -85,14 +120,24 @@ pub fn run(args: DiffArgs) -> anyhow::Result<()> {
                     Ok(ops) => print!("{}", diff_render::render_json_diff(&change.path, &ops)),
                     Err(_) => print!(
                         "{}",
-                        diff_render::render_unified_diff(&change.path, &old_bytes, &new_bytes)
+                        diff_render::render_unified_diff(
+                            old_path,
+                            &change.path,
+                            &old_bytes,
+                            &new_bytes
+                        )
                     ),
                 }
             } else {
                 // Text diff
                 print!(
                     "{}",
-                    diff_render::render_unified_diff(&change.path, &old_bytes, &new_bytes)
+                    diff_render::render_unified_diff(
+                        old_path,
+                        &change.path,
+                        &old_bytes,
+                        &new_bytes
+                    )
                 );
             }
         } else {
@@ -138,14 +183,7 @@ fn resolve_tree(
         None => return Ok(None),
     };
 
-    // Try as ref first
-    let id = if let Some(id) = store.get_ref(ref_name)? {
-        id
-    } else if let Ok(id) = ObjectId::from_hex(ref_name) {
-        id
-    } else {
-        anyhow::bail!("cannot resolve: {}", ref_name);
-    };
+    let id = store.rev_parse(ref_name)?;
 
     let obj = store.load_object(&id)?;
     match obj {
@@ -157,10 +195,7 @@ fn resolve_tree(
 
 fn load_blob_data(store: &ClawStore, id: Option<&ObjectId>) -> Vec<u8> {
     match id {
-        Some(id) => match store.load_object(id) {
-            Ok(Object::Blob(b)) => b.data,
-            _ => vec![],
-        },
+        Some(id) => store.read_blob(id).unwrap_or_default(),
         None => vec![],
     }
 }
@@ -0,0 +1,94 @@
+use clap::{Args, Subcommand};
+
+use claw_store::encryption::{self, EncryptionRules};
+use claw_store::repo;
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+use crate::output;
+
+#[derive(Args)]
+pub struct EncryptionArgs {
+    #[command(subcommand)]
+    command: EncryptionCommand,
+}
+
+#[derive(Subcommand)]
+enum EncryptionCommand {
+    /// Generate the repository data key in .claw/keys/data.key
+    Init,
+    /// Encrypt objects for paths matching a glob (`dir/` covers a directory)
+    Add {
+        /// Path pattern
+        pattern: String,
+    },
+    /// Stop encrypting new objects for a pattern
+    Remove {
+        /// Path pattern
+        pattern: String,
+    },
+    /// Show encryption rules and whether the data key is available
+    Status,
+}
+
+pub fn run(args: EncryptionArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let layout = store.layout();
+
+    match args.command {
+        EncryptionCommand::Init => {
+            encryption::generate_data_key(layout)?;
+            println!("Data key written to {}", layout.data_key_file().display());
+            println!("Back it up: encrypted objects cannot be read without it.");
+        }
+        EncryptionCommand::Add { pattern } => {
+            EncryptionRules::new(std::slice::from_ref(&pattern))?;
+            let mut config = repo::read_config(layout)?;
+            if config.encryption.paths.contains(&pattern) {
+                println!("Already encrypting {pattern}");
+                return Ok(());
+            }
+            config.encryption.paths.push(pattern.clone());
+            repo::write_config(layout, &config)?;
+            println!("Encrypting {pattern}");
+            println!(
+                "Content already stored for it stays unencrypted until it is snapshotted \
+                 again, and packed copies stay unencrypted."
+            );
+            if !store.has_data_key() {
+                println!("No data key yet; run `claw encryption init` before snapshotting.");
+            }
+        }
+        EncryptionCommand::Remove { pattern } => {
+            let mut config = repo::read_config(layout)?;
+            let before = config.encryption.paths.len();
+            config.encryption.paths.retain(|p| p != &pattern);
+            if config.encryption.paths.len() == before {
+                anyhow::bail!("no encryption rule for {pattern}");
+            }
+            repo::write_config(layout, &config)?;
+            println!("No longer encrypting {pattern} (existing objects stay encrypted)");
+        }
+        EncryptionCommand::Status => {
+            let config = repo::read_config(layout)?;
+            println!("{}", output::header("encryption"));
+            let key = if store.has_data_key() {
+                "available"
+            } else {
+                "missing"
+            };
+            println!("{}", output::kv("Data key", key));
+            if config.encryption.paths.is_empty() {
+                println!("{}", output::kv("Paths", "(none)"));
+            } else {
+                println!(
+                    "{}",
+                    output::kv("Paths", &config.encryption.paths.join(", "))
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
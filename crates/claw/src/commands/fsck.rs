@@ -0,0 +1,68 @@
+use clap::Args;
+
+use claw_store::fsck::fsck;
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+use crate::output;
+
+#[derive(Args)]
+pub struct FsckArgs {
+    /// Print the full report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn run(args: FsckArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let report = fsck(&store)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{}", output::header("fsck"));
+        println!(
+            "{}",
+            output::kv("Loose objects", &report.loose_objects.to_string())
+        );
+        println!(
+            "{}",
+            output::kv(
+                "Packed objects",
+                &format!("{} in {} pack(s)", report.packed_objects, report.packs)
+            )
+        );
+        println!("{}", output::kv("Refs", &report.refs.to_string()));
+        println!(
+            "{}",
+            output::kv("Reflog entries", &report.reflog_entries.to_string())
+        );
+        if report.encrypted_unverified > 0 {
+            println!(
+                "{}",
+                output::kv(
+                    "Unverified",
+                    &format!("{} encrypted (no data key)", report.encrypted_unverified)
+                )
+            );
+        }
+        println!(
+            "{}",
+            output::kv("Problems", &report.issues.len().to_string())
+        );
+        for issue in &report.issues {
+            println!(
+                "  {} {}: {}",
+                issue.kind.name(),
+                issue.location,
+                issue.message
+            );
+        }
+    }
+
+    if !report.is_ok() {
+        anyhow::bail!("fsck found {} problem(s)", report.issues.len());
+    }
+    Ok(())
+}
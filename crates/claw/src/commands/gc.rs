@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use clap::Args;
+
+use claw_core::id::ObjectId;
+use claw_merge::emit::expired_virtual_bases;
+use claw_merge::rerere;
+use claw_store::gc::{run_gc, GcOptions, DEFAULT_GRACE_PERIOD};
+use claw_store::oplog;
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+use crate::merge_state;
+use crate::output;
+
+#[derive(Args)]
+pub struct GcArgs {
+    /// Prune unreachable objects older than this (e.g. "now", "30m", "12h", "14d", "2w")
+    #[arg(long)]
+    prune: Option<String>,
+    /// Forget recorded conflict resolutions older than this (default "60d")
+    #[arg(long)]
+    expire_resolutions: Option<String>,
+    /// Forget undo history older than this (default "90d")
+    #[arg(long)]
+    expire_operations: Option<String>,
+    /// Show what would be packed and pruned without changing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+pub fn run(args: GcArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    let grace_period = match &args.prune {
+        Some(spec) => parse_duration(spec)?,
+        None => DEFAULT_GRACE_PERIOD,
+    };
+    let resolution_age = match &args.expire_resolutions {
+        Some(spec) => parse_duration(spec)?,
+        None => rerere::DEFAULT_EXPIRY,
+    };
+    let operation_age = match &args.expire_operations {
+        Some(spec) => parse_duration(spec)?,
+        None => oplog::DEFAULT_EXPIRY,
+    };
+
+    // Keep the revisions and conflict records of an in-progress merge alive
+    // even if no ref points at them.
+    let claw_dir = store.layout().claw_dir();
+    let mut extra_roots = Vec::new();
+    if merge_state::exists(&claw_dir) {
+        let state = merge_state::read_from(&claw_dir)?;
+        let heads = state.merge.other_heads.iter().map(|h| &h.revision);
+        let conflicts = state.conflicts.iter().filter_map(|c| c.object_id.as_ref());
+        for hex in [
+            &state.merge.left_revision,
+            &state.merge.right_revision,
+            &state.merge.base_revision,
+        ]
+        .into_iter()
+        .chain(heads)
+        .chain(conflicts)
+        .chain(&state.resolved)
+        {
+            if let Ok(id) = ObjectId::from_hex(hex) {
+                extra_roots.push(id);
+            }
+        }
+    }
+
+    // Expire old resolutions and operations first so what only they kept
+    // alive can be pruned in this run.
+    let expired = rerere::expired(&store, resolution_age)?;
+    if !args.dry_run {
+        for name in &expired {
+            store.delete_ref(name)?;
+        }
+    }
+    let expired_operations = oplog::expire(&store, operation_age, args.dry_run)?;
+    // A virtual merge base stays pinned while its merge is in progress.
+    let expired_bases = expired_virtual_bases(&store, grace_period, &extra_roots)?;
+    if !args.dry_run {
+        for name in &expired_bases {
+            store.delete_ref(name)?;
+        }
+    }
+
+    let report = run_gc(
+        &store,
+        &GcOptions {
+            grace_period,
+            dry_run: args.dry_run,
+            extra_roots,
+        },
+    )?;
+
+    if args.dry_run {
+        println!("{}", output::header("gc (dry run)"));
+    } else {
+        println!("{}", output::header("gc"));
+    }
+    println!("{}", output::kv("Reachable", &report.reachable.to_string()));
+    if report.missing > 0 {
+        println!("{}", output::kv("Missing", &report.missing.to_string()));
+    }
+    println!("{}", output::kv("Packed", &report.packed.to_string()));
+    if report.repacked > 0 {
+        println!("{}", output::kv("Repacked", &report.repacked.to_string()));
+    }
+    if report.packs_removed > 0 {
+        println!(
+            "{}",
+            output::kv("Packs replaced", &report.packs_removed.to_string())
+        );
+    }
+    println!("{}", output::kv("Pruned", &report.pruned.to_string()));
+    println!(
+        "{}",
+        output::kv("Kept (recent)", &report.kept_recent.to_string())
+    );
+    if report.kept_sealed > 0 {
+        println!(
+            "{}",
+            output::kv("Kept (sealed)", &report.kept_sealed.to_string())
+        );
+    }
+    if !expired.is_empty() {
+        println!(
+            "{}",
+            output::kv("Expired resolutions", &expired.len().to_string())
+        );
+    }
+    if !expired_bases.is_empty() {
+        println!(
+            "{}",
+            output::kv("Expired merge bases", &expired_bases.len().to_string())
+        );
+    }
+    if expired_operations > 0 {
+        println!(
+            "{}",
+            output::kv("Expired operations", &expired_operations.to_string())
+        );
+    }
+    if report.packed_refs > 0 {
+        println!(
+            "{}",
+            output::kv("Packed refs", &report.packed_refs.to_string())
+        );
+    }
+    if let Some(path) = &report.pack_path {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("{}", output::kv("Pack", &name));
+    }
+
+    Ok(())
+}
+
+fn parse_duration(spec: &str) -> anyhow::Result<Duration> {
+    let spec = spec.trim();
+    if spec == "now" {
+        return Ok(Duration::ZERO);
+    }
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (num, unit) = spec.split_at(split);
+    let value: u64 = num
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid duration: {spec}"))?;
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!("invalid duration unit in {spec} (use s, m, h, d or w)"),
+    };
+    // Ages are compared in milliseconds, so those must fit in a u64 too.
+    let secs = value
+        .checked_mul(secs)
+        .filter(|secs| secs.checked_mul(1000).is_some())
+        .ok_or_else(|| anyhow::anyhow!("invalid duration: {spec}"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units_and_overflow() {
+        assert_eq!(parse_duration("now").unwrap(), Duration::ZERO);
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(
+            parse_duration("2w").unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert!(parse_duration("3y").is_err());
+        for spec in ["18446744073709551615w", "18446744073709552s"] {
+            let err = parse_duration(spec).unwrap_err();
+            assert!(err.to_string().starts_with("invalid duration"));
+        }
+    }
+}
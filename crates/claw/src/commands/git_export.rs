@@ -10,7 +10,7 @@ use crate::config::find_repo_root;
 
 #[derive(Args)]
 pub struct GitExportArgs {
-    /// Ref to export (default: heads/main)
+    /// Ref or revision expression to export (default: heads/main)
     #[arg(long, name = "ref", default_value = "heads/main")]
     ref_name: String,
     /// Git branch name to create
@@ -49,9 +49,7 @@ pub fn run(args: GitExportArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
 
-    let rev_id = store
-        .get_ref(&args.ref_name)?
-        .ok_or_else(|| anyhow::anyhow!("ref not found: {}", args.ref_name))?;
+    let rev_id = store.rev_parse(&args.ref_name)?;
 
     let git_dir = root.join(&args.git_dir);
     let git_objects_dir = git_dir.join("objects");
@@ -0,0 +1,50 @@
+use clap::{Args, Subcommand};
+
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+use crate::output;
+
+#[derive(Args)]
+pub struct IndexArgs {
+    #[command(subcommand)]
+    command: IndexCommand,
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Rebuild the object index from loose objects and packs
+    Rebuild,
+    /// Show whether the object index is usable
+    Status,
+}
+
+pub fn run(args: IndexArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    match args.command {
+        IndexCommand::Rebuild => {
+            let count = store.rebuild_index()?;
+            println!("{}", output::header("index rebuild"));
+            println!("{}", output::kv("Indexed", &count.to_string()));
+        }
+        IndexCommand::Status => {
+            println!("{}", output::header("index"));
+            let state = if store.index_is_usable() {
+                "up to date"
+            } else {
+                "stale (run `claw index rebuild`)"
+            };
+            println!("{}", output::kv("State", state));
+            if let Some(index) = store.meta_index() {
+                println!(
+                    "{}",
+                    output::kv("Objects", &index.object_count()?.to_string())
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,14 +1,22 @@
+use std::path::Path;
+
 use clap::Args;
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
+use claw_core::types::{Conflict, ConflictKind, FileMode, Revision, TreeEntry};
 use claw_merge::emit::merge;
+use claw_merge::octopus::merge_octopus;
+use claw_merge::preview::preview_merge;
+use claw_merge::MergeError;
 use claw_patch::CodecRegistry;
 use claw_store::{ClawStore, HeadState};
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
 use crate::conflict_writer;
-use crate::merge_state::{self, ConflictEntry, MergeInfo, MergeState};
+use crate::merge_state::{
+    self, kind_name, ConflictEntry, MergeHead, MergeInfo, MergeKind, MergeState,
+};
 use crate::worktree;
 
 #[derive(Args)]
@@ -16,25 +24,33 @@ pub struct IntegrateArgs {
     /// Left ref (default: HEAD's branch)
     #[arg(long)]
     left: Option<String>,
-    /// Right ref to integrate
-    #[arg(long)]
-    right: String,
+    /// Right ref or revision expression to integrate; give several for an
+    /// octopus merge
+    #[arg(long, required = true, num_args = 1..)]
+    right: Vec<String>,
     /// Author name
     #[arg(short, long, default_value = "anonymous")]
     author: String,
     /// Merge message
     #[arg(short, long, default_value = "Integrate changes")]
     message: String,
+    /// Report what the merge would do without changing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Output the dry-run report as JSON
+    #[arg(long, requires = "dry_run")]
+    json: bool,
 }
 
 pub fn run(args: IntegrateArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
-    let registry = CodecRegistry::default();
+    let registry = codec_registry(&store)?;
 
     // Resolve left ref: default to HEAD's branch
-    let left_ref = match args.left {
-        Some(r) => r,
+    let left_ref = match &args.left {
+        Some(r) => claw_store::revparse::resolve_ref_name(&store, r)?
+            .ok_or_else(|| anyhow::anyhow!("--left must name a ref: {}", r))?,
         None => {
             let head = store.read_head()?;
             match head {
@@ -49,9 +65,23 @@ pub fn run(args: IntegrateArgs) -> anyhow::Result<()> {
     let left_id = store
         .get_ref(&left_ref)?
         .ok_or_else(|| anyhow::anyhow!("ref not found: {}", left_ref))?;
-    let right_id = store
-        .get_ref(&args.right)?
-        .ok_or_else(|| anyhow::anyhow!("ref not found: {}", args.right))?;
+    let right_ids = args
+        .right
+        .iter()
+        .map(|r| store.rev_parse(r))
+        .collect::<Result<Vec<_>, _>>()?;
+    if args.dry_run {
+        if right_ids.len() > 1 {
+            anyhow::bail!("--dry-run previews a single --right");
+        }
+        return print_preview(&store, &registry, &left_id, &right_ids[0], args.json);
+    }
+    if right_ids.len() > 1 {
+        return integrate_octopus(
+            &store, &registry, &root, &args, &left_ref, &left_id, &right_ids,
+        );
+    }
+    let right_id = right_ids[0];
 
     let result = merge(
         &store,
@@ -62,129 +92,450 @@ pub fn run(args: IntegrateArgs) -> anyhow::Result<()> {
         &args.message,
     )?;
 
+    report_base_conflicts(&result.base_conflicts);
+    report_reused(&result.reused_resolutions);
     if result.conflicts.is_empty() {
         // Clean merge: store revision, materialize tree, advance ref
-        let rev_id = store.store_object(&Object::Revision(result.revision))?;
-        store.update_ref_cas(
-            &left_ref,
-            Some(&left_id),
-            &rev_id,
-            &args.author,
-            &args.message,
-        )?;
-
-        // Materialize merged tree
-        if let Some(tree_id) = store.load_object(&rev_id)?.as_revision_tree() {
-            worktree::materialize_tree(&store, &tree_id, &root)?;
-        }
-
+        let rev_id = advance(&store, &root, &args, &left_ref, &left_id, result.revision)?;
         println!("Integrated successfully: {rev_id}");
     } else {
         // Conflicted merge: write conflict artifacts, MERGE_STATE.toml, do NOT advance ref
-        let mut conflict_entries = Vec::new();
-
-        for conflict in &result.conflicts {
-            let base_content =
-                load_file_from_revision(&store, &result.ancestor, &conflict.file_path);
-            let left_content = load_file_from_revision(&store, &left_id, &conflict.file_path);
-            let right_content = load_file_from_revision(&store, &right_id, &conflict.file_path);
-
-            let conflict_id = claw_core::id::ConflictId::new().to_string();
-
-            match conflict.codec_id.as_str() {
-                "json/tree" => {
-                    conflict_writer::write_json_conflict(
-                        &root,
-                        &conflict.file_path,
-                        &base_content,
-                        &left_content,
-                        &right_content,
-                    )?;
-                }
-                "binary" => {
-                    conflict_writer::write_binary_conflict(
-                        &root,
-                        &conflict.file_path,
-                        &base_content,
-                        &left_content,
-                        &right_content,
-                    )?;
-                }
-                _ => {
-                    conflict_writer::write_text_conflict(
-                        &root,
-                        &conflict.file_path,
-                        &base_content,
-                        &left_content,
-                        &right_content,
-                        &left_ref,
-                        &args.right,
-                    )?;
-                }
-            }
+        let info = MergeInfo {
+            kind: MergeKind::Merge,
+            left_ref: left_ref.clone(),
+            right_ref: args.right[0].clone(),
+            left_revision: left_id.to_hex(),
+            right_revision: right_id.to_hex(),
+            base_revision: result.ancestor.to_hex(),
+            other_heads: vec![],
+        };
+        let conflicts: Vec<_> = result.conflicts.into_iter().map(|c| (c, None)).collect();
+        write_conflict_state(&store, &root, &result.ancestor, &conflicts, info)?;
+    }
 
-            conflict_entries.push(ConflictEntry {
-                file_path: conflict.file_path.clone(),
-                conflict_id,
-                codec_id: conflict.codec_id.clone(),
-            });
-        }
+    Ok(())
+}
 
-        // Write MERGE_STATE.toml
-        let merge_state = MergeState {
-            merge: MergeInfo {
-                left_ref: left_ref.clone(),
-                right_ref: args.right.clone(),
-                left_revision: left_id.to_hex(),
-                right_revision: right_id.to_hex(),
-                base_revision: result.ancestor.to_hex(),
-            },
-            conflicts: conflict_entries,
-        };
-        merge_state::write_to(&store.layout().claw_dir(), &merge_state)?;
-
-        // Also materialize non-conflicting changes from the merged tree
-        // Use the left tree as the base for the working copy
-        let left_obj = store.load_object(&left_id)?;
-        if let Object::Revision(ref rev) = left_obj {
-            if let Some(ref tree_id) = rev.tree {
-                worktree::materialize_tree(&store, tree_id, &root)?;
-            }
+/// Report the predicted outcome of merging `right_id` into `left_id`.
+fn print_preview(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    left_id: &ObjectId,
+    right_id: &ObjectId,
+    json: bool,
+) -> anyhow::Result<()> {
+    let preview = preview_merge(store, registry, left_id, right_id)?;
+
+    if json {
+        let conflicts: Vec<serde_json::Value> = preview
+            .conflicts
+            .iter()
+            .map(|c| {
+                let hunks: Vec<serde_json::Value> = c
+                    .hunks
+                    .iter()
+                    .map(|h| {
+                        serde_json::json!({
+                            "base_line": h.base_line,
+                            "base": h.base,
+                            "left": h.left,
+                            "right": h.right,
+                        })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "path": c.path,
+                    "kind": c.kind,
+                    "hunks": hunks,
+                })
+            })
+            .collect();
+        let bases: Vec<String> = preview.bases.iter().map(|id| id.to_hex()).collect();
+        let output = serde_json::json!({
+            "bases": bases,
+            "clean": preview.clean,
+            "auto_merged": preview.auto_merged,
+            "driver_resolved": preview.driver_resolved,
+            "conflicts": conflicts,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    if preview.bases.len() > 1 {
+        println!(
+            "Merging against a virtual base built from {} merge bases.",
+            preview.bases.len()
+        );
+    }
+    for path in &preview.clean {
+        println!("  clean: {path}");
+    }
+    for path in &preview.auto_merged {
+        println!("  auto-merged: {path}");
+    }
+    for path in &preview.driver_resolved {
+        println!("  merge driver: {path}");
+    }
+    for conflict in &preview.conflicts {
+        match conflict.kind {
+            ConflictKind::Content => println!(
+                "  CONFLICT: {} ({} hunk(s))",
+                conflict.path,
+                conflict.hunks.len()
+            ),
+            kind => println!("  CONFLICT: {} ({})", conflict.path, kind_name(kind)),
         }
+    }
+    if preview.conflicts.is_empty() {
+        println!("Integration would succeed.");
+    } else {
+        println!(
+            "Integration would stop with {} conflict(s).",
+            preview.conflicts.len()
+        );
+    }
+    Ok(())
+}
+
+/// Merge several heads into the left ref at once. A conflict between two
+/// of the heads aborts before anything is written.
+fn integrate_octopus(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    root: &Path,
+    args: &IntegrateArgs,
+    left_ref: &str,
+    left_id: &ObjectId,
+    right_ids: &[ObjectId],
+) -> anyhow::Result<()> {
+    let ref_name = |id: &ObjectId| {
+        let index = right_ids.iter().position(|r| r == id).unwrap_or(0);
+        args.right[index].clone()
+    };
+    let result = match merge_octopus(
+        store,
+        registry,
+        left_id,
+        right_ids,
+        &args.author,
+        &args.message,
+    ) {
+        Err(MergeError::HeadsConflict {
+            path,
+            first,
+            second,
+        }) => anyhow::bail!(
+            "cannot integrate: {} and {} both change {}; integrate them separately",
+            ref_name(&first),
+            ref_name(&second),
+            path
+        ),
+        other => other?,
+    };
 
+    report_base_conflicts(&result.base_conflicts);
+    report_reused(&result.reused_resolutions);
+    if result.conflicts.is_empty() {
+        let rev_id = advance(store, root, args, left_ref, left_id, result.revision)?;
         println!(
-            "Merge has {} conflict(s). Resolve them and run `claw snapshot` to complete.",
-            result.conflicts.len()
+            "Integrated {} heads successfully: {rev_id}",
+            right_ids.len()
         );
-        for c in &result.conflicts {
-            println!("  CONFLICT: {} ({})", c.file_path, c.codec_id);
+        return Ok(());
+    }
+
+    let info = MergeInfo {
+        kind: MergeKind::Merge,
+        left_ref: left_ref.to_string(),
+        right_ref: args.right[0].clone(),
+        left_revision: left_id.to_hex(),
+        right_revision: right_ids[0].to_hex(),
+        base_revision: result.ancestor.to_hex(),
+        other_heads: args.right[1..]
+            .iter()
+            .zip(&right_ids[1..])
+            .map(|(ref_name, id)| MergeHead {
+                ref_name: ref_name.clone(),
+                revision: id.to_hex(),
+            })
+            .collect(),
+    };
+    let conflicts: Vec<_> = result
+        .conflicts
+        .into_iter()
+        .map(|(index, c)| (c, Some(args.right[index].clone())))
+        .collect();
+    write_conflict_state(store, root, &result.ancestor, &conflicts, info)
+}
+
+/// Warn about paths where the merge bases disagreed: the virtual base built
+/// from them uses the first base's content there.
+fn report_base_conflicts(paths: &[String]) {
+    for path in paths {
+        println!("Merge bases conflict on {path}; the virtual base keeps the first one's version");
+    }
+}
+
+/// Tell the user which conflicts a recorded resolution took care of.
+pub(crate) fn report_reused(paths: &[String]) {
+    for path in paths {
+        println!("Resolved {path} using a recorded resolution");
+    }
+}
+
+/// Store a clean merge revision, advance the left ref to it and check out
+/// its tree.
+fn advance(
+    store: &ClawStore,
+    root: &Path,
+    args: &IntegrateArgs,
+    left_ref: &str,
+    left_id: &ObjectId,
+    revision: Revision,
+) -> anyhow::Result<ObjectId> {
+    let rev_id = store.store_object(&Object::Revision(revision))?;
+    store.update_ref_cas(
+        left_ref,
+        Some(left_id),
+        &rev_id,
+        &args.author,
+        &args.message,
+    )?;
+
+    // Materialize merged tree
+    if let Some(tree_id) = store.load_object(&rev_id)?.as_revision_tree() {
+        worktree::materialize_tree(store, &tree_id, root)?;
+    }
+    Ok(rev_id)
+}
+
+/// Write conflict sidecars and MERGE_STATE.toml for a conflicted merge or
+/// pick, and check out the left side. The ref is left where it was.
+///
+/// Each conflict may carry the ref of the octopus head that caused it,
+/// which labels its right side.
+pub(crate) fn write_conflict_state(
+    store: &ClawStore,
+    root: &Path,
+    ancestor: &ObjectId,
+    conflicts: &[(Conflict, Option<String>)],
+    info: MergeInfo,
+) -> anyhow::Result<()> {
+    // Check out the left side first so the conflict files written below
+    // are not overwritten by it
+    let left_id = ObjectId::from_hex(&info.left_revision)?;
+    if let Object::Revision(ref rev) = store.load_object(&left_id)? {
+        if let Some(ref tree_id) = rev.tree {
+            worktree::materialize_tree(store, tree_id, root)?;
         }
     }
 
+    let conflict_entries = write_conflict_files(
+        store,
+        root,
+        ancestor,
+        conflicts,
+        &info.left_ref,
+        &info.right_ref,
+    )?;
+
+    let kind = info.kind;
+    let merge_state = MergeState {
+        merge: info,
+        conflicts: conflict_entries,
+        resolved: vec![],
+    };
+    let labels: Vec<String> = merge_state.conflicts.iter().map(|c| c.label()).collect();
+    // Write MERGE_STATE.toml
+    merge_state::write_to(&store.layout().claw_dir(), &merge_state)?;
+
+    let noun = match kind {
+        MergeKind::Merge => "Merge",
+        MergeKind::CherryPick => "Cherry-pick",
+        MergeKind::Revert => "Revert",
+    };
+    println!(
+        "{} has {} conflict(s). Resolve them and run `claw snapshot` to complete.",
+        noun,
+        labels.len()
+    );
+    for label in &labels {
+        println!("  CONFLICT: {label}");
+    }
+
     Ok(())
 }
 
-fn load_file_from_revision(store: &ClawStore, rev_id: &ObjectId, path: &str) -> Vec<u8> {
-    let obj = match store.load_object(rev_id) {
-        Ok(o) => o,
-        Err(_) => return vec![],
+/// Write each conflicted file into the worktree, with markers or sidecars
+/// depending on its codec, and return the entries for the state file. A
+/// conflict's head, when set, labels its right side instead of
+/// `right_label`.
+pub(crate) fn write_conflict_files(
+    store: &ClawStore,
+    root: &Path,
+    ancestor: &ObjectId,
+    conflicts: &[(Conflict, Option<String>)],
+    left_label: &str,
+    right_label: &str,
+) -> anyhow::Result<Vec<ConflictEntry>> {
+    let mut conflict_entries = Vec::new();
+
+    for (conflict, head) in conflicts {
+        let base_content = load_file_from_revision(store, ancestor, &conflict.file_path);
+        let left_content =
+            load_file_from_revision(store, &conflict.left_revision, &conflict.file_path);
+        let right_content =
+            load_file_from_revision(store, &conflict.right_revision, &conflict.file_path);
+
+        let conflict_id = claw_core::id::ConflictId::new().to_string();
+        let object_id = store.store_object(&Object::Conflict(conflict.clone()))?;
+
+        match conflict.codec_id.as_str() {
+            _ if conflict.kind != ConflictKind::Content => {
+                write_tree_conflict(
+                    store,
+                    root,
+                    conflict,
+                    left_label,
+                    head.as_deref().unwrap_or(right_label),
+                )?;
+            }
+            "json/tree" => {
+                conflict_writer::write_json_conflict(
+                    root,
+                    &conflict.file_path,
+                    &base_content,
+                    &left_content,
+                    &right_content,
+                )?;
+            }
+            "binary" => {
+                conflict_writer::write_binary_conflict(
+                    root,
+                    &conflict.file_path,
+                    &base_content,
+                    &left_content,
+                    &right_content,
+                )?;
+            }
+            _ => {
+                conflict_writer::write_text_conflict(
+                    root,
+                    &conflict.file_path,
+                    &base_content,
+                    &left_content,
+                    &right_content,
+                    left_label,
+                    head.as_deref().unwrap_or(right_label),
+                )?;
+            }
+        }
+
+        conflict_entries.push(ConflictEntry {
+            file_path: conflict.file_path.clone(),
+            conflict_id,
+            codec_id: conflict.codec_id.clone(),
+            head: head.clone(),
+            object_id: Some(object_id.to_hex()),
+            kind: conflict.kind,
+        });
+    }
+    Ok(conflict_entries)
+}
+
+/// Put both sides of a tree conflict in the worktree, which holds the left
+/// side. Whatever of the right side cannot live at the path goes next to it
+/// as `<path>.RIGHT`, a left file displaced by a right directory goes to
+/// `<path>.LEFT`, and `<path>.CONFLICT` describes the conflict.
+fn write_tree_conflict(
+    store: &ClawStore,
+    root: &Path,
+    conflict: &Conflict,
+    left_label: &str,
+    right_label: &str,
+) -> anyhow::Result<()> {
+    let path = &conflict.file_path;
+    let full = root.join(path);
+    let sidecar = |suffix: &str| format!("{path}.{suffix}");
+    let deleted = conflict
+        .base_revision
+        .as_ref()
+        .and_then(|base| entry_at(store, base, path))
+        .is_some();
+    let left = entry_at(store, &conflict.left_revision, path);
+    let right = entry_at(store, &conflict.right_revision, path);
+
+    if let Some(parent) = full.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let (mut left_at, mut right_at) = (path.clone(), path.clone());
+    match (&left, &right) {
+        // Only the right side still has it
+        (None, Some(right)) => worktree::materialize_entry(store, right, &full)?,
+        (Some(left), Some(right))
+            if left.mode != FileMode::Directory && right.mode == FileMode::Directory =>
+        {
+            left_at = sidecar("LEFT");
+            std::fs::rename(&full, root.join(&left_at))?;
+            worktree::materialize_entry(store, right, &full)?;
+        }
+        (_, Some(right)) => {
+            right_at = sidecar("RIGHT");
+            worktree::materialize_entry(store, right, &root.join(&right_at))?;
+        }
+        (_, None) => {}
+    }
+
+    let describe = |entry: &Option<TreeEntry>, at: &str| match entry {
+        None if deleted => "deleted".to_string(),
+        None => "absent".to_string(),
+        Some(entry) => format!("{} at {at}", describe_entry(store, entry)),
     };
-    let tree_id = match obj {
-        Object::Revision(ref rev) => match rev.tree {
-            Some(t) => t,
-            None => return vec![],
+    conflict_writer::write_tree_conflict_note(
+        root,
+        path,
+        kind_name(conflict.kind),
+        &[
+            (left_label, describe(&left, &left_at)),
+            (right_label, describe(&right, &right_at)),
+        ],
+    )
+}
+
+fn describe_entry(store: &ClawStore, entry: &TreeEntry) -> String {
+    match entry.mode {
+        FileMode::Regular => "file".to_string(),
+        FileMode::Executable => "executable file".to_string(),
+        FileMode::Directory => "directory".to_string(),
+        FileMode::Symlink => match store.read_blob(&entry.object_id) {
+            Ok(target) => format!("symlink to {}", String::from_utf8_lossy(&target)),
+            Err(_) => "symlink".to_string(),
         },
-        _ => return vec![],
-    };
-    find_blob_in_tree(store, &tree_id, path).unwrap_or_default()
+    }
+}
+
+pub(crate) fn load_file_from_revision(store: &ClawStore, rev_id: &ObjectId, path: &str) -> Vec<u8> {
+    entry_at(store, rev_id, path)
+        .and_then(|entry| store.read_blob(&entry.object_id).ok())
+        .unwrap_or_default()
 }
 
-fn find_blob_in_tree(store: &ClawStore, tree_id: &ObjectId, path: &str) -> Option<Vec<u8>> {
+/// The tree entry at `path` in a revision, if it has one.
+pub(crate) fn entry_at(store: &ClawStore, rev_id: &ObjectId, path: &str) -> Option<TreeEntry> {
+    let tree_id = match store.load_object(rev_id).ok()? {
+        Object::Revision(rev) => rev.tree?,
+        _ => return None,
+    };
     let parts: Vec<&str> = path.split('/').collect();
-    find_blob_recursive(store, tree_id, &parts)
+    find_entry_recursive(store, &tree_id, &parts)
 }
 
-fn find_blob_recursive(store: &ClawStore, tree_id: &ObjectId, parts: &[&str]) -> Option<Vec<u8>> {
+fn find_entry_recursive(
+    store: &ClawStore,
+    tree_id: &ObjectId,
+    parts: &[&str],
+) -> Option<TreeEntry> {
     if parts.is_empty() {
         return None;
     }
@@ -193,20 +544,12 @@ fn find_blob_recursive(store: &ClawStore, tree_id: &ObjectId, parts: &[&str]) ->
         Object::Tree(t) => t,
         _ => return None,
     };
-    for entry in &tree.entries {
-        if entry.name == parts[0] {
-            if parts.len() == 1 {
-                let blob_obj = store.load_object(&entry.object_id).ok()?;
-                if let Object::Blob(b) = blob_obj {
-                    return Some(b.data);
-                }
-                return None;
-            } else {
-                return find_blob_recursive(store, &entry.object_id, &parts[1..]);
-            }
-        }
+    let entry = tree.entries.into_iter().find(|e| e.name == parts[0])?;
+    if parts.len() == 1 {
+        Some(entry)
+    } else {
+        find_entry_recursive(store, &entry.object_id, &parts[1..])
     }
-    None
 }
 
 // Helper trait for Object
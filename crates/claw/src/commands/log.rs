@@ -1,14 +1,18 @@
 use clap::Args;
 
 use claw_core::id::ObjectId;
-use claw_core::object::Object;
+use claw_core::object::{Object, TypeTag};
+use claw_merge::renames;
+use claw_patch::CodecRegistry;
+use claw_store::tree_diff::{diff_trees, flatten_tree, ChangeKind, TreeChange};
 use claw_store::{ClawStore, HeadState};
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
+use crate::diff_render;
 
 #[derive(Args)]
 pub struct LogArgs {
-    /// Ref to start from (default: HEAD)
+    /// Ref or revision expression to start from (default: HEAD)
     #[arg(long, name = "ref")]
     ref_name: Option<String>,
     /// Maximum number of entries
@@ -20,6 +24,9 @@ pub struct LogArgs {
     /// Show all branches
     #[arg(long)]
     all: bool,
+    /// List the files each revision changed against its first parent
+    #[arg(long)]
+    stat: bool,
 }
 
 pub fn run(args: LogArgs) -> anyhow::Result<()> {
@@ -34,9 +41,7 @@ pub fn run(args: LogArgs) -> anyhow::Result<()> {
             tips.push((id, Some(name)));
         }
     } else if let Some(ref ref_name) = args.ref_name {
-        let id = store
-            .get_ref(ref_name)?
-            .ok_or_else(|| anyhow::anyhow!("ref not found: {}", ref_name))?;
+        let id = store.rev_parse(ref_name)?;
         tips.push((id, Some(ref_name.clone())));
     } else {
         // Default: HEAD
@@ -62,21 +67,50 @@ pub fn run(args: LogArgs) -> anyhow::Result<()> {
     }
 
     // Collect revisions from all tips, walking first-parent
-    let mut entries: Vec<LogEntry> = Vec::new();
+    // Walk the revision graph, then load only the revisions that are shown
+    let mut candidates: Vec<(ObjectId, u64)> = Vec::new();
     let mut visited = std::collections::HashSet::new();
 
     for (tip_id, _branch) in &tips {
-        walk_log(&store, tip_id, &mut entries, &mut visited, args.limit * 2)?;
+        walk_log(
+            &store,
+            tip_id,
+            &mut candidates,
+            &mut visited,
+            args.limit * 2,
+        )?;
     }
 
     // Sort by timestamp descending
-    entries.sort_by(|a, b| b.created_at_ms.cmp(&a.created_at_ms));
-    entries.truncate(args.limit);
+    candidates.sort_by(|a, b| b.1.cmp(&a.1));
+    candidates.truncate(args.limit);
+    let ids: Vec<ObjectId> = candidates.iter().map(|(id, _)| *id).collect();
+    // One index lookup for every shown revision's capsules
+    let capsules = store.referrers_of_type_many(&ids, TypeTag::Capsule)?;
+    let entries = ids
+        .iter()
+        .enumerate()
+        .filter_map(|(i, id)| {
+            let indexed_capsule = capsules.as_ref().and_then(|caps| caps[i].first().copied());
+            load_entry(&store, id, indexed_capsule).transpose()
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let registry = codec_registry(&store)?;
+    let stats = if args.stat {
+        entries
+            .iter()
+            .map(|e| revision_stat(&store, &registry, e))
+            .collect::<anyhow::Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
 
     if args.json {
         let json_entries: Vec<serde_json::Value> = entries
             .iter()
-            .map(|e| {
+            .enumerate()
+            .map(|(i, e)| {
                 let mut obj = serde_json::json!({
                     "revision_id": e.revision_id.to_hex(),
                     "author": e.author,
@@ -93,12 +127,29 @@ pub fn run(args: LogArgs) -> anyhow::Result<()> {
                 if let Some(ref cap) = e.capsule_id {
                     obj["capsule_id"] = serde_json::Value::String(cap.clone());
                 }
+                if let Some(stat) = stats.get(i) {
+                    obj["stat"] = stat
+                        .iter()
+                        .map(|s| {
+                            let mut file = serde_json::json!({
+                                "path": s.change.path,
+                                "status": status_name(&s.change.kind),
+                                "insertions": s.insertions,
+                                "deletions": s.deletions,
+                            });
+                            if let Some(ref old_path) = s.change.old_path {
+                                file["from"] = serde_json::json!(old_path);
+                            }
+                            file
+                        })
+                        .collect();
+                }
                 obj
             })
             .collect();
         println!("{}", serde_json::to_string_pretty(&json_entries)?);
     } else {
-        for entry in &entries {
+        for (i, entry) in entries.iter().enumerate() {
             println!("revision {}", entry.revision_id);
             if entry.parents.len() > 1 {
                 let parent_strs: Vec<String> = entry
@@ -124,6 +175,9 @@ pub fn run(args: LogArgs) -> anyhow::Result<()> {
             println!();
             println!("    {}", entry.summary);
             println!();
+            if let Some(stat) = stats.get(i) {
+                print_stat(stat);
+            }
         }
     }
 
@@ -132,6 +186,7 @@ pub fn run(args: LogArgs) -> anyhow::Result<()> {
 
 struct LogEntry {
     revision_id: ObjectId,
+    tree: Option<ObjectId>,
     author: String,
     created_at_ms: u64,
     summary: String,
@@ -144,57 +199,157 @@ struct LogEntry {
 fn walk_log(
     store: &ClawStore,
     start: &ObjectId,
-    entries: &mut Vec<LogEntry>,
+    candidates: &mut Vec<(ObjectId, u64)>,
     visited: &mut std::collections::HashSet<ObjectId>,
     limit: usize,
 ) -> anyhow::Result<()> {
     let mut current = Some(*start);
 
     while let Some(id) = current {
-        if entries.len() >= limit || !visited.insert(id) {
+        if candidates.len() >= limit || !visited.insert(id) {
             break;
         }
-
-        let obj = store.load_object(&id)?;
-        let rev = match obj {
-            Object::Revision(r) => r,
-            _ => break,
+        let Some(entry) = store.revision_entry(&id)? else {
+            break;
         };
+        candidates.push((id, entry.created_at_ms));
+        current = entry.parents.first().copied();
+    }
 
-        let change_id = rev.change_id.as_ref().map(|c| c.to_string());
+    Ok(())
+}
 
-        // Try to find intent title
-        let intent_title = if let Some(ref cid) = rev.change_id {
-            find_intent_title(store, cid)
-        } else {
-            None
-        };
+fn load_entry(
+    store: &ClawStore,
+    id: &ObjectId,
+    indexed_capsule: Option<ObjectId>,
+) -> anyhow::Result<Option<LogEntry>> {
+    let rev = match store.load_object(id)? {
+        Object::Revision(r) => r,
+        _ => return Ok(None),
+    };
+
+    let change_id = rev.change_id.as_ref().map(|c| c.to_string());
 
-        // Check for capsule reverse-mapping
-        let capsule_id = {
+    // Try to find intent title
+    let intent_title = if let Some(ref cid) = rev.change_id {
+        find_intent_title(store, cid)
+    } else {
+        None
+    };
+
+    // Check for capsule reverse-mapping: the object index first, then refs
+    let capsule_id = match indexed_capsule {
+        Some(cap_id) => Some(cap_id.to_string()),
+        None => {
             let prefix = &id.to_hex()[..16];
             store
                 .get_ref(&format!("capsules/by-revision/{}", prefix))?
                 .map(|cap_id| cap_id.to_string())
-        };
+        }
+    };
 
-        let first_parent = rev.parents.first().copied();
+    Ok(Some(LogEntry {
+        revision_id: *id,
+        tree: rev.tree,
+        author: rev.author,
+        created_at_ms: rev.created_at_ms,
+        summary: rev.summary,
+        parents: rev.parents,
+        change_id,
+        intent_title,
+        capsule_id,
+    }))
+}
 
-        entries.push(LogEntry {
-            revision_id: id,
-            author: rev.author,
-            created_at_ms: rev.created_at_ms,
-            summary: rev.summary,
-            parents: rev.parents,
-            change_id,
-            intent_title,
-            capsule_id,
-        });
+struct FileStat {
+    change: TreeChange,
+    /// Line counts; `None` for binary or chunked content.
+    insertions: Option<usize>,
+    deletions: Option<usize>,
+}
 
-        current = first_parent;
+fn revision_stat(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    entry: &LogEntry,
+) -> anyhow::Result<Vec<FileStat>> {
+    let parent_tree = match entry.parents.first() {
+        Some(parent) => match store.load_object(parent)? {
+            Object::Revision(rev) => rev.tree,
+            _ => None,
+        },
+        None => None,
+    };
+    let changes = diff_trees(store, parent_tree.as_ref(), entry.tree.as_ref(), "")?;
+    let old_files = match &parent_tree {
+        Some(tree) => flatten_tree(store, tree)?,
+        None => Default::default(),
+    };
+    renames::detect(store, registry, &old_files, changes, None)
+        .into_iter()
+        .map(|change| {
+            let old = text_content(store, change.old_id.as_ref())?;
+            let new = text_content(store, change.new_id.as_ref())?;
+            let (insertions, deletions) = match (old, new) {
+                (Some(old), Some(new)) => {
+                    let (ins, del) = diff_render::line_counts(&old, &new);
+                    (Some(ins), Some(del))
+                }
+                _ => (None, None),
+            };
+            Ok(FileStat {
+                change,
+                insertions,
+                deletions,
+            })
+        })
+        .collect()
+}
+
+/// Blob content as text; empty for a missing side, `None` if not countable.
+fn text_content(store: &ClawStore, id: Option<&ObjectId>) -> anyhow::Result<Option<String>> {
+    let Some(id) = id else {
+        return Ok(Some(String::new()));
+    };
+    if store.is_chunked_blob(id)? {
+        return Ok(None);
     }
+    Ok(String::from_utf8(store.read_blob(id)?).ok())
+}
 
-    Ok(())
+fn print_stat(stat: &[FileStat]) {
+    if stat.is_empty() {
+        return;
+    }
+    for s in stat {
+        let name = match &s.change.old_path {
+            Some(old_path) => format!("{} -> {}", old_path, s.change.path),
+            None => s.change.path.clone(),
+        };
+        let counts = match (s.insertions, s.deletions) {
+            (Some(ins), Some(del)) => format!("+{} -{}", ins, del),
+            _ => "bin".to_string(),
+        };
+        let tag = match s.change.kind {
+            ChangeKind::Renamed => format!("R{:03}", s.change.similarity.unwrap_or(0)),
+            ChangeKind::Copied => format!("C{:03}", s.change.similarity.unwrap_or(0)),
+            ref kind => status_name(kind)[..1].to_uppercase(),
+        };
+        println!("  {:<4} {} | {}", tag, name, counts);
+    }
+    println!();
+}
+
+fn status_name(kind: &ChangeKind) -> &'static str {
+    match kind {
+        ChangeKind::Added => "added",
+        ChangeKind::Deleted => "deleted",
+        ChangeKind::Modified => "modified",
+        ChangeKind::TypeChanged => "type_changed",
+        ChangeKind::Renamed => "renamed",
+        ChangeKind::Copied => "copied",
+    }
 }
 
 fn find_intent_title(store: &ClawStore, change_id: &claw_core::id::ChangeId) -> Option<String> {
@@ -4,18 +4,27 @@ pub mod branch;
 pub mod change;
 pub mod checkout;
 pub mod daemon;
+pub mod dict;
 pub mod diff;
+pub mod encryption;
+pub mod fsck;
+pub mod gc;
 pub mod git_export;
+pub mod index;
 pub mod init;
 pub mod integrate;
 pub mod intent;
 pub mod log;
+pub mod op;
 pub mod patch;
+pub mod pick;
 pub mod remote;
 pub mod resolve;
+pub mod rev_parse;
 pub mod ship;
 pub mod show;
 pub mod snapshot;
+pub mod stack;
 pub mod status;
 pub mod sync;
 
@@ -35,6 +44,12 @@ pub enum Commands {
     Sync(sync::SyncArgs),
     /// Integrate changes (merge)
     Integrate(integrate::IntegrateArgs),
+    /// Apply the changes of one revision onto HEAD
+    CherryPick(pick::CherryPickArgs),
+    /// Undo the changes of one revision on HEAD
+    Revert(pick::RevertArgs),
+    /// Manage stacks of changes in a workstream
+    Stack(stack::StackArgs),
     /// Ship an intent (finalize, produce capsule)
     Ship(ship::ShipArgs),
     /// Manage agent registrations
@@ -59,6 +74,22 @@ pub enum Commands {
     Status(status::StatusArgs),
     /// Show details of an object
     Show(show::ShowArgs),
+    /// Resolve revision expressions to object ids
+    RevParse(rev_parse::RevParseArgs),
+    /// Pack reachable objects and prune unreachable ones
+    Gc(gc::GcArgs),
+    /// Verify the integrity of objects, packs, refs and reflogs
+    Fsck(fsck::FsckArgs),
+    /// Inspect or rebuild the object index
+    Index(index::IndexArgs),
+    /// Train and list zstd dictionaries for small objects
+    Dict(dict::DictArgs),
+    /// Manage at-rest encryption of sensitive paths
+    Encryption(encryption::EncryptionArgs),
+    /// Inspect or restore the operation log
+    Op(op::OpArgs),
+    /// Undo the latest operation
+    Undo(op::UndoArgs),
     /// Manage merge conflicts
     Resolve(resolve::ResolveArgs),
     /// Manage remote repositories
@@ -68,7 +99,42 @@ pub enum Commands {
 }
 
 impl Commands {
+    /// Whether the command can move refs or HEAD and so gets an operation
+    /// log entry. `op` and `undo` record their own.
+    pub fn is_mutating(&self) -> bool {
+        matches!(
+            self,
+            Commands::Intent(_)
+                | Commands::Change(_)
+                | Commands::Patch(_)
+                | Commands::Sync(_)
+                | Commands::Integrate(_)
+                | Commands::CherryPick(_)
+                | Commands::Revert(_)
+                | Commands::Stack(_)
+                | Commands::Ship(_)
+                | Commands::Agent(_)
+                | Commands::Snapshot(_)
+                | Commands::Checkout(_)
+                | Commands::Branch(_)
+                | Commands::Resolve(_)
+        )
+    }
+
     pub async fn run(self) -> anyhow::Result<()> {
+        let pending = if self.is_mutating() {
+            op::begin()
+        } else {
+            None
+        };
+        let result = self.dispatch().await;
+        if let Some(pending) = pending {
+            pending.finish();
+        }
+        result
+    }
+
+    async fn dispatch(self) -> anyhow::Result<()> {
         match self {
             Commands::Init(args) => init::run(args),
             Commands::Intent(args) => intent::run(args),
@@ -76,6 +142,9 @@ impl Commands {
             Commands::Patch(args) => patch::run(args),
             Commands::Sync(args) => sync::run(args).await,
             Commands::Integrate(args) => integrate::run(args),
+            Commands::CherryPick(args) => pick::run_cherry_pick(args),
+            Commands::Revert(args) => pick::run_revert(args),
+            Commands::Stack(args) => stack::run(args),
             Commands::Ship(args) => ship::run(args),
             Commands::Agent(args) => agent::run(args),
             Commands::Daemon(args) => daemon::run(args).await,
@@ -88,6 +157,14 @@ impl Commands {
             Commands::GitExport(args) => git_export::run(args),
             Commands::Status(args) => status::run(args),
             Commands::Show(args) => show::run(args),
+            Commands::RevParse(args) => rev_parse::run(args),
+            Commands::Gc(args) => gc::run(args),
+            Commands::Fsck(args) => fsck::run(args),
+            Commands::Index(args) => index::run(args),
+            Commands::Dict(args) => dict::run(args),
+            Commands::Encryption(args) => encryption::run(args),
+            Commands::Op(args) => op::run(args),
+            Commands::Undo(args) => op::run_undo(args),
             Commands::Resolve(args) => resolve::run(args),
             Commands::Remote(args) => remote::run(args),
             Commands::Auth(args) => auth::run(args).await,
@@ -0,0 +1,249 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Subcommand};
+
+use claw_core::id::ObjectId;
+use claw_store::oplog::{self, RepoState};
+use claw_store::{ClawStore, HeadState};
+
+use crate::config::find_repo_root;
+use crate::ignore::IgnoreRules;
+use crate::merge_state;
+use crate::output;
+use crate::worktree;
+
+#[derive(Args)]
+pub struct OpArgs {
+    #[command(subcommand)]
+    command: OpCommand,
+}
+
+#[derive(Subcommand)]
+enum OpCommand {
+    /// List recorded operations, newest first
+    Log {
+        /// Maximum number of operations to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Put every ref and HEAD back to the state an operation recorded
+    Restore {
+        /// Operation id (hex prefix accepted)
+        op: String,
+        /// Restore even with uncommitted changes in the worktree
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct UndoArgs {
+    /// Undo even with uncommitted changes in the worktree
+    #[arg(long)]
+    force: bool,
+}
+
+pub fn run(args: OpArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    match args.command {
+        OpCommand::Log { limit, json } => {
+            let ops = oplog::list_operations(&store, limit)?;
+            if json {
+                let entries: Vec<serde_json::Value> = ops
+                    .iter()
+                    .map(|(id, op)| {
+                        serde_json::json!({
+                            "id": id.to_hex(),
+                            "parent": op.parent.map(|p| p.to_hex()),
+                            "command": op.command,
+                            "author": op.author,
+                            "timestamp": op.timestamp,
+                            "refs": op.refs.len(),
+                            "head": head_label(&RepoState::from_operation(op).head),
+                        })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+                return Ok(());
+            }
+            if ops.is_empty() {
+                println!("No operations recorded.");
+                return Ok(());
+            }
+            for (id, op) in &ops {
+                println!(
+                    "{}  {}  [{}]  {}",
+                    short(id),
+                    op.timestamp,
+                    op.author,
+                    op.command
+                );
+            }
+        }
+        OpCommand::Restore { op, force } => {
+            let id = store.rev_parse(&op)?;
+            let target = oplog::load_operation(&store, &id)?;
+            let command = format!("op restore {}", short(&id));
+            restore_state(
+                &store,
+                &root,
+                &RepoState::from_operation(&target),
+                &command,
+                force,
+            )?;
+            println!("Restored operation {}", short(&id));
+        }
+    }
+
+    Ok(())
+}
+
+/// Restore the state from before the latest operation. Undoing twice redoes.
+pub fn run_undo(args: UndoArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    sync_log(&store, &RepoState::capture(&store)?)?;
+    let Some(latest) = oplog::current_operation(&store)? else {
+        anyhow::bail!("nothing to undo");
+    };
+    let op = oplog::load_operation(&store, &latest)?;
+    let Some(parent) = op.parent else {
+        anyhow::bail!("nothing to undo: {} is the first operation", short(&latest));
+    };
+    let previous = oplog::load_operation(&store, &parent)?;
+    let command = format!("undo {}", short(&latest));
+    restore_state(
+        &store,
+        &root,
+        &RepoState::from_operation(&previous),
+        &command,
+        args.force,
+    )?;
+    println!("Undid operation {}: {}", short(&latest), op.command);
+    Ok(())
+}
+
+fn restore_state(
+    store: &ClawStore,
+    root: &Path,
+    target: &RepoState,
+    command: &str,
+    force: bool,
+) -> anyhow::Result<()> {
+    let current = RepoState::capture(store)?;
+    sync_log(store, &current)?;
+
+    let old_tree = worktree::head_tree(store)?;
+    if !force {
+        if let Some(ref tree) = old_tree {
+            let ignore = IgnoreRules::load(root);
+            let changed = worktree::count_uncommitted(store, root, &ignore, tree)?;
+            if changed > 0 {
+                anyhow::bail!(
+                    "uncommitted changes ({} files). Use --force to override.",
+                    changed
+                );
+            }
+        }
+    }
+
+    oplog::restore(store, target, command, &op_author())?;
+
+    // The merge state is not part of the log; whatever merge was in
+    // progress no longer matches the restored refs.
+    let claw_dir = store.layout().claw_dir();
+    if merge_state::exists(&claw_dir) {
+        merge_state::remove(&claw_dir)?;
+        println!("Aborted the merge in progress.");
+    }
+
+    let new_tree = worktree::head_tree(store)?;
+    if new_tree != old_tree {
+        if let Some(ref tree) = new_tree {
+            worktree::switch_tree(store, root, old_tree.as_ref(), tree)?;
+        }
+    }
+    println!("{}", output::kv("HEAD", &head_label(&target.head)));
+    Ok(())
+}
+
+/// Record an operation for the current state if the log does not end with
+/// it, e.g. on first use or after refs were moved by the daemon.
+fn sync_log(store: &ClawStore, state: &RepoState) -> anyhow::Result<()> {
+    let command = match oplog::current_operation(store)? {
+        None => "initial state",
+        Some(id) => {
+            let op = oplog::load_operation(store, &id)?;
+            if RepoState::from_operation(&op) == *state {
+                return Ok(());
+            }
+            "external changes"
+        }
+    };
+    oplog::record_operation(store, state, command, &op_author())?;
+    Ok(())
+}
+
+/// Ref state captured before a mutating command runs.
+pub struct PendingOperation {
+    root: PathBuf,
+    before: RepoState,
+}
+
+/// Snapshot the ref state ahead of a mutating command. Returns `None`
+/// outside a repository; the operation log never blocks a command.
+pub fn begin() -> Option<PendingOperation> {
+    let root = find_repo_root().ok()?;
+    let store = ClawStore::open(&root).ok()?;
+    let before = RepoState::capture(&store).ok()?;
+    if let Err(e) = sync_log(&store, &before) {
+        tracing::warn!("operation log: {e}");
+    }
+    Some(PendingOperation { root, before })
+}
+
+impl PendingOperation {
+    /// Record an operation if the command changed any ref or HEAD.
+    pub fn finish(self) {
+        let result = ClawStore::open(&self.root)
+            .map_err(anyhow::Error::from)
+            .and_then(|store| {
+                let after = RepoState::capture(&store)?;
+                if after != self.before {
+                    oplog::record_operation(&store, &after, &command_line(), &op_author())?;
+                }
+                Ok(())
+            });
+        if let Err(e) = result {
+            tracing::warn!("operation log: {e}");
+        }
+    }
+}
+
+fn command_line() -> String {
+    std::env::args().skip(1).collect::<Vec<_>>().join(" ")
+}
+
+fn op_author() -> String {
+    ["CLAW_AUTHOR", "USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_else(|| "claw".to_string())
+}
+
+fn head_label(head: &HeadState) -> String {
+    match head {
+        HeadState::Symbolic { ref_name } => ref_name.clone(),
+        HeadState::Detached { target } => format!("detached at {}", short(target)),
+    }
+}
+
+fn short(id: &ObjectId) -> String {
+    id.to_hex()[..12].to_string()
+}
@@ -2,11 +2,10 @@ use clap::{Args, Subcommand};
 use std::path::PathBuf;
 
 use claw_core::object::Object;
-use claw_core::types::{Blob, Patch};
-use claw_patch::CodecRegistry;
+use claw_core::types::Patch;
 use claw_store::ClawStore;
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
 
 #[derive(Args)]
 pub struct PatchArgs {
@@ -49,22 +48,14 @@ pub fn run(args: PatchArgs) -> anyhow::Result<()> {
         PatchCommand::Create { old, new, path } => {
             let root = find_repo_root()?;
             let store = ClawStore::open(&root)?;
-            let registry = CodecRegistry::default();
+            let registry = codec_registry(&store)?;
 
             let old_data = std::fs::read(&old)?;
             let new_data = std::fs::read(&new)?;
 
             // Store blobs
-            let old_blob = Object::Blob(Blob {
-                data: old_data.clone(),
-                media_type: None,
-            });
-            let new_blob = Object::Blob(Blob {
-                data: new_data.clone(),
-                media_type: None,
-            });
-            let old_id = store.store_object(&old_blob)?;
-            let new_id = store.store_object(&new_blob)?;
+            let old_id = store.store_blob_for_path(&path, old_data.as_slice())?;
+            let new_id = store.store_blob_for_path(&path, new_data.as_slice())?;
 
             // Determine codec from extension
             let ext = std::path::Path::new(&path)
@@ -87,7 +78,7 @@ pub fn run(args: PatchArgs) -> anyhow::Result<()> {
                 codec_payload: None,
             };
 
-            let patch_id = store.store_object(&Object::Patch(patch))?;
+            let patch_id = store.store_object_for_path(&path, &Object::Patch(patch))?;
             println!("Created patch: {patch_id}");
             println!("  Path: {path}");
             println!("  Codec: {}", codec.id());
@@ -95,7 +86,7 @@ pub fn run(args: PatchArgs) -> anyhow::Result<()> {
         PatchCommand::Apply { patch, file } => {
             let root = find_repo_root()?;
             let store = ClawStore::open(&root)?;
-            let registry = CodecRegistry::default();
+            let registry = codec_registry(&store)?;
 
             let patch_id = claw_core::id::ObjectId::from_display(&patch)?;
             let obj = store.load_object(&patch_id)?;
@@ -0,0 +1,156 @@
+use clap::Args;
+
+use claw_core::id::ObjectId;
+use claw_core::object::Object;
+use claw_merge::emit::MergeResult;
+use claw_merge::pick::{cherry_pick, revert};
+use claw_store::{ClawStore, HeadState};
+
+use crate::commands::integrate::{report_reused, write_conflict_state};
+use crate::config::{codec_registry, find_repo_root};
+use crate::ignore::IgnoreRules;
+use crate::merge_state::{self, MergeInfo, MergeKind};
+use crate::worktree;
+
+#[derive(Args)]
+pub struct CherryPickArgs {
+    /// Revision whose changes to apply onto HEAD
+    revision: String,
+    /// Author name
+    #[arg(short, long, default_value = "anonymous")]
+    author: String,
+    /// Message (default: derived from the picked revision)
+    #[arg(short, long)]
+    message: Option<String>,
+}
+
+#[derive(Args)]
+pub struct RevertArgs {
+    /// Revision whose changes to undo on HEAD
+    revision: String,
+    /// Author name
+    #[arg(short, long, default_value = "anonymous")]
+    author: String,
+    /// Message (default: derived from the reverted revision)
+    #[arg(short, long)]
+    message: Option<String>,
+}
+
+pub fn run_cherry_pick(args: CherryPickArgs) -> anyhow::Result<()> {
+    replay(
+        MergeKind::CherryPick,
+        &args.revision,
+        &args.author,
+        args.message,
+    )
+}
+
+pub fn run_revert(args: RevertArgs) -> anyhow::Result<()> {
+    replay(
+        MergeKind::Revert,
+        &args.revision,
+        &args.author,
+        args.message,
+    )
+}
+
+fn replay(
+    kind: MergeKind,
+    revision: &str,
+    author: &str,
+    message: Option<String>,
+) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let registry = codec_registry(&store)?;
+
+    let claw_dir = store.layout().claw_dir();
+    if merge_state::exists(&claw_dir) {
+        anyhow::bail!("a merge is in progress; finish it with `claw snapshot` first");
+    }
+    let head_tree = worktree::head_tree(&store)?;
+    if let Some(tree) = &head_tree {
+        let ignore = IgnoreRules::load(&root);
+        if worktree::count_uncommitted(&store, &root, &ignore, tree)? > 0 {
+            anyhow::bail!("working tree has uncommitted changes; snapshot them first");
+        }
+    }
+
+    let head_ref = match store.read_head()? {
+        HeadState::Symbolic { ref_name } => ref_name,
+        HeadState::Detached { .. } => anyhow::bail!("cannot replay onto a detached HEAD"),
+    };
+    let head_id = store
+        .get_ref(&head_ref)?
+        .ok_or_else(|| anyhow::anyhow!("no commits yet on {}", head_ref))?;
+    let rev_id = store.rev_parse(revision)?;
+    let summary = match store.load_object(&rev_id)? {
+        Object::Revision(rev) => rev.summary,
+        _ => anyhow::bail!("not a revision: {}", revision),
+    };
+
+    let (message, result) = match kind {
+        MergeKind::CherryPick => {
+            let message = message.unwrap_or_else(|| format!("Cherry-pick: {}", summary));
+            let result = cherry_pick(&store, &registry, &head_id, &rev_id, author, &message)?;
+            (message, result)
+        }
+        MergeKind::Revert => {
+            let message = message.unwrap_or_else(|| format!("Revert \"{}\"", summary));
+            let result = revert(&store, &registry, &head_id, &rev_id, author, &message)?;
+            (message, result)
+        }
+        MergeKind::Merge => unreachable!("merges go through integrate"),
+    };
+
+    report_reused(&result.reused_resolutions);
+    if !result.conflicts.is_empty() {
+        let info = MergeInfo {
+            kind,
+            left_ref: head_ref,
+            right_ref: revision.to_string(),
+            left_revision: head_id.to_hex(),
+            right_revision: rev_id.to_hex(),
+            base_revision: result.ancestor.to_hex(),
+            other_heads: vec![],
+        };
+        let conflicts: Vec<_> = result.conflicts.into_iter().map(|c| (c, None)).collect();
+        return write_conflict_state(&store, &root, &result.ancestor, &conflicts, info);
+    }
+
+    let new_id = commit(
+        &store,
+        &root,
+        (&head_ref, &head_id),
+        head_tree.as_ref(),
+        result,
+        author,
+        &message,
+    )?;
+    let verb = match kind {
+        MergeKind::Revert => "Reverted",
+        _ => "Cherry-picked",
+    };
+    println!("{} {} as {}", verb, rev_id, new_id);
+    Ok(())
+}
+
+/// Store the replayed revision, advance HEAD's ref to it and move the
+/// worktree from the old head tree to its tree.
+fn commit(
+    store: &ClawStore,
+    root: &std::path::Path,
+    (head_ref, head_id): (&str, &ObjectId),
+    head_tree: Option<&ObjectId>,
+    result: MergeResult,
+    author: &str,
+    message: &str,
+) -> anyhow::Result<ObjectId> {
+    let tree = result.revision.tree;
+    let new_id = store.store_object(&Object::Revision(result.revision))?;
+    store.update_ref_cas(head_ref, Some(head_id), &new_id, author, message)?;
+    if let Some(tree_id) = tree {
+        worktree::switch_tree(store, root, head_tree, &tree_id)?;
+    }
+    Ok(new_id)
+}
@@ -1,11 +1,18 @@
+use std::path::Path;
+
 use clap::{Args, Subcommand};
 
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
+use claw_core::types::{Conflict, ConflictKind, ConflictStatus, Patch, TreeEntry};
+use claw_merge::rerere;
+use claw_patch::CodecRegistry;
 use claw_store::ClawStore;
 
-use crate::config::find_repo_root;
-use crate::merge_state;
+use crate::commands::integrate::{entry_at, load_file_from_revision};
+use crate::config::{codec_registry, find_repo_root};
+use crate::conflict_writer;
+use crate::merge_state::{self, ConflictEntry, MergeState};
 use crate::worktree;
 
 #[derive(Args)]
@@ -23,6 +30,31 @@ enum ResolveCommand {
         /// Path of the conflicted file
         path: String,
     },
+    /// Resolve a conflict by keeping the path as it is in the working tree,
+    /// or as one side has it
+    Keep {
+        /// Path of the conflicted file
+        path: String,
+        /// Take the left side's version
+        #[arg(long, conflicts_with = "right")]
+        left: bool,
+        /// Take the right side's version
+        #[arg(long)]
+        right: bool,
+    },
+    /// Resolve a conflict by deleting the path
+    Delete {
+        /// Path of the conflicted file
+        path: String,
+    },
+    /// Resolve a conflict by moving the path elsewhere; for a file/directory
+    /// conflict, moves the displaced file so both are kept
+    Rename {
+        /// Path of the conflicted file
+        path: String,
+        /// Where to move it
+        new_path: String,
+    },
     /// Abort the merge and restore to the left revision
     Abort,
 }
@@ -31,6 +63,16 @@ pub fn run(args: ResolveArgs) -> anyhow::Result<()> {
     match args.command {
         ResolveCommand::List => run_list(),
         ResolveCommand::Mark { path } => run_mark(&path),
+        ResolveCommand::Keep { path, left, right } => {
+            let side = match (left, right) {
+                (true, _) => Some(Side::Left),
+                (_, true) => Some(Side::Right),
+                _ => None,
+            };
+            run_keep(&path, side)
+        }
+        ResolveCommand::Delete { path } => run_delete(&path),
+        ResolveCommand::Rename { path, new_path } => run_rename(&path, &new_path),
         ResolveCommand::Abort => run_abort(),
     }
 }
@@ -46,7 +88,7 @@ fn run_list() -> anyhow::Result<()> {
     }
 
     let ms = merge_state::read_from(&claw_dir)?;
-    println!("Merging: {} into {}", ms.merge.right_ref, ms.merge.left_ref);
+    println!("{}", ms.merge.describe());
     println!();
 
     if ms.conflicts.is_empty() {
@@ -55,17 +97,16 @@ fn run_list() -> anyhow::Result<()> {
     }
 
     for conflict in &ms.conflicts {
-        let file_path = root.join(&conflict.file_path);
-        let has_markers = check_conflict_markers(&file_path, &conflict.codec_id);
+        let has_markers = check_conflict_markers(&root, conflict);
         let status_tag = if has_markers { "unresolved" } else { "ready" };
-        println!(
-            "  {} {} ({})",
-            status_tag, conflict.file_path, conflict.codec_id
-        );
+        println!("  {} {}", status_tag, conflict.label());
     }
 
     println!();
     println!("  (use \"claw resolve mark <path>\" to mark a file as resolved)");
+    if ms.conflicts.iter().any(|c| c.is_tree_conflict()) {
+        println!("  (use \"claw resolve keep|delete|rename <path>\" to settle a tree conflict)");
+    }
     println!("  (use \"claw resolve abort\" to cancel the merge)");
 
     Ok(())
@@ -74,39 +115,118 @@ fn run_list() -> anyhow::Result<()> {
 fn run_mark(path: &str) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
-    let claw_dir = store.layout().claw_dir();
+    let (ms, idx) = find_conflict(&store, path)?;
 
-    if !merge_state::exists(&claw_dir) {
-        anyhow::bail!("No merge in progress.");
+    // Check that conflict markers have been removed
+    if check_conflict_markers(&root, &ms.conflicts[idx]) {
+        anyhow::bail!(
+            "File '{}' still contains conflict markers. Edit the file to resolve, then re-run.",
+            path
+        );
+    }
+    finish_resolution(&store, &root, ms, idx)
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+fn run_keep(path: &str, side: Option<Side>) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let (ms, idx) = find_conflict(&store, path)?;
+    let entry = &ms.conflicts[idx];
+
+    match side {
+        Some(side) => {
+            let conflict = load_conflict(&store, entry)?;
+            let revision = match side {
+                Side::Left => conflict.left_revision,
+                Side::Right => conflict.right_revision,
+            };
+            replace_path(
+                &store,
+                &root,
+                path,
+                entry_at(&store, &revision, path).as_ref(),
+            )?;
+        }
+        None if !entry.is_tree_conflict() && check_conflict_markers(&root, entry) => {
+            anyhow::bail!(
+                "File '{}' still contains conflict markers; pass --left or --right to take one side.",
+                path
+            );
+        }
+        None => {}
     }
+    finish_resolution(&store, &root, ms, idx)
+}
+
+fn run_delete(path: &str) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let (ms, idx) = find_conflict(&store, path)?;
+    replace_path(&store, &root, path, None)?;
+    finish_resolution(&store, &root, ms, idx)
+}
 
-    let mut ms = merge_state::read_from(&claw_dir)?;
+fn run_rename(path: &str, new_path: &str) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let (ms, idx) = find_conflict(&store, path)?;
 
+    // A file displaced by a directory waits in a sidecar
+    let displaced = ["LEFT", "RIGHT"]
+        .map(|suffix| root.join(format!("{path}.{suffix}")))
+        .into_iter()
+        .find(|p| ms.conflicts[idx].kind == ConflictKind::FileDirectory && p.exists());
+    let from = displaced.unwrap_or_else(|| root.join(path));
+    if from.symlink_metadata().is_err() {
+        anyhow::bail!("nothing at '{}' to rename", path);
+    }
+    let to = root.join(new_path);
+    if to.symlink_metadata().is_ok() {
+        anyhow::bail!("'{}' already exists", new_path);
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(&from, &to)?;
+    println!("Moved '{}' to '{}'.", path, new_path);
+    finish_resolution(&store, &root, ms, idx)
+}
+
+/// The merge state and the index of the conflict on `path`.
+fn find_conflict(store: &ClawStore, path: &str) -> anyhow::Result<(MergeState, usize)> {
+    let claw_dir = store.layout().claw_dir();
+    if !merge_state::exists(&claw_dir) {
+        anyhow::bail!("No merge in progress.");
+    }
+    let ms = merge_state::read_from(&claw_dir)?;
     let idx = ms
         .conflicts
         .iter()
         .position(|c| c.file_path == path)
         .ok_or_else(|| anyhow::anyhow!("'{}' is not a conflicted file", path))?;
+    Ok((ms, idx))
+}
 
-    // Check that conflict markers have been removed
-    let file_path = root.join(path);
-    let codec_id = &ms.conflicts[idx].codec_id;
-    if check_conflict_markers(&file_path, codec_id) {
-        anyhow::bail!(
-            "File '{}' still contains conflict markers. Edit the file to resolve, then re-run.",
-            path
-        );
+/// Record the resolution of conflict `idx`, clear its sidecars and drop it
+/// from the merge state.
+fn finish_resolution(
+    store: &ClawStore,
+    root: &Path,
+    mut ms: MergeState,
+    idx: usize,
+) -> anyhow::Result<()> {
+    if let Some(id) = record_resolution(store, &codec_registry(store)?, root, &ms.conflicts[idx])? {
+        ms.resolved.push(id.to_hex());
     }
-
-    // Remove conflict sidecars
-    let base_sidecar = root.join(format!("{}.BASE", path));
-    let right_sidecar = root.join(format!("{}.RIGHT", path));
-    let _ = std::fs::remove_file(base_sidecar);
-    let _ = std::fs::remove_file(right_sidecar);
-
-    // Remove from conflicts list
-    ms.conflicts.remove(idx);
-    merge_state::write_to(&claw_dir, &ms)?;
+    let path = ms.conflicts.remove(idx).file_path;
+    conflict_writer::remove_sidecars(root, &path);
+    merge_state::write_to(&store.layout().claw_dir(), &ms)?;
 
     println!("Marked '{}' as resolved.", path);
     if ms.conflicts.is_empty() {
@@ -114,7 +234,28 @@ fn run_mark(path: &str) -> anyhow::Result<()> {
     } else {
         println!("{} conflict(s) remaining.", ms.conflicts.len());
     }
+    Ok(())
+}
 
+/// Replace whatever is at `path` with `entry`, or just remove it.
+fn replace_path(
+    store: &ClawStore,
+    root: &Path,
+    path: &str,
+    entry: Option<&TreeEntry>,
+) -> anyhow::Result<()> {
+    let full = root.join(path);
+    match full.symlink_metadata() {
+        Ok(meta) if meta.is_dir() => std::fs::remove_dir_all(&full)?,
+        Ok(_) => std::fs::remove_file(&full)?,
+        Err(_) => {}
+    }
+    if let Some(entry) = entry {
+        if let Some(parent) = full.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        worktree::materialize_entry(store, entry, &full)?;
+    }
     Ok(())
 }
 
@@ -131,10 +272,7 @@ fn run_abort() -> anyhow::Result<()> {
 
     // Clean up conflict sidecars
     for conflict in &ms.conflicts {
-        let base_sidecar = root.join(format!("{}.BASE", conflict.file_path));
-        let right_sidecar = root.join(format!("{}.RIGHT", conflict.file_path));
-        let _ = std::fs::remove_file(base_sidecar);
-        let _ = std::fs::remove_file(right_sidecar);
+        conflict_writer::remove_sidecars(&root, &conflict.file_path);
     }
 
     // Restore worktree to left revision
@@ -156,9 +294,76 @@ fn run_abort() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Check if a file still contains conflict markers.
-fn check_conflict_markers(path: &std::path::Path, codec_id: &str) -> bool {
-    match codec_id {
+/// Store the resolved file as the conflict's resolution patch and remember
+/// it so the same conflict resolves itself the next time it comes up.
+/// Returns the stored, resolved `Conflict`.
+pub(crate) fn record_resolution(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    root: &Path,
+    entry: &ConflictEntry,
+) -> anyhow::Result<Option<ObjectId>> {
+    // States written before conflicts were stored have nothing to update
+    if entry.object_id.is_none() {
+        return Ok(None);
+    }
+    let mut conflict = load_conflict(store, entry)?;
+    // A tree conflict's resolution is the tree the snapshot records
+    if conflict.kind != ConflictKind::Content {
+        conflict.status = ConflictStatus::Resolved;
+        return Ok(Some(store.store_object(&Object::Conflict(conflict))?));
+    }
+    let path = &conflict.file_path;
+    let resolved = std::fs::read(root.join(path)).unwrap_or_default();
+    let base = match &conflict.base_revision {
+        Some(base) => load_file_from_revision(store, base, path),
+        None => vec![],
+    };
+    let left = load_file_from_revision(store, &conflict.left_revision, path);
+    let right = load_file_from_revision(store, &conflict.right_revision, path);
+
+    let patch = Patch {
+        target_path: path.clone(),
+        codec_id: conflict.codec_id.clone(),
+        base_object: None,
+        result_object: None,
+        ops: registry.get(&conflict.codec_id)?.diff(&base, &resolved)?,
+        codec_payload: None,
+    };
+    conflict.resolution_patch_ids = vec![store.store_object_for_path(path, &Object::Patch(patch))?];
+    conflict.status = ConflictStatus::Resolved;
+    let conflict_id = store.store_object(&Object::Conflict(conflict))?;
+
+    if rerere::record(store, &entry.file_path, &base, &left, &right, &resolved)? {
+        println!("Recorded resolution for {}", entry.file_path);
+    }
+    Ok(Some(conflict_id))
+}
+
+/// The stored `Conflict` behind a state entry.
+fn load_conflict(store: &ClawStore, entry: &ConflictEntry) -> anyhow::Result<Conflict> {
+    let object_id = entry.object_id.as_deref().ok_or_else(|| {
+        anyhow::anyhow!(
+            "no conflict record for '{}'; resolve it by hand and use `claw resolve mark`",
+            entry.file_path
+        )
+    })?;
+    match store.load_object(&ObjectId::from_hex(object_id)?)? {
+        Object::Conflict(conflict) => Ok(conflict),
+        _ => anyhow::bail!("{object_id} is not a conflict"),
+    }
+}
+
+/// Check if a conflicted file is still unresolved: it has conflict markers,
+/// or for a tree conflict its `.CONFLICT` note is still there.
+pub(crate) fn check_conflict_markers(root: &Path, conflict: &ConflictEntry) -> bool {
+    if conflict.is_tree_conflict() {
+        return root
+            .join(format!("{}.CONFLICT", conflict.file_path))
+            .exists();
+    }
+    let path = &root.join(&conflict.file_path);
+    match conflict.codec_id.as_str() {
         "json/tree" => {
             // JSON conflicts use a structured _conflict key
             if let Ok(content) = std::fs::read_to_string(path) {
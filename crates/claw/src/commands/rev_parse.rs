@@ -0,0 +1,30 @@
+use clap::Args;
+
+use claw_store::ClawStore;
+
+use crate::config::find_repo_root;
+
+#[derive(Args)]
+pub struct RevParseArgs {
+    /// Revision expressions (e.g. HEAD~2, main@{1}, change:<ulid>, abbreviated ids)
+    #[arg(required = true)]
+    revisions: Vec<String>,
+    /// Print ids in clw_ display format instead of hex
+    #[arg(long)]
+    display: bool,
+}
+
+pub fn run(args: RevParseArgs) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    for spec in &args.revisions {
+        let id = store.rev_parse(spec)?;
+        if args.display {
+            println!("{id}");
+        } else {
+            println!("{}", id.to_hex());
+        }
+    }
+    Ok(())
+}
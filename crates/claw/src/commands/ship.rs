@@ -13,7 +13,7 @@ pub struct ShipArgs {
     /// Intent ID to ship
     #[arg(short, long)]
     intent: String,
-    /// Revision ref to ship
+    /// Revision to ship (ref or revision expression)
     #[arg(short, long, default_value = "heads/main")]
     revision_ref: String,
     /// Agent ID
@@ -36,9 +36,7 @@ pub fn run(args: ShipArgs) -> anyhow::Result<()> {
     };
 
     // Load revision
-    let rev_id = store
-        .get_ref(&args.revision_ref)?
-        .ok_or_else(|| anyhow::anyhow!("ref not found: {}", args.revision_ref))?;
+    let rev_id = store.rev_parse(&args.revision_ref)?;
 
     // Generate ephemeral keypair for signing
     let keypair = KeyPair::generate();
@@ -54,12 +52,6 @@ pub fn run(args: ShipArgs) -> anyhow::Result<()> {
     let capsule = build_capsule(&rev_id, public, None, None, &keypair)?;
     let capsule_id = store.store_object(&Object::Capsule(capsule))?;
 
-    // Add capsule reverse-mapping ref
-    store.set_ref(
-        &format!("capsules/by-revision/{}", &rev_id.to_hex()[..16]),
-        &capsule_id,
-    )?;
-
     // Update intent status to done
     let mut updated_intent = intent;
     updated_intent.status = IntentStatus::Done;
@@ -67,7 +59,19 @@ pub fn run(args: ShipArgs) -> anyhow::Result<()> {
         .duration_since(std::time::UNIX_EPOCH)?
         .as_millis() as u64;
     let new_intent_id = store.store_object(&Object::Intent(updated_intent.clone()))?;
-    store.set_ref(&format!("intents/{}", updated_intent.id), &new_intent_id)?;
+
+    // Publish the capsule reverse-mapping and the updated intent together
+    let mut tx = store.transaction();
+    tx.set(
+        &format!("capsules/by-revision/{}", &rev_id.to_hex()[..16]),
+        &capsule_id,
+    )
+    .update(
+        &format!("intents/{}", updated_intent.id),
+        &intent_obj_id,
+        &new_intent_id,
+    );
+    tx.commit(&args.agent, &format!("ship intent {}", updated_intent.id))?;
 
     println!("Shipped intent: {}", updated_intent.id);
     println!("  Capsule: {capsule_id}");
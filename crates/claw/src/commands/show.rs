@@ -1,6 +1,6 @@
 use clap::Args;
 
-use claw_core::id::ObjectId;
+use claw_core::chunking::ChunkManifest;
 use claw_core::object::Object;
 use claw_core::types::FileMode;
 use claw_store::ClawStore;
@@ -10,7 +10,7 @@ use crate::output;
 
 #[derive(Args)]
 pub struct ShowArgs {
-    /// Object ID (hex or clw_ display format), or ref name
+    /// Object ID (full or abbreviated hex/clw_), ref name or revision expression
     object: String,
 }
 
@@ -18,24 +18,10 @@ pub fn run(args: ShowArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
 
-    // Resolve: try ref first, then hex, then display format
-    let id = if let Some(id) = store.get_ref(&args.object)? {
-        id
-    } else if let Ok(id) = ObjectId::from_hex(&args.object) {
-        if store.has_object(&id) {
-            id
-        } else {
-            anyhow::bail!("object not found: {}", args.object);
-        }
-    } else if let Ok(id) = ObjectId::from_display(&args.object) {
-        if store.has_object(&id) {
-            id
-        } else {
-            anyhow::bail!("object not found: {}", args.object);
-        }
-    } else {
-        anyhow::bail!("cannot resolve: {}", args.object);
-    };
+    let id = store.rev_parse(&args.object)?;
+    if !store.has_object(&id) {
+        anyhow::bail!("object not found: {}", args.object);
+    }
 
     let obj = store.load_object(&id)?;
     let type_name = obj.type_tag().name();
@@ -89,6 +75,19 @@ pub fn run(args: ShowArgs) -> anyhow::Result<()> {
                 println!("  {} {} {}", mode_str, entry.object_id, entry.name);
             }
         }
+        Object::Blob(blob) if blob.is_chunk_manifest() => {
+            let manifest = ChunkManifest::from_blob(&blob).transpose()?;
+            if let Some(manifest) = manifest {
+                println!(
+                    "(chunked, {} bytes in {} chunk(s))",
+                    manifest.total_size,
+                    manifest.chunks.len()
+                );
+                for chunk in &manifest.chunks {
+                    println!("  {} {}", chunk.id, chunk.size);
+                }
+            }
+        }
         Object::Blob(blob) => {
             if let Ok(text) = std::str::from_utf8(&blob.data) {
                 if blob.data.len() <= 8192 {
@@ -234,12 +233,54 @@ pub fn run(args: ShowArgs) -> anyhow::Result<()> {
                 );
             }
         }
+        Object::Operation(op) => {
+            println!("{}", output::kv("command", &op.command));
+            println!("{}", output::kv("author", &op.author));
+            println!("{}", output::kv("timestamp", &op.timestamp.to_string()));
+            if let Some(parent) = op.parent {
+                println!("{}", output::kv("parent", &parent.to_string()));
+            }
+            let head = match (&op.head_ref, op.head_target) {
+                (_, Some(target)) => format!("detached at {}", target),
+                (Some(ref_name), None) => ref_name.clone(),
+                (None, None) => "(none)".to_string(),
+            };
+            println!("{}", output::kv("HEAD", &head));
+            println!("{}", output::kv("refs", &format!("{}", op.refs.len())));
+            for r in &op.refs {
+                println!("  {} {}", r.target, r.name);
+            }
+        }
+    }
+
+    // Reverse edges come from the object index; skip them when it is stale.
+    if let Some(referrers) = store.referrers(&id)? {
+        if !referrers.is_empty() {
+            println!();
+            println!(
+                "{}",
+                output::kv("referenced by", &format!("{} object(s)", referrers.len()))
+            );
+            for referrer in referrers.iter().take(MAX_REFERRERS) {
+                let kind = store
+                    .object_type(referrer)
+                    .map(|tag| tag.name())
+                    .unwrap_or("unknown");
+                println!("  {} {}", kind, referrer);
+            }
+            if referrers.len() > MAX_REFERRERS {
+                println!("  ... and {} more", referrers.len() - MAX_REFERRERS);
+            }
+        }
     }
 
     println!();
     Ok(())
 }
 
+/// Referrers listed before the output is truncated.
+const MAX_REFERRERS: usize = 20;
+
 fn format_timestamp(ms: u64) -> String {
     let secs = ms / 1000;
     let h = (secs % 86400) / 3600;
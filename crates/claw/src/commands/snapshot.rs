@@ -3,13 +3,16 @@ use clap::Args;
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
 use claw_core::types::{Patch, Revision};
+use claw_merge::renames;
 use claw_patch::CodecRegistry;
-use claw_store::tree_diff::{diff_trees, ChangeKind};
-use claw_store::{ClawStore, HeadState};
+use claw_store::tree_diff::{diff_trees, flatten_tree, ChangeKind};
+use claw_store::{ClawStore, HeadState, StoreError};
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
+use crate::conflict_writer;
 use crate::ignore::IgnoreRules;
 use crate::merge_state;
+use crate::stack_state;
 use crate::worktree;
 
 #[derive(Args)]
@@ -28,11 +31,16 @@ pub struct SnapshotArgs {
 pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
     let root = find_repo_root()?;
     let store = ClawStore::open(&root)?;
-    let registry = CodecRegistry::default();
+    let registry = codec_registry(&store)?;
     let ignore = IgnoreRules::load(&root);
 
     let claw_dir = store.layout().claw_dir();
     let is_merge_completion = merge_state::exists(&claw_dir);
+    if stack_state::exists(&claw_dir) {
+        anyhow::bail!(
+            "a stack rebase is in progress; finish it with `claw stack rebase --continue`"
+        );
+    }
 
     // Resolve HEAD to get branch ref name
     let head_state = store.read_head()?;
@@ -56,14 +64,18 @@ pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
         .and_then(|s| claw_core::id::ChangeId::from_string(s).ok());
 
     if is_merge_completion {
-        // Merge completion: create two-parent revision
+        // Merge completion: create the merge (or pick) revision
         let ms = merge_state::read_from(&claw_dir)?;
-        let left_rev = ObjectId::from_hex(&ms.merge.left_revision)?;
-        let right_rev = ObjectId::from_hex(&ms.merge.right_revision)?;
+        let parents = ms
+            .merge
+            .parent_revisions()
+            .into_iter()
+            .map(ObjectId::from_hex)
+            .collect::<Result<Vec<_>, _>>()?;
 
         let revision = Revision {
             change_id,
-            parents: vec![left_rev, right_rev],
+            parents,
             patches: vec![],
             snapshot_base: None,
             tree: Some(new_tree),
@@ -86,10 +98,7 @@ pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
         merge_state::remove(&claw_dir)?;
         // Remove conflict sidecars
         for conflict in &ms.conflicts {
-            let base_path = root.join(format!("{}.BASE", conflict.file_path));
-            let right_path = root.join(format!("{}.RIGHT", conflict.file_path));
-            let _ = std::fs::remove_file(base_path);
-            let _ = std::fs::remove_file(right_path);
+            conflict_writer::remove_sidecars(&root, &conflict.file_path);
         }
 
         println!("Merge resolved: {rev_id}");
@@ -108,6 +117,13 @@ pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
         };
 
         let changes = diff_trees(&store, old_tree_id.as_ref(), Some(&new_tree), "")?;
+        let old_files = match &old_tree_id {
+            Some(tree) => flatten_tree(&store, tree)?,
+            None => Default::default(),
+        };
+        // Moved and copied files get a patch against their old content,
+        // which names it as its base, instead of a rewrite from nothing.
+        let changes = renames::detect(&store, &registry, &old_files, changes, None);
 
         if changes.is_empty() {
             println!("No changes to snapshot.");
@@ -115,51 +131,29 @@ pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
         }
 
         for change in &changes {
-            let ext = change.path.rsplit('.').next().unwrap_or("");
-            let codec = registry.get_by_extension(ext);
-
-            if let Some(codec) = codec {
-                let old_content = match (change.kind.clone(), change.old_id) {
-                    (ChangeKind::Modified, Some(id))
-                    | (ChangeKind::TypeChanged, Some(id))
-                    | (ChangeKind::Deleted, Some(id)) => {
-                        let obj = store.load_object(&id)?;
-                        match obj {
-                            Object::Blob(b) => b.data,
-                            _ => vec![],
-                        }
-                    }
-                    _ => vec![],
-                };
-                let new_content = match (change.kind.clone(), change.new_id) {
-                    (ChangeKind::Added, Some(id))
-                    | (ChangeKind::Modified, Some(id))
-                    | (ChangeKind::TypeChanged, Some(id)) => {
-                        let obj = store.load_object(&id)?;
-                        match obj {
-                            Object::Blob(b) => b.data,
-                            _ => vec![],
-                        }
-                    }
-                    _ => vec![],
-                };
-
-                if let Ok(ops) = codec.diff(&old_content, &new_content) {
-                    if !ops.is_empty() {
-                        let patch = Patch {
-                            target_path: change.path.clone(),
-                            codec_id: codec.id().to_string(),
-                            base_object: change.old_id,
-                            result_object: change.new_id,
-                            ops,
-                            codec_payload: None,
-                        };
-                        let patch_id = store.store_object(&Object::Patch(patch))?;
-                        patches.push(patch_id);
-                    }
-                }
+            let old_id = match change.kind {
+                ChangeKind::Added => None,
+                _ => change.old_id,
+            };
+            let new_id = match change.kind {
+                ChangeKind::Deleted => None,
+                _ => change.new_id,
+            };
+            // A move also deletes the old path.
+            if let (ChangeKind::Renamed, Some(old_path)) = (&change.kind, &change.old_path) {
+                patches.extend(file_patch(
+                    &store, &registry, old_path, old_id, None, false,
+                )?);
             }
-            // No codec for this extension - still track it via the tree change
+            let moved = matches!(change.kind, ChangeKind::Renamed | ChangeKind::Copied);
+            patches.extend(file_patch(
+                &store,
+                &registry,
+                &change.path,
+                old_id,
+                new_id,
+                moved,
+            )?);
         }
     }
 
@@ -188,3 +182,59 @@ pub fn run(args: SnapshotArgs) -> anyhow::Result<()> {
     println!("Snapshot: {rev_id}");
     Ok(())
 }
+
+/// Content of a blob, or nothing if `id` is not a blob.
+fn blob_content(store: &ClawStore, id: &ObjectId) -> anyhow::Result<Vec<u8>> {
+    match store.read_blob(id) {
+        Ok(data) => Ok(data),
+        Err(StoreError::NotABlob(_)) => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The patch taking `path` from `old` to `new`, both blob ids, or `None`
+/// when its codec can't diff them or there is nothing to record. `moved`
+/// keeps a patch without ops, which still records where the file came from.
+fn file_patch(
+    store: &ClawStore,
+    registry: &CodecRegistry,
+    path: &str,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    moved: bool,
+) -> anyhow::Result<Option<ObjectId>> {
+    let ext = path.rsplit('.').next().unwrap_or("");
+    // No codec for this extension - still tracked via the tree change
+    let Some(codec) = registry.get_by_extension(ext) else {
+        return Ok(None);
+    };
+    // Chunked blobs are too large for structural patches.
+    if [old, new]
+        .iter()
+        .flatten()
+        .any(|id| store.is_chunked_blob(id).unwrap_or(false))
+    {
+        return Ok(None);
+    }
+    let content = |id: Option<ObjectId>| match id {
+        Some(id) => blob_content(store, &id),
+        None => Ok(vec![]),
+    };
+    let Ok(ops) = codec.diff(&content(old)?, &content(new)?) else {
+        return Ok(None);
+    };
+    if ops.is_empty() && !moved {
+        return Ok(None);
+    }
+    let patch = Patch {
+        target_path: path.to_string(),
+        codec_id: codec.id().to_string(),
+        base_object: old,
+        result_object: new,
+        ops,
+        codec_payload: None,
+    };
+    Ok(Some(
+        store.store_object_for_path(path, &Object::Patch(patch))?,
+    ))
+}
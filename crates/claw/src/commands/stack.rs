@@ -0,0 +1,352 @@
+use std::path::Path;
+
+use clap::{Args, Subcommand};
+
+use claw_core::id::{ChangeId, ObjectId};
+use claw_core::object::Object;
+use claw_core::types::Revision;
+use claw_merge::ancestor::find_lca;
+use claw_merge::collect::tree_diff_patches;
+use claw_merge::stack::{rebase_stack, StackRebase};
+use claw_store::ClawStore;
+
+use crate::commands::integrate::{report_reused, write_conflict_files};
+use crate::commands::resolve::{check_conflict_markers, record_resolution};
+use crate::config::{codec_registry, find_repo_root};
+use crate::conflict_writer;
+use crate::ignore::IgnoreRules;
+use crate::merge_state::{self, ConflictEntry};
+use crate::stack_state::{self, StackChange, StackRebaseInfo, StackRebaseState};
+use crate::worktree;
+
+#[derive(Args)]
+pub struct StackArgs {
+    #[command(subcommand)]
+    command: StackCommand,
+}
+
+#[derive(Subcommand)]
+enum StackCommand {
+    /// Move every change of a workstream onto a new base, in stack order
+    Rebase(StackRebaseArgs),
+}
+
+#[derive(Args)]
+struct StackRebaseArgs {
+    /// Workstream whose changes to rebase
+    #[arg(required_unless_present_any = ["resume", "abort"])]
+    workstream: Option<String>,
+    /// Revision to move the stack onto
+    #[arg(long, required_unless_present_any = ["resume", "abort"])]
+    onto: Option<String>,
+    /// Resume after resolving the conflicts of a stopped rebase
+    #[arg(long = "continue", conflicts_with_all = ["workstream", "onto", "abort"])]
+    resume: bool,
+    /// Cancel a stopped rebase and restore every change's head
+    #[arg(long, conflicts_with_all = ["workstream", "onto"])]
+    abort: bool,
+}
+
+pub fn run(args: StackArgs) -> anyhow::Result<()> {
+    match args.command {
+        StackCommand::Rebase(args) if args.abort => run_abort(),
+        StackCommand::Rebase(args) if args.resume => run_continue(),
+        StackCommand::Rebase(args) => run_rebase(
+            args.workstream.as_deref().unwrap_or_default(),
+            args.onto.as_deref().unwrap_or_default(),
+        ),
+    }
+}
+
+fn run_rebase(workstream: &str, onto_spec: &str) -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let registry = codec_registry(&store)?;
+    let ignore = IgnoreRules::load(&root);
+
+    let claw_dir = store.layout().claw_dir();
+    if merge_state::exists(&claw_dir) {
+        anyhow::bail!("a merge is in progress; finish it with `claw snapshot` first");
+    }
+    if stack_state::exists(&claw_dir) {
+        anyhow::bail!("a stack rebase is in progress; use --continue or --abort");
+    }
+    let head_tree = worktree::head_tree(&store)?;
+    if let Some(tree) = &head_tree {
+        if worktree::count_uncommitted(&store, &root, &ignore, tree)? > 0 {
+            anyhow::bail!("working tree has uncommitted changes; snapshot them first");
+        }
+    }
+
+    let ws_id = store
+        .get_ref(&format!("workstreams/{workstream}"))?
+        .ok_or_else(|| anyhow::anyhow!("workstream not found: {workstream}"))?;
+    let Object::Workstream(ws) = store.load_object(&ws_id)? else {
+        anyhow::bail!("workstreams/{workstream} is not a workstream");
+    };
+
+    let mut stack = Vec::new();
+    for change_id in &ws.change_stack {
+        let change = load_change(&store, change_id)?;
+        if let Some(head) = change.head_revision {
+            stack.push((*change_id, head));
+        }
+    }
+    let Some((_, bottom)) = stack.first() else {
+        println!("Workstream {workstream} has no changes with revisions to rebase.");
+        return Ok(());
+    };
+
+    let onto = store.rev_parse(onto_spec)?;
+    let base = find_lca(&store, bottom, &onto)?
+        .ok_or_else(|| anyhow::anyhow!("stack and {onto_spec} share no history"))?;
+
+    let result = rebase_stack(&store, &registry, &stack, &base, &onto)?;
+    let info = StackRebaseInfo {
+        workstream: workstream.to_string(),
+        onto: onto_spec.to_string(),
+        stopped_at: 0,
+        revision: String::new(),
+        tip: String::new(),
+    };
+    finish(&store, &root, info, &stack, 0, head_tree.as_ref(), result)
+}
+
+fn run_continue() -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+    let registry = codec_registry(&store)?;
+    let ignore = IgnoreRules::load(&root);
+
+    let claw_dir = store.layout().claw_dir();
+    if !stack_state::exists(&claw_dir) {
+        anyhow::bail!("No stack rebase in progress.");
+    }
+    let state = stack_state::read_from(&claw_dir)?;
+    for conflict in &state.conflicts {
+        if check_conflict_markers(&root, conflict) {
+            anyhow::bail!(
+                "File '{}' still contains conflict markers. Edit the file to resolve, then re-run.",
+                conflict.file_path
+            );
+        }
+    }
+    for conflict in &state.conflicts {
+        record_resolution(&store, &registry, &root, conflict)?;
+    }
+    remove_sidecars(&root, &state.conflicts);
+
+    // The resolved worktree replaces the revision that conflicted
+    let revision_id = ObjectId::from_hex(&state.rebase.revision)?;
+    let tip = ObjectId::from_hex(&state.rebase.tip)?;
+    let Object::Revision(original) = store.load_object(&revision_id)? else {
+        anyhow::bail!("{} is not a revision", state.rebase.revision);
+    };
+    let resolved_tree = worktree::scan_worktree(&store, &root, &ignore)?;
+    let tip_tree = revision_tree(&store, &tip)?;
+    let patches = tree_diff_patches(&store, &registry, tip_tree.as_ref(), Some(&resolved_tree))?;
+    let resolved = store.store_object(&Object::Revision(Revision {
+        change_id: original.change_id,
+        parents: vec![tip],
+        patches,
+        snapshot_base: None,
+        tree: Some(resolved_tree),
+        capsule_id: None,
+        author: original.author,
+        created_at_ms: original.created_at_ms,
+        summary: original.summary,
+        policy_evidence: vec![],
+    }))?;
+
+    let stack = state
+        .changes
+        .iter()
+        .map(|c| {
+            Ok((
+                ChangeId::from_string(&c.change_id)?,
+                ObjectId::from_hex(&c.head)?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let offset = state.rebase.stopped_at;
+    let result = rebase_stack(&store, &registry, &stack[offset..], &revision_id, &resolved)?;
+    finish(
+        &store,
+        &root,
+        state.rebase,
+        &stack,
+        offset,
+        Some(&resolved_tree),
+        result,
+    )
+}
+
+fn run_abort() -> anyhow::Result<()> {
+    let root = find_repo_root()?;
+    let store = ClawStore::open(&root)?;
+
+    let claw_dir = store.layout().claw_dir();
+    if !stack_state::exists(&claw_dir) {
+        anyhow::bail!("No stack rebase in progress.");
+    }
+    let state = stack_state::read_from(&claw_dir)?;
+    remove_sidecars(&root, &state.conflicts);
+
+    for change in &state.changes {
+        let change_id = ChangeId::from_string(&change.change_id)?;
+        let head = ObjectId::from_hex(&change.head)?;
+        set_change_head(&store, &change_id, None, &head, "stack rebase --abort")?;
+    }
+
+    let tip_tree = revision_tree(&store, &ObjectId::from_hex(&state.rebase.tip)?)?;
+    if let Some(head_tree) = worktree::head_tree(&store)? {
+        worktree::switch_tree(&store, &root, tip_tree.as_ref(), &head_tree)?;
+    }
+    stack_state::remove(&claw_dir)?;
+
+    println!(
+        "Stack rebase of {} aborted. Change heads restored.",
+        state.rebase.workstream
+    );
+    Ok(())
+}
+
+/// Record the new change heads, then either check out the conflicts and
+/// save the state to resume from, or restore HEAD's tree.
+///
+/// `stack` is the whole stack as it was before the rebase; `result` covers
+/// it from `offset` on. `checked_out` is the tree in the worktree now.
+fn finish(
+    store: &ClawStore,
+    root: &Path,
+    mut info: StackRebaseInfo,
+    stack: &[(ChangeId, ObjectId)],
+    offset: usize,
+    checked_out: Option<&ObjectId>,
+    result: StackRebase,
+) -> anyhow::Result<()> {
+    for (change_id, head) in &result.rebased {
+        // Every rebased change still has the head it had when the rebase started
+        let started_at = stack
+            .iter()
+            .find(|(id, _)| id == change_id)
+            .map(|(_, head)| head);
+        set_change_head(store, change_id, started_at, head, "stack rebase")?;
+        println!("  rebased {} -> {}", change_id, head);
+    }
+    report_reused(&result.reused_resolutions);
+
+    let claw_dir = store.layout().claw_dir();
+    let Some(stop) = result.stopped else {
+        if let Some(head_tree) = worktree::head_tree(store)? {
+            worktree::switch_tree(store, root, checked_out, &head_tree)?;
+        }
+        stack_state::remove(&claw_dir)?;
+        println!("Rebased workstream {} onto {}.", info.workstream, info.onto);
+        return Ok(());
+    };
+
+    if let Some(tip_tree) = revision_tree(store, &stop.onto)? {
+        worktree::switch_tree(store, root, checked_out, &tip_tree)?;
+    }
+    let conflicts: Vec<_> = stop
+        .result
+        .conflicts
+        .into_iter()
+        .map(|c| (c, None))
+        .collect();
+    let conflict_entries = write_conflict_files(
+        store,
+        root,
+        &stop.result.ancestor,
+        &conflicts,
+        &info.onto,
+        &stop.revision.to_string(),
+    )?;
+
+    info.stopped_at = offset + stop.index;
+    info.revision = stop.revision.to_hex();
+    info.tip = stop.onto.to_hex();
+    let state = StackRebaseState {
+        rebase: info,
+        changes: stack
+            .iter()
+            .map(|(change_id, head)| StackChange {
+                change_id: change_id.to_string(),
+                head: head.to_hex(),
+            })
+            .collect(),
+        conflicts: conflict_entries,
+    };
+    stack_state::write_to(&claw_dir, &state)?;
+
+    println!(
+        "Stack rebase stopped at change {} with {} conflict(s).",
+        stop.change,
+        state.conflicts.len()
+    );
+    for conflict in &state.conflicts {
+        println!("  CONFLICT: {}", conflict.label());
+    }
+    println!("Resolve them and run `claw stack rebase --continue`, or `--abort` to cancel.");
+    Ok(())
+}
+
+fn load_change(
+    store: &ClawStore,
+    change_id: &ChangeId,
+) -> anyhow::Result<claw_core::types::Change> {
+    let obj_id = store
+        .get_ref(&format!("changes/{change_id}"))?
+        .ok_or_else(|| anyhow::anyhow!("change not found: {change_id}"))?;
+    match store.load_object(&obj_id)? {
+        Object::Change(change) => Ok(change),
+        _ => anyhow::bail!("changes/{change_id} is not a change"),
+    }
+}
+
+/// Point a change at `head`, failing if someone moved it away from `expected`
+/// (or rewrote the change) since it was read.
+fn set_change_head(
+    store: &ClawStore,
+    change_id: &ChangeId,
+    expected: Option<&ObjectId>,
+    head: &ObjectId,
+    message: &str,
+) -> anyhow::Result<()> {
+    let ref_name = format!("changes/{change_id}");
+    let old_id = store
+        .get_ref(&ref_name)?
+        .ok_or_else(|| anyhow::anyhow!("change not found: {change_id}"))?;
+    let Object::Change(mut change) = store.load_object(&old_id)? else {
+        anyhow::bail!("{ref_name} is not a change");
+    };
+    if change.head_revision.as_ref() == Some(head) {
+        return Ok(());
+    }
+    if let Some(expected) = expected {
+        if change.head_revision.as_ref() != Some(expected) {
+            anyhow::bail!("change {change_id} was updated during the stack rebase");
+        }
+    }
+    change.head_revision = Some(*head);
+    change.updated_at_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis() as u64;
+    let new_id = store.store_object(&Object::Change(change))?;
+    store.update_ref_cas(&ref_name, Some(&old_id), &new_id, "stack", message)?;
+    Ok(())
+}
+
+fn revision_tree(store: &ClawStore, id: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+    match store.load_object(id)? {
+        Object::Revision(rev) => Ok(rev.tree),
+        _ => Ok(None),
+    }
+}
+
+fn remove_sidecars(root: &Path, conflicts: &[ConflictEntry]) {
+    for conflict in conflicts {
+        conflict_writer::remove_sidecars(root, &conflict.file_path);
+    }
+}
@@ -1,10 +1,11 @@
 use clap::Args;
 
 use claw_core::object::Object;
-use claw_store::tree_diff::{diff_trees, ChangeKind};
+use claw_merge::renames;
+use claw_store::tree_diff::{diff_flat, flatten_tree, ChangeKind};
 use claw_store::{ClawStore, HeadState};
 
-use crate::config::find_repo_root;
+use crate::config::{codec_registry, find_repo_root};
 use crate::ignore::IgnoreRules;
 use crate::merge_state;
 use crate::output;
@@ -44,25 +45,38 @@ pub fn run(args: StatusArgs) -> anyhow::Result<()> {
         None
     };
 
-    // Scan worktree
+    // Hash the worktree without storing anything
     let ignore = IgnoreRules::load(&root);
-    let worktree_tree = worktree::scan_worktree(&store, &root, &ignore)?;
+    let worktree_files = worktree::worktree_files(&store, &root, &ignore)?;
+    let head_files = match &head_tree {
+        Some(tree) => flatten_tree(&store, tree)?,
+        None => Default::default(),
+    };
 
-    let changes = diff_trees(&store, head_tree.as_ref(), Some(&worktree_tree), "")?;
+    let changes = diff_flat(&head_files, &worktree_files);
+    let registry = codec_registry(&store)?;
+    let changes = renames::detect(&store, &registry, &head_files, changes, Some(&root));
 
     if args.json {
         let entries: Vec<serde_json::Value> = changes
             .iter()
             .map(|c| {
-                serde_json::json!({
+                let mut entry = serde_json::json!({
                     "path": c.path,
                     "status": match c.kind {
                         ChangeKind::Added => "added",
                         ChangeKind::Deleted => "deleted",
                         ChangeKind::Modified => "modified",
                         ChangeKind::TypeChanged => "type_changed",
+                        ChangeKind::Renamed => "renamed",
+                        ChangeKind::Copied => "copied",
                     },
-                })
+                });
+                if let Some(old_path) = &c.old_path {
+                    entry["from"] = serde_json::json!(old_path);
+                    entry["similarity"] = serde_json::json!(c.similarity);
+                }
+                entry
             })
             .collect();
         let output = serde_json::json!({
@@ -78,12 +92,12 @@ pub fn run(args: StatusArgs) -> anyhow::Result<()> {
 
     if in_merge {
         if let Ok(ms) = merge_state::read_from(&claw_dir) {
-            println!("Merging: {} into {}", ms.merge.right_ref, ms.merge.left_ref);
+            println!("{}", ms.merge.describe());
             let unresolved: Vec<_> = ms.conflicts.iter().collect();
             if !unresolved.is_empty() {
                 println!("{} unresolved conflict(s):", unresolved.len());
                 for c in &unresolved {
-                    println!("  CONFLICT: {} ({})", c.file_path, c.codec_id);
+                    println!("  CONFLICT: {}", c.label());
                 }
             }
             println!("  (use \"claw resolve\" to manage conflicts)");
@@ -105,6 +119,7 @@ pub fn run(args: StatusArgs) -> anyhow::Result<()> {
     let mut modified = Vec::new();
     let mut deleted = Vec::new();
     let mut type_changed = Vec::new();
+    let mut moved = Vec::new();
 
     for c in &changes {
         match c.kind {
@@ -112,6 +127,7 @@ pub fn run(args: StatusArgs) -> anyhow::Result<()> {
             ChangeKind::Deleted => deleted.push(c.path.as_str()),
             ChangeKind::Modified => modified.push(c.path.as_str()),
             ChangeKind::TypeChanged => type_changed.push(c.path.as_str()),
+            ChangeKind::Renamed | ChangeKind::Copied => moved.push(c),
         }
     }
 
@@ -130,6 +146,19 @@ pub fn run(args: StatusArgs) -> anyhow::Result<()> {
     for path in &type_changed {
         println!("  T  {}", path);
     }
+    for c in &moved {
+        let tag = if c.kind == ChangeKind::Renamed {
+            "R"
+        } else {
+            "C"
+        };
+        println!(
+            "  {}  {} -> {}",
+            tag,
+            c.old_path.as_deref().unwrap_or_default(),
+            c.path
+        );
+    }
 
     println!();
     println!("  (use \"claw snapshot -m <message>\" to record)");
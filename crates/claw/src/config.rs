@@ -1,5 +1,9 @@
 use std::path::PathBuf;
 
+use claw_patch::CodecRegistry;
+use claw_store::repo::read_config;
+use claw_store::ClawStore;
+
 /// Find the claw repo root by walking up from the current directory.
 pub fn find_repo_root() -> anyhow::Result<PathBuf> {
     let mut dir = std::env::current_dir()?;
@@ -12,3 +16,11 @@ pub fn find_repo_root() -> anyhow::Result<PathBuf> {
         }
     }
 }
+
+/// The codec registry, set up as `.claw/repo.toml` configures it.
+pub fn codec_registry(store: &ClawStore) -> anyhow::Result<CodecRegistry> {
+    let config = read_config(store.layout())?;
+    Ok(CodecRegistry::with_json_identity_keys(
+        &config.codecs.json.identity_keys,
+    ))
+}
@@ -1,6 +1,10 @@
 use std::path::Path;
 
-/// Write text conflict markers (<<<< ==== >>>>)
+use claw_patch::text_line::{merge3_regions, MergeRegion};
+
+/// Write the merge of a text file with a diff3-style marker block for each
+/// conflicting hunk (`<<<<<<<`, `|||||||` base, `=======`, `>>>>>>>`).
+/// Regions only one side changed are merged between the blocks.
 pub fn write_text_conflict(
     dir: &Path,
     path: &str,
@@ -14,45 +18,48 @@ pub fn write_text_conflict(
     if let Some(parent) = file_path.parent() {
         std::fs::create_dir_all(parent)?;
     }
+    let output = text_conflict(
+        &String::from_utf8_lossy(base),
+        &String::from_utf8_lossy(left),
+        &String::from_utf8_lossy(right),
+        left_label,
+        right_label,
+    );
+    std::fs::write(&file_path, output)?;
+    Ok(())
+}
 
-    let base_str = String::from_utf8_lossy(base);
-    let left_str = String::from_utf8_lossy(left);
-    let right_str = String::from_utf8_lossy(right);
-
-    // Simple 3-way conflict marker output
+fn text_conflict(
+    base: &str,
+    left: &str,
+    right: &str,
+    left_label: &str,
+    right_label: &str,
+) -> String {
     let mut output = String::new();
-
-    let base_lines: Vec<&str> = base_str.lines().collect();
-    let left_lines: Vec<&str> = left_str.lines().collect();
-    let right_lines: Vec<&str> = right_str.lines().collect();
-
-    // Find common prefix
-    let mut i = 0;
-    let min_len = base_lines
-        .len()
-        .min(left_lines.len())
-        .min(right_lines.len());
-    while i < min_len && left_lines[i] == right_lines[i] && left_lines[i] == base_lines[i] {
-        output.push_str(left_lines[i]);
-        output.push('\n');
-        i += 1;
-    }
-
-    // Emit conflict block
-    output.push_str(&format!("<<<<<<< {}\n", left_label));
-    for line in &left_lines[i..] {
-        output.push_str(line);
-        output.push('\n');
-    }
-    output.push_str("=======\n");
-    for line in &right_lines[i..] {
-        output.push_str(line);
-        output.push('\n');
+    let push_lines = |output: &mut String, lines: &[String]| {
+        for line in lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+    };
+    for region in merge3_regions(base, left, right) {
+        match region {
+            MergeRegion::Clean(lines) => push_lines(&mut output, &lines),
+            MergeRegion::Conflict {
+                base, left, right, ..
+            } => {
+                output.push_str(&format!("<<<<<<< {left_label}\n"));
+                push_lines(&mut output, &left);
+                output.push_str("||||||| base\n");
+                push_lines(&mut output, &base);
+                output.push_str("=======\n");
+                push_lines(&mut output, &right);
+                output.push_str(&format!(">>>>>>> {right_label}\n"));
+            }
+        }
     }
-    output.push_str(&format!(">>>>>>> {}\n", right_label));
-
-    std::fs::write(&file_path, output)?;
-    Ok(())
+    output
 }
 
 /// Write JSON conflict (structured)
@@ -97,3 +104,50 @@ pub fn write_binary_conflict(
     std::fs::write(format!("{}.RIGHT", file_path.display()), right)?;
     Ok(())
 }
+
+/// Write `<path>.CONFLICT`, describing a tree conflict and what each side
+/// has at the path.
+pub fn write_tree_conflict_note(
+    dir: &Path,
+    path: &str,
+    kind: &str,
+    sides: &[(&str, String)],
+) -> anyhow::Result<()> {
+    let mut note = format!("CONFLICT ({kind}): {path}\n");
+    for (label, side) in sides {
+        note.push_str(&format!("  {label}: {side}\n"));
+    }
+    note.push_str(&format!(
+        "Keep one version at {path}, delete it, or move it elsewhere, then remove this file.\n\
+         During a merge, `claw resolve keep|delete|rename {path}` does this for you.\n"
+    ));
+    std::fs::write(dir.join(format!("{path}.CONFLICT")), note)?;
+    Ok(())
+}
+
+/// Remove every sidecar a conflict may have left next to `path`.
+pub fn remove_sidecars(dir: &Path, path: &str) {
+    for suffix in ["BASE", "LEFT", "RIGHT", "CONFLICT"] {
+        let _ = std::fs::remove_file(dir.join(format!("{path}.{suffix}")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_each_conflicting_hunk_with_its_base() {
+        let base = "a\nb\nc\nd\ne\n";
+        let left = "A\nb\nc\nd\nleft\n";
+        let right = "a\nb\nC\nd\nright\n";
+        assert_eq!(
+            text_conflict(base, left, right, "main", "feature"),
+            "A\nb\nC\nd\n\
+             <<<<<<< main\nleft\n\
+             ||||||| base\ne\n\
+             =======\nright\n\
+             >>>>>>> feature\n"
+        );
+    }
+}
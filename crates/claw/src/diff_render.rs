@@ -1,21 +1,48 @@
-use similar::TextDiff;
+use similar::{ChangeTag, TextDiff};
 
-pub fn render_unified_diff(path: &str, old_bytes: &[u8], new_bytes: &[u8]) -> String {
+/// Unified diff between `old_path` and `new_path`, which differ only for
+/// renames and copies.
+pub fn render_unified_diff(
+    old_path: &str,
+    new_path: &str,
+    old_bytes: &[u8],
+    new_bytes: &[u8],
+) -> String {
     let old_str = String::from_utf8_lossy(old_bytes);
     let new_str = String::from_utf8_lossy(new_bytes);
 
     let diff = TextDiff::from_lines(old_str.as_ref(), new_str.as_ref());
-    let mut output = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut output = format!("--- a/{}\n+++ b/{}\n", old_path, new_path);
     output.push_str(
         &diff
             .unified_diff()
             .context_radius(3)
-            .header(&format!("a/{}", path), &format!("b/{}", path))
+            .header(&format!("a/{}", old_path), &format!("b/{}", new_path))
             .to_string(),
     );
     output
 }
 
+/// Inserted and deleted line counts between two texts.
+pub fn line_counts(old: &str, new: &str) -> (usize, usize) {
+    let diff = TextDiff::from_lines(old, new);
+    let mut insertions = 0;
+    let mut deletions = 0;
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => insertions += 1,
+            ChangeTag::Delete => deletions += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+    (insertions, deletions)
+}
+
+/// `rename from`/`copy from` header lines for a moved or copied file.
+pub fn render_move_header(kind: &str, old_path: &str, new_path: &str, similarity: u8) -> String {
+    format!("{kind} from {old_path}\n{kind} to {new_path}\nsimilarity index {similarity}%\n")
+}
+
 pub fn render_json_diff(path: &str, ops: &[claw_core::types::PatchOp]) -> String {
     let mut output = format!("--- a/{}\n+++ b/{}\n", path, path);
     for op in ops {
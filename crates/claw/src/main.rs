@@ -10,6 +10,7 @@ mod error;
 mod ignore;
 mod merge_state;
 mod output;
+mod stack_state;
 mod worktree;
 
 use commands::Commands;
@@ -1,5 +1,6 @@
 use std::path::Path;
 
+use claw_core::types::ConflictKind;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -7,15 +8,69 @@ pub struct MergeState {
     pub merge: MergeInfo,
     #[serde(default)]
     pub conflicts: Vec<ConflictEntry>,
+    /// Stored `Conflict` objects of the conflicts resolved so far, as hex,
+    /// kept here so gc holds on to them until the merge completes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resolved: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MergeInfo {
+    /// What produced the conflicts; decides the parents of the completing
+    /// snapshot. Absent in states written before picks existed.
+    #[serde(default)]
+    pub kind: MergeKind,
     pub left_ref: String,
     pub right_ref: String,
     pub left_revision: String,
     pub right_revision: String,
     pub base_revision: String,
+    /// The heads after the right one in an octopus merge.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other_heads: Vec<MergeHead>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeHead {
+    pub ref_name: String,
+    pub revision: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeKind {
+    #[default]
+    Merge,
+    CherryPick,
+    Revert,
+}
+
+impl MergeInfo {
+    /// One-line description for status output.
+    pub fn describe(&self) -> String {
+        match self.kind {
+            MergeKind::Merge => {
+                let mut heads = vec![self.right_ref.as_str()];
+                heads.extend(self.other_heads.iter().map(|h| h.ref_name.as_str()));
+                format!("Merging: {} into {}", heads.join(", "), self.left_ref)
+            }
+            MergeKind::CherryPick => {
+                format!("Cherry-picking: {} onto {}", self.right_ref, self.left_ref)
+            }
+            MergeKind::Revert => format!("Reverting: {} on {}", self.right_ref, self.left_ref),
+        }
+    }
+
+    /// Revisions the completing snapshot records as parents, as hex. Picks
+    /// and reverts stay linear; merges record every head.
+    pub fn parent_revisions(&self) -> Vec<&str> {
+        let mut parents = vec![self.left_revision.as_str()];
+        if self.kind == MergeKind::Merge {
+            parents.push(&self.right_revision);
+            parents.extend(self.other_heads.iter().map(|h| h.revision.as_str()));
+        }
+        parents
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +78,49 @@ pub struct ConflictEntry {
     pub file_path: String,
     pub conflict_id: String,
     pub codec_id: String,
+    /// In an octopus merge, the ref of the head whose changes conflicted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+    /// The stored `Conflict` object, which records the resolution.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object_id: Option<String>,
+    /// Absent for content conflicts.
+    #[serde(default, skip_serializing_if = "is_content")]
+    pub kind: ConflictKind,
+}
+
+fn is_content(kind: &ConflictKind) -> bool {
+    *kind == ConflictKind::Content
+}
+
+impl ConflictEntry {
+    /// The path and codec (or the kind of a tree conflict), and the
+    /// conflicting head when there is one.
+    pub fn label(&self) -> String {
+        let what = match self.kind {
+            ConflictKind::Content => self.codec_id.as_str(),
+            kind => kind_name(kind),
+        };
+        match &self.head {
+            Some(head) => format!("{} ({}) from {}", self.file_path, what, head),
+            None => format!("{} ({})", self.file_path, what),
+        }
+    }
+
+    pub fn is_tree_conflict(&self) -> bool {
+        self.kind != ConflictKind::Content
+    }
+}
+
+/// Short name of a conflict kind for messages.
+pub fn kind_name(kind: ConflictKind) -> &'static str {
+    match kind {
+        ConflictKind::Content => "content",
+        ConflictKind::DeleteModify => "delete/modify",
+        ConflictKind::Mode => "mode",
+        ConflictKind::Symlink => "symlink",
+        ConflictKind::FileDirectory => "file/directory",
+    }
 }
 
 const MERGE_STATE_FILE: &str = "MERGE_STATE.toml";
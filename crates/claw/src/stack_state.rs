@@ -0,0 +1,62 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::merge_state::ConflictEntry;
+
+/// A stack rebase stopped on a conflict, waiting for `--continue` or
+/// `--abort`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackRebaseState {
+    pub rebase: StackRebaseInfo,
+    /// Every change's head before the rebase started, bottom first.
+    #[serde(default)]
+    pub changes: Vec<StackChange>,
+    #[serde(default)]
+    pub conflicts: Vec<ConflictEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackRebaseInfo {
+    pub workstream: String,
+    pub onto: String,
+    /// Position in `changes` of the conflicting change.
+    pub stopped_at: usize,
+    /// The original revision whose replay conflicted.
+    pub revision: String,
+    /// The rebased revision it was replayed onto; checked out with the
+    /// conflicts.
+    pub tip: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StackChange {
+    pub change_id: String,
+    pub head: String,
+}
+
+const STACK_REBASE_FILE: &str = "STACK_REBASE.toml";
+
+pub fn write_to(claw_dir: &Path, state: &StackRebaseState) -> anyhow::Result<()> {
+    let content = toml::to_string_pretty(state)?;
+    std::fs::write(claw_dir.join(STACK_REBASE_FILE), content)?;
+    Ok(())
+}
+
+pub fn read_from(claw_dir: &Path) -> anyhow::Result<StackRebaseState> {
+    let content = std::fs::read_to_string(claw_dir.join(STACK_REBASE_FILE))?;
+    let state: StackRebaseState = toml::from_str(&content)?;
+    Ok(state)
+}
+
+pub fn exists(claw_dir: &Path) -> bool {
+    claw_dir.join(STACK_REBASE_FILE).exists()
+}
+
+pub fn remove(claw_dir: &Path) -> anyhow::Result<()> {
+    let path = claw_dir.join(STACK_REBASE_FILE);
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
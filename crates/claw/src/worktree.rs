@@ -1,101 +1,194 @@
 use std::collections::{BTreeMap, HashSet};
-use std::path::Path;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
+use claw_core::chunking;
 use claw_core::id::ObjectId;
 use claw_core::object::Object;
-use claw_core::types::{Blob, FileMode, Tree, TreeEntry};
+use claw_core::types::{FileMode, Tree, TreeEntry};
+use claw_store::tree_diff::{diff_flat, flatten_tree};
+use claw_store::worktree_index::{FileStat, WorktreeEntry, WorktreeIndex};
 use claw_store::ClawStore;
 
 use crate::ignore::IgnoreRules;
 
 /// Scan working directory, store blobs/trees, return root tree ObjectId.
+///
+/// Files whose stat data matches the worktree index reuse their cached blob
+/// id instead of being read and hashed again.
 pub fn scan_worktree(
     store: &ClawStore,
     root: &Path,
     ignore: &IgnoreRules,
 ) -> anyhow::Result<ObjectId> {
-    scan_dir(store, root, root, ignore)
+    let mut scan = Scan::new(store, root, ignore, true);
+    let id = scan_dir(&mut scan, root)?;
+    scan.finish()?;
+    Ok(id)
 }
 
-fn scan_dir(
+/// Blob id and mode of every worktree file, keyed by path.
+///
+/// Unlike [`scan_worktree`] this never writes objects: changed files are only
+/// hashed. Only the worktree index is updated.
+pub fn worktree_files(
     store: &ClawStore,
-    dir: &Path,
-    repo_root: &Path,
+    root: &Path,
     ignore: &IgnoreRules,
-) -> anyhow::Result<ObjectId> {
-    let mut entries_map: BTreeMap<String, TreeEntry> = BTreeMap::new();
-
-    let mut dir_entries: Vec<_> = std::fs::read_dir(dir)?.filter_map(|e| e.ok()).collect();
-    dir_entries.sort_by_key(|e| e.file_name());
+) -> anyhow::Result<BTreeMap<String, (ObjectId, FileMode)>> {
+    let mut scan = Scan::new(store, root, ignore, false);
+    let mut files = BTreeMap::new();
+    walk_dir(&mut scan, root, &mut files)?;
+    scan.finish()?;
+    Ok(files)
+}
 
-    for entry in dir_entries {
-        let file_name = entry.file_name().to_string_lossy().to_string();
-        let path = entry.path();
-        let rel_path = path
-            .strip_prefix(repo_root)
-            .unwrap_or(&path)
-            .to_string_lossy()
-            .to_string();
+struct Scan<'a> {
+    store: &'a ClawStore,
+    root: &'a Path,
+    ignore: &'a IgnoreRules,
+    index: WorktreeIndex,
+    seen: HashSet<String>,
+    /// Store blobs rather than just hashing them.
+    write: bool,
+}
 
-        let ft = entry.file_type()?;
-        let is_dir = ft.is_dir();
+/// One non-ignored directory entry.
+enum ScanEntry {
+    Dir(PathBuf),
+    File(ObjectId, FileMode),
+}
 
-        if ignore.is_ignored(&rel_path, is_dir) {
-            continue;
+impl<'a> Scan<'a> {
+    fn new(store: &'a ClawStore, root: &'a Path, ignore: &'a IgnoreRules, write: bool) -> Self {
+        Self {
+            store,
+            root,
+            ignore,
+            index: WorktreeIndex::load(store.layout()),
+            seen: HashSet::new(),
+            write,
         }
+    }
 
-        if ft.is_symlink() {
-            let target = std::fs::read_link(&path)?;
-            let target_str = target.to_string_lossy().to_string();
-            let blob = Blob {
-                data: target_str.into_bytes(),
-                media_type: None,
-            };
-            let id = store.store_object(&Object::Blob(blob))?;
-            entries_map.insert(
-                file_name.clone(),
-                TreeEntry {
-                    name: file_name,
-                    mode: FileMode::Symlink,
-                    object_id: id,
-                },
-            );
-        } else if is_dir {
-            let sub_tree_id = scan_dir(store, &path, repo_root, ignore)?;
-            entries_map.insert(
-                file_name.clone(),
-                TreeEntry {
-                    name: file_name,
-                    mode: FileMode::Directory,
-                    object_id: sub_tree_id,
-                },
-            );
-        } else if ft.is_file() {
-            let data = std::fs::read(&path)?;
-            let mode = detect_file_mode(&path);
-            let blob = Blob {
-                data,
-                media_type: None,
+    /// Sorted, non-ignored entries of `dir` with blob ids resolved for files.
+    fn read_dir(&mut self, dir: &Path) -> anyhow::Result<Vec<(String, String, ScanEntry)>> {
+        let mut dir_entries: Vec<_> = std::fs::read_dir(dir)?.filter_map(|e| e.ok()).collect();
+        dir_entries.sort_by_key(|e| e.file_name());
+
+        let mut out = Vec::new();
+        for entry in dir_entries {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            let rel_path = path
+                .strip_prefix(self.root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+
+            let ft = entry.file_type()?;
+            let is_dir = ft.is_dir();
+
+            if self.ignore.is_ignored(&rel_path, is_dir) {
+                continue;
+            }
+
+            let scanned = if ft.is_symlink() {
+                ScanEntry::File(
+                    self.blob_id(&rel_path, &path, FileMode::Symlink)?,
+                    FileMode::Symlink,
+                )
+            } else if is_dir {
+                ScanEntry::Dir(path)
+            } else if ft.is_file() {
+                let mode = detect_file_mode(&path);
+                ScanEntry::File(self.blob_id(&rel_path, &path, mode)?, mode)
+            } else {
+                continue;
             };
-            let id = store.store_object(&Object::Blob(blob))?;
-            entries_map.insert(
-                file_name.clone(),
-                TreeEntry {
-                    name: file_name,
-                    mode,
-                    object_id: id,
-                },
-            );
+            out.push((file_name, rel_path, scanned));
         }
+        Ok(out)
     }
 
-    let tree = Tree {
-        entries: entries_map.into_values().collect(),
-    };
-    let id = store.store_object(&Object::Tree(tree))?;
+    fn blob_id(&mut self, rel_path: &str, path: &Path, mode: FileMode) -> anyhow::Result<ObjectId> {
+        self.seen.insert(rel_path.to_string());
+        let stat = FileStat::from_metadata(&std::fs::symlink_metadata(path)?);
+        if let Some(id) = self.index.lookup(rel_path, &stat, mode) {
+            if !self.write || self.store.has_object(&id) {
+                return Ok(id);
+            }
+        }
+
+        let id = if mode == FileMode::Symlink {
+            let target = std::fs::read_link(path)?;
+            self.blob_from_reader(rel_path, target.to_string_lossy().as_bytes())?
+        } else {
+            self.blob_from_reader(rel_path, std::fs::File::open(path)?)?
+        };
+        self.index.insert(
+            rel_path.to_string(),
+            WorktreeEntry {
+                stat,
+                mode,
+                blob_id: id,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Store (or with `write` off, only hash) content streamed from `reader`.
+    fn blob_from_reader(&self, rel_path: &str, reader: impl Read) -> anyhow::Result<ObjectId> {
+        if self.write {
+            Ok(self.store.store_blob_for_path(rel_path, reader)?)
+        } else {
+            Ok(chunking::build_blob(reader, chunking::hash_object)?)
+        }
+    }
+
+    fn finish(mut self) -> anyhow::Result<()> {
+        let seen = self.seen;
+        self.index.retain_paths(|path| seen.contains(path));
+        self.index.save(self.store.layout())?;
+        Ok(())
+    }
+}
+
+fn scan_dir(scan: &mut Scan, dir: &Path) -> anyhow::Result<ObjectId> {
+    let mut entries = Vec::new();
+    for (name, _, entry) in scan.read_dir(dir)? {
+        let (mode, object_id) = match entry {
+            ScanEntry::Dir(path) => (FileMode::Directory, scan_dir(scan, &path)?),
+            ScanEntry::File(id, mode) => (mode, id),
+        };
+        entries.push(TreeEntry {
+            name,
+            mode,
+            object_id,
+        });
+    }
+
+    let tree = Tree { entries };
+    let id = scan.store.store_object(&Object::Tree(tree))?;
     Ok(id)
 }
 
+fn walk_dir(
+    scan: &mut Scan,
+    dir: &Path,
+    files: &mut BTreeMap<String, (ObjectId, FileMode)>,
+) -> anyhow::Result<()> {
+    for (_, rel_path, entry) in scan.read_dir(dir)? {
+        match entry {
+            ScanEntry::Dir(path) => walk_dir(scan, &path, files)?,
+            ScanEntry::File(id, mode) => {
+                files.insert(rel_path, (id, mode));
+            }
+        }
+    }
+    Ok(())
+}
+
 fn detect_file_mode(path: &Path) -> FileMode {
     #[cfg(unix)]
     {
@@ -122,43 +215,113 @@ pub fn materialize_tree(
     };
 
     for entry in &tree.entries {
-        let path = target_dir.join(&entry.name);
-        match entry.mode {
-            FileMode::Directory => {
-                std::fs::create_dir_all(&path)?;
-                materialize_tree(store, &entry.object_id, &path)?;
+        materialize_entry(store, entry, &target_dir.join(&entry.name))?;
+    }
+    Ok(())
+}
+
+/// Write one tree entry (a file, symlink or whole directory) to `path`.
+pub fn materialize_entry(store: &ClawStore, entry: &TreeEntry, path: &Path) -> anyhow::Result<()> {
+    match entry.mode {
+        FileMode::Directory => {
+            std::fs::create_dir_all(path)?;
+            materialize_tree(store, &entry.object_id, path)?;
+        }
+        FileMode::Symlink => {
+            let obj = store.load_object(&entry.object_id)?;
+            if let Object::Blob(b) = obj {
+                let target = String::from_utf8_lossy(&b.data);
+                // Remove existing file/dir if present
+                let _ = std::fs::remove_file(path);
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(target.as_ref(), path)?;
+                #[cfg(not(unix))]
+                std::fs::write(path, &b.data)?;
             }
-            FileMode::Symlink => {
-                let obj = store.load_object(&entry.object_id)?;
-                if let Object::Blob(b) = obj {
-                    let target = String::from_utf8_lossy(&b.data);
-                    // Remove existing file/dir if present
-                    let _ = std::fs::remove_file(&path);
-                    #[cfg(unix)]
-                    std::os::unix::fs::symlink(target.as_ref(), &path)?;
-                    #[cfg(not(unix))]
-                    std::fs::write(&path, &b.data)?;
+        }
+        _ => {
+            // Remove dir if file should go here
+            if path.is_dir() {
+                std::fs::remove_dir_all(path)?;
+            }
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            store.write_blob_to(&entry.object_id, &mut file)?;
+            file.flush()?;
+            // An existing file keeps its permissions, so set the exec bits
+            // either way
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mut perms = std::fs::metadata(path)?.permissions();
+                match entry.mode {
+                    FileMode::Executable => perms.set_mode(perms.mode() | 0o111),
+                    _ => perms.set_mode(perms.mode() & !0o111),
                 }
+                std::fs::set_permissions(path, perms)?;
             }
-            _ => {
-                let obj = store.load_object(&entry.object_id)?;
-                if let Object::Blob(b) = obj {
-                    // Remove dir if file should go here
-                    if path.is_dir() {
-                        std::fs::remove_dir_all(&path)?;
-                    }
-                    std::fs::write(&path, &b.data)?;
-                    #[cfg(unix)]
-                    if entry.mode == FileMode::Executable {
-                        use std::os::unix::fs::PermissionsExt;
-                        let mut perms = std::fs::metadata(&path)?.permissions();
-                        perms.set_mode(perms.mode() | 0o111);
-                        std::fs::set_permissions(&path, perms)?;
-                    }
+        }
+    }
+    Ok(())
+}
+
+/// Tree of the revision HEAD resolves to, if any.
+pub fn head_tree(store: &ClawStore) -> anyhow::Result<Option<ObjectId>> {
+    let Some(head_id) = store.resolve_head()? else {
+        return Ok(None);
+    };
+    match store.load_object(&head_id)? {
+        Object::Revision(rev) => Ok(rev.tree),
+        _ => Ok(None),
+    }
+}
+
+/// Number of worktree files that differ from `tree`. Writes no objects.
+pub fn count_uncommitted(
+    store: &ClawStore,
+    root: &Path,
+    ignore: &IgnoreRules,
+    tree: &ObjectId,
+) -> anyhow::Result<usize> {
+    let worktree_files = worktree_files(store, root, ignore)?;
+    let tree_files = flatten_tree(store, tree)?;
+    Ok(diff_flat(&tree_files, &worktree_files).len())
+}
+
+/// Replace the checked-out `old_tree` with `new_tree`, removing files that
+/// `new_tree` no longer tracks.
+pub fn switch_tree(
+    store: &ClawStore,
+    root: &Path,
+    old_tree: Option<&ObjectId>,
+    new_tree: &ObjectId,
+) -> anyhow::Result<()> {
+    if let Some(old_tree) = old_tree {
+        let old_paths = collect_tracked_paths(store, old_tree, "")?;
+        let new_paths = collect_tracked_paths(store, new_tree, "")?;
+        for old_path in &old_paths {
+            if !new_paths.contains(old_path) {
+                let full = root.join(old_path);
+                let _ = std::fs::remove_file(&full);
+                // Clean empty parent dirs
+                if let Some(parent) = full.parent() {
+                    let _ = remove_empty_dirs(parent, root);
                 }
             }
         }
     }
+    materialize_tree(store, new_tree, root)
+}
+
+fn remove_empty_dirs(dir: &Path, stop_at: &Path) -> std::io::Result<()> {
+    if dir == stop_at || !dir.starts_with(stop_at) {
+        return Ok(());
+    }
+    if dir.is_dir() && std::fs::read_dir(dir)?.next().is_none() {
+        std::fs::remove_dir(dir)?;
+        if let Some(parent) = dir.parent() {
+            remove_empty_dirs(parent, stop_at)?;
+        }
+    }
     Ok(())
 }
 
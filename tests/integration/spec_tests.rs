@@ -144,6 +144,7 @@ fn test_object_roundtrip_all_12_types() {
         resolution_patch_ids: vec![],
         status: ConflictStatus::Open,
         created_at_ms: 1000,
+        kind: ConflictKind::Content,
     });
     let conflict_obj_id = store.store_object(&conflict).unwrap();
     assert!(matches!(
@@ -286,7 +287,7 @@ fn test_json_semantic_merge() {
     use claw_patch::Codec;
     use serde_json::json;
 
-    let codec = JsonTreeCodec;
+    let codec = JsonTreeCodec::default();
 
     let base = serde_json::to_vec(&json!({
         "name": "project",
@@ -356,6 +357,7 @@ fn test_conflict_persistence_and_resolution() {
         resolution_patch_ids: vec![],
         status: ConflictStatus::Open,
         created_at_ms: 1000,
+        kind: ConflictKind::DeleteModify,
     });
     let conflict_obj_id = store.store_object(&conflict).unwrap();
 
@@ -366,6 +368,7 @@ fn test_conflict_persistence_and_resolution() {
         _ => panic!("expected conflict"),
     };
     assert_eq!(loaded_conflict.status, ConflictStatus::Open);
+    assert_eq!(loaded_conflict.kind, ConflictKind::DeleteModify);
     assert!(loaded_conflict.resolution_patch_ids.is_empty());
 
     // Resolve the conflict